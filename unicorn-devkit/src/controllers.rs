@@ -1,50 +1,203 @@
+use sdl2::{GameControllerSubsystem, HapticSubsystem, JoystickSubsystem};
 use sdl2::controller::GameController;
+use sdl2::haptic::Haptic;
 use sdl2::joystick::Joystick;
 
+use unicorn::config::MAX_PLAYERS;
+
+enum Device {
+    Controller(GameController),
+    Joystick(Joystick),
+}
+
+struct Pad {
+    instance_id: i32,
+    player: u8,
+    device: Device,
+    haptic: Option<Haptic>,
+}
+
 pub struct Controllers {
-    controllers: Vec<GameController>,
-    joysticks: Vec<Joystick>,
-    ids: Vec<u32>,
-    j_ids: Vec<u32>,
-    g_ids: Vec<u32>,
+    game_controller_subsystem: Option<GameControllerSubsystem>,
+    joystick_subsystem: Option<JoystickSubsystem>,
+    haptic_subsystem: Option<HapticSubsystem>,
+    pads: Vec<Pad>,
 }
 
 impl Controllers {
     pub fn new() -> Controllers {
         Controllers {
-            controllers: Vec::new(),
-            joysticks: Vec::new(),
-            ids: Vec::new(),
-            j_ids: Vec::new(),
-            g_ids: Vec::new(),
+            game_controller_subsystem: None,
+            joystick_subsystem: None,
+            haptic_subsystem: None,
+            pads: Vec::new(),
         }
     }
 
-    pub fn push_controller(&mut self, id: u32, controller: GameController) {
-        if !self.ids.contains(&id) {
-            info!("REGISTERED CONTROLLER {:?}", id);
+    pub fn set_subsystems(&mut self,
+                          game_controller_subsystem: GameControllerSubsystem,
+                          joystick_subsystem: JoystickSubsystem,
+                          haptic_subsystem: Option<HapticSubsystem>) {
+        self.game_controller_subsystem = Some(game_controller_subsystem);
+        self.joystick_subsystem = Some(joystick_subsystem);
+        self.haptic_subsystem = haptic_subsystem;
+    }
+
+    fn free_player(&self) -> Option<u8> {
+        (0..MAX_PLAYERS).find(|player| !self.pads.iter().any(|pad| pad.player == *player))
+    }
 
-            self.controllers.push(controller);
-            self.ids.push(id);
-            self.g_ids.push(id);
+    fn push(&mut self, index: u32, instance_id: i32, device: Device) -> Option<u8> {
+        if self.pads.iter().any(|pad| pad.instance_id == instance_id) {
+            return None;
         }
+
+        let player = match self.free_player() {
+            Some(player) => player,
+            None => {
+                info!("[Frontend][CONTROLLER] No free player slot for {:?}", instance_id);
+                return None;
+            }
+        };
+
+        let haptic = match self.haptic_subsystem {
+            Some(ref haptic_subsystem) => {
+                match haptic_subsystem.open_from_joystick_id(index as i32) {
+                    Ok(haptic) => Some(haptic),
+                    Err(e) => {
+                        info!("[Frontend][CONTROLLER] No rumble for {:?}: {:?}", index, e);
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+
+        info!("[Frontend][CONTROLLER] REGISTERED {:?} -> Player {:?}",
+              instance_id,
+              player);
+
+        self.pads.push(Pad {
+                           instance_id: instance_id,
+                           player: player,
+                           device: device,
+                           haptic: haptic,
+                       });
+
+        Some(player)
     }
 
-    pub fn push_joystick(&mut self, id: u32, joystick: Joystick) {
-        if !self.ids.contains(&id) {
-            info!("REGISTERED JOYSTICK {:?}", id);
+    /// Open the device `index` as a game controller, and return the player slot assigned to it
+    pub fn open_controller(&mut self, index: u32) -> Option<u8> {
+        let controller = match self.game_controller_subsystem {
+            Some(ref subsystem) => {
+                if !subsystem.is_game_controller(index) {
+                    return None;
+                }
+
+                match subsystem.open(index) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        error!("[Frontend][CONTROLLER] failed: {:?}", e);
+                        return None;
+                    }
+                }
+            }
+            None => return None,
+        };
+
+        info!("[Frontend][CONTROLLER] Success: opened \"{}\"", controller.name());
+        info!("[Frontend][CONTROLLER] Success: opened \"{}\"", controller.mapping());
 
-            self.joysticks.push(joystick);
-            self.ids.push(id);
-            self.j_ids.push(id);
+        let instance_id = controller.instance_id() as i32;
+        self.push(index, instance_id, Device::Controller(controller))
+    }
+
+    /// Open the device `index` as a raw joystick, unless it is handled as a game controller
+    pub fn open_joystick(&mut self, index: u32) -> Option<u8> {
+        if let Some(ref subsystem) = self.game_controller_subsystem {
+            if subsystem.is_game_controller(index) {
+                return None;
+            }
+        }
+
+        let joystick = match self.joystick_subsystem {
+            Some(ref subsystem) => {
+                match subsystem.open(index) {
+                    Ok(c) => c,
+                    Err(e) => {
+                        error!("[Frontend][JOYSTICK] failed: {:?}", e);
+                        return None;
+                    }
+                }
+            }
+            None => return None,
+        };
+
+        info!("[Frontend][JOYSTICK] Success: opened \"{}\"", joystick.name());
+
+        let instance_id = joystick.instance_id() as i32;
+        self.push(index, instance_id, Device::Joystick(joystick))
+    }
+
+    /// Close a removed device, and return the player slot that is now free
+    pub fn close(&mut self, instance_id: i32) -> Option<u8> {
+        match self.pads.iter().position(|pad| pad.instance_id == instance_id) {
+            Some(idx) => {
+                let pad = self.pads.remove(idx);
+                info!("[Frontend][CONTROLLER] REMOVED {:?} -> Player {:?}",
+                      instance_id,
+                      pad.player);
+                Some(pad.player)
+            }
+            None => None,
         }
     }
 
-    pub fn is_controller(&self, id: u32) -> bool {
-        self.g_ids.contains(&id)
+    pub fn player(&self, instance_id: i32) -> Option<u8> {
+        self.pads
+            .iter()
+            .find(|pad| pad.instance_id == instance_id)
+            .map(|pad| pad.player)
+    }
+
+    pub fn is_controller(&self, instance_id: i32) -> bool {
+        self.pads
+            .iter()
+            .any(|pad| {
+                     pad.instance_id == instance_id &&
+                     match pad.device {
+                         Device::Controller(_) => true,
+                         _ => false,
+                     }
+                 })
     }
 
-    pub fn is_joystick(&self, id: u32) -> bool {
-        self.j_ids.contains(&id)
+    pub fn is_joystick(&self, instance_id: i32) -> bool {
+        self.pads
+            .iter()
+            .any(|pad| {
+                     pad.instance_id == instance_id &&
+                     match pad.device {
+                         Device::Joystick(_) => true,
+                         _ => false,
+                     }
+                 })
+    }
+
+    pub fn rumble(&mut self, player: u8, strength: f32, duration: u32) {
+        for pad in self.pads.iter_mut() {
+            if pad.player != player {
+                continue;
+            }
+
+            if let Some(ref mut haptic) = pad.haptic {
+                if strength <= 0. || duration == 0 {
+                    haptic.rumble_stop();
+                } else {
+                    haptic.rumble_play(strength, duration);
+                }
+            }
+        }
     }
 }
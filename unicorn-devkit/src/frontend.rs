@@ -8,7 +8,6 @@ use std::path::Path;
 
 use chrono::Local;
 
-use sdl2::controller::Axis;
 use sdl2::keyboard::Scancode;
 use sdl2::mouse::MouseButton;
 
@@ -16,11 +15,12 @@ use renderer;
 use fps;
 use frametimes;
 use controllers;
-use input::{map_axis, map_button, map_button_joystick, map_axis_joystick};
+use input::{map_axis, map_button};
 
 use unicorn;
-use unicorn::gfx::Scale;
+use unicorn::gfx::{Scale, SCREEN_WIDTH, SCREEN_HEIGHT};
 use unicorn::config::scancode;
+use unicorn::config::touch::TouchPhase;
 
 
 pub fn map_sdlscancode(code: Scancode) -> scancode::Scancode {
@@ -315,6 +315,7 @@ pub struct Frontend {
     pub uc: unicorn::unicorn::Unicorn,
     scale: Scale,
     fps_counter: fps::FpsCounter,
+    text_input: bool,
 }
 
 
@@ -357,6 +358,8 @@ impl Frontend {
             uc: uc,
             scale: scale,
             fps_counter: fps::FpsCounter::new(),
+            // SDL starts with the text input enabled
+            text_input: true,
         })
     }
 
@@ -385,62 +388,72 @@ impl Frontend {
         info!("[Frontend] Init Controllers");
 
         let game_controller_subsystem = self.sdl.game_controller().unwrap();
+        let joystick_subsystem = self.sdl.joystick().unwrap();
+
+        let haptic_subsystem = match self.sdl.haptic() {
+            Ok(haptic_subsystem) => Some(haptic_subsystem),
+            Err(e) => {
+                error!("[Frontend][CONTROLLER] rumble not available: {:?}", e);
+                None
+            }
+        };
 
         info!("[Frontend] Loading the database of Game Controller");
         info!("[Frontend] -> {:?}",
               game_controller_subsystem.load_mappings(Path::new(&pathdb)));
 
-        let available = match game_controller_subsystem.num_joysticks() {
+        let available = match joystick_subsystem.num_joysticks() {
             Ok(n) => n,
-            Err(e) => panic!("[Frontend] can't enumerate joysticks: {}", e),
+            Err(e) => panic!("[Frontend][JOYSTICK] can't enumerate joysticks: {}", e),
         };
 
         info!("[Frontend][CONTROLLER] {} joysticks available", available);
 
+        self.controllers
+            .set_subsystems(game_controller_subsystem, joystick_subsystem, haptic_subsystem);
+
+        // Devices plugged later are assigned to a player in the event loop
         for id in 0..available {
-            if game_controller_subsystem.is_game_controller(id) {
-                info!("[Frontend][CONTROLLER] Attempting to open controller {}",
-                      id);
-
-                match game_controller_subsystem.open(id) {
-                    Ok(c) => {
-                        // We managed to find and open a game controller,
-                        // exit the loop
-                        info!("[Frontend][CONTROLLER] Success: opened \"{}\"", c.name());
-                        info!("[Frontend][CONTROLLER] Success: opened \"{}\"", c.mapping());
-
-                        self.controllers.push_controller(id, Some(c).unwrap());
-                        break;
-                    }
-                    Err(e) => error!("[Frontend][CONTROLLER] failed: {:?}", e),
-                }
-            } else {
-                info!("[Frontend][CONTROLLER] {} is not a game controller", id);
+            let player = match self.controllers.open_controller(id) {
+                Some(player) => Some(player),
+                None => self.controllers.open_joystick(id),
+            };
+
+            if let Some(player) = player {
+                self.uc.players.lock().unwrap().set_connected(player, true);
             }
         }
+    }
 
-        let joystick_subsystem = self.sdl.joystick().unwrap();
-
-        let available = match joystick_subsystem.num_joysticks() {
-            Ok(n) => n,
-            Err(e) => panic!("[Frontend][JOYSTICK] can't enumerate joysticks: {}", e),
-        };
+    /// Enable the SDL text input (IME, on-screen keyboard) only while a script
+    /// reads the text input, the editor is running or the console is visible
+    fn update_text_input(&mut self) {
+        let active = self.uc.players.lock().unwrap().text_input.active || self.uc.editing ||
+                     self.uc.repl.visible;
+        if active == self.text_input {
+            return;
+        }
 
-        info!("[Frontend][JOYSTICK] {} joysticks available", available);
+        self.text_input = active;
 
-        // Iterate over all available joysticks and stop once we manage to
-        // open one.
-        for id in 0..available {
-            match joystick_subsystem.open(id) {
-                Ok(c) => {
-                    info!("[Frontend][JOYSTICK] Success: opened \"{}\"", c.name());
-                    self.controllers.push_joystick(id, Some(c).unwrap());
-                }
-                Err(e) => error!("[Frontend][JOYSTICK] failed: {:?}", e),
+        if let Ok(video) = self.sdl.video() {
+            if active {
+                video.text_input().start();
+            } else {
+                video.text_input().stop();
             }
         }
     }
 
+    fn update_rumbles(&mut self) {
+        let rumbles = self.uc.players.lock().unwrap().take_rumbles();
+
+        for rumble in rumbles {
+            self.controllers
+                .rumble(rumble.player, rumble.strength, rumble.duration);
+        }
+    }
+
     #[allow(dead_code)]
     pub fn run_native_cartridge(&mut self) {
         self.uc.current_code_type = unicorn::unicorn::Code::RUST;
@@ -491,8 +504,8 @@ impl Frontend {
             let mouse_state = self.event_pump.mouse_state();
             let (width, height) = self.renderer.get_dimensions();
 
-            let mouse_state_x = (mouse_state.x() as f32 * (400.0 / width as f32)) as i32;
-            let mouse_state_y = (mouse_state.y() as f32 * (240.0 / height as f32)) as i32;
+            let mouse_state_x = (mouse_state.x() as f32 * (SCREEN_WIDTH as f32 / width as f32)) as i32;
+            let mouse_state_y = (mouse_state.y() as f32 * (SCREEN_HEIGHT as f32 / height as f32)) as i32;
 
             self.uc
                 .players
@@ -546,12 +559,55 @@ impl Frontend {
                             .unwrap()
                             .mouse_button_up();
                     }
+                    Event::FingerDown { finger_id, x, y, .. } => {
+                        self.uc
+                            .players
+                            .lock()
+                            .unwrap()
+                            .touch(finger_id,
+                                   (x * SCREEN_WIDTH as f32) as i32,
+                                   (y * SCREEN_HEIGHT as f32) as i32,
+                                   TouchPhase::Began,
+                                   self.uc.info.lock().unwrap().elapsed_time);
+                    }
+                    Event::FingerMotion { finger_id, x, y, .. } => {
+                        self.uc
+                            .players
+                            .lock()
+                            .unwrap()
+                            .touch(finger_id,
+                                   (x * SCREEN_WIDTH as f32) as i32,
+                                   (y * SCREEN_HEIGHT as f32) as i32,
+                                   TouchPhase::Moved,
+                                   self.uc.info.lock().unwrap().elapsed_time);
+                    }
+                    Event::FingerUp { finger_id, x, y, .. } => {
+                        self.uc
+                            .players
+                            .lock()
+                            .unwrap()
+                            .touch(finger_id,
+                                   (x * SCREEN_WIDTH as f32) as i32,
+                                   (y * SCREEN_HEIGHT as f32) as i32,
+                                   TouchPhase::Ended,
+                                   self.uc.info.lock().unwrap().elapsed_time);
+                    }
                     Event::TextInput { text, .. } => {
                         // info!("TEXT INPUT {:?}", text);
-                        if text.len() == 1 {
-                            self.uc.players.lock().unwrap().set_text(text.clone());
+                        if self.uc.repl.visible {
+                            self.uc.repl_text(&text);
+                        } else {
+                            self.uc.players.lock().unwrap().input_text(&text);
                         }
                     }
+                    Event::TextEditing { text, start, .. } => {
+                        self.uc
+                            .players
+                            .lock()
+                            .unwrap()
+                            .text_input
+                            .set_composition(&text, start);
+                    }
                     Event::KeyDown { scancode: Some(scancode), keycode, keymod, repeat, .. } => {
                         // info!("KEY DOWN {:?} {:?} {:?}", scancode, keycode, keymod);
 
@@ -559,6 +615,17 @@ impl Frontend {
                             break 'main;
                         }
 
+                        if scancode == Scancode::F7 {
+                            self.uc.toggle_repl();
+                            continue;
+                        }
+
+                        // The console takes the keyboard
+                        if self.uc.repl.visible {
+                            self.uc.repl_key(map_sdlscancode(scancode));
+                            continue;
+                        }
+
                         self.uc
                             .players
                             .lock()
@@ -589,8 +656,12 @@ impl Frontend {
                         } else if scancode == Scancode::F5 {
                             self.uc.save_current_cartridge();
                         } else if scancode == Scancode::F6 || scancode == Scancode::AcBack {
-                            self.uc.switch_code();
-                            self.uc.init();
+                            // Shift runs the code even with a syntax error
+                            let force = keymod.intersects(sdl2::keyboard::LSHIFTMOD |
+                                                          sdl2::keyboard::RSHIFTMOD);
+                            if self.uc.switch_code(force) {
+                                self.uc.init();
+                            }
                         }
 
                         if self.uc.players.lock().unwrap().get_value_quick(0, 7) == 1 {
@@ -605,112 +676,126 @@ impl Frontend {
                             .key_up(map_sdlmod(keymod), map_sdlscancode(scancode));
                     }
 
-                    Event::ControllerButtonDown { which: id, button, .. } => {
-                        if !self.controllers.is_controller(id as u32) {
-                            break;
+                    Event::ControllerDeviceAdded { which: index, .. } => {
+                        if let Some(player) = self.controllers.open_controller(index as u32) {
+                            self.uc.players.lock().unwrap().set_connected(player, true);
                         }
+                    }
 
-                        if let Some(key) = map_button(button) {
-                            self.uc
-                                .players
-                                .lock()
-                                .unwrap()
-                                .key_down_direct(0,
-                                                 key,
-                                                 false,
-                                                 self.uc.info.lock().unwrap().elapsed_time)
+                    Event::ControllerDeviceRemoved { which: id, .. } => {
+                        if let Some(player) = self.controllers.close(id as i32) {
+                            self.uc.players.lock().unwrap().set_connected(player, false);
                         }
                     }
 
-                    Event::ControllerButtonUp { which: id, button, .. } => {
-                        if !self.controllers.is_controller(id as u32) {
-                            break;
+                    Event::JoyDeviceAdded { which: index, .. } => {
+                        if let Some(player) = self.controllers.open_joystick(index as u32) {
+                            self.uc.players.lock().unwrap().set_connected(player, true);
+                        }
+                    }
+
+                    Event::JoyDeviceRemoved { which: id, .. } => {
+                        if !self.controllers.is_joystick(id as i32) {
+                            continue;
                         }
 
-                        if let Some(key) = map_button(button) {
-                            self.uc.players.lock().unwrap().key_up_direct(0, key);
+                        if let Some(player) = self.controllers.close(id as i32) {
+                            self.uc.players.lock().unwrap().set_connected(player, false);
                         }
                     }
 
-                    Event::ControllerAxisMotion { which: id, axis, value, .. } => {
-                        if !self.controllers.is_controller(id as u32) {
-                            break;
+                    Event::ControllerButtonDown { which: id, button, .. } => {
+                        if !self.controllers.is_controller(id as i32) {
+                            continue;
                         }
 
-                        if let Some((key, state)) = map_axis(axis, value) {
-                            if axis == Axis::LeftX && value == 128 {
-                                self.uc.players.lock().unwrap().key_direc_hor_up(0);
-                            } else if axis == Axis::LeftY && value == -129 {
-                                self.uc.players.lock().unwrap().key_direc_ver_up(0);
-                            } else {
-                                if state {
-                                    self.uc
-                                        .players
-                                        .lock()
-                                        .unwrap()
-                                        .key_down_direct(0,
-                                                         key,
-                                                         false,
-                                                         self.uc.info.lock().unwrap().elapsed_time);
-                                } else {
-                                    self.uc.players.lock().unwrap().key_up_direct(0, key);
-                                }
-                            }
+                        let player = self.controllers.player(id as i32).unwrap_or(0);
+
+                        self.uc
+                            .players
+                            .lock()
+                            .unwrap()
+                            .pad_button_down(player,
+                                             map_button(button),
+                                             self.uc.info.lock().unwrap().elapsed_time);
+                    }
+
+                    Event::ControllerButtonUp { which: id, button, .. } => {
+                        if !self.controllers.is_controller(id as i32) {
+                            continue;
                         }
+
+                        let player = self.controllers.player(id as i32).unwrap_or(0);
+
+                        self.uc
+                            .players
+                            .lock()
+                            .unwrap()
+                            .pad_button_up(player, map_button(button));
                     }
 
-                    Event::JoyAxisMotion { which: id, axis_idx, value, .. } => {
-                        if !self.controllers.is_joystick(id as u32) {
-                            break;
+                    Event::ControllerAxisMotion { which: id, axis, value, .. } => {
+                        if !self.controllers.is_controller(id as i32) {
+                            continue;
                         }
 
-                        if let Some((key, state)) = map_axis_joystick(axis_idx, value) {
-                            if axis_idx == 0 && value == 128 {
-                                self.uc.players.lock().unwrap().key_direc_hor_up(0);
-                            } else if axis_idx == 1 && value == -129 {
-                                self.uc.players.lock().unwrap().key_direc_ver_up(0);
-                            } else {
-                                if state {
-                                    self.uc
-                                        .players
-                                        .lock()
-                                        .unwrap()
-                                        .key_down_direct(0,
-                                                         key,
-                                                         false,
-                                                         self.uc.info.lock().unwrap().elapsed_time);
-                                } else {
-                                    self.uc.players.lock().unwrap().key_up_direct(0, key);
-                                }
-                            }
+                        let player = self.controllers.player(id as i32).unwrap_or(0);
+
+                        self.uc
+                            .players
+                            .lock()
+                            .unwrap()
+                            .set_axis(player,
+                                      map_axis(axis),
+                                      value,
+                                      self.uc.info.lock().unwrap().elapsed_time);
+                    }
+
+                    Event::JoyAxisMotion { which: id, axis_idx, value, .. } => {
+                        if !self.controllers.is_joystick(id as i32) {
+                            continue;
                         }
+
+                        let player = self.controllers.player(id as i32).unwrap_or(0);
+
+                        self.uc
+                            .players
+                            .lock()
+                            .unwrap()
+                            .set_axis(player,
+                                      axis_idx,
+                                      value,
+                                      self.uc.info.lock().unwrap().elapsed_time);
                     }
 
                     Event::JoyButtonDown { which: id, button_idx, .. } => {
-                        if !self.controllers.is_joystick(id as u32) {
-                            break;
+                        if !self.controllers.is_joystick(id as i32) {
+                            continue;
                         }
 
-                        if let Some(key) = map_button_joystick(button_idx) {
-                            self.uc
-                                .players
-                                .lock()
-                                .unwrap()
-                                .key_down_direct(0,
-                                                 key,
-                                                 false,
-                                                 self.uc.info.lock().unwrap().elapsed_time);
-                        }
+                        let player = self.controllers.player(id as i32).unwrap_or(0);
+
+                        self.uc
+                            .players
+                            .lock()
+                            .unwrap()
+                            .pad_button_down(player,
+                                             button_idx,
+                                             self.uc.info.lock().unwrap().elapsed_time);
                     }
 
                     Event::JoyButtonUp { which: id, button_idx, .. } => {
-                        if !self.controllers.is_joystick(id as u32) {
-                            break;
+                        if !self.controllers.is_joystick(id as i32) {
+                            continue;
                         }
 
-                        if let Some(key) = map_button_joystick(button_idx) {
-                            self.uc.players.lock().unwrap().key_up_direct(0, key);
-                        }
+                        let player = self.controllers.player(id as i32).unwrap_or(0);
+
+                        self.uc
+                            .players
+                            .lock()
+                            .unwrap()
+                            .pad_button_up(player, button_idx);
                     }
 
                     _ => (),
@@ -725,6 +810,8 @@ impl Frontend {
 
             self.uc.draw();
             self.uc.update_sound();
+            self.update_rumbles();
+            self.update_text_input();
 
             self.update_time();
 
@@ -1,74 +1,39 @@
-use unicorn::config::keys::PX8Key;
+use unicorn::config::bindings::{PAD_A, PAD_B, PAD_X, PAD_Y, PAD_BACK, PAD_GUIDE, PAD_START,
+                                PAD_LEFT_STICK, PAD_RIGHT_STICK, PAD_LEFT_SHOULDER,
+                                PAD_RIGHT_SHOULDER, PAD_DPAD_UP, PAD_DPAD_DOWN, PAD_DPAD_LEFT,
+                                PAD_DPAD_RIGHT};
+use unicorn::config::{AXIS_LEFT_X, AXIS_LEFT_Y, AXIS_RIGHT_X, AXIS_RIGHT_Y, AXIS_TRIGGER_LEFT,
+                      AXIS_TRIGGER_RIGHT};
 
 use sdl2::controller::{Axis, Button};
-use sdl2::keyboard::Scancode;
 
-pub fn map_button(button: Button) -> Option<PX8Key> {
+pub fn map_button(button: Button) -> u8 {
     match button {
-        Button::DPadRight => Some(PX8Key::Right),
-        Button::DPadLeft => Some(PX8Key::Left),
-        Button::DPadUp => Some(PX8Key::Up),
-        Button::DPadDown => Some(PX8Key::Down),
-        Button::A => Some(PX8Key::A),
-        Button::B => Some(PX8Key::B),
-        _ => None,
+        Button::A => PAD_A,
+        Button::B => PAD_B,
+        Button::X => PAD_X,
+        Button::Y => PAD_Y,
+        Button::Back => PAD_BACK,
+        Button::Guide => PAD_GUIDE,
+        Button::Start => PAD_START,
+        Button::LeftStick => PAD_LEFT_STICK,
+        Button::RightStick => PAD_RIGHT_STICK,
+        Button::LeftShoulder => PAD_LEFT_SHOULDER,
+        Button::RightShoulder => PAD_RIGHT_SHOULDER,
+        Button::DPadUp => PAD_DPAD_UP,
+        Button::DPadDown => PAD_DPAD_DOWN,
+        Button::DPadLeft => PAD_DPAD_LEFT,
+        Button::DPadRight => PAD_DPAD_RIGHT,
     }
 }
 
-pub fn map_button_joystick(button: u8) -> Option<PX8Key> {
-    match button {
-        0 => Some(PX8Key::A),
-        1 => Some(PX8Key::B),
-        _ => None,
-    }
-}
-
-pub fn map_axis(axis: Axis, value: i16) -> Option<(PX8Key, bool)> {
-    match axis {
-        Axis::LeftX => {
-            match value {
-                -32768...-16384 => Some((PX8Key::Left, true)),
-                -16383...-1 => Some((PX8Key::Left, false)),
-                0...16383 => Some((PX8Key::Right, false)),
-                16384...32767 => Some((PX8Key::Right, true)),
-                _ => None,
-            }
-        }
-
-        Axis::LeftY => {
-            match value {
-                -32768...-16384 => Some((PX8Key::Up, true)),
-                -16383...-1 => Some((PX8Key::Up, false)),
-                0...16383 => Some((PX8Key::Down, false)),
-                16384...32767 => Some((PX8Key::Down, true)),
-                _ => None,
-            }
-        }
-        _ => None,
-    }
-}
-
-pub fn map_axis_joystick(axis: u8, value: i16) -> Option<(PX8Key, bool)> {
+pub fn map_axis(axis: Axis) -> u8 {
     match axis {
-        0 => {
-            match value {
-                -32768...-16384 => Some((PX8Key::Left, true)),
-                -16383...-1 => Some((PX8Key::Left, false)),
-                1...16383 => Some((PX8Key::Right, false)),
-                16384...32767 => Some((PX8Key::Right, true)),
-                _ => None,
-            }
-        }
-
-        1 => {
-            match value {
-                -32768...-16384 => Some((PX8Key::Up, true)),
-                -16383...-1 => Some((PX8Key::Up, false)),
-                1...16383 => Some((PX8Key::Down, false)),
-                16384...32767 => Some((PX8Key::Down, true)),
-                _ => None,
-            }
-        }
-        _ => None,
+        Axis::LeftX => AXIS_LEFT_X,
+        Axis::LeftY => AXIS_LEFT_Y,
+        Axis::RightX => AXIS_RIGHT_X,
+        Axis::RightY => AXIS_RIGHT_Y,
+        Axis::TriggerLeft => AXIS_TRIGGER_LEFT,
+        Axis::TriggerRight => AXIS_TRIGGER_RIGHT,
     }
 }
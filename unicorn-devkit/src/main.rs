@@ -22,7 +22,10 @@ use getopts::Options;
 
 use unicorn::gfx;
 use unicorn::gfx::Scale;
-use unicorn::cartridge::Cartridge;
+use unicorn::cartridge::{Cartridge, CartridgeFormat};
+use unicorn::unicorn::Palettes;
+use unicorn::unicorn::sheet;
+use unicorn::unicorn::tiled::TiledMap;
 
 fn print_usage(program: &str, opts: &Options) {
     let brief = format!("Usage: {} FILE [options]", program);
@@ -55,7 +58,34 @@ fn main() {
                     "transform the PNG/Unicorn cartridge in P8",
                     "FILE");
     opts.optflagopt("s", "scale", "scale the display", "VALUE");
-    opts.optflagopt("b", "bind", "bind a server on a specific address", "ADDR");
+    opts.optflagopt("b",
+                    "bind",
+                    "bind the server of the console on a local address, any address with --unsafe",
+                    "ADDR");
+    opts.optflag("a", "api", "print the reference of the scripting API");
+    opts.optopt("",
+                "import-sheet",
+                "import a PNG in the sprite sheet of the cartridge",
+                "FILE");
+    opts.optopt("",
+                "export-sheet",
+                "export the sprite sheet of the cartridge in PNG",
+                "FILE");
+    opts.optopt("",
+                "import-map",
+                "import a Tiled map (TMX, or JSON) in the cartridge",
+                "FILE");
+    opts.optopt("",
+                "export-map",
+                "export the map of the cartridge in PNG, or for Tiled (TMX, JSON)",
+                "FILE");
+    opts.optopt("",
+                "history",
+                "number of operations kept by the undo history of the editors",
+                "DEPTH");
+    opts.optflag("u",
+                 "unsafe",
+                 "run the scripts without sandbox, and load the native cartridges");
     opts.optflag("v", "verbose", "Debug mode level");
     opts.optflag("h", "help", "print this help menu");
 
@@ -68,6 +98,11 @@ fn main() {
         return;
     }
 
+    if matches.opt_present("a") {
+        print!("{}", unicorn::plugins::api::reference());
+        return;
+    }
+
     if matches.opt_present("v") {
         if let Err(e) = fern::init_global_logger(logger_config, log::LogLevelFilter::Debug) {
             panic!("Failed to initialize global logger: {}", e);
@@ -93,6 +128,20 @@ fn main() {
         }
     }
 
+    let history_depth = match matches.opt_str("history") {
+        Some(value) => {
+            match value.parse::<usize>() {
+                Ok(depth) => depth,
+                Err(_) => {
+                    println!("Invalid history depth {:?}, a number of operations is expected", value);
+                    print_usage(&program, &opts);
+                    return;
+                }
+            }
+        }
+        None => unicorn::unicorn::editor::history::HISTORY_DEPTH,
+    };
+
     let fullscreen = matches.opt_present("f");
     let opengl = matches.opt_present("o");
 
@@ -107,7 +156,8 @@ fn main() {
                           opengl,
                           "unicorn.uni",
                           data_final,
-                          matches.opt_present("e"));
+                          matches.opt_present("e"),
+                          history_depth);
         return;
     };
 
@@ -134,8 +184,126 @@ fn main() {
                 Err(e) => panic!(e),
             }
         }
+    } else if ["import-sheet", "export-sheet", "import-map", "export-map"]
+                  .iter()
+                  .any(|name| matches.opt_present(name)) {
+        transfer_cartridge(&input, &matches);
     } else {
-        run_cartridge(scale, fullscreen, opengl, &input, matches.opt_present("e"));
+        let repl_address = if matches.opt_present("b") {
+            Some(matches
+                     .opt_str("b")
+                     .unwrap_or(unicorn::unicorn::repl::DEFAULT_ADDRESS.to_string()))
+        } else {
+            None
+        };
+
+        run_cartridge(scale,
+                      fullscreen,
+                      opengl,
+                      &input,
+                      matches.opt_present("e"),
+                      !matches.opt_present("u"),
+                      repl_address,
+                      history_depth);
+    }
+}
+
+/// Import and export the sprite sheet and the map of a cartridge, without
+/// running it. The imports are saved in the cartridge.
+pub fn transfer_cartridge(filename: &str, matches: &getopts::Matches) {
+    let result = if filename.contains(".uni") {
+        Cartridge::from_unicorn_file(filename)
+    } else if filename.contains(".uc") {
+        Cartridge::from_unicorn_splitted_file(filename)
+    } else {
+        Cartridge::from_dunicorn_file(filename)
+    };
+
+    let mut cartridge = match result {
+        Ok(cartridge) => cartridge,
+        Err(e) => panic!("{:?}", e),
+    };
+
+    // The colors of the cartridge replace the ones of the default palette
+    let mut palettes = Palettes::new();
+    palettes.init();
+    palettes.switch_to_palette("pico-8");
+    palettes.set_colors(cartridge.palette.colors.clone());
+    let colors = palettes.colors();
+
+    let mut sprites = cartridge.gfx.sprites.clone();
+    for (sprite, flags) in sprites.iter_mut().zip(cartridge.gff.flags.iter()) {
+        sprite.set_flags(*flags);
+    }
+
+    let mut map = cartridge.map.map.clone();
+    map.resize(unicorn::unicorn::MAP_WIDTH * unicorn::unicorn::MAP_HEIGHT, 0);
+
+    if let Some(input) = matches.opt_str("import-sheet") {
+        if let Err(e) = sheet::import_sheet_png(&input, &mut sprites, &colors) {
+            panic!("Impossible to import the sprite sheet {:?}: {}", input, e);
+        }
+    }
+
+    if let Some(input) = matches.opt_str("import-map") {
+        match TiledMap::load(&input) {
+            Ok(tiled) => {
+                tiled.apply(&mut map,
+                            unicorn::unicorn::MAP_WIDTH,
+                            unicorn::unicorn::MAP_HEIGHT,
+                            &mut sprites)
+            }
+            Err(e) => panic!("Impossible to import the map {:?}: {}", input, e),
+        }
+    }
+
+    if let Some(output) = matches.opt_str("export-sheet") {
+        if let Err(e) = sheet::export_sheet_png(&output, &sprites, &colors) {
+            panic!("Impossible to export the sprite sheet {:?}: {}", output, e);
+        }
+    }
+
+    if let Some(output) = matches.opt_str("export-map") {
+        let result = if output.ends_with(".png") {
+            sheet::export_map_png(&output,
+                                  &sprites,
+                                  &map,
+                                  unicorn::unicorn::MAP_WIDTH,
+                                  unicorn::unicorn::MAP_HEIGHT,
+                                  &colors)
+        } else {
+            // The tileset is the sprite sheet exported with the map
+            let image = matches.opt_str("export-sheet").unwrap_or("sheet.png".to_string());
+            TiledMap::new(&map,
+                          unicorn::unicorn::MAP_WIDTH,
+                          unicorn::unicorn::MAP_HEIGHT,
+                          &sprites)
+                    .save(&output, &image)
+        };
+
+        if let Err(e) = result {
+            panic!("Impossible to export the map {:?}: {}", output, e);
+        }
+    }
+
+    if matches.opt_present("import-sheet") || matches.opt_present("import-map") {
+        cartridge.gfx.set_sprites(sprites.clone());
+        cartridge.map.set_map(map);
+        cartridge.gff.set_flags(sprites);
+
+        match cartridge.format {
+            CartridgeFormat::UnicornFormat => {
+                cartridge.save_in_unicorn(filename,
+                                          format!("{:?}.{:?}.{:?}",
+                                                  unicorn::unicorn::VERSION,
+                                                  unicorn::unicorn::MAJOR_VERSION,
+                                                  unicorn::unicorn::MINOR_VERSION)
+                                                  .as_str());
+            }
+            CartridgeFormat::UnicornSplittedFormat => {
+                cartridge.save_in_unicorn_splitted();
+            }
+        }
     }
 }
 
@@ -143,12 +311,22 @@ pub fn run_cartridge(scale: gfx::Scale,
                      fullscreen: bool,
                      opengl: bool,
                      filename: &str,
-                     editor: bool) {
+                     editor: bool,
+                     sandbox: bool,
+                     repl_address: Option<String>,
+                     history_depth: usize) {
     let mut frontend = match frontend::Frontend::init(scale, fullscreen, opengl, false) {
         Err(error) => panic!("{:?}", error),
         Ok(frontend) => frontend,
     };
 
+    frontend.uc.sandbox = sandbox;
+    frontend.uc.configuration.lock().unwrap().set_history_depth(history_depth);
+    if let Some(address) = repl_address {
+        if let Err(e) = frontend.uc.start_repl_server(&address) {
+            error!("[Frontend] Impossible to start the console server {:?}", e);
+        }
+    }
     frontend.start("./sys/config/gamecontrollerdb.txt".to_string());
     frontend.run_cartridge(filename, editor);
 }
@@ -158,12 +336,15 @@ pub fn run_cartridge_raw(scale: gfx::Scale,
                          opengl: bool,
                          filename: &str,
                          data: Vec<u8>,
-                         editor: bool) {
+                         editor: bool,
+                         history_depth: usize) {
     let mut frontend = match frontend::Frontend::init(scale, fullscreen, opengl, false) {
         Err(error) => panic!("{:?}", error),
         Ok(frontend) => frontend,
     };
 
+    frontend.uc.configuration.lock().unwrap().set_history_depth(history_depth);
+
     frontend.start("./sys/config/gamecontrollerdb.txt".to_string());
     frontend.run_cartridge_raw(filename, data, editor);
 }
@@ -6,20 +6,91 @@ extern crate fern;
 extern crate time;
 
 #[macro_use]
+extern crate lazy_static;
+
 extern crate libretro_backend;
 
 use libretro_backend::{CoreInfo, AudioVideoInfo, PixelFormat, GameData, LoadGameResult, Region,
-                       RuntimeHandle, JoypadButton};
+                       RuntimeHandle, JoypadButton, Retro};
+use libretro_backend::libc::{c_char, c_void, size_t};
+use libretro_backend::libretro_sys::{self, EnvironmentFn, KeyboardCallback};
 
 use std::slice;
 use std::mem;
+use std::sync::Mutex;
+use std::os::raw::c_uint;
+
+use unicorn::config::Players;
+use unicorn::gfx::{SCREEN_WIDTH, SCREEN_HEIGHT};
+use unicorn::config::scancode::{Mod, Scancode};
+
+lazy_static! {
+    /// Keyboard events received from the frontend: (down, keycode, UTF-32 character)
+    static ref KEYBOARD_EVENTS: Mutex<Vec<(bool, c_uint, u32)>> = {
+        Mutex::new(Vec::new())
+    };
+}
+
+/// Keyboard callback given to the frontend in `retro_set_environment`
+static KEYBOARD_CALLBACK: KeyboardCallback = KeyboardCallback { callback: retro_keyboard_event };
+
+/// libretro keyboard callback (RETRO_ENVIRONMENT_SET_KEYBOARD_CALLBACK), it can be
+/// called from any thread so the events are queued until the next frame
+pub extern "C" fn retro_keyboard_event(down: bool,
+                                       keycode: c_uint,
+                                       character: u32,
+                                       _key_modifiers: u16) {
+    KEYBOARD_EVENTS
+        .lock()
+        .unwrap()
+        .push((down, keycode, character));
+}
+
+pub fn map_retrokey(keycode: c_uint) -> Scancode {
+    match keycode {
+        8 => Scancode::Backspace,
+        13 => Scancode::Return,
+        27 => Scancode::Escape,
+        127 => Scancode::Delete,
+        273 => Scancode::Up,
+        274 => Scancode::Down,
+        275 => Scancode::Right,
+        276 => Scancode::Left,
+        278 => Scancode::Home,
+        279 => Scancode::End,
+        _ => Scancode::NONE,
+    }
+}
+
+/// Send the queued keyboard events to the players
+pub fn flush_keyboard_events(players: &mut Players, elapsed: f64) {
+    let events: Vec<(bool, c_uint, u32)> = KEYBOARD_EVENTS.lock().unwrap().drain(..).collect();
+    for (down, keycode, character) in events {
+        let scancode = map_retrokey(keycode);
+
+        if down {
+            if scancode != Scancode::NONE {
+                players.key_down(Mod::NONE, scancode, false, elapsed);
+            }
+
+            if character != 0 {
+                if let Some(c) = std::char::from_u32(character) {
+                    players.input_text(&c.to_string());
+                }
+            }
+        } else if scancode != Scancode::NONE {
+            players.key_up(Mod::NONE, scancode);
+        }
+    }
+}
 
 struct UnicornCore {
     uc: unicorn::unicorn::Unicorn,
-    framebuffer: [u32; 400 * 240],
+    framebuffer: [u32; SCREEN_WIDTH * SCREEN_HEIGHT],
     audio_buffer: Vec<i16>,
     mouse_x: i16,
     mouse_y: i16,
+    buttons: [bool; 16],
     game_data: Option<GameData>,
 }
 
@@ -31,15 +102,25 @@ pub fn as_bytes<T: Copy>(array: &[T]) -> &[u8] {
     }
 }
 
-pub fn map_libretrocancode(button: JoypadButton) -> unicorn::config::scancode::Scancode {
+pub fn map_libretro_button(button: JoypadButton) -> u8 {
+    use unicorn::config::bindings::*;
+
     match button {
-        JoypadButton::A => unicorn::config::scancode::Scancode::Z,
-        JoypadButton::B => unicorn::config::scancode::Scancode::X,
-        JoypadButton::Left => unicorn::config::scancode::Scancode::Left,
-        JoypadButton::Right => unicorn::config::scancode::Scancode::Right,
-        JoypadButton::Up => unicorn::config::scancode::Scancode::Up,
-        JoypadButton::Down => unicorn::config::scancode::Scancode::Down,
-        _ => unicorn::config::scancode::Scancode::NONE,
+        JoypadButton::A => PAD_A,
+        JoypadButton::B => PAD_B,
+        JoypadButton::X => PAD_X,
+        JoypadButton::Y => PAD_Y,
+        JoypadButton::Start => PAD_START,
+        JoypadButton::Select => PAD_BACK,
+        JoypadButton::L1 => PAD_LEFT_SHOULDER,
+        JoypadButton::R1 => PAD_RIGHT_SHOULDER,
+        JoypadButton::L3 => PAD_LEFT_STICK,
+        JoypadButton::R3 => PAD_RIGHT_STICK,
+        JoypadButton::Left => PAD_DPAD_LEFT,
+        JoypadButton::Right => PAD_DPAD_RIGHT,
+        JoypadButton::Up => PAD_DPAD_UP,
+        JoypadButton::Down => PAD_DPAD_DOWN,
+        _ => PAD_GUIDE,
     }
 }
 
@@ -47,10 +128,11 @@ impl UnicornCore {
     fn new() -> UnicornCore {
         UnicornCore {
             uc: unicorn::unicorn::Unicorn::new(),
-            framebuffer: [0; 400 * 240],
+            framebuffer: [0; SCREEN_WIDTH * SCREEN_HEIGHT],
             audio_buffer: Vec::with_capacity(44100),
             mouse_x: 0,
             mouse_y: 0,
+            buttons: [false; 16],
             game_data: None,
         }
     }
@@ -108,7 +190,7 @@ impl libretro_backend::Core for UnicornCore {
             true => {
                 self.game_data = Some(game_data);
                 let av_info = AudioVideoInfo::new()
-                    .video( 400, 240, 60.0, PixelFormat::ARGB8888 )
+                    .video( SCREEN_WIDTH as u32, SCREEN_HEIGHT as u32, 60.0, PixelFormat::ARGB8888 )
                     //.audio( 44100.0 )
                     .region( Region::NTSC );
 
@@ -132,29 +214,35 @@ impl libretro_backend::Core for UnicornCore {
         macro_rules! update_controllers {
             ( $( $button:ident ),+ ) => (
                 $(
-                    if handle.is_joypad_button_pressed( 0, JoypadButton::$button ) {
+                    let button = map_libretro_button(JoypadButton::$button);
+                    let pressed = handle.is_joypad_button_pressed( 0, JoypadButton::$button );
+
+                    if pressed && !self.buttons[button as usize] {
                         self.uc
                             .players
                             .lock()
                             .unwrap()
-                            .key_down(unicorn::config::scancode::Mod::NONE,
-                                      map_libretrocancode(JoypadButton::$button),
-                                      false,
-                                      self.uc.info.lock().unwrap().elapsed_time);
+                            .pad_button_down(0,
+                                             button,
+                                             self.uc.info.lock().unwrap().elapsed_time);
 
-                    } else {
+                    } else if !pressed && self.buttons[button as usize] {
                         self.uc
                             .players
                             .lock()
                             .unwrap()
-                            .key_up(unicorn::config::scancode::Mod::NONE,
-                                    map_libretrocancode(JoypadButton::$button));
+                            .pad_button_up(0, button);
                     }
+
+                    self.buttons[button as usize] = pressed;
                  )+
             )
         }
 
-        update_controllers!(A, B, Start, Select, Left, Up, Right, Down);
+        update_controllers!(A, B, X, Y, L1, R1, L3, R3, Start, Select, Left, Up, Right, Down);
+
+        let elapsed = self.uc.info.lock().unwrap().elapsed_time;
+        flush_keyboard_events(&mut self.uc.players.lock().unwrap(), elapsed);
 
         self.mouse_x += handle.mouse_x();
         self.mouse_y += handle.mouse_y();
@@ -209,4 +297,113 @@ impl libretro_backend::Core for UnicornCore {
 }
 
 
-libretro_core!(UnicornCore);
\ No newline at end of file
+// The libretro API is exported here instead of with `libretro_core!`, because
+// `retro_set_environment` must also register the keyboard callback.
+static mut LIBRETRO_INSTANCE: *mut Retro<UnicornCore> = 0 as *mut Retro<UnicornCore>;
+
+unsafe fn instance() -> &'static mut Retro<UnicornCore> {
+    assert_ne!(LIBRETRO_INSTANCE, 0 as *mut _);
+    &mut *LIBRETRO_INSTANCE
+}
+
+macro_rules! retro_export {
+    () => ();
+    ( fn $name:ident ( $( $arg:ident: $ty:ty ),* ) $( -> $ret:ty )* => static $func:path;
+      $( $rest:tt )* ) => (
+        #[doc(hidden)]
+        #[no_mangle]
+        pub unsafe extern "C" fn $name( $( $arg: $ty ),* ) $( -> $ret )* {
+            $func( $( $arg ),* )
+        }
+
+        retro_export!( $( $rest )* );
+    );
+    ( fn $name:ident ( $( $arg:ident: $ty:ty ),* ) $( -> $ret:ty )* => $method:ident;
+      $( $rest:tt )* ) => (
+        #[doc(hidden)]
+        #[no_mangle]
+        pub unsafe extern "C" fn $name( $( $arg: $ty ),* ) $( -> $ret )* {
+            instance().$method( $( $arg ),* )
+        }
+
+        retro_export!( $( $rest )* );
+    );
+}
+
+fn api_version() -> c_uint {
+    libretro_sys::API_VERSION
+}
+
+unsafe fn init() {
+    assert_eq!(LIBRETRO_INSTANCE, 0 as *mut _);
+    LIBRETRO_INSTANCE = Box::into_raw(Box::new(libretro_backend::construct::<UnicornCore>()));
+}
+
+unsafe fn deinit() {
+    assert_ne!(LIBRETRO_INSTANCE, 0 as *mut _);
+    drop(Box::from_raw(LIBRETRO_INSTANCE));
+    LIBRETRO_INSTANCE = 0 as *mut _;
+}
+
+unsafe fn set_environment(callback: EnvironmentFn) {
+    Retro::<UnicornCore>::on_set_environment(callback);
+
+    let keyboard_callback = &KEYBOARD_CALLBACK as *const KeyboardCallback as *mut c_void;
+    if !callback(libretro_sys::ENVIRONMENT_SET_KEYBOARD_CALLBACK, keyboard_callback) {
+        warn!("[LIBRETRO][KEYBOARD] The frontend doesn't send the keyboard events");
+    }
+}
+
+retro_export! {
+    fn retro_api_version() -> c_uint => static api_version;
+    fn retro_init() => static init;
+    fn retro_deinit() => static deinit;
+    fn retro_set_environment(callback: EnvironmentFn) => static set_environment;
+    fn retro_get_system_info(info: *mut libretro_sys::SystemInfo)
+                             => static Retro::<UnicornCore>::on_get_system_info;
+    fn retro_set_video_refresh(callback: libretro_sys::VideoRefreshFn) => on_set_video_refresh;
+    fn retro_set_audio_sample(callback: libretro_sys::AudioSampleFn) => on_set_audio_sample;
+    fn retro_set_audio_sample_batch(callback: libretro_sys::AudioSampleBatchFn) => on_set_audio_sample_batch;
+    fn retro_set_input_poll(callback: libretro_sys::InputPollFn) => on_set_input_poll;
+    fn retro_set_input_state(callback: libretro_sys::InputStateFn) => on_set_input_state;
+    fn retro_get_system_av_info(info: *mut libretro_sys::SystemAvInfo) => on_get_system_av_info;
+    fn retro_set_controller_port_device(port: c_uint, device: c_uint) => on_set_controller_port_device;
+    fn retro_reset() => on_reset;
+    fn retro_run() => on_run;
+    fn retro_serialize_size() -> size_t => on_serialize_size;
+    fn retro_serialize(data: *mut c_void, size: size_t) -> bool => on_serialize;
+    fn retro_unserialize(data: *const c_void, size: size_t) -> bool => on_unserialize;
+    fn retro_cheat_reset() => on_cheat_reset;
+    fn retro_cheat_set(index: c_uint, is_enabled: bool, code: *const c_char) => on_cheat_set;
+    fn retro_load_game(game: *const libretro_sys::GameInfo) -> bool => on_load_game;
+    fn retro_load_game_special(game_type: c_uint,
+                               info: *const libretro_sys::GameInfo,
+                               num_info: size_t)
+                               -> bool => on_load_game_special;
+    fn retro_unload_game() => on_unload_game;
+    fn retro_get_region() -> c_uint => on_get_region;
+    fn retro_get_memory_data(id: c_uint) -> *mut c_void => on_get_memory_data;
+    fn retro_get_memory_size(id: c_uint) -> size_t => on_get_memory_size;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keyboard_events() {
+        let mut players = Players::new();
+        players.text_input.start("", 0);
+
+        retro_keyboard_event(true, 97, 'a' as u32, 0);
+        retro_keyboard_event(false, 97, 0, 0);
+        retro_keyboard_event(true, 98, 'é' as u32, 0);
+        retro_keyboard_event(true, 8, 8, 0);
+        retro_keyboard_event(true, 99, 'c' as u32, 0);
+        assert_eq!(players.text_input.get(), "");
+
+        flush_keyboard_events(&mut players, 0.0);
+        assert_eq!(players.text_input.get(), "ac");
+        assert!(KEYBOARD_EVENTS.lock().unwrap().is_empty());
+    }
+}
@@ -1,3 +1,4 @@
+use std::fs;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::Cursor;
@@ -13,6 +14,7 @@ use std::result::Result;
 use std::collections::HashMap;
 use std::u32;
 use std::str;
+use std::path::{Component, Path, PathBuf};
 
 use serde_json;
 
@@ -20,6 +22,7 @@ use regex::Regex;
 
 use unicorn;
 use unicorn::RGB;
+use unicorn::autotile::AutotileRule;
 
 use gfx::Sprite;
 
@@ -27,7 +30,9 @@ use gfx::Sprite;
 
 RANDOM COMMENT
 version XX
-__python__ __javascript__ __lua__
+__python__ __javascript__ __lua__ __wasm__ __rust__
+
+__file__ enemies.lua -> module of the code, one section by file
 
 __palette__
 
@@ -37,6 +42,8 @@ __gff__
 
 __map__ 400*60 -> 24.000
 
+__autotile__ -> one rule by line: terrain style sprites...
+
 __sfx__
 
 __music__
@@ -169,12 +176,57 @@ impl CartridgePalette {
 
 }
 
+/// Code file loaded by `require`/`import`, in a `__file__ name` section
+pub struct CartridgeFile {
+    pub name: String,
+    pub lines: Vec<String>,
+    /// Entry of the JSON file of the splitted format, relative to its directory.
+    /// Next to the code for the new files.
+    pub path: Option<String>,
+}
+
+impl CartridgeFile {
+    pub fn new(name: &str, lines: &[String]) -> CartridgeFile {
+        CartridgeFile {
+            name: name.to_string(),
+            lines: lines.to_vec(),
+            path: None,
+        }
+    }
+
+    pub fn get_data(&self) -> String {
+        let mut data = "".to_string();
+
+        for line in &self.lines {
+            data = data + line;
+            data.push('\n');
+        }
+
+        data
+    }
+}
+
+/// Lines of the text editor, without their last character
+fn editor_lines(lines: Vec<String>) -> Vec<String> {
+    lines.into_iter()
+        .map(|mut line| {
+                 if line.len() > 0 {
+                     let len = line.len();
+                     line.truncate(len - 1);
+                 }
+                 line
+             })
+        .collect()
+}
+
 pub struct CartridgeCode {
     pub lines: Vec<String>,
     pub data: Vec<u8>,
     pub version: u8,
     pub code_type: String,
     pub filename: String,
+    /// The other code files, sorted by name
+    pub files: Vec<CartridgeFile>,
 }
 
 impl CartridgeCode {
@@ -187,6 +239,7 @@ impl CartridgeCode {
             version: 0,
             code_type: "".to_string(),
             filename: "".to_string(),
+            files: Vec::new(),
         }
     }
 
@@ -199,6 +252,7 @@ impl CartridgeCode {
             version: 0,
             code_type: code_type,
             filename: "".to_string(),
+            files: Vec::new(),
         }
     }
 
@@ -248,17 +302,43 @@ impl CartridgeCode {
             return cart.data;
         }
 
+        if self.code_type == "wasm" || self.code_type == "rust" {
+            return self.lines.join("\n");
+        }
+
         "".to_string()
     }
 
     pub fn set_data(&mut self, lines: Vec<String>) {
-        self.lines.clear();
-        for mut line in lines {
-            if line.len() > 0 {
-                let len = line.len();
-                line.truncate(len - 1);
+        self.lines = editor_lines(lines);
+    }
+
+    /// Add the code file `name`, or replace its lines
+    pub fn add_file(&mut self, name: &str, lines: &[String]) {
+        match self.files.iter().position(|file| file.name == name) {
+            Some(idx) => self.files[idx].lines = lines.to_vec(),
+            None => {
+                self.files.push(CartridgeFile::new(name, lines));
+                self.files.sort_by(|a, b| a.name.cmp(&b.name));
             }
-            self.lines.push(line);
+        }
+    }
+
+    /// Sources of the other code files, by name
+    pub fn get_files(&self) -> HashMap<String, String> {
+        self.files
+            .iter()
+            .map(|file| (file.name.clone(), file.get_data()))
+            .collect()
+    }
+
+    /// Code files of the text editor, the other ones are removed
+    pub fn set_files_data(&mut self, files: Vec<(String, Vec<String>)>) {
+        self.files.retain(|file| files.iter().any(|&(ref name, _)| *name == file.name));
+
+        for (name, lines) in files {
+            let lines = editor_lines(lines);
+            self.add_file(&name, &lines);
         }
     }
 }
@@ -517,7 +597,7 @@ impl CartridgeMap {
 
         for y in 0..unicorn::MAP_HEIGHT {
             for x in 0..unicorn::MAP_WIDTH {
-                let idx_sprite = *self.map.get(x * unicorn::MAP_WIDTH + y).unwrap_or(&0);
+                let idx_sprite = *self.map.get(x + y * unicorn::MAP_WIDTH).unwrap_or(&0);
                 data.push_str(&format!("{:03x}", idx_sprite));
             }
             data.push('\n');
@@ -531,6 +611,49 @@ impl CartridgeMap {
     }
 }
 
+/// Autotile rules of the terrains of the map
+pub struct CartridgeAutotile {
+    pub rules: Vec<AutotileRule>,
+}
+
+impl CartridgeAutotile {
+    pub fn empty() -> CartridgeAutotile {
+        CartridgeAutotile { rules: Vec::new() }
+    }
+
+    pub fn new(lines: &[String]) -> CartridgeAutotile {
+        info!("[CARTRIDGE] CartridgeAutotile");
+
+        let mut rules = Vec::new();
+        for line in lines {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            match AutotileRule::parse(line) {
+                Some(rule) => rules.push(rule),
+                None => warn!("[CARTRIDGE] Invalid autotile rule {:?}", line),
+            }
+        }
+
+        CartridgeAutotile { rules: rules }
+    }
+
+    pub fn get_data(&mut self) -> String {
+        let mut data = String::new();
+
+        for rule in &self.rules {
+            data.push_str(&format!("{}\n", rule));
+        }
+
+        data
+    }
+
+    pub fn set_rules(&mut self, rules: Vec<AutotileRule>) {
+        self.rules = rules;
+    }
+}
+
 pub enum CartridgeFormat {
     UnicornSplittedFormat = 0,
     UnicornFormat = 1,
@@ -543,6 +666,7 @@ pub struct Cartridge {
     pub version: String,
     pub gfx: CartridgeGFX,
     pub map: CartridgeMap,
+    pub autotile: CartridgeAutotile,
     pub gff: CartridgeGFF,
     pub code: CartridgeCode,
     pub palette: CartridgePalette,
@@ -550,7 +674,19 @@ pub struct Cartridge {
     pub format: CartridgeFormat,
 }
 
-pub static SECTION_DELIM_RE: &'static str = r"^__(\w+)__$";
+pub static SECTION_DELIM_RE: &'static str = r"^__(\w+)__( \S+)?$";
+
+/// Prefix of the sections of the code files
+pub static FILE_SECTION: &'static str = "__file__ ";
+
+/// Add the code files of the sections to `code`
+fn read_files(code: &mut CartridgeCode, sections: &HashMap<String, Vec<String>>) {
+    for (section_name, section) in sections {
+        if section_name.starts_with(FILE_SECTION) {
+            code.add_file(&section_name[FILE_SECTION.len()..], section);
+        }
+    }
+}
 
 #[derive(Debug)]
 pub enum Error {
@@ -608,6 +744,7 @@ fn read_from_uniformat<R: io::BufRead>(filename: &str, buf: &mut R) -> Result<Ca
     let mut cartridge_code;
     let cartridge_palette;
     let cartridge_map;
+    let cartridge_autotile;
     let cartridge_gff;
     let cartridge_music;
 
@@ -621,10 +758,18 @@ fn read_from_uniformat<R: io::BufRead>(filename: &str, buf: &mut R) -> Result<Ca
     } else if sections.contains_key("__javascript__") {
         cartridge_code = CartridgeCode::new("javascript".to_string(),
                                             sections.get_mut("__javascript__").unwrap());
+    } else if sections.contains_key("__wasm__") {
+        cartridge_code = CartridgeCode::new("wasm".to_string(),
+                                            sections.get_mut("__wasm__").unwrap());
+    } else if sections.contains_key("__rust__") {
+        cartridge_code = CartridgeCode::new("rust".to_string(),
+                                            sections.get_mut("__rust__").unwrap());
     } else {
         return Err(Error::Err("NO CODE DATA".to_string()));
     }
 
+    read_files(&mut cartridge_code, &sections);
+
     match sections.get_mut("__palette__") {
         Some(vec_section) => cartridge_palette = CartridgePalette::new(vec_section),
         _ => cartridge_palette = CartridgePalette::empty(),
@@ -640,6 +785,11 @@ fn read_from_uniformat<R: io::BufRead>(filename: &str, buf: &mut R) -> Result<Ca
         _ => cartridge_map = CartridgeMap::empty(),
     }
 
+    match sections.get_mut("__autotile__") {
+        Some(vec_section) => cartridge_autotile = CartridgeAutotile::new(vec_section),
+        _ => cartridge_autotile = CartridgeAutotile::empty(),
+    }
+
     match sections.get_mut("__gff__") {
         Some(vec_section) => cartridge_gff = CartridgeGFF::new(vec_section),
         _ => cartridge_gff = CartridgeGFF::empty(),
@@ -662,6 +812,7 @@ fn read_from_uniformat<R: io::BufRead>(filename: &str, buf: &mut R) -> Result<Ca
            code: cartridge_code,
            palette: cartridge_palette,
            map: cartridge_map,
+           autotile: cartridge_autotile,
            gff: cartridge_gff,
            music: cartridge_music,
            format: CartridgeFormat::UnicornFormat,
@@ -672,6 +823,36 @@ fn read_from_uniformat<R: io::BufRead>(filename: &str, buf: &mut R) -> Result<Ca
 struct UnicornSplittedFormat {
     code: String,
     data: String,
+    /// The other code files, relative to the directory of the JSON file
+    #[serde(default)]
+    files: Vec<String>,
+}
+
+/// Path of a code file listed in the JSON file, relative to its directory.
+/// `None` for the absolute paths and the paths with `..`.
+fn resolve_code_file(cart_dir: &Path, file: &str) -> Option<PathBuf> {
+    let mut resolved = cart_dir.to_path_buf();
+    for component in Path::new(file).components() {
+        match component {
+            Component::Normal(name) => resolved.push(name),
+            Component::CurDir => (),
+            Component::ParentDir | Component::RootDir | Component::Prefix(_) => return None,
+        }
+    }
+
+    Some(resolved)
+}
+
+fn read_lines(filename: &str) -> Result<Vec<String>, Error> {
+    let f = try!(File::open(filename));
+    let buf_reader = BufReader::new(f);
+
+    let mut lines = Vec::new();
+    for line in buf_reader.lines() {
+        lines.push(try!(line));
+    }
+
+    Ok(lines)
 }
 
 
@@ -684,6 +865,7 @@ impl Cartridge {
             version: "".to_string(),
             gfx: CartridgeGFX::empty(),
             map: CartridgeMap::empty(),
+            autotile: CartridgeAutotile::empty(),
             gff: CartridgeGFF::empty(),
             code: CartridgeCode::empty(),
             palette: CartridgePalette::empty(),
@@ -767,6 +949,7 @@ impl Cartridge {
         let mut cartridge_code;
         let cartridge_palette;
         let cartridge_map;
+        let cartridge_autotile;
         let cartridge_music;
 
         if code_file.contains(".py") {
@@ -781,6 +964,27 @@ impl Cartridge {
 
         cartridge_code.set_filename(code_file);
 
+        // Named by their path from the directory of the code
+        let code_dir = Path::new(code_file).parent().unwrap_or(Path::new(""));
+        let cart_dir = Path::new(filename).parent().unwrap_or(Path::new(""));
+        for file in &json.files {
+            let path = match resolve_code_file(cart_dir, file) {
+                Some(path) => path,
+                None => return Err(Error::Err(format!("Invalid code file {:?} in {:?}", file, filename))),
+            };
+            let lines = try!(read_lines(&path.to_string_lossy()));
+            let name = path.strip_prefix(code_dir)
+                .unwrap_or(Path::new(file))
+                .to_string_lossy()
+                .into_owned();
+            cartridge_code.add_file(&name, &lines);
+
+            // Saved where it was read
+            if let Some(code_file) = cartridge_code.files.iter_mut().find(|code_file| code_file.name == name) {
+                code_file.path = Some(file.clone());
+            }
+        }
+
         match sections.get_mut("__palette__") {
             Some(vec_section) => cartridge_palette = CartridgePalette::new(vec_section),
             _ => cartridge_palette = CartridgePalette::empty(),
@@ -796,6 +1000,11 @@ impl Cartridge {
             _ => cartridge_map = CartridgeMap::empty(),
         }
 
+        match sections.get_mut("__autotile__") {
+            Some(vec_section) => cartridge_autotile = CartridgeAutotile::new(vec_section),
+            _ => cartridge_autotile = CartridgeAutotile::empty(),
+        }
+
         match sections.get_mut("__gff__") {
             Some(vec_section) => cartridge_gff = CartridgeGFF::new(vec_section),
             _ => cartridge_gff = CartridgeGFF::empty(),
@@ -816,6 +1025,7 @@ impl Cartridge {
                code: cartridge_code,
                palette: cartridge_palette,
                map: cartridge_map,
+               autotile: cartridge_autotile,
                gff: cartridge_gff,
                music: cartridge_music,
                format: CartridgeFormat::UnicornSplittedFormat,
@@ -866,6 +1076,7 @@ impl Cartridge {
         let cartridge_palette;
         let mut cartridge_code;
         let cartridge_map;
+        let cartridge_autotile;
         let cartridge_music;
 
         cartridge_code = CartridgeCode::new("javascript".to_string(), &code_section);
@@ -887,6 +1098,11 @@ impl Cartridge {
             _ => cartridge_map = CartridgeMap::empty(),
         }
 
+        match sections.get_mut("__autotile__") {
+            Some(vec_section) => cartridge_autotile = CartridgeAutotile::new(vec_section),
+            _ => cartridge_autotile = CartridgeAutotile::empty(),
+        }
+
         match sections.get_mut("__gff__") {
             Some(vec_section) => cartridge_gff = CartridgeGFF::new(vec_section),
             _ => cartridge_gff = CartridgeGFF::empty(),
@@ -907,6 +1123,7 @@ impl Cartridge {
                code: cartridge_code,
                palette: cartridge_palette,
                map: cartridge_map,
+               autotile: cartridge_autotile,
                gff: cartridge_gff,
                music: cartridge_music,
                format: CartridgeFormat::UnicornSplittedFormat,
@@ -927,6 +1144,12 @@ impl Cartridge {
         f.write_all(self.code.get_data().clone().as_bytes())
             .unwrap();
 
+        for file in &self.code.files {
+            f.write_all(format!("{}{}\n", FILE_SECTION, file.name).as_bytes())
+                .unwrap();
+            f.write_all(file.get_data().as_bytes()).unwrap();
+        }
+
         f.write_all(b"__palette__\n").unwrap();
         f.write_all(self.palette.get_data().clone().as_bytes()).unwrap();
 
@@ -939,6 +1162,9 @@ impl Cartridge {
         f.write_all(b"__map__\n").unwrap();
         f.write_all(self.map.get_data().clone().as_bytes()).unwrap();
 
+        f.write_all(b"__autotile__\n").unwrap();
+        f.write_all(self.autotile.get_data().as_bytes()).unwrap();
+
         f.write_all(b"__sfx__\n").unwrap();
 
         f.write_all(b"__music__\n").unwrap();
@@ -948,7 +1174,7 @@ impl Cartridge {
         info!("Save the date of the Unicorn Splitted file in {:?}", self.data_filename);
 
         match self.format {
-            CartridgeFormat::UnicornFormat => {
+            CartridgeFormat::UnicornSplittedFormat => {
                 let mut f = File::create(self.data_filename.clone()).unwrap();
 
                 f.write_all(b"__gfx__\n").unwrap();
@@ -960,14 +1186,73 @@ impl Cartridge {
                 f.write_all(b"__map__\n").unwrap();
                 f.write_all(self.map.get_data().clone().as_bytes()).unwrap();
 
+                f.write_all(b"__autotile__\n").unwrap();
+                f.write_all(self.autotile.get_data().as_bytes()).unwrap();
+
                 f.write_all(b"__sfx__\n").unwrap();
 
                 f.write_all(b"__music__\n").unwrap();
+
+                self.save_code_files();
             }
             _ => (),
         }
     }
 
+    /// Write the code and the code files of the splitted format, the JSON file
+    /// lists the files added and removed in the editor
+    fn save_code_files(&mut self) {
+        let mut f = File::create(self.code.filename.clone()).unwrap();
+        f.write_all(self.code.get_data().as_bytes()).unwrap();
+
+        let code_dir = Path::new(&self.code.filename).parent().unwrap_or(Path::new("")).to_path_buf();
+        let cart_dir = Path::new(&self.filename).parent().unwrap_or(Path::new("")).to_path_buf();
+
+        let mut entries = Vec::new();
+        for file in &mut self.code.files {
+            let entry = match file.path {
+                Some(ref entry) => entry.clone(),
+                None => {
+                    let path = code_dir.join(&file.name);
+                    match path.strip_prefix(&cart_dir) {
+                        Ok(entry) => entry.to_string_lossy().into_owned(),
+                        Err(_) => file.name.clone(),
+                    }
+                }
+            };
+
+            let path = match resolve_code_file(&cart_dir, &entry) {
+                Some(path) => path,
+                None => {
+                    warn!("[CARTRIDGE] Invalid code file {:?}, not saved", entry);
+                    continue;
+                }
+            };
+            info!("Save the code file {:?} in {:?}", file.name, path);
+
+            if let Some(dir) = path.parent() {
+                fs::create_dir_all(dir).unwrap();
+            }
+            let mut f = File::create(&path).unwrap();
+            f.write_all(file.get_data().as_bytes()).unwrap();
+
+            file.path = Some(entry.clone());
+            entries.push(entry);
+        }
+
+        let mut data = String::new();
+        File::open(&self.filename).unwrap().read_to_string(&mut data).unwrap();
+        let mut json: UnicornSplittedFormat = serde_json::from_str(&data).unwrap();
+
+        if json.files != entries {
+            info!("Save the list of the code files in {:?}", self.filename);
+
+            json.files = entries;
+            let mut f = File::create(&self.filename).unwrap();
+            f.write_all(serde_json::to_string_pretty(&json).unwrap().as_bytes()).unwrap();
+        }
+    }
+
 
     pub fn dump(&mut self, filename: &str) {
         info!("Dump the code in {:?}", filename);
@@ -986,3 +1271,81 @@ impl fmt::Debug for Cartridge {
                self.gfx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    fn write(path: &Path, data: &str) {
+        File::create(path).unwrap().write_all(data.as_bytes()).unwrap();
+    }
+
+    fn read(path: &Path) -> String {
+        let mut data = String::new();
+        File::open(path).unwrap().read_to_string(&mut data).unwrap();
+        data
+    }
+
+    #[test]
+    fn save_splitted_code_files() {
+        let dir = TempDir::new("unicorn").unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+
+        write(Path::new(&path("main.lua")), "require(\"enemies\")\n");
+        write(Path::new(&path("enemies.lua")), "x = 1\n");
+        write(Path::new(&path("old.lua")), "y = 1\n");
+        write(Path::new(&path("data.uni")), "__gfx__\n__map__\n");
+        write(Path::new(&path("cart.json")),
+              &format!(r#"{{"code": "{}", "data": "{}", "files": ["enemies.lua", "old.lua"]}}"#,
+                       path("main.lua"),
+                       path("data.uni")));
+
+        let mut cartridge = Cartridge::from_unicorn_splitted_file(&path("cart.json")).unwrap();
+        assert_eq!(cartridge.code.files.len(), 2);
+
+        // Lines of the text editor, with their newline
+        cartridge.code.set_files_data(vec![("enemies.lua".to_string(), vec!["x = 2\n".to_string()]),
+                                           ("world/map.lua".to_string(), vec!["z = 3\n".to_string()])]);
+        cartridge.save_code_files();
+
+        assert_eq!(read(Path::new(&path("enemies.lua"))), "x = 2\n");
+        assert_eq!(read(Path::new(&path("world/map.lua"))), "z = 3\n");
+        assert_eq!(read(Path::new(&path("main.lua"))), "require(\"enemies\")\n");
+
+        let cartridge = Cartridge::from_unicorn_splitted_file(&path("cart.json")).unwrap();
+        let names: Vec<&str> = cartridge.code.files.iter().map(|file| file.name.as_str()).collect();
+        assert_eq!(names, vec!["enemies.lua", "world/map.lua"]);
+
+        let json: UnicornSplittedFormat = serde_json::from_str(&read(Path::new(&path("cart.json")))).unwrap();
+        assert_eq!(json.files, vec!["enemies.lua", "world/map.lua"]);
+    }
+
+    #[test]
+    fn reject_code_files_outside_the_cartridge() {
+        let dir = TempDir::new("unicorn").unwrap();
+        let path = |name: &str| dir.path().join(name).to_string_lossy().into_owned();
+
+        write(Path::new(&path("main.lua")), "x = 1\n");
+        write(Path::new(&path("data.uni")), "__gfx__\n");
+        write(Path::new(&path("secret.lua")), "y = 1\n");
+
+        for file in &["../secret.lua", "world/../../secret.lua", &path("secret.lua")] {
+            write(Path::new(&path("cart.json")),
+                  &format!(r#"{{"code": "{}", "data": "{}", "files": ["{}"]}}"#,
+                           path("main.lua"),
+                           path("data.uni"),
+                           file));
+            assert!(Cartridge::from_unicorn_splitted_file(&path("cart.json")).is_err(), "{}", file);
+        }
+
+        // The files added in the editor stay in the directory too
+        write(Path::new(&path("cart.json")),
+              &format!(r#"{{"code": "{}", "data": "{}"}}"#, path("main.lua"), path("data.uni")));
+        let mut cartridge = Cartridge::from_unicorn_splitted_file(&path("cart.json")).unwrap();
+        cartridge.code.set_files_data(vec![("../escape.lua".to_string(), vec!["z = 3\n".to_string()])]);
+        cartridge.save_code_files();
+
+        assert!(!dir.path().parent().unwrap().join("escape.lua").exists());
+    }
+}
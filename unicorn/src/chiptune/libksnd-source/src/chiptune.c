@@ -374,6 +374,39 @@ KLYSAPI void Chiptune_SetVolume(ChiptunePlayer *player, int volume)
 }
 
 
+KLYSAPI void Chiptune_SetChannelVolume(ChiptunePlayer *player, int chan, int volume)
+{
+	if (chan < 0 || chan >= player->cyd_music.n_channels)
+		return;
+
+	cyd_lock(&player->cyd_music, 1);
+	mus_set_channel_volume(&player->mus_music, chan, volume);
+	cyd_lock(&player->cyd_music, 0);
+}
+
+
+KLYSAPI void Chiptune_SetChannelPanning(ChiptunePlayer *player, int chan, int panning)
+{
+	if (chan < 0 || chan >= player->cyd_music.n_channels)
+		return;
+
+#ifdef STEREOOUTPUT
+	cyd_lock(&player->cyd_music, 1);
+	cyd_set_panning(&player->cyd_music, &player->cyd_music.channel[chan], panning);
+	cyd_lock(&player->cyd_music, 0);
+#endif
+}
+
+
+KLYSAPI int Chiptune_IsChannelPlaying(ChiptunePlayer *player, int chan)
+{
+	if (chan < 0 || chan >= player->cyd_music.n_channels)
+		return 0;
+
+	return (player->cyd_music.channel[chan].flags & CYD_CHN_ENABLE_GATE) != 0;
+}
+
+
 KLYSAPI void Chiptune_GetVUMeters(ChiptunePlayer *player, int *dest, int n_channels)
 {
 	int temp[MUS_MAX_CHANNELS];
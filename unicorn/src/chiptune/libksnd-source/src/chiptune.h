@@ -207,6 +207,29 @@ KLYSAPI extern void Chiptune_SetPlayerQuality(ChiptunePlayer *player, int oversa
  */
 KLYSAPI extern void Chiptune_SetVolume(ChiptunePlayer *player, int volume);
 
+/**
+ * Set the volume of a single player channel.
+ *
+ * @param player player context
+ * @param chan channel index [0..CHANNELS-1]
+ * @param volume volume [0..128]
+ */
+KLYSAPI extern void Chiptune_SetChannelVolume(ChiptunePlayer *player, int chan, int volume);
+
+/**
+ * Set the stereo position of a single player channel.
+ *
+ * @param player player context
+ * @param chan channel index [0..CHANNELS-1]
+ * @param panning panning [0..128], 64 is the center
+ */
+KLYSAPI extern void Chiptune_SetChannelPanning(ChiptunePlayer *player, int chan, int panning);
+
+/**
+ * Returns non-zero if a note is currently gated on the channel.
+ */
+KLYSAPI extern int Chiptune_IsChannelPlaying(ChiptunePlayer *player, int chan);
+
 /**
  * Enable or disable song looping.
  *
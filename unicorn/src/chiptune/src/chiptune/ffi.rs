@@ -161,6 +161,9 @@ extern "C" {
   pub fn Chiptune_SetPlayerQuality(player: chiptune_player, oversample: c_int);
   pub fn Chiptune_SetVolume(player: chiptune_player, volume: c_int);
   pub fn Chiptune_SetLooping(player: chiptune_player, looping: c_int);
+  pub fn Chiptune_SetChannelVolume(player: chiptune_player, chan: c_int, volume: c_int);
+  pub fn Chiptune_SetChannelPanning(player: chiptune_player, chan: c_int, panning: c_int);
+  pub fn Chiptune_IsChannelPlaying(player: chiptune_player, chan: c_int) -> c_int;
   pub fn Chiptune_GetMusicPlayPosition(player: chiptune_player) -> c_int;
   pub fn Chiptune_GetSoundPlayPosition(player: chiptune_player, chan: c_int) -> c_int;
   pub fn Chiptune_GetSongInfo(player: chiptune_player) -> c_int;
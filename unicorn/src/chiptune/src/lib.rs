@@ -498,6 +498,24 @@ pub mod chiptune {
       }
     }
 
+    pub fn set_channel_volume(&mut self, chan: c_int, volume: c_int) {
+      unsafe {
+        ffi::Chiptune_SetChannelVolume(self.P, chan, volume);
+      }
+    }
+
+    pub fn set_channel_panning(&mut self, chan: c_int, panning: c_int) {
+      unsafe {
+        ffi::Chiptune_SetChannelPanning(self.P, chan, panning);
+      }
+    }
+
+    pub fn is_channel_playing(&mut self, chan: c_int) -> bool {
+      unsafe {
+        ffi::Chiptune_IsChannelPlaying(self.P, chan) != 0
+      }
+    }
+
     pub fn get_music_position(&mut self) -> c_int {
       unsafe {
         return ffi::Chiptune_GetMusicPlayPosition(self.P);
@@ -638,6 +656,16 @@ pub mod chiptune {
     pub fn set_looping(&mut self, looping: i32) {
     }
 
+    pub fn set_channel_volume(&mut self, chan: i32, volume: i32) {
+    }
+
+    pub fn set_channel_panning(&mut self, chan: i32, panning: i32) {
+    }
+
+    pub fn is_channel_playing(&mut self, chan: i32) -> bool {
+      false
+    }
+
     pub fn get_music_position(&mut self) -> i32 {
       0
     }
@@ -0,0 +1,321 @@
+use std::env;
+use std::fs;
+use std::fs::File;
+use std::io::prelude::*;
+use std::path::PathBuf;
+
+use serde_json;
+
+use config::keys::PX8Key;
+use config::scancode::Scancode;
+use config::{MAX_PLAYERS, AXIS_LEFT_X, AXIS_LEFT_Y};
+
+/// Gamepad buttons, numbered like the SDL2 game controller buttons
+pub const PAD_A: u8 = 0;
+pub const PAD_B: u8 = 1;
+pub const PAD_X: u8 = 2;
+pub const PAD_Y: u8 = 3;
+pub const PAD_BACK: u8 = 4;
+pub const PAD_GUIDE: u8 = 5;
+pub const PAD_START: u8 = 6;
+pub const PAD_LEFT_STICK: u8 = 7;
+pub const PAD_RIGHT_STICK: u8 = 8;
+pub const PAD_LEFT_SHOULDER: u8 = 9;
+pub const PAD_RIGHT_SHOULDER: u8 = 10;
+pub const PAD_DPAD_UP: u8 = 11;
+pub const PAD_DPAD_DOWN: u8 = 12;
+pub const PAD_DPAD_LEFT: u8 = 13;
+pub const PAD_DPAD_RIGHT: u8 = 14;
+
+/// An axis bound to a button is pressed past this value
+pub const AXIS_THRESHOLD: i32 = 16384;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Input {
+    Key(Scancode),
+    Button(u8),
+    /// Axis index and direction (true for the positive side)
+    Axis(u8, bool),
+}
+
+impl Input {
+    pub fn is_key(&self) -> bool {
+        match *self {
+            Input::Key(_) => true,
+            _ => false,
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match *self {
+            Input::Key(scancode) => {
+                let name = scancode.name();
+                if name.is_empty() {
+                    format!("{:?}", scancode).to_uppercase()
+                } else {
+                    name.to_string()
+                }
+            }
+            Input::Button(button) => format!("PAD{:?}", button),
+            Input::Axis(axis, positive) => {
+                format!("AXIS{:?}{}", axis, if positive { "+" } else { "-" })
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Binding {
+    pub player: u8,
+    pub key: PX8Key,
+    pub input: Input,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Bindings {
+    pub bindings: Vec<Binding>,
+}
+
+impl Bindings {
+    pub fn new() -> Bindings {
+        let mut bindings = Bindings { bindings: Vec::new() };
+        bindings.reset();
+        bindings
+    }
+
+    pub fn reset(&mut self) {
+        self.bindings.clear();
+
+        self.add(0, PX8Key::Right, Input::Key(Scancode::Right));
+        self.add(0, PX8Key::Left, Input::Key(Scancode::Left));
+        self.add(0, PX8Key::Up, Input::Key(Scancode::Up));
+        self.add(0, PX8Key::Down, Input::Key(Scancode::Down));
+
+        self.add(0, PX8Key::A, Input::Key(Scancode::Z));
+        self.add(0, PX8Key::A, Input::Key(Scancode::C));
+        self.add(0, PX8Key::A, Input::Key(Scancode::N));
+        self.add(0, PX8Key::B, Input::Key(Scancode::X));
+        self.add(0, PX8Key::B, Input::Key(Scancode::V));
+        self.add(0, PX8Key::B, Input::Key(Scancode::M));
+
+        self.add(1, PX8Key::Right, Input::Key(Scancode::F));
+        self.add(1, PX8Key::Left, Input::Key(Scancode::S));
+        self.add(1, PX8Key::Up, Input::Key(Scancode::E));
+        self.add(1, PX8Key::Down, Input::Key(Scancode::D));
+
+        self.add(1, PX8Key::A, Input::Key(Scancode::LShift));
+        self.add(1, PX8Key::A, Input::Key(Scancode::Tab));
+        self.add(1, PX8Key::A, Input::Key(Scancode::A));
+        self.add(1, PX8Key::A, Input::Key(Scancode::Q));
+        self.add(1, PX8Key::B, Input::Key(Scancode::B));
+
+        self.add(0, PX8Key::Pause, Input::Key(Scancode::Escape));
+
+        self.add(0, PX8Key::Enter, Input::Key(Scancode::KpEnter));
+        self.add(0, PX8Key::Enter, Input::Key(Scancode::Return));
+
+        for player in 0..MAX_PLAYERS {
+            self.add(player, PX8Key::Right, Input::Button(PAD_DPAD_RIGHT));
+            self.add(player, PX8Key::Left, Input::Button(PAD_DPAD_LEFT));
+            self.add(player, PX8Key::Up, Input::Button(PAD_DPAD_UP));
+            self.add(player, PX8Key::Down, Input::Button(PAD_DPAD_DOWN));
+            self.add(player, PX8Key::A, Input::Button(PAD_A));
+            self.add(player, PX8Key::B, Input::Button(PAD_B));
+            self.add(player, PX8Key::X, Input::Button(PAD_X));
+            self.add(player, PX8Key::Y, Input::Button(PAD_Y));
+            self.add(player, PX8Key::L, Input::Button(PAD_LEFT_SHOULDER));
+            self.add(player, PX8Key::R, Input::Button(PAD_RIGHT_SHOULDER));
+            self.add(player, PX8Key::Start, Input::Button(PAD_START));
+            self.add(player, PX8Key::Select, Input::Button(PAD_BACK));
+
+            self.add(player, PX8Key::Right, Input::Axis(AXIS_LEFT_X, true));
+            self.add(player, PX8Key::Left, Input::Axis(AXIS_LEFT_X, false));
+            self.add(player, PX8Key::Down, Input::Axis(AXIS_LEFT_Y, true));
+            self.add(player, PX8Key::Up, Input::Axis(AXIS_LEFT_Y, false));
+        }
+    }
+
+    pub fn add(&mut self, player: u8, key: PX8Key, input: Input) {
+        self.bindings.push(Binding {
+                               player: player,
+                               key: key,
+                               input: input,
+                           });
+    }
+
+    /// Bind `input` to the `key` of `player`: it replaces the previous input of the same
+    /// kind (keyboard or gamepad) for this key, and unbinds `input` from the other keys
+    pub fn set(&mut self, player: u8, key: PX8Key, input: Input) {
+        self.bindings
+            .retain(|binding| {
+                        let same_key = binding.player == player && binding.key == key &&
+                                       binding.input.is_key() == input.is_key();
+                        let same_input = binding.input == input &&
+                                         (input.is_key() || binding.player == player);
+                        !same_key && !same_input
+                    });
+
+        self.add(player, key, input);
+    }
+
+    pub fn lookup_key(&self, scancode: Scancode) -> Vec<(u8, PX8Key)> {
+        self.bindings
+            .iter()
+            .filter(|binding| binding.input == Input::Key(scancode))
+            .map(|binding| (binding.player, binding.key))
+            .collect()
+    }
+
+    pub fn lookup_button(&self, player: u8, button: u8) -> Vec<PX8Key> {
+        self.bindings
+            .iter()
+            .filter(|binding| binding.player == player && binding.input == Input::Button(button))
+            .map(|binding| binding.key)
+            .collect()
+    }
+
+    pub fn lookup_axis(&self, player: u8, axis: u8) -> Vec<(PX8Key, bool)> {
+        self.bindings
+            .iter()
+            .filter_map(|binding| match binding.input {
+                            Input::Axis(idx, positive) if binding.player == player &&
+                                                          idx == axis => {
+                                Some((binding.key, positive))
+                            }
+                            _ => None,
+                        })
+            .collect()
+    }
+
+    pub fn inputs(&self, player: u8, key: PX8Key) -> Vec<Input> {
+        self.bindings
+            .iter()
+            .filter(|binding| binding.player == player && binding.key == key)
+            .map(|binding| binding.input)
+            .collect()
+    }
+
+    pub fn load(filename: &str) -> Result<Bindings, String> {
+        let mut f = try!(File::open(filename).map_err(|e| e.to_string()));
+
+        let mut data = String::new();
+        try!(f.read_to_string(&mut data).map_err(|e| e.to_string()));
+
+        serde_json::from_str(&data).map_err(|e| e.to_string())
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), String> {
+        let path = PathBuf::from(filename);
+        if let Some(parent) = path.parent() {
+            try!(fs::create_dir_all(parent).map_err(|e| e.to_string()));
+        }
+
+        let data = try!(serde_json::to_string_pretty(self).map_err(|e| e.to_string()));
+
+        let mut f = try!(File::create(&path).map_err(|e| e.to_string()));
+        f.write_all(data.as_bytes()).map_err(|e| e.to_string())
+    }
+}
+
+/// User configuration file of the bindings, shared by all the frontends
+pub fn user_config_path() -> String {
+    let mut path = match env::home_dir() {
+        Some(path) => path,
+        None => PathBuf::from("."),
+    };
+    path.push(".config/unicorn/keys.json");
+    path.to_string_lossy().into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use tempdir::TempDir;
+
+    use super::*;
+
+    #[test]
+    fn lookup_the_defaults() {
+        let bindings = Bindings::new();
+
+        assert_eq!(bindings.lookup_key(Scancode::Z), vec![(0, PX8Key::A)]);
+        assert_eq!(bindings.lookup_key(Scancode::F), vec![(1, PX8Key::Right)]);
+        assert!(bindings.lookup_key(Scancode::F12).is_empty());
+
+        assert_eq!(bindings.lookup_button(2, PAD_A), vec![PX8Key::A]);
+        assert_eq!(bindings.lookup_axis(3, AXIS_LEFT_X),
+                   vec![(PX8Key::Right, true), (PX8Key::Left, false)]);
+    }
+
+    #[test]
+    fn remap_a_key() {
+        let mut bindings = Bindings::new();
+
+        // Q was the A of the second player
+        bindings.set(0, PX8Key::A, Input::Key(Scancode::Q));
+
+        assert_eq!(bindings.inputs(0, PX8Key::A),
+                   vec![Input::Button(PAD_A), Input::Key(Scancode::Q)]);
+        assert!(bindings.lookup_key(Scancode::Z).is_empty());
+        assert_eq!(bindings.lookup_key(Scancode::Q), vec![(0, PX8Key::A)]);
+        assert_eq!(bindings.inputs(1, PX8Key::A).len(), 4);
+    }
+
+    #[test]
+    fn remap_a_button() {
+        let mut bindings = Bindings::new();
+
+        bindings.set(0, PX8Key::B, Input::Button(PAD_A));
+
+        assert!(bindings.lookup_button(0, PAD_B).is_empty());
+        assert_eq!(bindings.lookup_button(0, PAD_A), vec![PX8Key::B]);
+        assert_eq!(bindings.inputs(0, PX8Key::A).len(), 3);
+        // The gamepads of the other players keep their buttons
+        assert_eq!(bindings.lookup_button(1, PAD_A), vec![PX8Key::A]);
+        assert_eq!(bindings.lookup_button(1, PAD_B), vec![PX8Key::B]);
+    }
+
+    #[test]
+    fn input_names() {
+        assert_eq!(Input::Key(Scancode::Z).name(), "Z");
+        assert_eq!(Input::Button(PAD_START).name(), "PAD6");
+        assert_eq!(Input::Axis(AXIS_LEFT_Y, false).name(), "AXIS1-");
+    }
+
+    #[test]
+    fn save_and_load() {
+        let dir = TempDir::new("unicorn").unwrap();
+        let path = dir.path().join("config/keys.json");
+        let filename = path.to_str().unwrap();
+
+        let mut bindings = Bindings::new();
+        bindings.set(1, PX8Key::Start, Input::Key(Scancode::P));
+        bindings.save(filename).unwrap();
+
+        let loaded = Bindings::load(filename).unwrap();
+        assert_eq!(loaded.lookup_key(Scancode::P), vec![(1, PX8Key::Start)]);
+        assert_eq!(loaded.bindings.len(), bindings.bindings.len());
+    }
+
+    #[test]
+    fn parse_a_file() {
+        let dir = TempDir::new("unicorn").unwrap();
+        let path = dir.path().join("keys.json");
+        let filename = path.to_str().unwrap();
+
+        File::create(&path)
+            .unwrap()
+            .write_all(br#"{"bindings": [
+                {"player": 0, "key": "A", "input": {"Key": "Space"}},
+                {"player": 1, "key": "Left", "input": {"Axis": [2, false]}}
+            ]}"#)
+            .unwrap();
+
+        let bindings = Bindings::load(filename).unwrap();
+        assert_eq!(bindings.lookup_key(Scancode::Space), vec![(0, PX8Key::A)]);
+        assert_eq!(bindings.lookup_axis(1, 2), vec![(PX8Key::Left, false)]);
+
+        File::create(&path).unwrap().write_all(b"{\"bindings\": 1}").unwrap();
+        assert!(Bindings::load(filename).is_err());
+        assert!(Bindings::load(dir.path().join("none.json").to_str().unwrap()).is_err());
+    }
+}
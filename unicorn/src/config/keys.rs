@@ -1,7 +1,6 @@
 use std::fmt;
-use config::scancode::Scancode;
 
-#[derive(Eq, PartialEq, Hash, Clone, Copy)]
+#[derive(Eq, PartialEq, Hash, Clone, Copy, Serialize, Deserialize)]
 pub enum PX8Key {
     Right,
     Left,
@@ -11,8 +10,30 @@ pub enum PX8Key {
     B,
     Pause,
     Enter,
+    X,
+    Y,
+    L,
+    R,
+    Start,
+    Select,
 }
 
+/// Every button of a player, in the order of the `btn` index
+pub const PX8_KEYS: [PX8Key; 14] = [PX8Key::Left,
+                                    PX8Key::Right,
+                                    PX8Key::Up,
+                                    PX8Key::Down,
+                                    PX8Key::A,
+                                    PX8Key::B,
+                                    PX8Key::Enter,
+                                    PX8Key::Pause,
+                                    PX8Key::X,
+                                    PX8Key::Y,
+                                    PX8Key::L,
+                                    PX8Key::R,
+                                    PX8Key::Start,
+                                    PX8Key::Select];
+
 impl fmt::Debug for PX8Key {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         use self::PX8Key::*;
@@ -28,36 +49,17 @@ impl fmt::Debug for PX8Key {
                    B => "B",
                    Pause => "Pause",
                    Enter => "Enter",
+                   X => "X",
+                   Y => "Y",
+                   L => "L",
+                   R => "R",
+                   Start => "Start",
+                   Select => "Select",
                })
 
     }
 }
 
-
-pub fn map_keycode(key: Scancode) -> (Option<PX8Key>, u8) {
-    match key {
-        Scancode::Right => (Some(PX8Key::Right), 0),
-        Scancode::Left => (Some(PX8Key::Left), 0),
-        Scancode::Up => (Some(PX8Key::Up), 0),
-        Scancode::Down => (Some(PX8Key::Down), 0),
-
-        Scancode::Z | Scancode::C | Scancode::N => (Some(PX8Key::A), 0),
-        Scancode::X | Scancode::V | Scancode::M => (Some(PX8Key::B), 0),
-
-        Scancode::F => (Some(PX8Key::Right), 1),
-        Scancode::S => (Some(PX8Key::Left), 1),
-        Scancode::E => (Some(PX8Key::Up), 1),
-        Scancode::D => (Some(PX8Key::Down), 1),
-
-        Scancode::LShift | Scancode::Tab => (Some(PX8Key::A), 1),
-
-        Scancode::A | Scancode::Q => (Some(PX8Key::A), 1),
-        Scancode::B => (Some(PX8Key::B), 1),
-
-        Scancode::Escape => (Some(PX8Key::Pause), 0),
-
-        Scancode::KpEnter | Scancode::Return => (Some(PX8Key::Enter), 0),
-
-        _ => (None, 0),
-    }
-}
\ No newline at end of file
+pub fn map_index(index: u8) -> Option<PX8Key> {
+    PX8_KEYS.get(index as usize).cloned()
+}
@@ -1,11 +1,30 @@
+pub mod bindings;
 pub mod keys;
 pub mod scancode;
+pub mod textinput;
+pub mod touch;
 
-use self::keys::PX8Key;
+use self::bindings::{Bindings, Input, AXIS_THRESHOLD};
+use self::keys::{PX8Key, PX8_KEYS};
+use self::textinput::TextInput;
+use self::touch::{Touch, TouchPhase, VirtualPad};
 use self::scancode::{Scancode, Mod};
 
 use std::collections::HashMap;
 
+pub const MAX_PLAYERS: u8 = 4;
+
+/// Analog axes: left stick X/Y, right stick X/Y, left and right triggers
+pub const AXIS_LEFT_X: u8 = 0;
+pub const AXIS_LEFT_Y: u8 = 1;
+pub const AXIS_RIGHT_X: u8 = 2;
+pub const AXIS_RIGHT_Y: u8 = 3;
+pub const AXIS_TRIGGER_LEFT: u8 = 4;
+pub const AXIS_TRIGGER_RIGHT: u8 = 5;
+pub const AXIS_COUNT: usize = 6;
+
+const AXIS_DEAD_ZONE: i32 = 4096;
+
 pub struct Mouse {
     pub x: i32,
     pub y: i32,
@@ -25,10 +44,19 @@ impl Mouse {
         }
     }
 }
+#[derive(Clone, Debug)]
+pub struct Rumble {
+    pub player: u8,
+    pub strength: f32,
+    pub duration: u32,
+}
+
 pub struct PlayerKeys {
     frames: HashMap<PX8Key, f64>,
     keys: HashMap<PX8Key, bool>,
     keys_quick: HashMap<PX8Key, bool>,
+    axes: [i16; AXIS_COUNT],
+    pub connected: bool,
 }
 
 impl PlayerKeys {
@@ -36,28 +64,17 @@ impl PlayerKeys {
         let mut keys = HashMap::new();
         let mut keys_quick = HashMap::new();
 
-        keys.insert(PX8Key::Down, false);
-        keys.insert(PX8Key::Up, false);
-        keys.insert(PX8Key::Left, false);
-        keys.insert(PX8Key::Right, false);
-        keys.insert(PX8Key::A, false);
-        keys.insert(PX8Key::B, false);
-        keys.insert(PX8Key::Enter, false);
-        keys.insert(PX8Key::Pause, false);
-
-        keys_quick.insert(PX8Key::Down, false);
-        keys_quick.insert(PX8Key::Up, false);
-        keys_quick.insert(PX8Key::Left, false);
-        keys_quick.insert(PX8Key::Right, false);
-        keys_quick.insert(PX8Key::A, false);
-        keys_quick.insert(PX8Key::B, false);
-        keys_quick.insert(PX8Key::Enter, false);
-        keys_quick.insert(PX8Key::Pause, false);
+        for key in PX8_KEYS.iter() {
+            keys.insert(*key, false);
+            keys_quick.insert(*key, false);
+        }
 
         PlayerKeys {
             frames: HashMap::new(),
             keys: keys,
             keys_quick: keys_quick,
+            axes: [0; AXIS_COUNT],
+            connected: false,
         }
     }
 }
@@ -69,13 +86,20 @@ pub struct Players {
     pub akeys_quick: HashMap<Scancode, bool>,
     pub all_frames: HashMap<Scancode, f64>,
     pub text: String,
+    pub rumbles: Vec<Rumble>,
+    pub bindings: Bindings,
+    last_input: Option<Input>,
+    pub touches: Vec<Touch>,
+    pub virtual_pad: VirtualPad,
+    pub text_input: TextInput,
 }
 
 impl Players {
     pub fn new() -> Players {
         let mut keys = HashMap::new();
-        keys.insert(0, PlayerKeys::new());
-        keys.insert(1, PlayerKeys::new());
+        for player in 0..MAX_PLAYERS {
+            keys.insert(player, PlayerKeys::new());
+        }
 
         Players {
             pkeys: keys,
@@ -84,9 +108,171 @@ impl Players {
             akeys_quick: HashMap::new(),
             all_frames: HashMap::new(),
             text: "".to_string(),
+            rumbles: Vec::new(),
+            bindings: Bindings::new(),
+            last_input: None,
+            touches: Vec::new(),
+            virtual_pad: VirtualPad::new(),
+            text_input: TextInput::new(),
+        }
+    }
+
+    /// UTF-8 text typed on the keyboard (or committed by an IME)
+    pub fn input_text(&mut self, text: &str) {
+        if text.chars().count() == 1 {
+            self.set_text(text.to_string());
+        }
+
+        self.text_input.insert(text);
+    }
+
+    /// Touch event, the coordinates are in screen pixels
+    pub fn touch(&mut self, id: i64, x: i32, y: i32, phase: TouchPhase, elapsed: f64) {
+        debug!("TOUCH {:?} {:?} {:?} {:?}", id, x, y, phase);
+
+        match self.touches.iter().position(|touch| touch.id == id) {
+            Some(idx) => {
+                let touch = &mut self.touches[idx];
+                touch.x = x;
+                touch.y = y;
+                touch.phase = phase;
+            }
+            None => {
+                self.touches.push(Touch {
+                                      id: id,
+                                      x: x,
+                                      y: y,
+                                      phase: phase,
+                                  })
+            }
+        }
+
+        for (key, pressed) in self.virtual_pad.update(&self.touches) {
+            if pressed {
+                self.key_down_direct(0, key, false, elapsed);
+            } else {
+                self.key_up_direct(0, key);
+            }
+        }
+    }
+
+    pub fn touch_count(&self) -> usize {
+        self.touches.len()
+    }
+
+    pub fn get_touch(&self, idx: usize) -> Option<Touch> {
+        self.touches.get(idx).cloned()
+    }
+
+    pub fn load_bindings(&mut self, filename: &str) {
+        match Bindings::load(filename) {
+            Ok(bindings) => {
+                info!("[Players] Bindings loaded from {:?}", filename);
+                self.bindings = bindings;
+            }
+            Err(e) => info!("[Players] Default bindings, {:?}: {:?}", filename, e),
+        }
+    }
+
+    pub fn save_bindings(&mut self, filename: &str) {
+        match self.bindings.save(filename) {
+            Ok(_) => info!("[Players] Bindings saved in {:?}", filename),
+            Err(e) => error!("[Players] Failed to save the bindings in {:?}: {:?}", filename, e),
+        }
+    }
+
+    /// Last raw input pressed (key, gamepad button or axis), used to capture a new binding
+    pub fn take_last_input(&mut self) -> Option<Input> {
+        self.last_input.take()
+    }
+
+    pub fn set_connected(&mut self, player: u8, connected: bool) {
+        info!("[Players] Player {:?} connected {:?}", player, connected);
+
+        if let Some(keys) = self.pkeys.get_mut(&player) {
+            keys.connected = connected;
+
+            if !connected {
+                for key in PX8_KEYS.iter() {
+                    keys.keys.insert(*key, false);
+                    keys.keys_quick.insert(*key, false);
+                }
+                keys.axes = [0; AXIS_COUNT];
+            }
+        }
+    }
+
+    /// Number of players with an input device, the keyboard always counts as player 0
+    pub fn player_count(&self) -> u8 {
+        let connected = self.pkeys.values().filter(|keys| keys.connected).count() as u8;
+        if connected == 0 { 1 } else { connected }
+    }
+
+    pub fn set_axis(&mut self, player: u8, axis: u8, value: i16, elapsed: f64) {
+        let previous = match self.pkeys.get_mut(&player) {
+            Some(keys) => {
+                match keys.axes.get_mut(axis as usize) {
+                    Some(current) => {
+                        let previous = *current as i32;
+                        *current = value;
+                        previous
+                    }
+                    None => return,
+                }
+            }
+            None => return,
+        };
+
+        let value = value as i32;
+
+        for (key, positive) in self.bindings.lookup_axis(player, axis) {
+            let pressed = |value: i32| if positive {
+                value >= AXIS_THRESHOLD
+            } else {
+                value <= -AXIS_THRESHOLD
+            };
+
+            if pressed(value) && !pressed(previous) {
+                self.last_input = Some(Input::Axis(axis, positive));
+                self.key_down_direct(player, key, false, elapsed);
+            } else if !pressed(value) && pressed(previous) {
+                self.key_up_direct(player, key);
+            }
         }
     }
 
+    /// Axis value in [-1.0, 1.0] for the sticks and [0.0, 1.0] for the triggers
+    pub fn axis(&self, player: u8, axis: u8) -> f64 {
+        match self.pkeys.get(&player) {
+            Some(keys) => {
+                match keys.axes.get(axis as usize) {
+                    Some(&value) => {
+                        let value = value as i32;
+                        if value.abs() < AXIS_DEAD_ZONE {
+                            0.
+                        } else {
+                            (value as f64 / 32767.).max(-1.).min(1.)
+                        }
+                    }
+                    None => 0.,
+                }
+            }
+            None => 0.,
+        }
+    }
+
+    pub fn rumble(&mut self, player: u8, strength: f32, duration: u32) {
+        self.rumbles.push(Rumble {
+                              player: player,
+                              strength: strength.max(0.).min(1.),
+                              duration: duration,
+                          });
+    }
+
+    pub fn take_rumbles(&mut self) -> Vec<Rumble> {
+        self.rumbles.drain(..).collect()
+    }
+
     pub fn clear_text(&mut self) {
         self.text = "".to_string();
     }
@@ -132,6 +318,12 @@ impl Players {
             self.mouse.state = 0;
         }
 
+        // The ended touches are visible during one frame
+        self.touches.retain(|touch| touch.is_active());
+        for touch in self.touches.iter_mut() {
+            touch.phase = TouchPhase::Stationary;
+        }
+
         for (key_val, value) in self.akeys.iter_mut() {
             if *value {
                 match self.all_frames.get(&key_val) {
@@ -202,11 +394,42 @@ impl Players {
 
         self.all_frames.insert(scancode, elapsed);
 
-        if let (Some(key), player) = self::keys::map_keycode(scancode) {
+        if !repeat {
+            self.last_input = Some(Input::Key(scancode));
+        }
+
+        if self.text_input.key(scancode) {
+            return;
+        }
+
+        for (player, key) in self.bindings.lookup_key(scancode) {
+            // Only Enter and Pause are sent to the game while typing
+            if self.text_input.active && key != PX8Key::Enter && key != PX8Key::Pause {
+                continue;
+            }
+
             self.key_down_direct(player, key, repeat, elapsed);
         }
     }
 
+    pub fn pad_button_down(&mut self, player: u8, button: u8, elapsed: f64) {
+        debug!("PAD BUTTON {:?} Player {:?} -> DOWN", button, player);
+
+        self.last_input = Some(Input::Button(button));
+
+        for key in self.bindings.lookup_button(player, button) {
+            self.key_down_direct(player, key, false, elapsed);
+        }
+    }
+
+    pub fn pad_button_up(&mut self, player: u8, button: u8) {
+        debug!("PAD BUTTON {:?} Player {:?} -> UP", button, player);
+
+        for key in self.bindings.lookup_button(player, button) {
+            self.key_up_direct(player, key);
+        }
+    }
+
     pub fn key_down_direct(&mut self, player: u8, key: PX8Key, repeat: bool, elapsed: f64) {
         debug!("KEY {:?} {:?} {:?} Player {:?} -> DOWN",
                key,
@@ -268,7 +491,7 @@ impl Players {
         self.akeys.insert(scancode, false);
         self.akeys_quick.insert(scancode, false);
 
-        if let (Some(key), player) = self::keys::map_keycode(scancode) {
+        for (player, key) in self.bindings.lookup_key(scancode) {
             self.key_up_direct(player, key);
         }
     }
@@ -286,41 +509,17 @@ impl Players {
     }
 
     pub fn get_value(&self, player: u8, index: u8) -> u8 {
-        match self.pkeys.get(&player) {
-            Some(keys) => {
-                match index {
-                    0 if keys.keys[&PX8Key::Left] => 1,
-                    1 if keys.keys[&PX8Key::Right] => 1,
-                    2 if keys.keys[&PX8Key::Up] => 1,
-                    3 if keys.keys[&PX8Key::Down] => 1,
-                    4 if keys.keys[&PX8Key::A] => 1,
-                    5 if keys.keys[&PX8Key::B] => 1,
-                    6 if keys.keys[&PX8Key::Enter] => 1,
-                    7 if keys.keys[&PX8Key::Pause] => 1,
-                    _ => 0,
-                }
-            }
-            None => 0,
+        match (self.pkeys.get(&player), self::keys::map_index(index)) {
+            (Some(keys), Some(key)) if keys.keys[&key] => 1,
+            _ => 0,
         }
     }
 
 
     pub fn get_value_quick(&mut self, player: u8, index: u8) -> u8 {
-        match self.pkeys.get(&player) {
-            Some(keys) => {
-                match index {
-                    0 if keys.keys_quick[&PX8Key::Left] => 1,
-                    1 if keys.keys_quick[&PX8Key::Right] => 1,
-                    2 if keys.keys_quick[&PX8Key::Up] => 1,
-                    3 if keys.keys_quick[&PX8Key::Down] => 1,
-                    4 if keys.keys_quick[&PX8Key::A] => 1,
-                    5 if keys.keys_quick[&PX8Key::B] => 1,
-                    6 if keys.keys_quick[&PX8Key::Enter] => 1,
-                    7 if keys.keys_quick[&PX8Key::Pause] => 1,
-                    _ => 0,
-                }
-            }
-            None => 0,
+        match (self.pkeys.get(&player), self::keys::map_index(index)) {
+            (Some(keys), Some(key)) if keys.keys_quick[&key] => 1,
+            _ => 0,
         }
     }
 
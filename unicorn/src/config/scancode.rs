@@ -9,7 +9,7 @@ pub enum Mod {
 }
 
 #[repr(i32)]
-#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug, Serialize, Deserialize)]
 pub enum Scancode {
     A,
     B,
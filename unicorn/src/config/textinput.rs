@@ -0,0 +1,191 @@
+use config::scancode::Scancode;
+
+/// Text typed by the player while a script requested it, with a cursor and
+/// the IME composition (preedit) text
+pub struct TextInput {
+    pub active: bool,
+    text: Vec<char>,
+    cursor: usize,
+    pub composition: String,
+    pub composition_cursor: i32,
+    pub max_length: usize,
+}
+
+impl TextInput {
+    pub fn new() -> TextInput {
+        TextInput {
+            active: false,
+            text: Vec::new(),
+            cursor: 0,
+            composition: "".to_string(),
+            composition_cursor: 0,
+            max_length: 0,
+        }
+    }
+
+    /// Start to capture the text, `max_length` is in characters (0 for no limit)
+    pub fn start(&mut self, text: &str, max_length: usize) {
+        info!("[Players][TextInput] Start {:?} {:?}", text, max_length);
+
+        self.active = true;
+        self.max_length = max_length;
+        self.text = text.chars().collect();
+        if self.max_length > 0 {
+            self.text.truncate(self.max_length);
+        }
+        self.cursor = self.text.len();
+        self.composition.clear();
+        self.composition_cursor = 0;
+    }
+
+    pub fn stop(&mut self) -> String {
+        info!("[Players][TextInput] Stop");
+
+        self.active = false;
+        self.composition.clear();
+        self.get()
+    }
+
+    pub fn get(&self) -> String {
+        self.text.iter().cloned().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    pub fn insert(&mut self, text: &str) {
+        if !self.active {
+            return;
+        }
+
+        self.composition.clear();
+        self.composition_cursor = 0;
+
+        for c in text.chars().filter(|c| !c.is_control()) {
+            if self.max_length > 0 && self.text.len() >= self.max_length {
+                break;
+            }
+            self.text.insert(self.cursor, c);
+            self.cursor += 1;
+        }
+    }
+
+    pub fn set_composition(&mut self, text: &str, cursor: i32) {
+        if !self.active {
+            return;
+        }
+
+        self.composition = text.to_string();
+        self.composition_cursor = cursor;
+    }
+
+    /// Editing keys, called for the repeated key events too.
+    /// Returns true if the key was used by the text input
+    pub fn key(&mut self, scancode: Scancode) -> bool {
+        if !self.active || !self.composition.is_empty() {
+            return false;
+        }
+
+        match scancode {
+            Scancode::Backspace => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.text.remove(self.cursor);
+                }
+            }
+            Scancode::Delete => {
+                if self.cursor < self.text.len() {
+                    self.text.remove(self.cursor);
+                }
+            }
+            Scancode::Left => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                }
+            }
+            Scancode::Right => {
+                if self.cursor < self.text.len() {
+                    self.cursor += 1;
+                }
+            }
+            Scancode::Home => self.cursor = 0,
+            Scancode::End => self.cursor = self.text.len(),
+            _ => return false,
+        }
+
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edit_at_the_cursor() {
+        let mut input = TextInput::new();
+
+        input.insert("lost");
+        assert_eq!(input.get(), "");
+
+        input.start("helo", 0);
+        assert_eq!(input.cursor(), 4);
+
+        assert!(input.key(Scancode::Left));
+        input.insert("l");
+        assert_eq!(input.get(), "hello");
+        assert_eq!(input.cursor(), 4);
+
+        assert!(input.key(Scancode::Home));
+        assert!(input.key(Scancode::Delete));
+        assert!(input.key(Scancode::Backspace));
+        assert_eq!(input.get(), "ello");
+        assert_eq!(input.cursor(), 0);
+
+        assert!(input.key(Scancode::End));
+        input.insert("!\n");
+        assert_eq!(input.get(), "ello!");
+        assert!(input.key(Scancode::Right));
+        assert_eq!(input.cursor(), 5);
+
+        assert!(!input.key(Scancode::A));
+        assert_eq!(input.stop(), "ello!");
+        assert!(!input.key(Scancode::Backspace));
+    }
+
+    #[test]
+    fn max_length() {
+        let mut input = TextInput::new();
+
+        input.start("abcdef", 4);
+        assert_eq!(input.get(), "abcd");
+        assert_eq!(input.cursor(), 4);
+
+        input.insert("e");
+        assert_eq!(input.get(), "abcd");
+
+        input.key(Scancode::Home);
+        input.key(Scancode::Delete);
+        input.insert("xyz");
+        assert_eq!(input.get(), "xbcd");
+        assert_eq!(input.cursor(), 1);
+    }
+
+    #[test]
+    fn composition() {
+        let mut input = TextInput::new();
+        input.start("", 0);
+
+        input.set_composition("ni", 2);
+        assert!(!input.key(Scancode::Backspace));
+        assert_eq!(input.composition, "ni");
+
+        input.insert("に");
+        assert_eq!(input.get(), "に");
+        assert_eq!(input.cursor(), 1);
+        assert!(input.composition.is_empty());
+        assert!(input.key(Scancode::Backspace));
+        assert_eq!(input.get(), "");
+    }
+}
@@ -0,0 +1,214 @@
+use config::keys::PX8Key;
+use gfx::{SCREEN_WIDTH, SCREEN_HEIGHT};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TouchPhase {
+    Began = 0,
+    Moved = 1,
+    Stationary = 2,
+    Ended = 3,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Touch {
+    pub id: i64,
+    pub x: i32,
+    pub y: i32,
+    pub phase: TouchPhase,
+}
+
+impl Touch {
+    pub fn is_active(&self) -> bool {
+        self.phase != TouchPhase::Ended
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VirtualPadMode {
+    Off = 0,
+    On = 1,
+    /// Displayed as soon as a touch event is received
+    Auto = 2,
+}
+
+#[derive(Clone, Debug)]
+pub struct VirtualButton {
+    pub key: PX8Key,
+    pub x: i32,
+    pub y: i32,
+    pub radius: i32,
+    pub pressed: bool,
+}
+
+impl VirtualButton {
+    pub fn new(key: PX8Key, x: i32, y: i32, radius: i32) -> VirtualButton {
+        VirtualButton {
+            key: key,
+            x: x,
+            y: y,
+            radius: radius,
+            pressed: false,
+        }
+    }
+
+    pub fn contains(&self, x: i32, y: i32) -> bool {
+        let dx = x - self.x;
+        let dy = y - self.y;
+        dx * dx + dy * dy <= self.radius * self.radius
+    }
+}
+
+/// On-screen D-pad and buttons for the touch screens, driving player 0
+pub struct VirtualPad {
+    pub mode: VirtualPadMode,
+    pub touched: bool,
+    pub buttons: Vec<VirtualButton>,
+}
+
+impl VirtualPad {
+    pub fn new() -> VirtualPad {
+        let mut pad = VirtualPad {
+            mode: VirtualPadMode::Auto,
+            touched: false,
+            buttons: Vec::new(),
+        };
+        pad.reset();
+        pad
+    }
+
+    /// Default layout: D-pad in the bottom left corner of the screen, A/B in the bottom right
+    pub fn reset(&mut self) {
+        self.mode = VirtualPadMode::Auto;
+        self.buttons.clear();
+
+        let (width, height) = (SCREEN_WIDTH as i32, SCREEN_HEIGHT as i32);
+
+        self.buttons.push(VirtualButton::new(PX8Key::Left, 24, height - 50, 14));
+        self.buttons.push(VirtualButton::new(PX8Key::Right, 72, height - 50, 14));
+        self.buttons.push(VirtualButton::new(PX8Key::Up, 48, height - 74, 14));
+        self.buttons.push(VirtualButton::new(PX8Key::Down, 48, height - 26, 14));
+        self.buttons.push(VirtualButton::new(PX8Key::A, width - 64, height - 40, 18));
+        self.buttons.push(VirtualButton::new(PX8Key::B, width - 24, height - 68, 18));
+    }
+
+    pub fn set_mode(&mut self, mode: i32) {
+        self.mode = match mode {
+            0 => VirtualPadMode::Off,
+            1 => VirtualPadMode::On,
+            _ => VirtualPadMode::Auto,
+        };
+    }
+
+    /// Move the button `idx` of the layout
+    pub fn set_button(&mut self, idx: usize, x: i32, y: i32, radius: i32) {
+        if let Some(button) = self.buttons.get_mut(idx) {
+            button.x = x;
+            button.y = y;
+            button.radius = radius;
+        }
+    }
+
+    pub fn visible(&self) -> bool {
+        match self.mode {
+            VirtualPadMode::Off => false,
+            VirtualPadMode::On => true,
+            VirtualPadMode::Auto => self.touched,
+        }
+    }
+
+    /// Compute the state of each button from the active touches, and
+    /// return the buttons that changed (key, pressed)
+    pub fn update(&mut self, touches: &[Touch]) -> Vec<(PX8Key, bool)> {
+        let mut changes = Vec::new();
+
+        if !touches.is_empty() {
+            self.touched = true;
+        }
+
+        let enabled = self.visible();
+
+        for button in self.buttons.iter_mut() {
+            let pressed = enabled &&
+                          touches
+                              .iter()
+                              .any(|touch| touch.is_active() && button.contains(touch.x, touch.y));
+
+            if pressed != button.pressed {
+                button.pressed = pressed;
+                changes.push((button.key, pressed));
+            }
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Players;
+
+    #[test]
+    fn touch_lifecycle() {
+        let mut players = Players::new();
+
+        players.touch(7, 10, 20, TouchPhase::Began, 0.);
+        assert_eq!(players.touch_count(), 1);
+        assert_eq!(players.touches[0].phase, TouchPhase::Began);
+
+        players.update(0.1);
+        assert_eq!(players.touches[0].phase, TouchPhase::Stationary);
+
+        players.touch(7, 15, 25, TouchPhase::Moved, 0.2);
+        assert_eq!((players.touches[0].x, players.touches[0].y), (15, 25));
+
+        players.touch(7, 15, 25, TouchPhase::Ended, 0.3);
+        assert_eq!(players.touch_count(), 1);
+
+        players.update(0.4);
+        assert_eq!(players.touch_count(), 0);
+    }
+
+    #[test]
+    fn virtual_pad_synthesizes_keys() {
+        let mut players = Players::new();
+        let (x, y) = (players.virtual_pad.buttons[4].x, players.virtual_pad.buttons[4].y);
+
+        assert!(!players.virtual_pad.visible());
+
+        players.touch(1, x, y, TouchPhase::Began, 0.);
+        assert!(players.virtual_pad.visible());
+        assert!(players.btn(0, 4));
+
+        players.touch(1, x + 100, y, TouchPhase::Moved, 0.1);
+        assert!(!players.btn(0, 4));
+
+        players.touch(1, x, y, TouchPhase::Moved, 0.2);
+        assert!(players.btn(0, 4));
+
+        players.touch(1, x, y, TouchPhase::Ended, 0.3);
+        assert!(!players.btn(0, 4));
+    }
+
+    #[test]
+    fn virtual_pad_on_the_screen() {
+        let pad = VirtualPad::new();
+
+        for button in pad.buttons.iter() {
+            assert!(button.x - button.radius >= 0);
+            assert!(button.y - button.radius >= 0);
+            assert!(button.x + button.radius < SCREEN_WIDTH as i32);
+            assert!(button.y + button.radius < SCREEN_HEIGHT as i32);
+        }
+    }
+
+    #[test]
+    fn virtual_pad_off() {
+        let mut players = Players::new();
+        let (x, y) = (players.virtual_pad.buttons[0].x, players.virtual_pad.buttons[0].y);
+
+        players.virtual_pad.set_mode(0);
+        players.touch(1, x, y, TouchPhase::Began, 0.);
+        assert!(!players.btn(0, 0));
+    }
+}
@@ -3,12 +3,17 @@ mod fonts;
 use std::fmt;
 
 use unicorn;
+use unicorn::autotile::{self, AutotileRule};
 use std::cmp;
 use std::ptr;
 use num_traits::pow;
 use std::f64;
 use std::f64::consts::PI;
 
+/// Size of the screen of the console, in pixels
+pub const SCREEN_WIDTH: usize = 400;
+pub const SCREEN_HEIGHT: usize = 240;
+
 // Fixed pitch font definition
 #[allow(dead_code)]
 pub struct Font {
@@ -270,6 +275,10 @@ pub struct Screen {
     pub sprites: Vec<Sprite>,
 
     pub map: Vec<u32>,
+    /// Rules of the terrains of the map
+    pub autotiles: Vec<AutotileRule>,
+    /// The sprites or the map were changed, cleared by the history of the editors
+    pub edited: bool,
 
     pub transparency_map: [bool; 256],
 
@@ -296,6 +305,8 @@ impl Screen {
             aspect_ratio: width as f32 / height as f32,
             sprites: Vec::new(),
             map: Vec::new(),
+            autotiles: Vec::new(),
+            edited: false,
             transparency_map: [false; 256],
             color_map: [0; 256],
             color: 0,
@@ -369,6 +380,10 @@ impl Screen {
         self.map = map;
     }
 
+    pub fn set_autotiles(&mut self, autotiles: Vec<AutotileRule>) {
+        self.autotiles = autotiles;
+    }
+
     pub fn set_sprites_flags(&mut self, flags: Vec<u8>) {
         if flags.len() != self.sprites.len() {
             error!("Invalid number of flags {:?} --> {:?}",
@@ -476,6 +491,7 @@ impl Screen {
         let idx_sprite = (x / 8) + 50 * (y / 8);
         let sprite = &mut self.sprites[idx_sprite as usize];
         sprite.set_data(((x % 8) + (y % 8) * 8) as usize, col as u8);
+        self.edited = true;
     }
 
     pub fn fget(&mut self, idx: u32, v: u8) -> bool {
@@ -497,12 +513,14 @@ impl Screen {
     pub fn fset(&mut self, idx: u32, flag: u8, value: bool) {
         if (idx as usize) < self.sprites.len() {
             self.sprites[idx as usize].set_flag(flag, value);
+            self.edited = true;
         }
     }
 
     pub fn fset_all(&mut self, idx: u32, flags: u8) {
         if (idx as usize) < self.sprites.len() {
             self.sprites[idx as usize].set_flags(flags);
+            self.edited = true;
         }
     }
 
@@ -1063,8 +1081,7 @@ impl Screen {
 
                 //debug!("MAP X {:?} MAP Y {:?}", map_x, map_y);
 
-                let idx_sprite: u32 = *self.map.get(((map_x as usize) % unicorn::MAP_WIDTH) * unicorn::MAP_WIDTH + (map_y as usize) % unicorn::MAP_HEIGHT).unwrap_or(&0);
-                //self.map[(map_x as usize) % unicorn::MAP_WIDTH][(map_y as usize) % unicorn::MAP_HEIGHT];
+                let idx_sprite: u32 = *self.map.get((map_x as usize) % unicorn::MAP_WIDTH + ((map_y as usize) % unicorn::MAP_HEIGHT) * unicorn::MAP_WIDTH).unwrap_or(&0);
 
                 // Skip the sprite 0
                 if idx_sprite != 0 {
@@ -1106,12 +1123,11 @@ impl Screen {
             return 0;
         }
 
-        if x as usize > unicorn::MAP_WIDTH || y as usize >= unicorn::MAP_HEIGHT {
+        if x as usize >= unicorn::MAP_WIDTH || y as usize >= unicorn::MAP_HEIGHT {
             return 0;
         }
 
-        0
-        //self.map[x as usize][y as usize]
+        *self.map.get(x as usize + y as usize * unicorn::MAP_WIDTH).unwrap_or(&0)
     }
 
     pub fn mset(&mut self, x: i32, y: i32, v: u32) {
@@ -1121,11 +1137,32 @@ impl Screen {
             return;
         }
 
-        if x as usize > unicorn::MAP_WIDTH || y as usize >= unicorn::MAP_HEIGHT {
+        if x as usize >= unicorn::MAP_WIDTH || y as usize >= unicorn::MAP_HEIGHT {
             return;
         }
 
-  //      self.map[x as usize][y as usize] = v;
+        // The map of the cartridge can be missing or shorter
+        if self.map.len() < unicorn::MAP_WIDTH * unicorn::MAP_HEIGHT {
+            self.map.resize(unicorn::MAP_WIDTH * unicorn::MAP_HEIGHT, 0);
+        }
+
+        self.map[x as usize + y as usize * unicorn::MAP_WIDTH] = v;
+        self.edited = true;
+    }
+
+    /// Choose the sprites of the terrains of a region of the map, and the
+    /// cells around it, with their neighbors
+    pub fn autotile(&mut self, x: i32, y: i32, w: i32, h: i32) {
+        if self.map.len() < unicorn::MAP_WIDTH * unicorn::MAP_HEIGHT {
+            self.map.resize(unicorn::MAP_WIDTH * unicorn::MAP_HEIGHT, 0);
+        }
+
+        autotile::resolve(&mut self.map,
+                          unicorn::MAP_WIDTH as i32,
+                          unicorn::MAP_HEIGHT as i32,
+                          &self.autotiles,
+                          (x, y, w, h));
+        self.edited = true;
     }
 
     pub fn sspr(&mut self,
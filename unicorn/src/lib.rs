@@ -8,6 +8,16 @@ extern crate cpython;
 #[cfg(feature = "duktape")]
 extern crate duktape;
 
+#[cfg(feature = "wasm")]
+extern crate wasmi;
+#[cfg(feature = "wasm")]
+extern crate parity_wasm;
+#[cfg(feature = "wasm")]
+extern crate base64;
+
+#[cfg(feature = "dylib")]
+extern crate libloading;
+
 extern crate chiptune;
 
 extern crate nalgebra;
@@ -30,6 +40,7 @@ extern crate syntect;
 extern crate gapbuffer;
 extern crate tempdir;
 extern crate unicode_width;
+extern crate xml;
 
 #[macro_use]
 extern crate log;
@@ -54,6 +65,10 @@ pub mod cartridge;
 pub mod plugins;
 pub mod sound;
 
+// The tests of the text editor import its modules from the root, like in iota
+#[cfg(test)]
+use unicorn::editor::text_editor::{buffer, command, textobject, view};
+
 include!(concat!(env!("OUT_DIR"), "/parameters.rs"));
 
 // #[cfg(target_os="android")]
@@ -0,0 +1,1808 @@
+use std::fmt;
+use std::mem;
+use std::sync::{Arc, Mutex};
+#[cfg(test)]
+use std::sync::mpsc;
+
+use time;
+
+use config::Players;
+use gfx::Screen;
+use plugins::scheduler;
+use sound::sound::Sound;
+use unicorn::info::Info;
+use unicorn::noise::Noise;
+use unicorn::{Palettes, UnicornConfig};
+
+/// Object called by a function of the API, used to group the reference
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Sound,
+    Screen,
+    Map,
+    Players,
+    Noise,
+    Palettes,
+    System,
+}
+
+/// The scripting API, shared by the Lua, Python and Javascript plugins.
+///
+/// Each function is described by its signature: `name(arg: type = default, ...) -> type`,
+/// the types are `int`, `float`, `bool`, `str`, `list` (of integers) and `any`.
+/// The arguments with a default value are optional, `nil` means that the value
+/// is computed by the function itself.
+/// Each function is implemented by its handler, in the module `handlers`.
+const API: [(Target, &'static str, &'static str, Handler); 78] =
+    [(Target::Sound,
+      "music(id: int = -1, filename: str = \"\", loops: int = 0, start_position: int = 0, \
+       channel: int = -1)",
+      "Play a music, from the cartridge (id) or from a file",
+      handlers::music),
+     (Target::Sound,
+      "sfx(id: int = -1, filename: str = \"\", note: int = 13312, panning: int = 64, \
+       rate: int = 50, loops: int = 0, channel: int = -1, priority: int = 0)",
+      "Play a sound effect, a busy channel is stolen only by a higher priority",
+      handlers::sfx),
+     (Target::Sound, "music_stop()", "Stop the music", handlers::music_stop),
+     (Target::Sound, "music_pause()", "Pause the music", handlers::music_pause),
+     (Target::Sound, "music_resume()", "Resume the music", handlers::music_resume),
+     (Target::Sound,
+      "music_position() -> int",
+      "Position in the current music",
+      handlers::music_position),
+     (Target::Sound,
+      "music_volume(volume: int)",
+      "Volume of the music bus (0-128)",
+      handlers::music_volume),
+     (Target::Sound,
+      "sfx_volume(volume: int)",
+      "Volume of the sound effects bus (0-128)",
+      handlers::sfx_volume),
+     (Target::Sound,
+      "master_volume(volume: int)",
+      "Volume of all the sounds (0-128)",
+      handlers::master_volume),
+     (Target::Sound,
+      "channel_volume(chan: int, volume: int)",
+      "Volume of a channel (0-128)",
+      handlers::channel_volume),
+     (Target::Sound,
+      "channel_panning(chan: int, panning: int = -1)",
+      "Panning of a channel (0 left - 255 right), -1 to use the panning of the sound",
+      handlers::channel_panning),
+     (Target::Sound,
+      "channel_reserve(chan: int, reserved: bool = true)",
+      "Reserve a channel, so it is only used when it is explicitly requested",
+      handlers::channel_reserve),
+     (Target::Sound,
+      "music_ducking(volume: int = 128, priority: int = 1)",
+      "Lower the music to volume while a sound effect with this priority is playing",
+      handlers::music_ducking),
+
+     (Target::Screen,
+      "camera(x: int = -1, y: int = -1)",
+      "Set the camera offset",
+      handlers::camera),
+     (Target::Screen,
+      "circ(x: int, y: int, r: int, color: int = -1)",
+      "Draw a circle",
+      handlers::circ),
+     (Target::Screen,
+      "circfill(x: int, y: int, r: int, color: int = -1)",
+      "Draw a filled circle",
+      handlers::circfill),
+     (Target::Screen,
+      "clip(x: int = -1, y: int = -1, w: int = -1, h: int = -1)",
+      "Set the clipping rectangle, reset without arguments",
+      handlers::clip),
+     (Target::Screen, "cls(value: int = -1)", "Clear the screen", handlers::cls),
+     (Target::Screen, "color(color: int)", "Set the default color", handlers::color),
+     (Target::Screen,
+      "ellipse(x: int, y: int, rx: int, ry: int, color: int = -1)",
+      "Draw an ellipse",
+      handlers::ellipse),
+     (Target::Screen,
+      "ellipsefill(x: int, y: int, rx: int, ry: int, color: int = -1)",
+      "Draw a filled ellipse",
+      handlers::ellipsefill),
+     (Target::Screen,
+      "fget(idx: int, flag: int = nil) -> any",
+      "Get a flag of a sprite, or all the flags as a bitfield",
+      handlers::fget),
+     (Target::Screen,
+      "fset(idx: int, flag: int, value: bool = nil)",
+      "Set a flag of a sprite, or all the flags as a bitfield",
+      handlers::fset),
+     (Target::Screen, "font(name: str = \"pico8\")", "Select the font", handlers::font),
+     (Target::Screen,
+      "line(x1: int, y1: int, x2: int, y2: int, color: int = -1)",
+      "Draw a line",
+      handlers::line),
+     (Target::Screen, "mode_width() -> int", "Width of the screen", handlers::mode_width),
+     (Target::Screen, "mode_height() -> int", "Height of the screen", handlers::mode_height),
+     (Target::Screen,
+      "pal(c0: int = -1, c1: int = -1)",
+      "Draw the color c0 with c1, reset without arguments",
+      handlers::pal),
+     (Target::Screen,
+      "palt(c: int = -1, t: bool = false)",
+      "Set the transparency of a color, reset without arguments",
+      handlers::palt),
+     (Target::Screen, "pget(x: int, y: int) -> int", "Color of a pixel", handlers::pget),
+     (Target::Screen,
+      "pset(x: int, y: int, color: int = -1)",
+      "Set the color of a pixel",
+      handlers::pset),
+     (Target::Screen,
+      "print(text: str, x: int = -1, y: int = -1, color: int = -1)",
+      "Print a text",
+      handlers::print),
+     (Target::Screen,
+      "rect(x1: int, y1: int, x2: int, y2: int, color: int = -1)",
+      "Draw a rectangle",
+      handlers::rect),
+     (Target::Screen,
+      "rectfill(x1: int, y1: int, x2: int, y2: int, color: int = -1)",
+      "Draw a filled rectangle",
+      handlers::rectfill),
+     (Target::Screen,
+      "sget(x: int, y: int) -> int",
+      "Color of a pixel of the sprite sheet",
+      handlers::sget),
+     (Target::Screen,
+      "sset(x: int, y: int, color: int = -1)",
+      "Set the color of a pixel of the sprite sheet",
+      handlers::sset),
+     (Target::Screen,
+      "spr(n: int, x: int, y: int, w: int = 1, h: int = 1, flip_x: bool = false, \
+       flip_y: bool = false)",
+      "Draw w*h sprites from the sprite n",
+      handlers::spr),
+     (Target::Screen,
+      "sspr(sx: int, sy: int, sw: int, sh: int, dx: int, dy: int, dw: int = nil, \
+       dh: int = nil, flip_x: bool = false, flip_y: bool = false)",
+      "Draw a part of the sprite sheet, stretched to dw*dh",
+      handlers::sspr),
+     (Target::Screen,
+      "sspr2(sx: int, sy: int, sw: int, sh: int, dx: int, dy: int, angle: float = 0.0, \
+       zoom: float = 1.0, flip_x: bool = false, flip_y: bool = false) -> list",
+      "Draw a part of the sprite sheet rotated and zoomed, returns the drawn size",
+      handlers::sspr2),
+     (Target::Screen,
+      "trigon(x1: int, y1: int, x2: int, y2: int, x3: int, y3: int, color: int = -1)",
+      "Draw a triangle",
+      handlers::trigon),
+     (Target::Screen,
+      "polygon(x: list, y: list, color: int = -1)",
+      "Draw a polygon, with at least 3 points",
+      handlers::polygon),
+     (Target::Screen,
+      "memcpy(dest_addr: int, source_addr: int, len: int)",
+      "Copy a part of the memory",
+      handlers::memcpy),
+
+     (Target::Map,
+      "spr_map(cel_x: int, cel_y: int, sx: int, sy: int, cel_w: int, cel_h: int, \
+       layer: int = 0)",
+      "Draw a part of the map",
+      handlers::spr_map),
+     (Target::Map, "mget(x: int, y: int) -> int", "Sprite of a cell of the map", handlers::mget),
+     (Target::Map,
+      "mset(x: int, y: int, v: int)",
+      "Set the sprite of a cell of the map",
+      handlers::mset),
+     (Target::Map,
+      "autotile(x: int = 0, y: int = 0, w: int = nil, h: int = nil)",
+      "Choose the sprites of the terrains in a part of the map (all by default) with the \
+       autotile rules",
+      handlers::autotile),
+
+     (Target::Players,
+      "btn(x: int, p: int = 0) -> bool",
+      "Is the button x of the player p pressed, x can also be a character of the keyboard",
+      handlers::btn),
+     (Target::Players,
+      "btnp(x: int, p: int = 0) -> bool",
+      "Was the button x of the player p just pressed (with key repeat)",
+      handlers::btnp),
+     (Target::Players,
+      "axis(x: int, p: int = 0) -> float",
+      "Value of an analog axis of the player p, between -1.0 and 1.0",
+      handlers::axis),
+     (Target::Players,
+      "rumble(strength: float, duration: int, p: int = 0)",
+      "Rumble the gamepad of the player p, strength between 0.0 and 1.0, duration in ms",
+      handlers::rumble),
+     (Target::Players,
+      "player_count() -> int",
+      "Number of connected players",
+      handlers::player_count),
+     (Target::Players, "touch_count() -> int", "Number of touches", handlers::touch_count),
+     (Target::Players, "touch_id(i: int) -> int", "Id of the touch i", handlers::touch_id),
+     (Target::Players, "touch_x(i: int) -> int", "X position of the touch i", handlers::touch_x),
+     (Target::Players, "touch_y(i: int) -> int", "Y position of the touch i", handlers::touch_y),
+     (Target::Players,
+      "touch_phase(i: int) -> int",
+      "Phase of the touch i: 0 began, 1 moved, 2 stationary, 3 ended",
+      handlers::touch_phase),
+     (Target::Players,
+      "virtual_pad(mode: int = 1)",
+      "Virtual gamepad: 0 hidden, 1 always displayed, 2 displayed after the first touch",
+      handlers::virtual_pad),
+     (Target::Players,
+      "virtual_pad_button(idx: int, x: int, y: int, r: int)",
+      "Move a button of the virtual gamepad",
+      handlers::virtual_pad_button),
+     (Target::Players,
+      "textinput_start(text: str = \"\", max_length: int = 0)",
+      "Start to capture the text typed by the player",
+      handlers::textinput_start),
+     (Target::Players,
+      "textinput_get() -> str",
+      "Text typed by the player",
+      handlers::textinput_get),
+     (Target::Players,
+      "textinput_cursor() -> int",
+      "Position of the cursor in the text",
+      handlers::textinput_cursor),
+     (Target::Players,
+      "textinput_stop() -> str",
+      "Stop to capture the text, returns the text",
+      handlers::textinput_stop),
+     (Target::Players, "mouse_x() -> int", "X position of the mouse", handlers::mouse_x),
+     (Target::Players, "mouse_y() -> int", "Y position of the mouse", handlers::mouse_y),
+     (Target::Players, "mouse_state() -> int", "State of the mouse buttons", handlers::mouse_state),
+     (Target::Players,
+      "mouse_statep() -> int",
+      "State of the mouse buttons, only for the frame they were pressed",
+      handlers::mouse_statep),
+     (Target::Players,
+      "stat(x: int, y: any = nil) -> any",
+      "System information, see the table of `stat` below",
+      handlers::stat),
+
+     (Target::Noise,
+      "noise(x: float, y: float, z: float) -> float",
+      "Perlin noise value",
+      handlers::noise),
+     (Target::Noise, "noise_set_seed(seed: int)", "Seed of the noise", handlers::noise_set_seed),
+
+     (Target::Palettes,
+      "palette(color: int, r: int, g: int, b: int)",
+      "Set the RGB value of a color",
+      handlers::palette),
+     (Target::Palettes,
+      "palette_hexa(color: int, value: int)",
+      "Set the RGB value of a color, as 0xRRGGBB",
+      handlers::palette_hexa),
+     (Target::Palettes, "reset_palette()", "Restore the default palette", handlers::reset_palette),
+     (Target::Palettes,
+      "switch_palette(name: str)",
+      "Switch to a palette by its name",
+      handlers::switch_palette),
+
+     (Target::System,
+      "unicorn_time() -> int",
+      "Time since the start, in ms",
+      handlers::unicorn_time),
+     (Target::System,
+      "unicorn_time_sec() -> float",
+      "Time since the start, in seconds",
+      handlers::unicorn_time_sec),
+     (Target::System,
+      "show_mouse(value: bool = true)",
+      "Display the mouse cursor",
+      handlers::show_mouse),
+     (Target::System,
+      "rnd(x: float = 1.0) -> float",
+      "Random number between 0 and x",
+      handlers::rnd),
+     (Target::System,
+      "ease(name: str, t: float) -> float",
+      "Easing curve of the tweens at t between 0.0 and 1.0, nil for an unknown name",
+      handlers::ease)];
+
+/// Implementation of a function of the API, called with the prepared arguments
+pub type Handler = fn(&ApiContext, &Args) -> ApiValue;
+
+/// Values of `stat(x, y)`, by ranges of x
+const STATS: [(i64, i64, &'static str); 15] =
+    [(0, 0, "Memory used by the virtual machine of the script, in KB (allocated blocks in Python)"),
+     (1, 1, "CPU time of `_update` and `_draw` in the previous frame, as a fraction of a frame"),
+     (2, 2, "CPU time of `_update` in the previous frame, in ms"),
+     (3, 3, "CPU time of `_draw` in the previous frame, in ms"),
+     (7, 7, "Frames per second"),
+     (8, 8, "Target frames per second"),
+     (16, 16, "Is the sound channel y playing"),
+     (24, 24, "Position in the current music"),
+     (28, 28, "Is the key y held, y is a character of the keyboard"),
+     (29, 29, "Was the key y just pressed"),
+     (32, 33, "X and Y position of the mouse"),
+     (34, 35, "State of the mouse buttons, then only for the frame they were pressed"),
+     (40, 40, "Name of the current palette"),
+     (80, 85, "UTC date and time: year, month, day, hour, minute and second"),
+     (90, 95, "Local date and time: year, month, day, hour, minute and second")];
+
+/// Field of a date for `stat`, from 0 (year) to 5 (second)
+fn date_field(tm: &time::Tm, field: i64) -> i64 {
+    let value = match field {
+        0 => tm.tm_year + 1900,
+        1 => tm.tm_mon + 1,
+        2 => tm.tm_mday,
+        3 => tm.tm_hour,
+        4 => tm.tm_min,
+        5 => tm.tm_sec,
+        _ => 0,
+    };
+    value as i64
+}
+
+/// Python builtins that can't be shadowed by the API
+const PYTHON_RENAMED: [(&'static str, &'static str); 1] = [("print", "unicorn_print")];
+
+/// Positional order of the Lua wrappers that differs from the table: the Lua
+/// cartridges already give the channel before the other options
+const LUA_ORDER: [(&'static str, &'static [&'static str]); 2] =
+    [("music", &["id", "filename", "channel", "loops", "start_position"]),
+     ("sfx", &["id", "filename", "channel", "note", "panning", "rate", "loops", "priority"])];
+
+lazy_static! {
+    pub static ref FUNCTIONS: Vec<ApiFunction> = API.iter()
+        .map(|&(target, signature, doc, handler)| {
+            match ApiFunction::parse(target, signature, doc, handler) {
+                Ok(function) => function,
+                Err(err) => panic!("[PLUGIN][API] {}", err),
+            }
+        })
+        .collect();
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ApiType {
+    Nil,
+    Int,
+    Float,
+    Bool,
+    Str,
+    List,
+    Any,
+}
+
+impl ApiType {
+    pub fn parse(value: &str) -> Result<ApiType, String> {
+        match value {
+            "nil" => Ok(ApiType::Nil),
+            "int" => Ok(ApiType::Int),
+            "float" => Ok(ApiType::Float),
+            "bool" => Ok(ApiType::Bool),
+            "str" => Ok(ApiType::Str),
+            "list" => Ok(ApiType::List),
+            "any" => Ok(ApiType::Any),
+            _ => Err(format!("Unknown type {:?}", value)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ApiType::Nil => "nil",
+            ApiType::Int => "int",
+            ApiType::Float => "float",
+            ApiType::Bool => "bool",
+            ApiType::Str => "str",
+            ApiType::List => "list",
+            ApiType::Any => "any",
+        }
+    }
+}
+
+/// Value exchanged between the scripts and the API
+#[derive(Clone, Debug, PartialEq)]
+pub enum ApiValue {
+    Nil,
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Str(String),
+    List(Vec<i64>),
+}
+
+impl ApiValue {
+    /// Parse a default value of the API description
+    pub fn parse(kind: ApiType, value: &str) -> Result<ApiValue, String> {
+        if value == "nil" {
+            return Ok(ApiValue::Nil);
+        }
+
+        match kind {
+            ApiType::Int => {
+                value
+                    .parse::<i64>()
+                    .map(ApiValue::Int)
+                    .map_err(|e| e.to_string())
+            }
+            ApiType::Float => {
+                value
+                    .parse::<f64>()
+                    .map(ApiValue::Float)
+                    .map_err(|e| e.to_string())
+            }
+            ApiType::Bool => {
+                match value {
+                    "true" => Ok(ApiValue::Bool(true)),
+                    "false" => Ok(ApiValue::Bool(false)),
+                    _ => Err(format!("Invalid boolean {:?}", value)),
+                }
+            }
+            ApiType::Str => {
+                if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+                    Ok(ApiValue::Str(value[1..value.len() - 1].to_string()))
+                } else {
+                    Err(format!("Invalid string {:?}", value))
+                }
+            }
+            _ => Err(format!("No default value for the type {:?}", kind)),
+        }
+    }
+
+    /// Convert a value received from a script to the type of the argument
+    pub fn coerce(self, kind: ApiType) -> ApiValue {
+        match (kind, self) {
+            (_, ApiValue::Nil) => ApiValue::Nil,
+            (ApiType::Int, ApiValue::Float(v)) => ApiValue::Int(v.floor() as i64),
+            (ApiType::Int, ApiValue::Bool(v)) => ApiValue::Int(v as i64),
+            (ApiType::Float, ApiValue::Int(v)) => ApiValue::Float(v as f64),
+            (ApiType::Bool, ApiValue::Int(v)) => ApiValue::Bool(v != 0),
+            (ApiType::Bool, ApiValue::Float(v)) => ApiValue::Bool(v != 0.),
+            (ApiType::Str, ApiValue::Int(v)) => ApiValue::Str(v.to_string()),
+            (ApiType::Str, ApiValue::Float(v)) => ApiValue::Str(v.to_string()),
+            (ApiType::Str, ApiValue::Bool(v)) => ApiValue::Str(v.to_string()),
+            (ApiType::List, ApiValue::Str(v)) => {
+                // The Javascript plugin can't exchange arrays
+                ApiValue::List(v.split(',')
+                                   .filter_map(|v| v.trim().parse::<f64>().ok())
+                                   .map(|v| v.floor() as i64)
+                                   .collect())
+            }
+            (_, value) => value,
+        }
+    }
+}
+
+impl fmt::Display for ApiValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ApiValue::Nil => write!(f, "nil"),
+            ApiValue::Int(v) => write!(f, "{}", v),
+            ApiValue::Float(v) => write!(f, "{:?}", v),
+            ApiValue::Bool(v) => write!(f, "{}", v),
+            ApiValue::Str(ref v) => write!(f, "{:?}", v),
+            ApiValue::List(ref v) => write!(f, "{:?}", v),
+        }
+    }
+}
+
+pub struct ApiArg {
+    pub name: String,
+    pub kind: ApiType,
+    /// None for a required argument
+    pub default: Option<ApiValue>,
+}
+
+pub struct ApiFunction {
+    pub name: String,
+    pub target: Target,
+    pub args: Vec<ApiArg>,
+    pub ret: ApiType,
+    pub doc: &'static str,
+    pub handler: Handler,
+}
+
+impl ApiFunction {
+    pub fn parse(target: Target,
+                 signature: &str,
+                 doc: &'static str,
+                 handler: Handler)
+                 -> Result<ApiFunction, String> {
+        let open = try!(signature
+                            .find('(')
+                            .ok_or(format!("Missing '(' in {:?}", signature)));
+        let close = try!(signature
+                             .rfind(')')
+                             .ok_or(format!("Missing ')' in {:?}", signature)));
+
+        let name = signature[..open].trim().to_string();
+
+        let mut args: Vec<ApiArg> = Vec::new();
+        for arg in signature[open + 1..close].split(',') {
+            let arg = arg.trim();
+            if arg.is_empty() {
+                continue;
+            }
+
+            let (declaration, default) = match arg.find('=') {
+                Some(pos) => (&arg[..pos], Some(arg[pos + 1..].trim())),
+                None => (arg, None),
+            };
+
+            let mut declaration = declaration.split(':');
+            let arg_name = declaration.next().unwrap_or("").trim().to_string();
+            let kind = try!(ApiType::parse(declaration.next().unwrap_or("").trim()));
+
+            let default = match default {
+                Some(value) => Some(try!(ApiValue::parse(kind, value))),
+                None => None,
+            };
+
+            if default.is_none() && args.iter().any(|arg| arg.default.is_some()) {
+                return Err(format!("{}: required argument {:?} after an optional one",
+                                   name,
+                                   arg_name));
+            }
+
+            args.push(ApiArg {
+                          name: arg_name,
+                          kind: kind,
+                          default: default,
+                      });
+        }
+
+        let ret = signature[close + 1..].trim();
+        let ret = if ret.is_empty() {
+            ApiType::Nil
+        } else if ret.starts_with("->") {
+            try!(ApiType::parse(ret[2..].trim()))
+        } else {
+            return Err(format!("Invalid return type in {:?}", signature));
+        };
+
+        Ok(ApiFunction {
+               name: name,
+               target: target,
+               args: args,
+               ret: ret,
+               doc: doc,
+               handler: handler,
+           })
+    }
+
+    pub fn signature(&self) -> String {
+        self.signature_in(&(0..self.args.len()).collect::<Vec<usize>>())
+    }
+
+    pub fn lua_signature(&self) -> String {
+        self.signature_in(&self.lua_order())
+    }
+
+    fn signature_in(&self, order: &[usize]) -> String {
+        let args: Vec<String> = order
+            .iter()
+            .map(|&idx| &self.args[idx])
+            .map(|arg| match arg.default {
+                     Some(ref value) => format!("{}: {} = {}", arg.name, arg.kind.name(), value),
+                     None => format!("{}: {}", arg.name, arg.kind.name()),
+                 })
+            .collect();
+
+        match self.ret {
+            ApiType::Nil => format!("{}({})", self.name, args.join(", ")),
+            ret => format!("{}({}) -> {}", self.name, args.join(", "), ret.name()),
+        }
+    }
+
+    /// Fill the missing arguments with their default value and convert them,
+    /// in place
+    pub fn prepare(&self, mut values: Vec<ApiValue>) -> Args {
+        values.resize(self.args.len(), ApiValue::Nil);
+
+        for (value, arg) in values.iter_mut().zip(self.args.iter()) {
+            *value = match mem::replace(value, ApiValue::Nil) {
+                ApiValue::Nil => arg.default.clone().unwrap_or(ApiValue::Nil),
+                value => value.coerce(arg.kind),
+            };
+        }
+
+        Args { values: values }
+    }
+
+    fn arg_names(&self) -> Vec<&str> {
+        self.args.iter().map(|arg| arg.name.as_str()).collect()
+    }
+
+    /// Indexes of the arguments, in the order of the parameters of the Lua wrapper
+    fn lua_order(&self) -> Vec<usize> {
+        match LUA_ORDER.iter().find(|&&(name, _)| name == self.name) {
+            Some(&(_, names)) => {
+                names
+                    .iter()
+                    .filter_map(|name| self.args.iter().position(|arg| arg.name == *name))
+                    .collect()
+            }
+            None => (0..self.args.len()).collect(),
+        }
+    }
+}
+
+/// Arguments of a call, the accessors return a zero value for the missing ones
+pub struct Args {
+    values: Vec<ApiValue>,
+}
+
+impl Args {
+    pub fn get(&self, idx: usize) -> &ApiValue {
+        &self.values[idx]
+    }
+
+    pub fn is_nil(&self, idx: usize) -> bool {
+        self.values[idx] == ApiValue::Nil
+    }
+
+    pub fn int(&self, idx: usize) -> i64 {
+        match self.values[idx] {
+            ApiValue::Int(v) => v,
+            ApiValue::Float(v) => v.floor() as i64,
+            ApiValue::Bool(v) => v as i64,
+            _ => 0,
+        }
+    }
+
+    pub fn float(&self, idx: usize) -> f64 {
+        match self.values[idx] {
+            ApiValue::Int(v) => v as f64,
+            ApiValue::Float(v) => v,
+            _ => 0.,
+        }
+    }
+
+    pub fn bool(&self, idx: usize) -> bool {
+        match self.values[idx] {
+            ApiValue::Bool(v) => v,
+            ApiValue::Int(v) => v != 0,
+            _ => false,
+        }
+    }
+
+    pub fn string(&self, idx: usize) -> String {
+        match self.values[idx] {
+            ApiValue::Str(ref v) => v.clone(),
+            _ => "".to_string(),
+        }
+    }
+
+    pub fn list(&self, idx: usize) -> Vec<i32> {
+        match self.values[idx] {
+            ApiValue::List(ref v) => v.iter().map(|v| *v as i32).collect(),
+            _ => Vec::new(),
+        }
+    }
+}
+
+pub fn find(name: &str) -> Option<usize> {
+    FUNCTIONS.iter().position(|function| function.name == name)
+}
+
+/// Objects used by the API, cloned into each plugin
+#[derive(Clone)]
+pub struct ApiContext {
+    pub palettes: Arc<Mutex<Palettes>>,
+    pub players: Arc<Mutex<Players>>,
+    pub info: Arc<Mutex<Info>>,
+    pub screen: Arc<Mutex<Screen>>,
+    pub sound: Arc<Mutex<Sound>>,
+    pub noise: Arc<Mutex<Noise>>,
+    pub config: Arc<Mutex<UnicornConfig>>,
+}
+
+impl ApiContext {
+    pub fn new(palettes: Arc<Mutex<Palettes>>,
+               players: Arc<Mutex<Players>>,
+               info: Arc<Mutex<Info>>,
+               screen: Arc<Mutex<Screen>>,
+               sound: Arc<Mutex<Sound>>,
+               noise: Arc<Mutex<Noise>>,
+               config: Arc<Mutex<UnicornConfig>>)
+               -> ApiContext {
+        ApiContext {
+            palettes: palettes,
+            players: players,
+            info: info,
+            screen: screen,
+            sound: sound,
+            noise: noise,
+            config: config,
+        }
+    }
+
+    /// Value `x` of the table `STATS`
+    fn stat(&self, x: i64, y: &ApiValue) -> ApiValue {
+        match x {
+            0 => {
+                let (memory, sample_memory) = {
+                    let info = self.info.lock().unwrap();
+                    (info.memory, info.sample_memory)
+                };
+                ApiValue::Float(sample_memory.map_or(memory, |sample| sample()))
+            }
+            1 => {
+                let info = self.info.lock().unwrap();
+                let frame_time = 1000.0 / scheduler::FRAMES_PER_SECOND as f64;
+                ApiValue::Float((info.update_time + info.draw_time) / frame_time)
+            }
+            2 => ApiValue::Float(self.info.lock().unwrap().update_time),
+            3 => ApiValue::Float(self.info.lock().unwrap().draw_time),
+            7 => ApiValue::Float(self.info.lock().unwrap().fps),
+            8 => ApiValue::Int(scheduler::FRAMES_PER_SECOND as i64),
+            16 => {
+                let chan = match *y {
+                    ApiValue::Int(chan) => chan as i32,
+                    ApiValue::Float(chan) => chan as i32,
+                    _ => -1,
+                };
+                ApiValue::Bool(self.sound.lock().unwrap().is_channel_playing(chan))
+            }
+            24 => ApiValue::Int(self.sound.lock().unwrap().chiptune_get_position() as i64),
+            28 | 29 => {
+                let key = match *y {
+                    ApiValue::Str(ref key) => key.to_uppercase(),
+                    ApiValue::Int(key) => key.to_string(),
+                    _ => "".to_string(),
+                };
+
+                let players = self.players.lock().unwrap();
+                let keys = if x == 28 { &players.akeys } else { &players.akeys_quick };
+                let value = !key.is_empty() &&
+                            keys.iter().any(|(scancode, pressed)| *pressed && scancode.name() == key);
+                ApiValue::Bool(value)
+            }
+            32 => ApiValue::Int(self.players.lock().unwrap().mouse.x as i64),
+            33 => ApiValue::Int(self.players.lock().unwrap().mouse.y as i64),
+            34 => ApiValue::Int(self.players.lock().unwrap().mouse.state as i64),
+            35 => ApiValue::Int(self.players.lock().unwrap().mouse.state_quick as i64),
+            40 => ApiValue::Str(self.palettes.lock().unwrap().name.clone()),
+            80...85 => ApiValue::Int(date_field(&time::now_utc(), x - 80)),
+            90...95 => ApiValue::Int(date_field(&time::now(), x - 90)),
+            _ => ApiValue::Int(0),
+        }
+    }
+
+    /// Call the function `idx` of `FUNCTIONS`
+    pub fn call(&self, idx: usize, values: Vec<ApiValue>) -> ApiValue {
+        let function = match FUNCTIONS.get(idx) {
+            Some(function) => function,
+            None => {
+                warn!("[PLUGIN][API] Unknown function {:?}", idx);
+                return ApiValue::Nil;
+            }
+        };
+
+        (function.handler)(self, &function.prepare(values))
+    }
+}
+
+/// Implementation of the functions of `API`, called with the prepared arguments
+mod handlers {
+    use rand;
+    use rand::Rng;
+
+    use config::touch::Touch;
+    use plugins::scheduler;
+    use sound::sound::{BUS_MASTER, BUS_MUSIC, BUS_SFX};
+    use unicorn;
+
+    use super::{ApiContext, ApiValue, Args};
+
+    // Sound
+
+    pub fn music(c: &ApiContext, a: &Args) -> ApiValue {
+        c.sound
+            .lock()
+            .unwrap()
+            .music(a.int(0) as i32,
+                   a.string(1),
+                   a.int(4) as i32,
+                   a.int(2) as i32,
+                   a.int(3) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn sfx(c: &ApiContext, a: &Args) -> ApiValue {
+        c.sound
+            .lock()
+            .unwrap()
+            .sfx_priority(a.int(0) as i32,
+                          a.string(1),
+                          a.int(6) as i32,
+                          a.int(2) as u16,
+                          a.int(3) as i32,
+                          a.int(4) as i32,
+                          a.int(5) as i32,
+                          a.int(7) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn music_stop(c: &ApiContext, _a: &Args) -> ApiValue {
+        c.sound.lock().unwrap().music_stop();
+        ApiValue::Nil
+    }
+
+    pub fn music_pause(c: &ApiContext, _a: &Args) -> ApiValue {
+        c.sound.lock().unwrap().music_pause();
+        ApiValue::Nil
+    }
+
+    pub fn music_resume(c: &ApiContext, _a: &Args) -> ApiValue {
+        c.sound.lock().unwrap().music_resume();
+        ApiValue::Nil
+    }
+
+    pub fn music_position(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.sound.lock().unwrap().chiptune_get_position() as i64)
+    }
+
+    pub fn music_volume(c: &ApiContext, a: &Args) -> ApiValue {
+        c.sound.lock().unwrap().bus_volume(BUS_MUSIC, a.int(0) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn sfx_volume(c: &ApiContext, a: &Args) -> ApiValue {
+        c.sound.lock().unwrap().bus_volume(BUS_SFX, a.int(0) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn master_volume(c: &ApiContext, a: &Args) -> ApiValue {
+        c.sound.lock().unwrap().bus_volume(BUS_MASTER, a.int(0) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn channel_volume(c: &ApiContext, a: &Args) -> ApiValue {
+        c.sound
+            .lock()
+            .unwrap()
+            .channel_volume(a.int(0) as i32, a.int(1) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn channel_panning(c: &ApiContext, a: &Args) -> ApiValue {
+        c.sound
+            .lock()
+            .unwrap()
+            .channel_panning(a.int(0) as i32, a.int(1) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn channel_reserve(c: &ApiContext, a: &Args) -> ApiValue {
+        c.sound
+            .lock()
+            .unwrap()
+            .channel_reserve(a.int(0) as i32, a.bool(1));
+        ApiValue::Nil
+    }
+
+    pub fn music_ducking(c: &ApiContext, a: &Args) -> ApiValue {
+        c.sound
+            .lock()
+            .unwrap()
+            .music_ducking(a.int(0) as i32, a.int(1) as i32);
+        ApiValue::Nil
+    }
+
+    // Screen
+
+    pub fn camera(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .camera(a.int(0) as i32, a.int(1) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn circ(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .circ(a.int(0) as i32,
+                  a.int(1) as i32,
+                  a.int(2) as i32,
+                  a.int(3) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn circfill(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .circfill(a.int(0) as i32,
+                      a.int(1) as i32,
+                      a.int(2) as i32,
+                      a.int(3) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn clip(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .clip(a.int(0) as i32,
+                  a.int(1) as i32,
+                  a.int(2) as i32,
+                  a.int(3) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn cls(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen.lock().unwrap().cls(a.int(0) as i8);
+        ApiValue::Nil
+    }
+
+    pub fn color(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen.lock().unwrap().color(a.int(0) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn ellipse(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .ellipse(a.int(0) as i32,
+                     a.int(1) as i32,
+                     a.int(2) as i32,
+                     a.int(3) as i32,
+                     a.int(4) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn ellipsefill(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .ellipsefill(a.int(0) as i32,
+                         a.int(1) as i32,
+                         a.int(2) as i32,
+                         a.int(3) as i32,
+                         a.int(4) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn fget(c: &ApiContext, a: &Args) -> ApiValue {
+        let mut screen = c.screen.lock().unwrap();
+        if a.is_nil(1) {
+            return ApiValue::Int(screen.fget_all(a.int(0) as u32) as i64);
+        }
+        ApiValue::Bool(screen.fget(a.int(0) as u32, a.int(1) as u8))
+    }
+
+    pub fn fset(c: &ApiContext, a: &Args) -> ApiValue {
+        let mut screen = c.screen.lock().unwrap();
+        if a.is_nil(2) {
+            screen.fset_all(a.int(0) as u32, a.int(1) as u8);
+        } else {
+            screen.fset(a.int(0) as u32, a.int(1) as u8, a.bool(2));
+        }
+        ApiValue::Nil
+    }
+
+    pub fn font(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen.lock().unwrap().font(&a.string(0));
+        ApiValue::Nil
+    }
+
+    pub fn line(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .line(a.int(0) as i32,
+                  a.int(1) as i32,
+                  a.int(2) as i32,
+                  a.int(3) as i32,
+                  a.int(4) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn mode_width(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.screen.lock().unwrap().mode_width() as i64)
+    }
+
+    pub fn mode_height(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.screen.lock().unwrap().mode_height() as i64)
+    }
+
+    pub fn pal(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .pal(a.int(0) as i32, a.int(1) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn palt(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen.lock().unwrap().palt(a.int(0) as i32, a.bool(1));
+        ApiValue::Nil
+    }
+
+    pub fn pget(c: &ApiContext, a: &Args) -> ApiValue {
+        let value = c.screen
+            .lock()
+            .unwrap()
+            .pget(a.int(0) as u32, a.int(1) as u32);
+        ApiValue::Int(value as i64)
+    }
+
+    pub fn pset(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .pset(a.int(0) as i32, a.int(1) as i32, a.int(2) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn print(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .print(a.string(0),
+                   a.int(1) as i32,
+                   a.int(2) as i32,
+                   a.int(3) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn rect(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .rect(a.int(0) as i32,
+                  a.int(1) as i32,
+                  a.int(2) as i32,
+                  a.int(3) as i32,
+                  a.int(4) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn rectfill(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .rectfill(a.int(0) as i32,
+                      a.int(1) as i32,
+                      a.int(2) as i32,
+                      a.int(3) as i32,
+                      a.int(4) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn sget(c: &ApiContext, a: &Args) -> ApiValue {
+        let value = c.screen
+            .lock()
+            .unwrap()
+            .sget(a.int(0) as u32, a.int(1) as u32);
+        ApiValue::Int(value as i64)
+    }
+
+    pub fn sset(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .sset(a.int(0) as u32, a.int(1) as u32, a.int(2) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn spr(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .spr(a.int(0) as u32,
+                 a.int(1) as i32,
+                 a.int(2) as i32,
+                 a.int(3) as u32,
+                 a.int(4) as u32,
+                 a.bool(5),
+                 a.bool(6));
+        ApiValue::Nil
+    }
+
+    pub fn sspr(c: &ApiContext, a: &Args) -> ApiValue {
+        let dw = if a.is_nil(6) { a.int(2) } else { a.int(6) };
+        let dh = if a.is_nil(7) { a.int(3) } else { a.int(7) };
+
+        c.screen
+            .lock()
+            .unwrap()
+            .sspr(a.int(0) as u32,
+                  a.int(1) as u32,
+                  a.int(2) as u32,
+                  a.int(3) as u32,
+                  a.int(4) as i32,
+                  a.int(5) as i32,
+                  dw as u32,
+                  dh as u32,
+                  a.bool(8),
+                  a.bool(9));
+        ApiValue::Nil
+    }
+
+    pub fn sspr2(c: &ApiContext, a: &Args) -> ApiValue {
+        let (dw, dh) = c.screen
+            .lock()
+            .unwrap()
+            .sspr2(a.int(0) as u32,
+                   a.int(1) as u32,
+                   a.int(2) as u32,
+                   a.int(3) as u32,
+                   a.int(4) as i32,
+                   a.int(5) as i32,
+                   a.float(6),
+                   a.float(7),
+                   a.bool(8),
+                   a.bool(9));
+        ApiValue::List(vec![dw as i64, dh as i64])
+    }
+
+    pub fn trigon(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .trigon(a.int(0) as i32,
+                    a.int(1) as i32,
+                    a.int(2) as i32,
+                    a.int(3) as i32,
+                    a.int(4) as i32,
+                    a.int(5) as i32,
+                    a.int(6) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn polygon(c: &ApiContext, a: &Args) -> ApiValue {
+        let vx = a.list(0);
+        let vy = a.list(1);
+
+        if vx.len() != vy.len() || vx.len() < 3 {
+            return ApiValue::Int(-1);
+        }
+
+        c.screen.lock().unwrap().polygon(vx, vy, a.int(2) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn memcpy(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .memcpy(a.int(0) as u32, a.int(1) as u32, a.int(2) as u32);
+        ApiValue::Nil
+    }
+
+    // Map
+
+    pub fn spr_map(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .map(a.int(0) as u32,
+                 a.int(1) as u32,
+                 a.int(2) as i32,
+                 a.int(3) as i32,
+                 a.int(4) as u32,
+                 a.int(5) as u32,
+                 a.int(6) as u8);
+        ApiValue::Nil
+    }
+
+    pub fn mget(c: &ApiContext, a: &Args) -> ApiValue {
+        let value = c.screen
+            .lock()
+            .unwrap()
+            .mget(a.int(0) as i32, a.int(1) as i32);
+        ApiValue::Int(value as i64)
+    }
+
+    pub fn mset(c: &ApiContext, a: &Args) -> ApiValue {
+        c.screen
+            .lock()
+            .unwrap()
+            .mset(a.int(0) as i32, a.int(1) as i32, a.int(2) as u32);
+        ApiValue::Nil
+    }
+
+    pub fn autotile(c: &ApiContext, a: &Args) -> ApiValue {
+        let w = if a.is_nil(2) { unicorn::MAP_WIDTH as i64 } else { a.int(2) };
+        let h = if a.is_nil(3) { unicorn::MAP_HEIGHT as i64 } else { a.int(3) };
+
+        c.screen
+            .lock()
+            .unwrap()
+            .autotile(a.int(0) as i32, a.int(1) as i32, w as i32, h as i32);
+        ApiValue::Nil
+    }
+
+    // Players
+
+    /// `btn` and `btnp`, the button can also be a character of the keyboard
+    fn button(c: &ApiContext, a: &Args, quick: bool) -> ApiValue {
+        let mut players = c.players.lock().unwrap();
+
+        let value = match *a.get(0) {
+            ApiValue::Str(ref s) => {
+                let s = s.chars().next().map(|s| s as i32).unwrap_or(0);
+                if quick { players.btnp2(s) } else { players.btn2(s) }
+            }
+            _ => {
+                if quick {
+                    players.btnp(a.int(1) as u8, a.int(0) as u8)
+                } else {
+                    players.btn(a.int(1) as u8, a.int(0) as u8)
+                }
+            }
+        };
+        ApiValue::Bool(value)
+    }
+
+    pub fn btn(c: &ApiContext, a: &Args) -> ApiValue {
+        button(c, a, false)
+    }
+
+    pub fn btnp(c: &ApiContext, a: &Args) -> ApiValue {
+        button(c, a, true)
+    }
+
+    pub fn axis(c: &ApiContext, a: &Args) -> ApiValue {
+        let value = c.players
+            .lock()
+            .unwrap()
+            .axis(a.int(1) as u8, a.int(0) as u8);
+        ApiValue::Float(value)
+    }
+
+    pub fn rumble(c: &ApiContext, a: &Args) -> ApiValue {
+        c.players
+            .lock()
+            .unwrap()
+            .rumble(a.int(2) as u8, a.float(0) as f32, a.int(1) as u32);
+        ApiValue::Nil
+    }
+
+    pub fn player_count(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.players.lock().unwrap().player_count() as i64)
+    }
+
+    pub fn touch_count(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.players.lock().unwrap().touch_count() as i64)
+    }
+
+    /// Field of the touch `i`, -1 without this touch
+    fn touch(c: &ApiContext, a: &Args, field: fn(&Touch) -> i64) -> ApiValue {
+        let touch = c.players
+            .lock()
+            .unwrap()
+            .get_touch(a.int(0) as usize);
+        ApiValue::Int(touch.map(|touch| field(&touch)).unwrap_or(-1))
+    }
+
+    pub fn touch_id(c: &ApiContext, a: &Args) -> ApiValue {
+        touch(c, a, |touch| touch.id)
+    }
+
+    pub fn touch_x(c: &ApiContext, a: &Args) -> ApiValue {
+        touch(c, a, |touch| touch.x as i64)
+    }
+
+    pub fn touch_y(c: &ApiContext, a: &Args) -> ApiValue {
+        touch(c, a, |touch| touch.y as i64)
+    }
+
+    pub fn touch_phase(c: &ApiContext, a: &Args) -> ApiValue {
+        touch(c, a, |touch| touch.phase as i64)
+    }
+
+    pub fn virtual_pad(c: &ApiContext, a: &Args) -> ApiValue {
+        c.players
+            .lock()
+            .unwrap()
+            .virtual_pad
+            .set_mode(a.int(0) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn virtual_pad_button(c: &ApiContext, a: &Args) -> ApiValue {
+        c.players
+            .lock()
+            .unwrap()
+            .virtual_pad
+            .set_button(a.int(0) as usize,
+                        a.int(1) as i32,
+                        a.int(2) as i32,
+                        a.int(3) as i32);
+        ApiValue::Nil
+    }
+
+    pub fn textinput_start(c: &ApiContext, a: &Args) -> ApiValue {
+        c.players
+            .lock()
+            .unwrap()
+            .text_input
+            .start(&a.string(0), a.int(1) as usize);
+        ApiValue::Nil
+    }
+
+    pub fn textinput_get(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Str(c.players.lock().unwrap().text_input.get())
+    }
+
+    pub fn textinput_cursor(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.players.lock().unwrap().text_input.cursor() as i64)
+    }
+
+    pub fn textinput_stop(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Str(c.players.lock().unwrap().text_input.stop())
+    }
+
+    pub fn mouse_x(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.players.lock().unwrap().mouse_coordinate(0) as i64)
+    }
+
+    pub fn mouse_y(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.players.lock().unwrap().mouse_coordinate(1) as i64)
+    }
+
+    pub fn mouse_state(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.players.lock().unwrap().mouse_state() as i64)
+    }
+
+    pub fn mouse_statep(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.players.lock().unwrap().mouse_state_quick() as i64)
+    }
+
+    pub fn stat(c: &ApiContext, a: &Args) -> ApiValue {
+        c.stat(a.int(0), a.get(1))
+    }
+
+    // Noise
+
+    pub fn noise(c: &ApiContext, a: &Args) -> ApiValue {
+        let value = c.noise
+            .lock()
+            .unwrap()
+            .get(a.float(0), a.float(1), a.float(2));
+        ApiValue::Float(value)
+    }
+
+    pub fn noise_set_seed(c: &ApiContext, a: &Args) -> ApiValue {
+        c.noise.lock().unwrap().set_seed(a.int(0) as u32);
+        ApiValue::Nil
+    }
+
+    // Palettes
+
+    pub fn palette(c: &ApiContext, a: &Args) -> ApiValue {
+        c.palettes
+            .lock()
+            .unwrap()
+            .set_color(a.int(0) as u32,
+                       a.int(1) as u8,
+                       a.int(2) as u8,
+                       a.int(3) as u8);
+        ApiValue::Nil
+    }
+
+    pub fn palette_hexa(c: &ApiContext, a: &Args) -> ApiValue {
+        let value = a.int(1);
+        c.palettes
+            .lock()
+            .unwrap()
+            .set_color(a.int(0) as u32,
+                       ((value & 0xFF0000) >> 16) as u8,
+                       ((value & 0x00FF00) >> 8) as u8,
+                       (value & 0x0000FF) as u8);
+        ApiValue::Nil
+    }
+
+    pub fn reset_palette(c: &ApiContext, _a: &Args) -> ApiValue {
+        c.palettes.lock().unwrap().reset();
+        ApiValue::Nil
+    }
+
+    pub fn switch_palette(c: &ApiContext, a: &Args) -> ApiValue {
+        let name = a.string(0);
+        let mut palettes = c.palettes.lock().unwrap();
+        if palettes.palettes.contains_key(&name) {
+            palettes.switch_to_palette(&name);
+        } else {
+            warn!("[PLUGIN][API] Unknown palette {:?}", name);
+        }
+        ApiValue::Nil
+    }
+
+    // System
+
+    pub fn unicorn_time(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Int(c.info.lock().unwrap().time())
+    }
+
+    pub fn unicorn_time_sec(c: &ApiContext, _a: &Args) -> ApiValue {
+        ApiValue::Float(c.info.lock().unwrap().time_sec())
+    }
+
+    pub fn show_mouse(c: &ApiContext, a: &Args) -> ApiValue {
+        c.config.lock().unwrap().toggle_mouse(a.bool(0));
+        ApiValue::Nil
+    }
+
+    pub fn rnd(_c: &ApiContext, a: &Args) -> ApiValue {
+        ApiValue::Float(rand::thread_rng().gen::<f64>() * a.float(0))
+    }
+
+    pub fn ease(_c: &ApiContext, a: &Args) -> ApiValue {
+        match scheduler::ease(&a.string(0), a.float(1)) {
+            Some(value) => ApiValue::Float(value),
+            None => ApiValue::Nil,
+        }
+    }
+}
+
+/// Context of a new console for the tests of the plugins, with the receiver
+/// of the sound packets
+#[cfg(test)]
+pub fn test_context() -> (ApiContext, mpsc::Receiver<Vec<u8>>) {
+    let (csend, crecv) = mpsc::channel();
+    let mut screen = Screen::new(400, 240);
+    screen.init();
+
+    let context = ApiContext::new(Arc::new(Mutex::new(Palettes::new())),
+                                  Arc::new(Mutex::new(Players::new())),
+                                  Arc::new(Mutex::new(Info::new())),
+                                  Arc::new(Mutex::new(screen)),
+                                  Arc::new(Mutex::new(Sound::new(csend))),
+                                  Arc::new(Mutex::new(Noise::new())),
+                                  Arc::new(Mutex::new(UnicornConfig::new())));
+    (context, crecv)
+}
+
+/// Lua wrappers, calling the native `UnicornObject:api(idx, ...)`
+pub fn lua_bindings() -> String {
+    let mut code = String::new();
+
+    for (idx, function) in FUNCTIONS.iter().enumerate() {
+        let args: Vec<&str> = function
+            .lua_order()
+            .iter()
+            .map(|&arg| function.args[arg].name.as_str())
+            .collect();
+        let args = args.join(", ");
+        let mut call_args = vec![idx.to_string()];
+        call_args.extend(function.arg_names().iter().map(|name| name.to_string()));
+
+        code.push_str(&format!("{} = function({})\n  return UnicornObject:api({})\nend\n\n",
+                               function.name,
+                               args,
+                               call_args.join(", ")));
+    }
+
+    code
+}
+
+/// Python wrappers, calling the native `unicorn_api.call(idx, [...])`
+pub fn python_bindings() -> String {
+    let mut code = String::new();
+
+    for (idx, function) in FUNCTIONS.iter().enumerate() {
+        let args: Vec<String> = function
+            .args
+            .iter()
+            .map(|arg| match arg.default {
+                     Some(_) => format!("{}=None", arg.name),
+                     None => arg.name.clone(),
+                 })
+            .collect();
+
+        code.push_str(&format!("def {}({}):\n    return unicorn_api.call({}, [{}])\n\n",
+                               python_name(&function.name),
+                               args.join(", "),
+                               idx,
+                               function.arg_names().join(", ")));
+    }
+
+    code
+}
+
+pub fn python_name(name: &str) -> String {
+    match PYTHON_RENAMED.iter().find(|&&(from, _)| from == name) {
+        Some(&(_, to)) => to.to_string(),
+        None => name.to_string(),
+    }
+}
+
+/// Javascript wrappers, calling the native `_api(idx, ...)`. The arrays can't be
+/// exchanged with Duktape, so the lists are sent and received as "1,2,3" strings
+pub fn javascript_bindings() -> String {
+    let mut code = String::new();
+
+    for (idx, function) in FUNCTIONS.iter().enumerate() {
+        let mut call_args = vec![idx.to_string()];
+        call_args.extend(function
+                             .args
+                             .iter()
+                             .map(|arg| match arg.kind {
+                                      ApiType::List => format!("({} || []).join(\",\")", arg.name),
+                                      _ => arg.name.clone(),
+                                  }));
+
+        let call = format!("_api({})", call_args.join(", "));
+        let call = match function.ret {
+            ApiType::List => format!("String({}).split(\",\").map(Number)", call),
+            _ => call,
+        };
+
+        code.push_str(&format!("function {}({}) {{\n    return {};\n}}\n\n",
+                               function.name,
+                               function.arg_names().join(", "),
+                               call));
+    }
+
+    code
+}
+
+/// Name and signature of the functions in `language` ("lua", "python",
+/// "javascript"), for the completion of the code editor
+pub fn signatures(language: &str) -> Vec<(String, String)> {
+    FUNCTIONS
+        .iter()
+        .map(|function| {
+            let signature = function.signature();
+            match language {
+                "python" => {
+                    let name = python_name(&function.name);
+                    let signature = format!("{}{}", name, &signature[function.name.len()..]);
+                    (name, signature)
+                }
+                "lua" => (function.name.clone(), function.lua_signature()),
+                _ => (function.name.clone(), signature),
+            }
+        })
+        .collect()
+}
+
+/// Markdown reference of the API, printed by `unicorn-devkit --api`
+pub fn reference() -> String {
+    let targets = [(Target::Sound, "Sound"),
+                   (Target::Screen, "Graphics"),
+                   (Target::Map, "Map"),
+                   (Target::Players, "Input"),
+                   (Target::Noise, "Noise"),
+                   (Target::Palettes, "Palettes"),
+                   (Target::System, "System")];
+
+    let mut reference = String::new();
+    reference.push_str("# Unicorn API\n\n");
+    reference.push_str("The same functions are available in Lua, Python and Javascript.\n");
+    reference.push_str("The optional arguments can be omitted or given as nil/None/undefined.\n");
+
+    for &(target, title) in targets.iter() {
+        reference.push_str(&format!("\n## {}\n\n", title));
+
+        for function in FUNCTIONS.iter().filter(|function| function.target == target) {
+            let python = python_name(&function.name);
+            if python != function.name {
+                reference.push_str(&format!("* `{}`: {} (`{}` in Python)\n",
+                                            function.signature(),
+                                            function.doc,
+                                            python));
+            } else {
+                reference.push_str(&format!("* `{}`: {}\n", function.signature(), function.doc));
+            }
+
+            if function.lua_signature() != function.signature() {
+                reference.push_str(&format!("  * In Lua: `{}`\n", function.lua_signature()));
+            }
+        }
+    }
+
+    reference.push_str("\n## stat\n\n");
+    reference.push_str("Unknown values of x are 0.\n\n");
+    for &(first, last, doc) in STATS.iter() {
+        if first == last {
+            reference.push_str(&format!("* `{}`: {}\n", first, doc));
+        } else {
+            reference.push_str(&format!("* `{}-{}`: {}\n", first, last, doc));
+        }
+    }
+
+    reference
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_signature() {
+        let function = ApiFunction::parse(Target::Screen,
+                                          "spr(n: int, flip_x: bool = false) -> list",
+                                          "",
+                                          handlers::spr)
+                .unwrap();
+
+        assert_eq!(function.name, "spr");
+        assert_eq!(function.args.len(), 2);
+        assert_eq!(function.args[0].default, None);
+        assert_eq!(function.args[1].kind, ApiType::Bool);
+        assert_eq!(function.args[1].default, Some(ApiValue::Bool(false)));
+        assert_eq!(function.ret, ApiType::List);
+        assert_eq!(function.signature(), "spr(n: int, flip_x: bool = false) -> list");
+
+        assert!(ApiFunction::parse(Target::Screen, "spr(n: int = 1, x: int)", "", handlers::spr)
+                    .is_err());
+        assert!(ApiFunction::parse(Target::Screen, "spr(n: integer)", "", handlers::spr).is_err());
+    }
+
+    #[test]
+    fn signatures_by_language() {
+        let lua = signatures("lua");
+        let python = signatures("python");
+        assert_eq!(lua.len(), FUNCTIONS.len());
+
+        let idx = find("print").unwrap();
+        assert_eq!(lua[idx].0, "print");
+        assert_eq!(python[idx].0, "unicorn_print");
+        assert!(python[idx].1.starts_with("unicorn_print("));
+    }
+
+    #[test]
+    fn api_is_valid() {
+        assert_eq!(FUNCTIONS.len(), API.len());
+
+        for (idx, function) in FUNCTIONS.iter().enumerate() {
+            assert_eq!(find(&function.name), Some(idx), "{} is duplicated", function.name);
+
+            let mut order = function.lua_order();
+            order.sort();
+            assert_eq!(order,
+                       (0..function.args.len()).collect::<Vec<usize>>(),
+                       "{} has invalid Lua arguments",
+                       function.name);
+        }
+    }
+
+    /// (name, index) of the wrappers of `code`, the name is on the first line of a
+    /// wrapper and the index is given to `call` on the second one
+    fn registered(code: &str, name_end: &str, call: &str) -> Vec<(String, usize)> {
+        let lines: Vec<&str> = code.lines().collect();
+
+        lines
+            .windows(2)
+            .filter_map(|pair| {
+                let name = pair[0]
+                    .trim_left_matches("def ")
+                    .trim_left_matches("function ")
+                    .split(name_end)
+                    .next()
+                    .unwrap();
+                let start = match pair[1].find(call) {
+                    Some(start) => start + call.len(),
+                    None => return None,
+                };
+                let idx: String = pair[1][start..]
+                    .chars()
+                    .take_while(|c| c.is_digit(10))
+                    .collect();
+                idx.parse().ok().map(|idx| (name.to_string(), idx))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_function_has_its_handler() {
+        let bindings = [("lua", registered(&lua_bindings(), " = ", "UnicornObject:api(")),
+                        ("python", registered(&python_bindings(), "(", "unicorn_api.call(")),
+                        ("javascript", registered(&javascript_bindings(), "(", "_api("))];
+
+        for &(language, ref registered) in bindings.iter() {
+            assert_eq!(registered.len(), API.len());
+
+            for &(_, signature, _, handler) in API.iter() {
+                let name = &signature[..signature.find('(').unwrap()];
+                let name = match language {
+                    "python" => python_name(name),
+                    _ => name.to_string(),
+                };
+
+                match registered.iter().find(|&&(ref registered, _)| *registered == name) {
+                    Some(&(_, idx)) => {
+                        assert!(FUNCTIONS[idx].handler as usize == handler as usize,
+                                "{} is not dispatched to its handler in {}",
+                                name,
+                                language)
+                    }
+                    None => panic!("{} is not registered in {}", name, language),
+                }
+            }
+        }
+
+        // Only the functions sharing an implementation share a handler
+        for (idx, function) in FUNCTIONS.iter().enumerate() {
+            for other in FUNCTIONS[idx + 1..].iter() {
+                assert!(function.handler as usize != other.handler as usize,
+                        "{} and {} have the same handler",
+                        function.name,
+                        other.name);
+            }
+        }
+    }
+
+    #[test]
+    fn lua_order() {
+        let sfx = find("sfx").unwrap();
+        let wrapper = format!("sfx = function(id, filename, channel, note, panning, rate, loops, \
+                               priority)\n  return UnicornObject:api({}, id, filename, note, \
+                               panning, rate, loops, channel, priority)\n",
+                              sfx);
+        assert!(lua_bindings().contains(&wrapper));
+
+        let music = &FUNCTIONS[find("music").unwrap()];
+        assert!(music.lua_signature().starts_with("music(id: int = -1, filename: str = \"\", \
+                                                   channel: int = -1, loops: int = 0"));
+        assert!(music.signature().ends_with("channel: int = -1)"));
+    }
+
+    #[test]
+    fn call_handlers() {
+        let (context, _crecv) = test_context();
+
+        let pset = vec![ApiValue::Int(3), ApiValue::Int(4), ApiValue::Int(7)];
+        context.call(find("pset").unwrap(), pset);
+        assert_eq!(context.call(find("pget").unwrap(), vec![ApiValue::Int(3), ApiValue::Int(4)]),
+                   ApiValue::Int(7));
+
+        let mset = vec![ApiValue::Int(1), ApiValue::Int(2), ApiValue::Int(5)];
+        context.call(find("mset").unwrap(), mset);
+        assert_eq!(context.call(find("mget").unwrap(), vec![ApiValue::Int(1), ApiValue::Int(2)]),
+                   ApiValue::Int(5));
+
+        assert_eq!(context.call(find("mode_width").unwrap(), vec![]), ApiValue::Int(400));
+        assert_eq!(context.call(find("touch_x").unwrap(), vec![ApiValue::Int(0)]),
+                   ApiValue::Int(-1));
+        assert_eq!(context.call(find("polygon").unwrap(), vec![]), ApiValue::Int(-1));
+        assert_eq!(context.call(find("ease").unwrap(), vec![ApiValue::Str("unknown".to_string())]),
+                   ApiValue::Nil);
+        assert_eq!(context.call(FUNCTIONS.len(), vec![]), ApiValue::Nil);
+    }
+
+    #[test]
+    fn stats_are_sorted() {
+        for stats in STATS.windows(2) {
+            assert!(stats[0].0 <= stats[0].1);
+            assert!(stats[0].1 < stats[1].0, "{} overlaps {}", stats[0].0, stats[1].0);
+        }
+    }
+
+    #[test]
+    fn stat_date() {
+        let tm = time::strptime("2018-03-04 05:06:07", "%Y-%m-%d %H:%M:%S").unwrap();
+        let fields: Vec<i64> = (0..6).map(|field| date_field(&tm, field)).collect();
+        assert_eq!(fields, vec![2018, 3, 4, 5, 6, 7]);
+    }
+
+    #[test]
+    fn prepare_arguments() {
+        let function = &FUNCTIONS[find("sspr").unwrap()];
+        let args = function.prepare(vec![ApiValue::Float(1.5),
+                                         ApiValue::Float(-1.5),
+                                         ApiValue::Int(8),
+                                         ApiValue::Int(8),
+                                         ApiValue::Nil,
+                                         ApiValue::Int(0),
+                                         ApiValue::Nil,
+                                         ApiValue::Nil,
+                                         ApiValue::Int(1)]);
+
+        assert_eq!(args.int(0), 1);
+        assert_eq!(args.int(1), -2);
+        assert!(args.is_nil(4));
+        assert!(args.is_nil(6));
+        assert_eq!(args.bool(8), true);
+        assert_eq!(args.bool(9), false);
+
+        let function = &FUNCTIONS[find("polygon").unwrap()];
+        let args = function.prepare(vec![ApiValue::Str("1,2.5,3".to_string())]);
+        assert_eq!(args.list(0), vec![1, 2, 3]);
+        assert_eq!(args.int(2), -1);
+    }
+}
@@ -1,9 +1,104 @@
+#include <stdlib.h>
+
 #include "duktape.h"
 
+/* Size of each allocation, stored before it to count the size of the heap */
+typedef union {
+    duk_size_t size;
+    double align_double;
+    void *align_ptr;
+} duk_rs_header;
+
+static void *duk_rs_alloc(void *udata, duk_size_t size) {
+    duk_rs_header *header;
+
+    if (size == 0) {
+        return NULL;
+    }
+
+    header = (duk_rs_header *) malloc(sizeof(duk_rs_header) + size);
+    if (header == NULL) {
+        return NULL;
+    }
+
+    header->size = size;
+    *((duk_size_t *) udata) += size;
+    return (void *) (header + 1);
+}
+
+static void duk_rs_free(void *udata, void *ptr) {
+    duk_rs_header *header;
+
+    if (ptr == NULL) {
+        return;
+    }
+
+    header = ((duk_rs_header *) ptr) - 1;
+    *((duk_size_t *) udata) -= header->size;
+    free(header);
+}
+
+static void *duk_rs_realloc(void *udata, void *ptr, duk_size_t size) {
+    duk_rs_header *header;
+    duk_size_t old_size;
+
+    if (ptr == NULL) {
+        return duk_rs_alloc(udata, size);
+    }
+
+    if (size == 0) {
+        duk_rs_free(udata, ptr);
+        return NULL;
+    }
+
+    header = ((duk_rs_header *) ptr) - 1;
+    old_size = header->size;
+
+    header = (duk_rs_header *) realloc(header, sizeof(duk_rs_header) + size);
+    if (header == NULL) {
+        return NULL;
+    }
+
+    header->size = size;
+    *((duk_size_t *) udata) += size;
+    *((duk_size_t *) udata) -= old_size;
+    return (void *) (header + 1);
+}
+
 DUK_EXTERNAL duk_context *_duk_create_heap_default(void) {
-    return duk_create_heap_default();
+    duk_context *ctx;
+    duk_size_t *heap_size = (duk_size_t *) calloc(1, sizeof(duk_size_t));
+
+    if (heap_size == NULL) {
+        return NULL;
+    }
+
+    ctx = duk_create_heap(duk_rs_alloc, duk_rs_realloc, duk_rs_free, (void *) heap_size, NULL);
+    if (ctx == NULL) {
+        free(heap_size);
+    }
+    return ctx;
+}
+
+DUK_EXTERNAL void _duk_destroy_heap(duk_context *ctx) {
+    duk_memory_functions funcs;
+
+    duk_get_memory_functions(ctx, &funcs);
+    duk_destroy_heap(ctx);
+    free(funcs.udata);
+}
+
+/* Bytes allocated by the heap */
+DUK_EXTERNAL duk_size_t _duk_heap_size(duk_context *ctx) {
+    duk_memory_functions funcs;
+
+    duk_get_memory_functions(ctx, &funcs);
+    if (funcs.udata == NULL) {
+        return 0;
+    }
+    return *((duk_size_t *) funcs.udata);
 }
 
 DUK_EXTERNAL void _duk_eval_string(duk_context *ctx, const char *src) {
     duk_eval_raw((ctx), (src), 0, 0 /*args*/ | DUK_COMPILE_EVAL | DUK_COMPILE_NOSOURCE | DUK_COMPILE_STRLEN | DUK_COMPILE_NOFILENAME);
-}
\ No newline at end of file
+}
@@ -72,11 +72,16 @@ pub type duk_c_function =
 #[allow(non_snake_case)]
 extern "C" {
     pub fn _duk_create_heap_default() -> duk_context;
+    pub fn _duk_destroy_heap(ctx: duk_context);
+    pub fn _duk_heap_size(ctx: duk_context) -> duk_size_t;
     pub fn _duk_eval_string(ctx: duk_context, src: *const c_char);
     pub fn duk_push_context_dump(ctx: duk_context);
     pub fn duk_eval_raw(ctx: duk_context,
                         src_buffer: *const ::libc::c_char,
                         src_length: duk_size_t, flags: duk_uint_t) -> duk_int_t;
+    pub fn duk_compile_raw(ctx: duk_context,
+                           src_buffer: *const ::libc::c_char,
+                           src_length: duk_size_t, flags: duk_uint_t) -> duk_int_t;
     pub fn duk_get_int(ctx: duk_context, idx: duk_idx_t) -> duk_int_t;
     pub fn duk_destroy_heap(ctx: duk_context);
     pub fn duk_get_top(ctx: duk_context) -> duk_idx_t;
@@ -12,6 +12,7 @@ use types::*;
 use errors::*;
 use encoder::*;
 
+use std::cell::Cell;
 use std::cmp;
 use std::ffi::CString;
 use std::ptr::null_mut;
@@ -22,6 +23,7 @@ use cesu8::{to_cesu8, from_cesu8};
 use std::borrow::Cow;
 use std::marker::PhantomData;
 use std::sync::{Arc, Mutex};
+use std::time::Instant;
 
 macro_rules! assert_stack_height_unchanged {
     ($ctx:ident, $body:block) => {
@@ -51,6 +53,26 @@ pub unsafe fn from_lstring(data: *const i8, len: ffi::duk_size_t) ->
     }
 }
 
+thread_local! {
+    static EXEC_DEADLINE: Cell<Option<Instant>> = Cell::new(None);
+}
+
+/// Abort the scripts of this thread still running after `deadline` with a
+/// RangeError, or never if `None`.  Checked by the interrupt counter of
+/// duktape, see `DUK_USE_EXEC_TIMEOUT_CHECK` in `duk_config.h`.
+pub fn set_exec_deadline(deadline: Option<Instant>) {
+    EXEC_DEADLINE.with(|d| d.set(deadline));
+}
+
+#[doc(hidden)]
+#[no_mangle]
+pub extern "C" fn duktape_rs_exec_timeout_check(_udata: *mut c_void) -> ffi::duk_bool_t {
+    EXEC_DEADLINE.with(|d| match d.get() {
+        Some(deadline) => (Instant::now() >= deadline) as ffi::duk_bool_t,
+        None => 0,
+    })
+}
+
 const RUST_FN_PROP: [i8; 5] = [-1, 'r' as i8, 'f' as i8, 'n' as i8, 0];
 
 pub trait Foo {
@@ -87,6 +109,11 @@ impl Context {
         }
     }
 
+    /// Bytes allocated by the heap of the context
+    pub fn heap_size(&mut self) -> usize {
+        unsafe { ffi::_duk_heap_size(self.C) as usize }
+    }
+
     pub fn eval_string(&mut self, src: String) {
         unsafe {
             let src = CString::new(src).unwrap();
@@ -121,6 +148,30 @@ impl Context {
         }
     }
 
+    /// Compile `code` without running it, to check its syntax
+    pub fn compile_from(&mut self, filename: &str, code: &str) -> DuktapeResult<()> {
+        unsafe {
+            assert_stack_height_unchanged!(self, {
+                ffi::duk_push_lstring(self.C, filename.as_ptr() as *const c_char,
+                                      filename.len() as ffi::duk_size_t);
+                let status = ffi::duk_compile_raw(self.C,
+                                                  code.as_ptr() as *const c_char,
+                                                  code.len() as ffi::duk_size_t,
+                                                  1 |
+                                                  ffi::DUK_COMPILE_NOSOURCE |
+                                                  ffi::DUK_COMPILE_SAFE);
+                // The compiled function or the error
+                let result = if status == ffi::DUK_EXEC_SUCCESS {
+                    Ok(())
+                } else {
+                    self.get_result(status).map(|_| ())
+                };
+                ffi::duk_pop(self.C);
+                result
+            })
+        }
+    }
+
     pub fn eval(&mut self, code: &str) -> DuktapeResult<Value<'static>> {
         self.eval_from("<eval>", code)
     }
@@ -267,7 +318,7 @@ impl Drop for Context {
     if self.owned {
         self.owned = false;
         println!("Deleted the javascript context");
-        unsafe { ffi::duk_destroy_heap(self.C) }
+        unsafe { ffi::_duk_destroy_heap(self.C) }
     }
   }
 }
@@ -0,0 +1,384 @@
+//! Native Rust cartridges, built as a `cdylib` and loaded by the `__rust__` section.
+//!
+//! The section contains the path of the library, relative to the cartridge. The
+//! library implements `RustPlugin` and exports it with the `unicorn_rust_plugin!`
+//! macro:
+//!
+//! ```ignore
+//! #[macro_use]
+//! extern crate unicorn;
+//!
+//! unicorn_rust_plugin!(MyCartridge::new());
+//! ```
+//!
+//! This is not a stable C ABI: `RustPluginVTable` is only `repr(C)` to be read by
+//! the console, its functions receive pointers to the Rust types of unicorn
+//! (`Screen`, `Players`, `Info`), whose layout depends on the version of unicorn
+//! and of the compiler. The library must be built with the same compiler and the
+//! same version of unicorn. The layout of the vtable and the version of unicorn
+//! are checked when the library is loaded, the compiler can't be.
+//!
+//! The library is reloaded when the file changes, and `init` is called again with
+//! a new plugin. The native cartridges are only loaded with `--unsafe`.
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_void};
+use std::path::{Path, PathBuf};
+
+use config::Players;
+use gfx::Screen;
+use plugins::sandbox::SandboxPolicy;
+use unicorn::RustPlugin;
+use unicorn::info::Info;
+
+/// Incremented when the layout of `RustPluginVTable` changes
+pub const RUST_PLUGIN_ABI_VERSION: u32 = 2;
+
+/// Version of unicorn, compiled in the plugins by `vtable`
+pub const UNICORN_VERSION: &'static str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
+/// Name of the function exported by `unicorn_rust_plugin!`
+pub const RUST_PLUGIN_SYMBOL: &'static [u8] = b"unicorn_rust_plugin\0";
+
+#[repr(C)]
+pub struct RustPluginVTable {
+    pub abi_version: u32,
+    /// `UNICORN_VERSION` of the plugin
+    pub unicorn_version: *const c_char,
+    pub data: *mut c_void,
+    pub init: extern "C" fn(*mut c_void, *mut Screen) -> f64,
+    pub update: extern "C" fn(*mut c_void, *mut Players) -> f64,
+    pub draw: extern "C" fn(*mut c_void, *mut Screen, *mut Info) -> f64,
+    pub destroy: extern "C" fn(*mut c_void),
+}
+
+extern "C" fn plugin_init<T: RustPlugin>(data: *mut c_void, screen: *mut Screen) -> f64 {
+    unsafe { (*(data as *mut T)).init(&mut *screen) }
+}
+
+extern "C" fn plugin_update<T: RustPlugin>(data: *mut c_void, players: *mut Players) -> f64 {
+    unsafe { (*(data as *mut T)).update(&mut *players) }
+}
+
+extern "C" fn plugin_draw<T: RustPlugin>(data: *mut c_void,
+                                         screen: *mut Screen,
+                                         info: *mut Info)
+                                         -> f64 {
+    unsafe { (*(data as *mut T)).draw(&mut *screen, &mut *info) }
+}
+
+extern "C" fn plugin_destroy<T: RustPlugin>(data: *mut c_void) {
+    unsafe {
+        drop(Box::from_raw(data as *mut T));
+    }
+}
+
+/// Wrap a plugin, used by `unicorn_rust_plugin!`
+pub fn vtable<T: RustPlugin>(plugin: T) -> RustPluginVTable {
+    RustPluginVTable {
+        abi_version: RUST_PLUGIN_ABI_VERSION,
+        unicorn_version: UNICORN_VERSION.as_ptr() as *const c_char,
+        data: Box::into_raw(Box::new(plugin)) as *mut c_void,
+        init: plugin_init::<T>,
+        update: plugin_update::<T>,
+        draw: plugin_draw::<T>,
+        destroy: plugin_destroy::<T>,
+    }
+}
+
+/// Check the layout and the version of unicorn of a vtable returned by a library.
+/// With the right layout but another version, the plugin is destroyed.
+pub fn check_vtable(vtable: &RustPluginVTable) -> Result<(), String> {
+    if vtable.abi_version != RUST_PLUGIN_ABI_VERSION {
+        // The data can't be destroyed with an unknown layout
+        return Err(format!("ABI version {}, expected {}",
+                           vtable.abi_version,
+                           RUST_PLUGIN_ABI_VERSION));
+    }
+
+    let version = unsafe { CStr::from_ptr(vtable.unicorn_version) };
+    if version.to_bytes_with_nul() != UNICORN_VERSION.as_bytes() {
+        (vtable.destroy)(vtable.data);
+        return Err(format!("built with unicorn {}, expected {}",
+                           version.to_string_lossy(),
+                           UNICORN_VERSION.trim_right_matches('\0')));
+    }
+
+    Ok(())
+}
+
+/// Path of the library of the `__rust__` section. Even with `--unsafe`, the library
+/// is a file of the cartridge: `None` if it is outside of `root`.
+pub fn library_path(root: &Path, library: &str) -> Option<PathBuf> {
+    SandboxPolicy::new(root).resolve(library)
+}
+
+/// Export a `RustPlugin` from a `cdylib` cartridge
+#[macro_export]
+macro_rules! unicorn_rust_plugin {
+    ($plugin:expr) => {
+        #[no_mangle]
+        pub extern "C" fn unicorn_rust_plugin() -> $crate::plugins::dylib_plugin::RustPluginVTable {
+            $crate::plugins::dylib_plugin::vtable($plugin)
+        }
+    }
+}
+
+#[cfg(feature = "dylib")]
+pub mod plugin {
+    use std::env;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::process;
+    use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+    use std::time::SystemTime;
+
+    use libloading::{Library, Symbol};
+
+    use config::Players;
+    use gfx::Screen;
+    use unicorn::RustPlugin;
+    use unicorn::info::Info;
+
+    use super::{check_vtable, RustPluginVTable, RUST_PLUGIN_SYMBOL};
+
+    /// Number of updates between two checks of the library file
+    const RELOAD_CHECK_FRAMES: u32 = 30;
+
+    static LOADED_COUNT: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    struct LoadedLibrary {
+        vtable: RustPluginVTable,
+        // Dropped after the vtable is destroyed
+        _library: Library,
+        copy: PathBuf,
+    }
+
+    impl Drop for LoadedLibrary {
+        fn drop(&mut self) {
+            (self.vtable.destroy)(self.vtable.data);
+            let _ = fs::remove_file(&self.copy);
+        }
+    }
+
+    fn modified(path: &Path) -> Option<SystemTime> {
+        fs::metadata(path).and_then(|m| m.modified()).ok()
+    }
+
+    fn load_library(path: &Path) -> Result<LoadedLibrary, String> {
+        // Load a copy, so the library can be rebuilt while it is running, and
+        // a new version is not confused with the previous one by the loader
+        let filename = path.file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or("cartridge".to_string());
+        let copy = env::temp_dir().join(format!("unicorn-{}-{}-{}",
+                                                process::id(),
+                                                LOADED_COUNT.fetch_add(1, Ordering::SeqCst),
+                                                filename));
+
+        try!(fs::copy(path, &copy).map_err(|e| format!("{:?}: {}", path, e)));
+
+        let library = match Library::new(&copy) {
+            Ok(library) => library,
+            Err(e) => {
+                let _ = fs::remove_file(&copy);
+                return Err(format!("{:?}: {}", path, e));
+            }
+        };
+
+        let vtable = unsafe {
+            let result: Result<Symbol<extern "C" fn() -> RustPluginVTable>, _> =
+                library.get(RUST_PLUGIN_SYMBOL);
+            match result {
+                Ok(constructor) => constructor(),
+                Err(e) => {
+                    let _ = fs::remove_file(&copy);
+                    return Err(format!("{:?}: {}", path, e));
+                }
+            }
+        };
+
+        if let Err(e) = check_vtable(&vtable) {
+            let _ = fs::remove_file(&copy);
+            return Err(format!("{:?}: {}", path, e));
+        }
+
+        Ok(LoadedLibrary {
+               vtable: vtable,
+               _library: library,
+               copy: copy,
+           })
+    }
+
+    pub struct DylibPlugin {
+        path: PathBuf,
+        modified: Option<SystemTime>,
+        library: LoadedLibrary,
+        frames: u32,
+        need_init: bool,
+    }
+
+    impl DylibPlugin {
+        pub fn load(path: &Path) -> Result<DylibPlugin, String> {
+            info!("[PLUGIN][DYLIB] Load {:?}", path);
+
+            let library = try!(load_library(path));
+
+            Ok(DylibPlugin {
+                   path: path.to_path_buf(),
+                   modified: modified(path),
+                   library: library,
+                   frames: 0,
+                   need_init: false,
+               })
+        }
+
+        fn reload_if_modified(&mut self) {
+            self.frames += 1;
+            if self.frames < RELOAD_CHECK_FRAMES {
+                return;
+            }
+            self.frames = 0;
+
+            let current = modified(&self.path);
+            if current.is_none() || current == self.modified {
+                return;
+            }
+
+            match load_library(&self.path) {
+                Ok(library) => {
+                    info!("[PLUGIN][DYLIB] Reloaded {:?}", self.path);
+                    self.modified = current;
+                    self.library = library;
+                    self.need_init = true;
+                }
+                // Probably still being written, try again later
+                Err(e) => warn!("[PLUGIN][DYLIB] Impossible to reload {}", e),
+            }
+        }
+    }
+
+    impl RustPlugin for DylibPlugin {
+        fn init(&mut self, screen: &mut Screen) -> f64 {
+            self.need_init = false;
+            (self.library.vtable.init)(self.library.vtable.data, screen)
+        }
+
+        fn update(&mut self, players: &mut Players) -> f64 {
+            self.reload_if_modified();
+            if self.need_init {
+                return 0.0;
+            }
+            (self.library.vtable.update)(self.library.vtable.data, players)
+        }
+
+        fn draw(&mut self, screen: &mut Screen, info: &mut Info) -> f64 {
+            if self.need_init {
+                self.init(screen);
+            }
+            (self.library.vtable.draw)(self.library.vtable.data, screen, info)
+        }
+    }
+}
+
+#[cfg(not(feature = "dylib"))]
+pub mod plugin {
+    use std::path::Path;
+
+    use config::Players;
+    use gfx::Screen;
+    use unicorn::RustPlugin;
+    use unicorn::info::Info;
+
+    pub struct DylibPlugin {}
+
+    impl DylibPlugin {
+        pub fn load(path: &Path) -> Result<DylibPlugin, String> {
+            Err(format!("{:?}: dylib plugin disabled", path))
+        }
+    }
+
+    // Keep the compatibility
+    impl RustPlugin for DylibPlugin {
+        fn init(&mut self, _screen: &mut Screen) -> f64 {
+            0.0
+        }
+        fn update(&mut self, _players: &mut Players) -> f64 {
+            0.0
+        }
+        fn draw(&mut self, _screen: &mut Screen, _info: &mut Info) -> f64 {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::sync::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+
+    use tempdir::TempDir;
+
+    use super::*;
+
+    static DESTROYED: AtomicUsize = ATOMIC_USIZE_INIT;
+
+    struct TestPlugin;
+
+    impl RustPlugin for TestPlugin {
+        fn init(&mut self, _screen: &mut Screen) -> f64 {
+            0.0
+        }
+        fn update(&mut self, _players: &mut Players) -> f64 {
+            0.0
+        }
+        fn draw(&mut self, _screen: &mut Screen, _info: &mut Info) -> f64 {
+            0.0
+        }
+    }
+
+    impl Drop for TestPlugin {
+        fn drop(&mut self) {
+            DESTROYED.fetch_add(1, Ordering::SeqCst);
+        }
+    }
+
+    #[test]
+    fn check_the_vtable() {
+        let table = vtable(TestPlugin);
+        assert!(check_vtable(&table).is_ok());
+        (table.destroy)(table.data);
+
+        let destroyed = DESTROYED.load(Ordering::SeqCst);
+
+        let mut table = vtable(TestPlugin);
+        table.abi_version = RUST_PLUGIN_ABI_VERSION + 1;
+        assert!(check_vtable(&table).unwrap_err().starts_with("ABI version"));
+        // Unknown layout, not destroyed
+        assert_eq!(DESTROYED.load(Ordering::SeqCst), destroyed);
+        (table.destroy)(table.data);
+
+        let mut table = vtable(TestPlugin);
+        table.unicorn_version = b"0.0.0-other\0".as_ptr() as *const c_char;
+        assert!(check_vtable(&table).unwrap_err().starts_with("built with unicorn 0.0.0-other"));
+        assert_eq!(DESTROYED.load(Ordering::SeqCst), destroyed + 2);
+    }
+
+    #[test]
+    fn library_in_the_cartridge() {
+        let dir = TempDir::new("unicorn").unwrap();
+        let root = dir.path().join("cart");
+        fs::create_dir(&root).unwrap();
+
+        assert_eq!(library_path(&root, "target/libcart.so"),
+                   Some(root.join("target/libcart.so")));
+        assert_eq!(library_path(&root, "./target/../libcart.so"),
+                   Some(root.join("libcart.so")));
+
+        assert_eq!(library_path(&root, "../libcart.so"), None);
+        assert_eq!(library_path(&root, "target/../../libcart.so"), None);
+        assert_eq!(library_path(&root, "/usr/lib/libcart.so"), None);
+        let absolute = root.join("libcart.so");
+        assert_eq!(library_path(&root, absolute.to_str().unwrap()), None);
+    }
+}
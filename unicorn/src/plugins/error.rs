@@ -0,0 +1,266 @@
+//! Errors of the scripts, with the location and the traceback displayed by the
+//! error screen of the console.
+
+use std::fmt;
+
+/// Name of the code of the cartridge in the locations of the errors
+pub const CODE_FILENAME: &'static str = "main";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ScriptError {
+    pub message: String,
+    pub file: Option<String>,
+    /// Counted from 1
+    pub line: Option<usize>,
+    pub traceback: Vec<String>,
+}
+
+/// The main code or one of the code files of the cartridge, `enemies.lua`
+fn is_code_file(file: &str) -> bool {
+    file == CODE_FILENAME ||
+    (file.contains('.') && !file.starts_with('/') && !file.starts_with('<'))
+}
+
+/// Split `file:line: message`
+fn split_location(text: &str) -> Option<(String, usize, String)> {
+    for (idx, _) in text.match_indices(':') {
+        if idx == 0 {
+            continue;
+        }
+
+        let rest = &text[idx + 1..];
+        let digits: String = rest.chars().take_while(|c| c.is_digit(10)).collect();
+        if digits.is_empty() {
+            continue;
+        }
+
+        let message = &rest[digits.len()..];
+        if !message.is_empty() && !message.starts_with(": ") {
+            continue;
+        }
+
+        if let Ok(line) = digits.parse() {
+            return Some((text[..idx].to_string(),
+                         line,
+                         message.trim_left_matches(": ").to_string()));
+        }
+    }
+
+    None
+}
+
+/// Number following `prefix` in `text`
+fn number_after(text: &str, prefix: &str) -> Option<usize> {
+    text.find(prefix).and_then(|idx| {
+        let digits: String = text[idx + prefix.len()..]
+            .chars()
+            .take_while(|c| c.is_digit(10))
+            .collect();
+        digits.parse().ok()
+    })
+}
+
+impl ScriptError {
+    pub fn new(message: &str) -> ScriptError {
+        ScriptError {
+            message: message.to_string(),
+            file: None,
+            line: None,
+            traceback: Vec::new(),
+        }
+    }
+
+    /// `file:line: message`, followed by the `stack traceback:` of `luaL_traceback`
+    pub fn from_lua(error: &str) -> ScriptError {
+        let mut lines = error.lines();
+        let first = lines.next().unwrap_or("");
+
+        let mut script_error = match split_location(first) {
+            Some((file, line, message)) => {
+                ScriptError {
+                    message: message,
+                    file: Some(file),
+                    line: Some(line),
+                    traceback: Vec::new(),
+                }
+            }
+            None => ScriptError::new(first),
+        };
+
+        let mut in_traceback = false;
+        for line in lines {
+            let line = line.trim();
+            if line == "stack traceback:" {
+                in_traceback = true;
+            } else if in_traceback {
+                script_error.traceback.push(line.to_string());
+            } else {
+                // Multiple lines message
+                script_error.message.push('\n');
+                script_error.message.push_str(line);
+            }
+        }
+
+        script_error
+    }
+
+    /// Lines of `traceback.format_exception`
+    pub fn from_python(lines: &[String]) -> ScriptError {
+        let text = lines.concat();
+        let lines: Vec<&str> = text.lines().filter(|line| !line.trim().is_empty()).collect();
+
+        let message = lines.last().map_or("", |line| line.trim());
+        let mut script_error = ScriptError::new(message);
+
+        for line in lines.iter().take(lines.len().saturating_sub(1)) {
+            let line = line.trim();
+            if line.starts_with("Traceback (most recent call last)") {
+                continue;
+            }
+
+            // File "main", line 12, in _update
+            if line.starts_with("File \"") {
+                let file = line[6..].split('"').next().unwrap_or("").to_string();
+                if let Some(number) = number_after(line, "line ") {
+                    if script_error.file.is_none() || is_code_file(&file) {
+                        script_error.file = Some(file);
+                        script_error.line = Some(number);
+                    }
+                }
+            }
+
+            script_error.traceback.push(line.to_string());
+        }
+
+        script_error
+    }
+
+    /// `stack` property of a duktape error
+    pub fn from_javascript(stack: &str) -> ScriptError {
+        let mut lines = stack.lines();
+        let mut script_error = ScriptError::new(lines.next().unwrap_or("").trim());
+
+        for line in lines {
+            let line = line.trim();
+            // Functions of duktape, written in C
+            if line.ends_with("internal") {
+                continue;
+            }
+
+            // at _update (main:12)
+            if script_error.line.is_none() {
+                if let (Some(start), Some(end)) = (line.rfind('('), line.rfind(')')) {
+                    if start < end {
+                        if let Some((file, number, _)) = split_location(&line[start + 1..end]) {
+                            if is_code_file(&file) {
+                                script_error.file = Some(file);
+                                script_error.line = Some(number);
+                            }
+                        }
+                    }
+                }
+            }
+
+            script_error.traceback.push(line.to_string());
+        }
+
+        // SyntaxError: ... (line 12)
+        if script_error.line.is_none() {
+            if let Some(number) = number_after(&script_error.message, "(line ") {
+                script_error.file = Some(CODE_FILENAME.to_string());
+                script_error.line = Some(number);
+            }
+        }
+
+        script_error
+    }
+
+    /// The error, then the traceback
+    pub fn report(&self) -> String {
+        let mut report = self.to_string();
+        for line in self.traceback.iter() {
+            report.push_str("\n    ");
+            report.push_str(line);
+        }
+        report
+    }
+}
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (&self.file, self.line) {
+            (&Some(ref file), Some(line)) => write!(f, "{}:{}: {}", file, line, self.message),
+            _ => write!(f, "{}", self.message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lua_error() {
+        let error = ScriptError::from_lua("main:12: attempt to call a nil value (global 'foo')\n\
+                                           stack traceback:\n\
+                                           \t[C]: in function 'foo'\n\
+                                           \tmain:12: in function '_update'");
+
+        assert_eq!(error.message, "attempt to call a nil value (global 'foo')");
+        assert_eq!(error.file, Some("main".to_string()));
+        assert_eq!(error.line, Some(12));
+        assert_eq!(error.traceback,
+                   vec!["[C]: in function 'foo'", "main:12: in function '_update'"]);
+        assert_eq!(error.to_string(),
+                   "main:12: attempt to call a nil value (global 'foo')");
+
+        let error = ScriptError::from_lua("not enough memory");
+        assert_eq!(error, ScriptError::new("not enough memory"));
+    }
+
+    #[test]
+    fn python_error() {
+        let lines = vec!["Traceback (most recent call last):\n".to_string(),
+                         "  File \"<string>\", line 1, in <module>\n".to_string(),
+                         "  File \"main\", line 7, in _update\n    x = y + 1\n".to_string(),
+                         "NameError: name 'y' is not defined\n".to_string()];
+        let error = ScriptError::from_python(&lines);
+
+        assert_eq!(error.message, "NameError: name 'y' is not defined");
+        assert_eq!(error.file, Some("main".to_string()));
+        assert_eq!(error.line, Some(7));
+        assert_eq!(error.traceback.len(), 3);
+
+        let lines = vec!["Traceback (most recent call last):\n".to_string(),
+                         "  File \"main\", line 7, in _update\n".to_string(),
+                         "  File \"enemies.py\", line 3, in move\n".to_string(),
+                         "  File \"/usr/lib/python3/random.py\", line 9, in randint\n".to_string(),
+                         "ValueError: empty range\n".to_string()];
+        let error = ScriptError::from_python(&lines);
+
+        assert_eq!(error.file, Some("enemies.py".to_string()));
+        assert_eq!(error.line, Some(3));
+    }
+
+    #[test]
+    fn javascript_error() {
+        let error = ScriptError::from_javascript("ReferenceError: identifier 'y' undefined\n    \
+                                                  at [anon] (duk_js_var.c:1234) internal\n    \
+                                                  at _update (main:5)\n    \
+                                                  at eval (eval:1)");
+
+        assert_eq!(error.message, "ReferenceError: identifier 'y' undefined");
+        assert_eq!(error.file, Some("main".to_string()));
+        assert_eq!(error.line, Some(5));
+        assert_eq!(error.traceback, vec!["at _update (main:5)", "at eval (eval:1)"]);
+
+        let error = ScriptError::from_javascript("TypeError: undefined not callable\n    \
+                                                  at move (enemies.js:4)\n    \
+                                                  at _update (main:5)");
+        assert_eq!(error.file, Some("enemies.js".to_string()));
+        assert_eq!(error.line, Some(4));
+
+        let error = ScriptError::from_javascript("SyntaxError: parse error (line 3)");
+        assert_eq!(error.line, Some(3));
+    }
+}
@@ -1,627 +1,512 @@
 #[cfg(feature = "duktape")]
 pub mod plugin {
+    use std::borrow::Cow;
+    use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
+    use std::time::Instant;
 
     use duktape::*;
     use duktape::types::*;
     use duktape::errors::*;
+    use serde_json;
+
+    use plugins::api;
+    use plugins::api::{ApiContext, ApiValue};
+    use plugins::error::{ScriptError, CODE_FILENAME};
+    use plugins::sandbox;
+    use plugins::sandbox::SandboxPolicy;
+    use plugins::scheduler;
+
+    /// Script side of the API: pico-8 compatibility and math helpers
+    const JAVASCRIPT_API: &'static str = r#"
+function unicorn_print(str, x, y, col) {
+    return print(str, x, y, col);
+}
 
-    use config::Players;
+function time() {
+    return unicorn_time();
+}
 
-    use unicorn::info::Info;
-    use unicorn::noise::Noise;
-    use sound::sound::Sound;
+function touch(i) {
+    return [touch_id(i), touch_x(i), touch_y(i), touch_phase(i)];
+}
 
-    use gfx::Screen;
+function map(cel_x, cel_y, sx, sy, cel_w, cel_h, layer) {
+    spr_map(cel_x, cel_y, sx, sy, cel_w, cel_h, layer);
+}
 
-    pub struct JavascriptPluginRust {
-        info: Vec<Arc<Mutex<Info>>>,
-        screen: Vec<Arc<Mutex<Screen>>>,
-        players: Vec<Arc<Mutex<Players>>>,
-        sound: Vec<Arc<Mutex<Sound>>>,
-    }
+function mapdraw(cel_x, cel_y, sx, sy, cel_w, cel_h, layer) {
+    spr_map(cel_x, cel_y, sx, sy, cel_w, cel_h, layer);
+}
 
-    impl JavascriptPluginRust {
-        pub fn new() -> JavascriptPluginRust {
-            JavascriptPluginRust {
-                info: Vec::new(),
-                screen: Vec::new(),
-                players: Vec::new(),
-                sound: Vec::new(),
-            }
-        }
+function flip() {
+}
 
-        pub fn set_info(&mut self, info: Arc<Mutex<Info>>) {
-            self.info.push(info);
-        }
+function peek(addr) {
+    return 0;
+}
 
-        pub fn set_screen(&mut self, screen: Arc<Mutex<Screen>>) {
-            self.screen.push(screen);
-        }
+function poke(addr, val) {
+}
 
-        pub fn set_players(&mut self, players: Arc<Mutex<Players>>) {
-            self.players.push(players);
-        }
+function cartdata(x) {
+}
 
+function dget(x) {
+    return 0;
+}
 
-        pub fn set_sound(&mut self, sound: Arc<Mutex<Sound>>) {
-            self.sound.push(sound);
-        }
+function dset(x, y) {
+}
 
-        pub fn cls(&self,
-                   _ctx: &mut Context,
-                   args: &[Value<'static>])
-                   -> DuktapeResult<Value<'static>> {
-            // println!("RUST CLS {:?}", args);
-            let mut value: i8 = -1;
+function add(t, v) {
+    t.push(v);
+}
 
-            if let Value::Number(arg) = args[0] {
-                value = arg as i8;
-            }
+function flr(x) {
+    return Math.floor(x);
+}
 
-            self.screen[0].lock().unwrap().cls(value);
-            Ok(Value::Number(0.))
-        }
+function ceil(x) {
+    return Math.ceil(x);
+}
 
-        pub fn music(&self,
-                     _ctx: &mut Context,
-                     args: &[Value<'static>])
-                     -> DuktapeResult<Value<'static>> {
-            let mut id: i32 = -1;
-            let mut filename: String = "".to_string();
-            let mut channel: i32 = -1;
-            let mut start_position: i32 = 0;
-            let mut loops: i32 = 0;
+function sqrt(x) {
+    return Math.sqrt(x);
+}
 
-            if let Value::Number(arg) = args[0] {
-                id = arg as i32;
-            }
+function abs(x) {
+    return Math.abs(x);
+}
 
-            if let Value::String(ref arg) = args[1] {
-                filename = arg.to_string();
-            }
+function min(a, b) {
+    return Math.min(a, b);
+}
 
-            if let Value::Number(arg) = args[2] {
-                loops = arg as i32;
-            }
+function max(a, b) {
+    return Math.max(a, b);
+}
 
-            if let Value::Number(arg) = args[3] {
-                start_position = arg as i32;
-            }
+function mid(x, y, z) {
+    x = x || 0;
+    y = y || 0;
+    z = z || 0;
+    return x > y ? x : (y > z ? z : y);
+}
 
-            if let Value::Number(arg) = args[4] {
-                channel = arg as i32;
-            }
+function cos(x) {
+    return Math.cos((x || 0) * (Math.PI * 2));
+}
 
-            self.sound[0].lock().unwrap().music(id, filename, channel, loops, start_position);
+function sin(x) {
+    return Math.sin(-(x || 0) * (Math.PI * 2));
+}
 
-            Ok(Value::Number(0.))
-        }
+function atan2(y, x) {
+    var v = Math.atan2(y, x);
+    return ((((v - Math.PI) / (Math.PI * 2)) + 0.25) % 1.0 + 1.0) % 1.0;
+}
 
-        pub fn sfx(&self,
-                   _ctx: &mut Context,
-                   args: &[Value<'static>])
-                   -> DuktapeResult<Value<'static>> {
-            let mut id: i32 = -1;
-            let mut filename: String = "".to_string();
-            let mut channel: i32 = -1;
-            let mut note: u16 = 13312;
-            let mut panning: i32 = 64;
-            let mut rate: i32 = 50;
-            let mut loops: i32 = 0;
+function sgn(x) {
+    return x < 0 ? -1 : 1;
+}
 
-            if let Value::Number(arg) = args[0] {
-                id = arg as i32;
-            }
+function band(x, y) {
+    return Math.floor(x) & Math.floor(y);
+}
 
-            if let Value::String(ref arg) = args[1] {
-                filename = arg.to_string();
-            }
+function bor(x, y) {
+    return Math.floor(x) | Math.floor(y);
+}
 
-            if let Value::Number(arg) = args[2] {
-                note = arg as u16;
-            }
+function bxor(x, y) {
+    return Math.floor(x) ^ Math.floor(y);
+}
 
-            if let Value::Number(arg) = args[3] {
-                panning = arg as i32;
-            }
+function bnot(x) {
+    return ~Math.floor(x);
+}
 
-            if let Value::Number(arg) = args[4] {
-                rate = arg as i32;
-            }
+function shl(x, y) {
+    return Math.floor(x) << Math.floor(y);
+}
 
-            if let Value::Number(arg) = args[5] {
-                loops = loops as i32;
-            }
+function shr(x, y) {
+    return Math.floor(x) >> Math.floor(y);
+}
 
-            if let Value::Number(arg) = args[6] {
-                channel = channel as i32;
-            }
+// Code files of the cartridge, compiled by the plugin
+var unicorn_modules = { factories: {}, cache: {} };
 
-            self.sound[0].lock().unwrap().sfx(id, filename, channel, note, panning, rate, loops);
+function require(id) {
+    var filename = id.replace(/^\.\//, '');
+    if (filename.slice(-3) !== '.js') {
+        filename += '.js';
+    }
 
-            Ok(Value::Number(0.))
-        }
+    var cache = unicorn_modules.cache;
+    if (cache.hasOwnProperty(filename)) {
+        return cache[filename].exports;
+    }
 
-        pub fn btnp(&self,
-                    _ctx: &mut Context,
-                    args: &[Value<'static>])
-                    -> DuktapeResult<Value<'static>> {
-            // info!("RUST BTNP {:?}", args);
-            let mut p: u8 = 0;
-            let mut x: u8 = 0;
+    var factory = unicorn_modules.factories[filename];
+    if (factory === undefined) {
+        throw new Error("cannot find module '" + id + "'");
+    }
 
-            if let Value::Number(arg) = args[0] {
-                x = arg as u8;
-            }
+    var module = { id: filename, exports: {} };
+    cache[filename] = module;
+    factory.call(module.exports, module, module.exports, require);
+    return module.exports;
+}
 
-            if let Value::Number(arg) = args[1] {
-                p = arg as u8;
-            }
+// Scheduler, the tasks are coroutines resumed at each frame
+var unicorn_tasks = { last_id: 0, tasks: {}, current: null, fps: 60 };
+var UNICORN_TASK_DONE = {};
+
+function spawn(fn) {
+    unicorn_tasks.last_id += 1;
+    unicorn_tasks.tasks[unicorn_tasks.last_id] = {
+        thread: new Duktape.Thread(function () {
+            fn();
+            return UNICORN_TASK_DONE;
+        }),
+        wait: 0
+    };
+    return unicorn_tasks.last_id;
+}
 
-            let value = self.players[0].lock().unwrap().btnp(p, x);
+function wait(frames) {
+    if (unicorn_tasks.current === null) {
+        throw new Error('wait called outside of a task');
+    }
+    Duktape.Thread.yield(frames || 1);
+}
 
-            Ok(Value::Bool(value))
-        }
+function after(seconds, fn) {
+    return spawn(function () {
+        wait(seconds * unicorn_tasks.fps);
+        fn();
+    });
+}
 
-        pub fn unicorn_time(&self,
-                            _ctx: &mut Context,
-                            args: &[Value<'static>])
-                            -> DuktapeResult<Value<'static>> {
-            // info!("RUST TIME {:?}", args);
+function every(seconds, fn) {
+    return spawn(function () {
+        do {
+            wait(seconds * unicorn_tasks.fps);
+        } while (fn() !== false);
+    });
+}
 
-            Ok(Value::Number(self.info[0].lock().unwrap().time() as f64))
+function tween(obj, field, to, duration, easing) {
+    easing = easing || 'linear';
+    if (typeof easing !== 'function') {
+        var name = easing;
+        if (ease(name, 0) == null) {
+            throw new Error('unknown easing ' + name);
         }
+        easing = function (t) { return ease(name, t); };
+    }
 
-        pub fn print(&self,
-                     _ctx: &mut Context,
-                     args: &[Value<'static>])
-                     -> DuktapeResult<Value<'static>> {
-            let mut text: String = "".to_string();
-            let mut x: i32 = 0;
-            let mut y: i32 = 0;
-            let mut color: i32 = -1;
-
-            if args.len() < 3 {
-                return Ok(Value::Number(-1.));
-            }
-
-            // info!("RUST PRINT {:?}", args);
-
-            if let Value::String(ref n) = args[0] {
-                text = n.to_string();
-            }
-
-            if let Value::Number(n) = args[1] {
-                x = n as i32;
-            }
-
-            if let Value::Number(n) = args[2] {
-                y = n as i32;
-            }
-
-            match args[3] {
-                Value::Number(n) => color = n as i32,
-                _ => (),
+    return spawn(function () {
+        var from = obj[field];
+        var frames = Math.max(1, Math.floor(duration * unicorn_tasks.fps + 0.5));
+        for (var frame = 1; frame <= frames; frame++) {
+            obj[field] = from + (to - from) * easing(frame / frames);
+            if (frame < frames) {
+                wait(1);
             }
-
-            self.screen[0].lock().unwrap().print(text, x, y, color);
-
-            Ok(Value::Number(0.))
         }
+    });
+}
 
-        pub fn pset(&self,
-                    _ctx: &mut Context,
-                    args: &[Value<'static>])
-                    -> DuktapeResult<Value<'static>> {
-            let mut x: i32 = 0;
-            let mut y: i32 = 0;
-            let mut color: i32 = -1;
-
-            if args.len() < 2 {
-                return Ok(Value::Number(-1.));
-            }
-
-            // println!("RUST PSET {:?}", args);
-
-            if let Value::Number(n) = args[0] {
-                x = n as i32;
-            }
-
-            if let Value::Number(n) = args[1] {
-                y = n as i32;
-            }
-
-            match args[2] {
-                Value::Number(n) => color = n as i32,
-                _ => (),
-            }
-
-            self.screen[0].lock().unwrap().pset(x, y, color);
+function cancel(id) {
+    delete unicorn_tasks.tasks[id];
+}
 
-            Ok(Value::Number(0.))
+function unicorn_scheduler_tick() {
+    var ids = Object.keys(unicorn_tasks.tasks).sort(function (a, b) { return a - b; });
+    for (var i = 0; i < ids.length; i++) {
+        var task = unicorn_tasks.tasks[ids[i]];
+        // Cancelled by another task
+        if (task === undefined) {
+            continue;
         }
 
-        pub fn spr(&self,
-                   _ctx: &mut Context,
-                   args: &[Value<'static>])
-                   -> DuktapeResult<Value<'static>> {
-            let mut n: u32 = 0;
-            let mut x: i32 = 0;
-            let mut y: i32 = 0;
-            let mut w: u32 = 1;
-            let mut h: u32 = 1;
-            let mut flip_x: bool = false;
-            let mut flip_y: bool = false;
-
-            if args.len() < 3 {
-                return Ok(Value::Number(-1.));
-            }
-
-            // info!("RUST SPR {:?}", args);
-
-            if let Value::Number(arg) = args[0] {
-                n = arg as u32;
-            }
+        task.wait -= 1;
+        if (task.wait > 0) {
+            continue;
+        }
 
-            if let Value::Number(arg) = args[1] {
-                x = arg as i32;
-            }
+        var value;
+        unicorn_tasks.current = task;
+        try {
+            value = Duktape.Thread.resume(task.thread);
+        } catch (e) {
+            delete unicorn_tasks.tasks[ids[i]];
+            throw e;
+        } finally {
+            unicorn_tasks.current = null;
+        }
 
-            if let Value::Number(arg) = args[2] {
-                y = arg as i32;
-            }
+        if (value === UNICORN_TASK_DONE) {
+            delete unicorn_tasks.tasks[ids[i]];
+        } else {
+            task.wait = Math.max(1, Math.ceil(value));
+        }
+    }
+}
+"#;
+
+    /// Javascript string of `text`
+    fn string_literal(text: &str) -> String {
+        // JSON strings are javascript strings, except these separators
+        serde_json::to_string(text)
+            .unwrap_or("''".to_string())
+            .replace("\u{2028}", "\\u2028")
+            .replace("\u{2029}", "\\u2029")
+    }
 
-            if let Value::Number(arg) = args[3] {
-                w = arg as u32;
-            }
+    pub struct JavascriptPluginRust {
+        context: Vec<ApiContext>,
+    }
 
-            if let Value::Number(arg) = args[4] {
-                h = arg as u32;
-            }
+    impl JavascriptPluginRust {
+        pub fn new() -> JavascriptPluginRust {
+            JavascriptPluginRust { context: Vec::new() }
+        }
 
-            if let Value::Bool(arg) = args[5] {
-                flip_x = arg as bool;
-            }
+        pub fn set_context(&mut self, context: ApiContext) {
+            self.context.push(context);
+        }
 
-            if let Value::Bool(arg) = args[6] {
-                flip_y = arg as bool;
+        fn to_value(value: &Value) -> ApiValue {
+            match *value {
+                Value::Bool(v) => ApiValue::Bool(v),
+                Value::Number(v) => {
+                    if v.fract() == 0. {
+                        ApiValue::Int(v as i64)
+                    } else {
+                        ApiValue::Float(v)
+                    }
+                }
+                Value::String(ref v) => ApiValue::Str(v.to_string()),
+                _ => ApiValue::Nil,
             }
-
-
-            self.screen[0].lock().unwrap().spr(n, x, y, w, h, flip_x, flip_y);
-
-            Ok(Value::Number(0.))
         }
+    }
 
-        pub fn sspr(&self,
+    impl Foo for JavascriptPluginRust {
+        fn dispatch(&mut self,
                     _ctx: &mut Context,
                     args: &[Value<'static>])
                     -> DuktapeResult<Value<'static>> {
-            let mut sx: u32 = 0;
-            let mut sy: u32 = 0;
-            let mut sw: u32 = 0;
-            let mut sh: u32 = 0;
-            let mut dx: i32 = 0;
-            let mut dy: i32 = 0;
-            let mut dw: u32 = 0;
-            let mut dh: u32 = 0;
-
-            let mut flip_x: bool = false;
-            let mut flip_y: bool = false;
-
-            // info!("RUST SSPR {:?}", args);
-
-            if let Value::Number(arg) = args[0] {
-                sx = arg as u32;
-            }
-
-            if let Value::Number(arg) = args[1] {
-                sy = arg as u32;
-            }
-
-            if let Value::Number(arg) = args[2] {
-                sw = arg as u32;
-            }
-
-            if let Value::Number(arg) = args[3] {
-                sh = arg as u32;
-            }
-
-            if let Value::Number(arg) = args[4] {
-                dx = arg as i32;
-            }
-
-            if let Value::Number(arg) = args[5] {
-                dy = arg as i32;
-            }
-
-            if let Value::Number(arg) = args[6] {
-                dw = arg as u32;
-            } else {
-                dw = sw as u32;
-            }
-
-            if let Value::Number(arg) = args[7] {
-                dh = arg as u32;
-            } else {
-                dh = sh as u32;
-            }
-
-            if let Value::Bool(arg) = args[8] {
-                flip_x = arg as bool;
-            }
-
-            if let Value::Bool(arg) = args[9] {
-                flip_y = arg as bool;
-            }
-
-
-            self.screen[0].lock().unwrap().sspr(sx, sy, sw, sh, dx, dy, dw, dh, flip_x, flip_y);
-
-            Ok(Value::Number(0.))
+            // args[0] is the magic number of `_api`, args[1] the index of the function
+            if let Value::Number(function_idx) = args[1] {
+                let values = args[2..args.len()]
+                    .iter()
+                    .map(JavascriptPluginRust::to_value)
+                    .collect();
+
+                let value = match self.context[0].call(function_idx as usize, values) {
+                    ApiValue::Nil => Value::Undefined,
+                    ApiValue::Int(v) => Value::Number(v as f64),
+                    ApiValue::Float(v) => Value::Number(v),
+                    ApiValue::Bool(v) => Value::Bool(v),
+                    ApiValue::Str(v) => Value::String(Cow::Owned(v)),
+                    ApiValue::List(v) => {
+                        let v: Vec<String> = v.iter().map(|v| v.to_string()).collect();
+                        Value::String(Cow::Owned(v.join(",")))
+                    }
+                };
+
+                return Ok(value);
+            }
+
+            Ok(Value::Undefined)
         }
+    }
 
-        pub fn sspr2(&self,
-                     _ctx: &mut Context,
-                     args: &[Value<'static>])
-                     -> DuktapeResult<Value<'static>> {
-            let mut sx: u32 = 0;
-            let mut sy: u32 = 0;
-            let mut sw: u32 = 0;
-            let mut sh: u32 = 0;
-            let mut dx: i32 = 0;
-            let mut dy: i32 = 0;
-            let mut angle: f64 = 0.0;
-            let mut zoom: f64 = 1.0;
-
-            let mut flip_x: bool = false;
-            let mut flip_y: bool = false;
-
-            if let Value::Number(arg) = args[0] {
-                sx = arg as u32;
-            }
-
-            if let Value::Number(arg) = args[1] {
-                sy = arg as u32;
-            }
-
-            if let Value::Number(arg) = args[2] {
-                sw = arg as u32;
-            }
+    /// Compile `code` in a new context without running it, to check its syntax
+    pub fn check_syntax(filename: &str, code: &str) -> Result<(), ScriptError> {
+        let mut ctx = Context::new();
+        ctx.compile_from(filename, code).map_err(|err| {
+            let mut error = ScriptError::from_javascript(&err.to_string());
+            // The line of the syntax errors is found without the file
+            if error.line.is_some() {
+                error.file = Some(filename.to_string());
+            }
+            error
+        })
+    }
 
-            if let Value::Number(arg) = args[3] {
-                sh = arg as u32;
-            }
+    pub struct JavascriptPlugin {
+        ctx: Context,
+        javascript: Arc<Mutex<JavascriptPluginRust>>,
+        sandbox: Option<SandboxPolicy>,
+        /// Deadline of the last evaluation
+        deadline: Option<Instant>,
+        loaded_code: bool,
+        error: Option<ScriptError>,
+        /// Sources of the modules of the cartridge, by filename
+        files: HashMap<String, String>,
+    }
 
-            if let Value::Number(arg) = args[4] {
-                dx = arg as i32;
+    impl JavascriptPlugin {
+        pub fn new() -> JavascriptPlugin {
+            JavascriptPlugin {
+                ctx: Context::new(),
+                javascript: Arc::new(Mutex::new(JavascriptPluginRust::new())),
+                sandbox: None,
+                deadline: None,
+                loaded_code: false,
+                error: None,
+                files: HashMap::new(),
             }
+        }
 
-            if let Value::Number(arg) = args[5] {
-                dy = arg as i32;
-            }
+        pub fn load(&mut self, context: ApiContext, sandbox: SandboxPolicy) {
+            info!("[PLUGIN][JAVASCRIPT] Init plugin");
+            self.javascript.lock().unwrap().set_context(context);
+            self.sandbox = Some(sandbox);
 
-            if let Value::Number(arg) = args[6] {
-                angle = arg as f64;
-            }
+            self.ctx.register(0x1, "_api", self.javascript.clone(), None);
 
-            if let Value::Number(arg) = args[7] {
-                zoom = arg as f64;
+            match self.ctx.eval(&api::javascript_bindings()) {
+                Result::Ok(_) => (),
+                Result::Err(err) => warn!("Error to load the API {:?}", err),
             }
 
-            if let Value::Bool(arg) = args[8] {
-                flip_x = arg as bool;
+            match self.ctx.eval(JAVASCRIPT_API) {
+                Result::Ok(_) => (),
+                Result::Err(err) => warn!("Error to load the API {:?}", err),
             }
 
-            if let Value::Bool(arg) = args[9] {
-                flip_y = arg as bool;
+            let fps = format!("unicorn_tasks.fps = {};", scheduler::FRAMES_PER_SECOND);
+            if let Result::Err(err) = self.ctx.eval(&fps) {
+                warn!("Error to load the scheduler {:?}", err);
             }
-
-            self.screen[0]
-                .lock()
-                .unwrap()
-                .sspr2(sx, sy, sw, sh, dx, dy, angle, zoom, flip_x, flip_y);
-
-            Ok(Value::Number(0.))
         }
 
-        pub fn pal(&self,
-                   _ctx: &mut Context,
-                   args: &[Value<'static>])
-                   -> DuktapeResult<Value<'static>> {
-            // println!("RUST CLS {:?}", args);
-            let mut c0: i32 = -1;
-            let mut c1: i32 = -1;
-
-            if let Value::Number(arg) = args[0] {
-                c0 = arg as i32;
-            }
-
-            if let Value::Number(arg) = args[1] {
-                c1 = arg as i32;
-            }
+        /// Evaluate `code` with a new budget, aborted by duktape after the deadline
+        fn eval(&mut self, filename: &str, code: &str) -> Result<Value<'static>, ScriptError> {
+            self.deadline = self.sandbox
+                .as_ref()
+                .and_then(|sandbox| sandbox.budget().deadline());
 
-            self.screen[0].lock().unwrap().pal(c0, c1);
+            set_exec_deadline(self.deadline);
+            let result = self.ctx.eval_from(filename, code);
+            set_exec_deadline(None);
 
-            Ok(Value::Number(0.))
+            result.map_err(|err| self.budget_error(ScriptError::from_javascript(&err.to_string())))
         }
 
-        pub fn circ(&self,
-                    _ctx: &mut Context,
-                    args: &[Value<'static>])
-                    -> DuktapeResult<Value<'static>> {
-            let mut x: i32 = -1;
-            let mut y: i32 = -1;
-            let mut r: i32 = -1;
-            let mut color: i32 = -1;
-
-            if let Value::Number(arg) = args[0] {
-                x = arg as i32;
-            }
-
-            if let Value::Number(arg) = args[1] {
-                y = arg as i32;
-            }
-
-            if let Value::Number(arg) = args[2] {
-                r = arg as i32;
-            }
-
-            if let Value::Number(arg) = args[3] {
-                color = color as i32;
+        /// Prefix the errors of the evaluations aborted after the deadline
+        fn budget_error(&self, mut error: ScriptError) -> ScriptError {
+            if self.deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                error.message = format!("{}: {}", sandbox::BUDGET_EXCEEDED, error.message);
             }
 
-            self.screen[0].lock().unwrap().circ(x, y, r, color);
-
-            Ok(Value::Number(0.))
+            error
         }
 
-        pub fn circfill(&self,
-                        _ctx: &mut Context,
-                        args: &[Value<'static>])
-                        -> DuktapeResult<Value<'static>> {
-            let mut x: i32 = -1;
-            let mut y: i32 = -1;
-            let mut r: i32 = -1;
-            let mut color: i32 = -1;
-
-            if let Value::Number(arg) = args[0] {
-                x = arg as i32;
-            }
-
-            if let Value::Number(arg) = args[1] {
-                y = arg as i32;
-            }
-
-            if let Value::Number(arg) = args[2] {
-                r = arg as i32;
-            }
-
-            if let Value::Number(arg) = args[3] {
-                color = arg as i32;
+        /// Call the global function `name` if it is defined
+        fn call(&mut self, name: &str) -> Result<(), ScriptError> {
+            let code = format!("(function() {{
+                                  if (typeof {0} !== 'function') {{ return ''; }}
+                                  try {{ {0}(); }} catch (e) {{ return (e && e.stack) || String(e); }}
+                                  return '';
+                                }})()",
+                               name);
+
+            match try!(self.eval("eval", &code)) {
+                // The stack of the caught error
+                Value::String(ref stack) if !stack.is_empty() => {
+                    Err(self.budget_error(ScriptError::from_javascript(stack)))
+                }
+                _ => Ok(()),
             }
-
-            self.screen[0].lock().unwrap().circfill(x, y, r, color);
-
-            Ok(Value::Number(0.))
         }
 
-        pub fn line(&self,
-                    _ctx: &mut Context,
-                    args: &[Value<'static>])
-                    -> DuktapeResult<Value<'static>> {
-            let mut x1: i32 = -1;
-            let mut y1: i32 = -1;
-            let mut x2: i32 = -1;
-            let mut y2: i32 = -1;
-            let mut color: i32 = -1;
-
-            if let Value::Number(arg) = args[0] {
-                x1 = arg as i32;
-            }
+        /// Modules of the cartridge, used by the next `load_code`
+        pub fn set_files(&mut self, files: HashMap<String, String>) {
+            self.files = files;
+        }
 
-            if let Value::Number(arg) = args[1] {
-                y1 = arg as i32;
+        /// Compile the modules found by `require`, evaluated by their first `require`
+        fn load_files(&mut self) -> Result<(), ScriptError> {
+            try!(self.eval("eval", "unicorn_modules = { factories: {}, cache: {} };"));
+
+            let files: Vec<(String, String)> = self.files
+                .iter()
+                .map(|(filename, source)| (filename.clone(), source.clone()))
+                .collect();
+
+            for (filename, source) in files {
+                // The source starts on the first line, for the line numbers
+                let code = format!("unicorn_modules.factories[{}] = function (module, exports, require) {{{}\n}};",
+                                   string_literal(&filename),
+                                   source);
+
+                if let Err(mut error) = self.eval(&filename, &code) {
+                    // The syntax errors are located in the module, not in the main code
+                    if error.file.as_ref().map_or(false, |file| file == CODE_FILENAME) {
+                        error.file = Some(filename.clone());
+                    }
+                    return Err(error);
+                }
             }
 
-            if let Value::Number(arg) = args[2] {
-                x2 = arg as i32;
-            }
+            Ok(())
+        }
 
-            if let Value::Number(arg) = args[3] {
-                y2 = arg as i32;
-            }
+        /// Evaluate a line of the REPL in the global scope
+        pub fn repl(&mut self, code: &str) -> Result<String, ScriptError> {
+            let code = format!("(function() {{
+                                  var value = (0, eval)({});
+                                  return value === undefined ? '' : String(value);
+                                }})()",
+                               string_literal(code));
 
-            if let Value::Number(arg) = args[4] {
-                color = arg as i32;
+            match try!(self.eval("repl", &code)) {
+                Value::String(value) => Ok(value.into_owned()),
+                _ => Ok("".to_string()),
             }
-
-            self.screen[0].lock().unwrap().line(x1, y1, x2, y2, color);
-
-            Ok(Value::Number(0.))
         }
-    }
 
-    impl Foo for JavascriptPluginRust {
-        fn dispatch(&mut self,
-                    _ctx: &mut Context,
-                    args: &[Value<'static>])
-                    -> DuktapeResult<Value<'static>> {
-            //  println!("I'm here {:?}", args);
-            //  println!("CONTEXT {:?}", _ctx.dump_context());
-
-            if let Value::Number(function_idx) = args[0] {
-                let args = &args[1..args.len()];
-
-                let function_idx = function_idx as i32;
-
-                match function_idx {
-                    0x1 => return self.pset(_ctx, args),
-                    0x2 => return self.cls(_ctx, args),
-                    0x3 => return self.unicorn_time(_ctx, args),
-                    0x4 => return self.print(_ctx, args),
-                    0x5 => return self.spr(_ctx, args),
-                    0x6 => return self.btnp(_ctx, args),
-                    0x7 => return self.sspr(_ctx, args),
-                    0x8 => return self.pal(_ctx, args),
-                    0x9 => return self.sfx(_ctx, args),
-                    0x10 => return self.music(_ctx, args),
-                    0x11 => return self.circ(_ctx, args),
-                    0x12 => return self.circfill(_ctx, args),
-                    0x13 => return self.line(_ctx, args),
-                    0x14 => return self.sspr2(_ctx, args),
-
-                    _ => (),
+        /// Names of the global variables, completed by the REPL
+        pub fn globals(&mut self) -> Vec<String> {
+            match self.eval("repl", "Object.getOwnPropertyNames(this).join(' ')") {
+                Ok(Value::String(names)) => {
+                    names.split_whitespace().map(|name| name.to_string()).collect()
                 }
+                _ => Vec::new(),
             }
+        }
 
-            Ok(Value::Number(0.))
+        /// Keep the error, displayed instead of the cartridge
+        fn report(&mut self, name: &str, error: ScriptError) {
+            warn!("[PLUGIN][JAVASCRIPT] {} = {}", name, error.report());
+            self.error = Some(error);
         }
-    }
 
-    pub struct JavascriptPlugin {
-        ctx: Context,
-        javascript: Arc<Mutex<JavascriptPluginRust>>,
-        loaded_code: bool,
-    }
+        pub fn take_error(&mut self) -> Option<ScriptError> {
+            self.error.take()
+        }
 
-    impl JavascriptPlugin {
-        pub fn new() -> JavascriptPlugin {
-            JavascriptPlugin {
-                ctx: Context::new(),
-                javascript: Arc::new(Mutex::new(JavascriptPluginRust::new())),
-                loaded_code: false,
-            }
+        /// Memory of the heap, in KB
+        pub fn memory(&mut self) -> f64 {
+            self.ctx.heap_size() as f64 / 1024.0
         }
 
-        pub fn load(&mut self,
-                    players: Arc<Mutex<Players>>,
-                    info: Arc<Mutex<Info>>,
-                    screen: Arc<Mutex<Screen>>,
-                    noise: Arc<Mutex<Noise>>,
-                    sound: Arc<Mutex<Sound>>) {
-            info!("[PLUGIN][JAVASCRIPT] Init plugin");
-            self.javascript.lock().unwrap().set_info(info.clone());
-            self.javascript.lock().unwrap().set_screen(screen.clone());
-            self.javascript.lock().unwrap().set_players(players.clone());
-            self.javascript.lock().unwrap().set_sound(sound.clone());
-
-            self.ctx.register(0x1, "pset", self.javascript.clone(), Some(3));
-            self.ctx.register(0x2, "cls", self.javascript.clone(), Some(1));
-            self.ctx.register(0x3, "unicorn_time", self.javascript.clone(), Some(0));
-            self.ctx.register(0x4, "print", self.javascript.clone(), Some(4));
-            self.ctx.register(0x5, "spr", self.javascript.clone(), Some(7));
-            self.ctx.register(0x6, "btnp", self.javascript.clone(), Some(2));
-            self.ctx.register(0x7, "sspr", self.javascript.clone(), Some(10));
-            self.ctx.register(0x8, "pal", self.javascript.clone(), Some(2));
-            self.ctx.register(0x9, "sfx", self.javascript.clone(), Some(7));
-            self.ctx.register(0x10, "music", self.javascript.clone(), Some(5));
-            self.ctx.register(0x11, "circ", self.javascript.clone(), Some(4));
-            self.ctx.register(0x12, "circfill", self.javascript.clone(), Some(4));
-            self.ctx.register(0x13, "line", self.javascript.clone(), Some(5));
-            self.ctx.register(0x14, "sspr2", self.javascript.clone(), Some(10));
+        pub fn tick(&mut self) {
+            if !self.loaded_code {
+                return;
+            }
 
+            if let Err(error) = self.call("unicorn_scheduler_tick") {
+                self.report("SCHEDULER", error);
+            }
         }
 
         pub fn init(&mut self) {
@@ -629,9 +514,8 @@ pub mod plugin {
                 return;
             }
 
-            match self.ctx.eval("_init();") {
-                Result::Ok(_) => (),
-                Result::Err(err) => warn!("Error during the init function {:?}", err),
+            if let Err(error) = self.call("_init") {
+                self.report("INIT", error);
             }
         }
 
@@ -640,9 +524,8 @@ pub mod plugin {
                 return false;
             }
 
-            match self.ctx.eval("_draw();") {
-                Result::Ok(_) => (),
-                Result::Err(err) => warn!("Error during the draw function {:?}", err),
+            if let Err(error) = self.call("_draw") {
+                self.report("DRAW", error);
             }
 
             true
@@ -652,9 +535,9 @@ pub mod plugin {
             if !self.loaded_code {
                 return false;
             }
-            match self.ctx.eval("_update();") {
-                Result::Ok(_) => (),
-                Result::Err(err) => warn!("Error during the update function {:?}", err),
+
+            if let Err(error) = self.call("_update") {
+                self.report("UPDATE", error);
             }
 
             true
@@ -663,11 +546,17 @@ pub mod plugin {
         pub fn load_code(&mut self, data: String) -> bool {
             info!("[PLUGIN][JAVASCRIPT] LOAD CODE");
 
-            match self.ctx.eval(&data) {
+            self.error = None;
+
+            // Tasks of the previous code
+            let result = self.eval("eval", "unicorn_tasks.tasks = {};")
+                .and_then(|_| self.load_files())
+                .and_then(|_| self.eval(CODE_FILENAME, &data));
+            match result {
                 Result::Ok(_) => self.loaded_code = true,
-                Result::Err(err) => {
+                Result::Err(error) => {
                     self.loaded_code = false;
-                    warn!("Error to load the code {:?}", err);
+                    self.report("LOAD CODE", error);
                 }
             }
 
@@ -678,16 +567,15 @@ pub mod plugin {
 
 #[cfg(not(feature = "duktape"))]
 pub mod plugin {
-    use std::sync::{Arc, Mutex};
+    use std::collections::HashMap;
 
-    use config::Players;
-
-    use unicorn::noise::Noise;
-    use unicorn::info::Info;
-    use sound::sound::Sound;
-
-    use gfx::Screen;
+    use plugins::api::ApiContext;
+    use plugins::error::ScriptError;
+    use plugins::sandbox::SandboxPolicy;
 
+    pub fn check_syntax(_filename: &str, _code: &str) -> Result<(), ScriptError> {
+        Ok(())
+    }
 
     pub struct JavascriptPlugin {}
 
@@ -697,17 +585,26 @@ pub mod plugin {
         }
 
         // Keep the compatibility
-        pub fn load(&mut self,
-                    _players: Arc<Mutex<Players>>,
-                    _info: Arc<Mutex<Info>>,
-                    _screen: Arc<Mutex<Screen>>,
-                    _noise: Arc<Mutex<Noise>>,
-                    _sound: Arc<Mutex<Sound>>) {
+        pub fn load(&mut self, _context: ApiContext, _sandbox: SandboxPolicy) {
             panic!("Javascript plugin disabled");
         }
+        pub fn take_error(&mut self) -> Option<ScriptError> {
+            None
+        }
+        pub fn repl(&mut self, _code: &str) -> Result<String, ScriptError> {
+            Err(ScriptError::new("Javascript plugin disabled"))
+        }
+        pub fn globals(&mut self) -> Vec<String> {
+            Vec::new()
+        }
+        pub fn set_files(&mut self, _files: HashMap<String, String>) {}
         pub fn load_code(&mut self, _data: String) -> bool {
             false
         }
+        pub fn memory(&mut self) -> f64 {
+            0.0
+        }
+        pub fn tick(&mut self) {}
         pub fn init(&mut self) {}
         pub fn draw(&mut self) -> bool {
             false
@@ -717,3 +614,49 @@ pub mod plugin {
         }
     }
 }
+
+#[cfg(all(test, feature = "duktape"))]
+mod tests {
+    use std::path::Path;
+
+    use plugins::api::{self, FUNCTIONS};
+    use plugins::sandbox::SandboxPolicy;
+
+    use super::plugin::JavascriptPlugin;
+
+    /// Helpers written in the Lua and Python scripts instead of the API table
+    const SCRIPT_HELPERS: [&'static str; 14] = ["atan2", "bxor", "cartdata", "ceil", "cos",
+                                                "flip", "flr", "mid", "peek", "poke", "sin",
+                                                "sqrt", "time", "touch"];
+
+    /// Names of the global functions of a loaded plugin
+    fn global_functions() -> Vec<String> {
+        let (context, _crecv) = api::test_context();
+        let mut plugin = JavascriptPlugin::new();
+        plugin.load(context, SandboxPolicy::new(Path::new(".")));
+
+        let names = plugin
+            .repl("Object.getOwnPropertyNames(this).filter(function (name) {
+                     return typeof this[name] === 'function';
+                   }, this).join(' ')")
+            .unwrap();
+
+        names.split_whitespace().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn javascript_api_parity() {
+        let functions = global_functions();
+
+        let missing: Vec<&str> = FUNCTIONS
+            .iter()
+            .map(|function| function.name.as_str())
+            .chain(SCRIPT_HELPERS.iter().cloned())
+            .filter(|name| !functions.iter().any(|function| function == name))
+            .collect();
+
+        assert!(missing.is_empty(),
+                "Missing javascript bindings: {:?}",
+                missing);
+    }
+}
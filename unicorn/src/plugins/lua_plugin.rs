@@ -1,35 +1,266 @@
 #[cfg(feature = "unicorn_plugin_lua")]
 pub mod plugin {
+    use std::collections::HashMap;
     use std::sync::{Arc, Mutex};
 
-    use rand;
-    use rand::Rng;
-
     use unicorn_plugin_lua as lua;
-    use unicorn_plugin_lua::ffi::lua_State;
-    use unicorn_plugin_lua::{State, Function, ThreadStatus};
+    use unicorn_plugin_lua::ffi::{lua_Debug, lua_State};
+    use unicorn_plugin_lua::{State, Function, GcOption, MASKCOUNT, ThreadStatus, Type};
     use libc::c_int;
 
-    use config::Players;
-
-    use unicorn::info::Info;
-    use unicorn::noise::Noise;
-    use sound::sound::Sound;
+    use plugins::api;
+    use plugins::api::{ApiContext, ApiValue};
+    use plugins::error::{ScriptError, CODE_FILENAME};
+    use plugins::sandbox;
+    use plugins::sandbox::{Budget, SandboxPolicy};
+    use plugins::scheduler;
+
+    /// Instructions between two checks of the budget
+    const HOOK_COUNT: c_int = 1000;
+
+    /// Standard library available in the sandbox, the files are limited to the
+    /// directory of the cartridge by `UnicornObject:path`
+    const LUA_SANDBOX: &'static str = r#"
+      do
+        local open, lines = io.open, io.lines
+        local clock, date, difftime, time = os.clock, os.date, os.difftime, os.time
+        local traceback = debug.traceback
+        local load_chunk = load
+
+        local function confined(filename)
+          local path = UnicornObject:path(filename)
+          if path == nil then
+            error("access denied: "..filename, 3)
+          end
+          return path
+        end
+
+        io = {
+          open = function(filename, mode)
+            return open(confined(filename), mode)
+          end,
+          lines = function(filename, ...)
+            return lines(confined(filename), ...)
+          end,
+        }
+        os = { clock = clock, date = date, difftime = difftime, time = time }
+        debug = { traceback = traceback }
+
+        -- No binary chunks
+        load = function(chunk, name, mode, env)
+          return load_chunk(chunk, name, "t", env)
+        end
+        dofile = nil
+        loadfile = nil
+
+        -- Lua modules of the cartridge directory only
+        package.path = UnicornObject:path("?.lua")
+        package.cpath = ""
+        package.loadlib = nil
+        package.searchpath = nil
+        package.searchers[4] = nil
+        package.searchers[3] = nil
+        package.loaded.io = io
+        package.loaded.os = os
+        package.loaded.debug = debug
+      end
+    "#;
+
+    /// Evaluation of the REPL, and the names completed by the REPL
+    const LUA_REPL: &'static str = r#"
+      function UnicornLua.repl(code)
+        local chunk, err = load("return "..code, "=repl", "t")
+        if chunk == nil then
+          chunk, err = load(code, "=repl", "t")
+        end
+        if chunk == nil then
+          error(err, 0)
+        end
+
+        local results = table.pack(chunk())
+        for i = 1, results.n do
+          results[i] = tostring(results[i])
+        end
+        return table.concat(results, "\t", 1, results.n)
+      end
+
+      function UnicornLua.globals()
+        local names = {}
+        for name in pairs(_G) do
+          if type(name) == "string" then
+            names[#names + 1] = name
+          end
+        end
+        return table.concat(names, " ")
+      end
+    "#;
+
+    /// Searcher of `require` for the code files of the cartridge, before the
+    /// Lua modules of the directory
+    const LUA_FILES: &'static str = r#"
+      do
+        local load = load
+
+        UnicornLua.files = {}
+
+        local function module_name(filename)
+          return (string.gsub(string.gsub(filename, "%.lua$", ""), "/", "."))
+        end
+
+        function UnicornLua.searcher(name)
+          local filename = string.gsub(name, "%.", "/")..".lua"
+          local source = UnicornLua.files[filename]
+          if source == nil then
+            return "\n\tno file '"..filename.."' in the cartridge"
+          end
+
+          local chunk, err = load(source, "="..filename, "t")
+          if chunk == nil then
+            error(err, 0)
+          end
+          return chunk, filename
+        end
+
+        -- The modules are loaded again with the new code
+        function UnicornLua.unload()
+          for filename in pairs(UnicornLua.files) do
+            package.loaded[module_name(filename)] = nil
+          end
+        end
+
+        table.insert(package.searchers, 2, UnicornLua.searcher)
+      end
+    "#;
+
+    /// Scheduler of the tasks, see `plugins::scheduler`. The coroutines of the
+    /// tasks are resumed by `LuaPlugin::tick`
+    const LUA_SCHEDULER: &'static str = r#"
+      do
+        local tasks, last_id = {}, 0
+
+        function spawn(fn, ...)
+          local args = table.pack(...)
+          last_id = last_id + 1
+          tasks[last_id] = {
+            thread = coroutine.create(function()
+              return fn(table.unpack(args, 1, args.n))
+            end),
+            wait = 0,
+          }
+          return last_id
+        end
+
+        function wait(frames)
+          if not coroutine.isyieldable() then
+            error("wait called outside of a task", 2)
+          end
+          coroutine.yield(frames or 1)
+        end
+
+        function after(seconds, fn)
+          return spawn(function()
+            wait(seconds * UnicornLua.fps)
+            fn()
+          end)
+        end
+
+        function every(seconds, fn)
+          return spawn(function()
+            repeat
+              wait(seconds * UnicornLua.fps)
+            until fn() == false
+          end)
+        end
+
+        function tween(obj, field, to, duration, easing)
+          easing = easing or "linear"
+          if type(easing) ~= "function" then
+            local name = easing
+            if ease(name, 0) == nil then
+              error("unknown easing "..tostring(name), 2)
+            end
+            easing = function(t) return ease(name, t) end
+          end
 
-    use gfx::Screen;
+          return spawn(function()
+            local from = obj[field]
+            local frames = math.max(1, math.floor(duration * UnicornLua.fps + 0.5))
+            for frame = 1, frames do
+              obj[field] = from + (to - from) * easing(frame / frames)
+              if frame < frames then
+                wait()
+              end
+            end
+          end)
+        end
+
+        function cancel(id)
+          tasks[id] = nil
+        end
+
+        -- The tasks to resume in this frame, in the order of their creation
+        function UnicornLua.ready()
+          local ids = {}
+          for id, task in pairs(tasks) do
+            if task.done then
+              tasks[id] = nil
+            else
+              ids[#ids + 1] = id
+            end
+          end
+          table.sort(ids)
+
+          local ready = {}
+          for _, id in ipairs(ids) do
+            local task = tasks[id]
+            task.wait = task.wait - 1
+            if task.wait <= 0 then
+              ready[#ready + 1] = task
+            end
+          end
+          return ready
+        end
+
+        function UnicornLua.reset_tasks()
+          for id in pairs(tasks) do
+            tasks[id] = nil
+          end
+        end
+      end
+    "#;
 
     pub struct ExtraData {
         /* External objects */
-        pub players: Arc<Mutex<Players>>,
-        pub screen: Arc<Mutex<Screen>>,
-        pub info: Arc<Mutex<Info>>,
-        pub noise: Arc<Mutex<Noise>>,
-        pub sound: Arc<Mutex<Sound>>,
+        pub context: ApiContext,
+        pub sandbox: SandboxPolicy,
+        /* Budget of the current call */
+        pub budget: Budget,
+    }
+
+    /// Abort the script when the budget of the call is exceeded
+    extern "C" fn budget_hook(lua_context: *mut lua_State, _ar: *mut lua_Debug) {
+        let mut state = unsafe { State::from_ptr(lua_context) };
+
+        let spent = state.with_extra_typed(|data: &mut ExtraData| {
+                                               data.budget.spend(HOOK_COUNT as u64)
+                                           });
+        if !spent {
+            // Check every instruction, so a pcall can't catch the error forever
+            state.set_hook(Some(budget_hook), MASKCOUNT, 1);
+
+            state.location(0);
+            state.push_string(sandbox::BUDGET_EXCEEDED);
+            state.concat(2);
+            state.error();
+        }
     }
 
     pub struct LuaPlugin {
         lua_state: Arc<Mutex<lua::State>>,
         loaded_code: bool,
+        error: Option<ScriptError>,
+        /// Sources of the modules of the cartridge, by filename
+        files: HashMap<String, String>,
     }
 
     impl LuaPlugin {
@@ -37,24 +268,20 @@ pub mod plugin {
             LuaPlugin {
                 lua_state: Arc::new(Mutex::new(lua::State::new())),
                 loaded_code: false,
+                error: None,
+                files: HashMap::new(),
             }
         }
 
         #[allow(unused)]
-        pub fn load(&mut self,
-                    players: Arc<Mutex<Players>>,
-                    info: Arc<Mutex<Info>>,
-                    screen: Arc<Mutex<Screen>>,
-                    noise: Arc<Mutex<Noise>>,
-                    sound: Arc<Mutex<Sound>>) {
+        pub fn load(&mut self, context: ApiContext, sandbox: SandboxPolicy) {
             info!("[PLUGIN][LUA] Init plugin");
 
+            let enabled = sandbox.enabled;
             let extra = ExtraData {
-                players: players.clone(),
-                info: info.clone(),
-                screen: screen.clone(),
-                noise: noise.clone(),
-                sound: sound.clone(),
+                context: context,
+                sandbox: sandbox,
+                budget: Budget::unlimited(),
             };
 
             let mut lua_state = self.lua_state.lock().unwrap();
@@ -74,6 +301,9 @@ pub mod plugin {
 
             lua_state.pop(2);
 
+            let value = lua_state.do_string(LUA_REPL);
+            info!("[PLUGIN][LUA][Unicorn][REPL] = {:?}", value);
+
             /* Create the UnicornLua object */
             let value = lua_state.do_string("UnicornObject = UnicornLua.new()");
             info!("[PLUGIN][LUA][Unicorn][CREATE Unicorn OBJECT] = {:?}", value);
@@ -81,715 +311,138 @@ pub mod plugin {
             let value = lua_state.do_string(r#"debug_print = print"#);
             info!("[PLUGIN][LUA][Unicorn][EXPORT DEBUG PRINT FUNCTION] = {:?}", value);
 
-            /* Audio */
-            let value = lua_state.do_string(r#"chiptune_play = function(filetype, filename, loops, start_position, channel)
-              if start_position == nil then
-                start_position = 0
-              end
-
-              if loops == nil then
-                loops = 0
-              end
-
-              if channel == nil then
-                channel = -1
-              end
-
-              UnicornObject:chiptune_play(filetype, filename, loops, start_position, channel)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][SOUND_PLAY] = {:?}", value);
-
-            let value = lua_state.do_string(r#"camera = function(x, y)
-
-              x = math.floor(x)
-              y = math.floor(y)
-
-              UnicornObject:camera(x, y)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][CAMERA] = {:?}", value);
-
-            let value = lua_state.do_string(r#"btn = function(x, p)
-
-              x = math.floor(x)
-
-              if p == nil then
-                p = 0
-              end
-
-              return UnicornObject:btn(p, x) == 1
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][TBN] = {:?}", value);
-
-            let value = lua_state.do_string(r#"btnp = function(x, p)
-
-              x = math.floor(x)
-
-              if p == nil then
-                p = 0
-              end
+            /* API generated from plugins::api */
+            let value = lua_state.do_string(&api::lua_bindings());
+            info!("[PLUGIN][LUA][Unicorn][API] = {:?}", value);
 
-              return UnicornObject:btnp(p, x) == 1
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][BTNP] = {:?}", value);
+            if enabled {
+                let value = lua_state.do_string(LUA_SANDBOX);
+                info!("[PLUGIN][LUA][Unicorn][SANDBOX] = {:?}", value);
+            }
 
+            // After the sandbox, which removes some searchers
+            let value = lua_state.do_string(LUA_FILES);
+            info!("[PLUGIN][LUA][Unicorn][FILES] = {:?}", value);
 
-            let value = lua_state.do_string(r#"rect = function(x0, y0, x1, y1, color)
+            let value = lua_state.do_string(LUA_SCHEDULER);
+            info!("[PLUGIN][LUA][Unicorn][SCHEDULER] = {:?}", value);
 
-              x0 = math.floor(x0)
-              y0 = math.floor(y0)
-              x1 = math.floor(x1)
-              y1 = math.floor(y1)
+            let value = lua_state.do_string(&format!("UnicornLua.fps = {}", scheduler::FRAMES_PER_SECOND));
+            info!("[PLUGIN][LUA][Unicorn][FPS] = {:?}", value);
 
-              if color == nil then
-                color = -1
+            let value = lua_state.do_string(r#"map = function(cel_x, cel_y, sx, sy, cel_w, cel_h, layer)
+              spr_map(cel_x, cel_y, sx, sy, cel_w, cel_h, layer)
               end
 
-              color = math.floor(color)
-
-              UnicornObject:rect(x0, y0, x1, y1, color)
+              mapdraw = function(cel_x, cel_y, sx, sy, cel_w, cel_h, layer)
+              spr_map(cel_x, cel_y, sx, sy, cel_w, cel_h, layer)
               end
               "#);
-            info!("[PLUGIN][LUA][Unicorn][RECT] = {:?}", value);
-
-            let value = lua_state.do_string(r#"rectfill = function(x0, y0, x1, y1, color)
-
-              x0 = math.floor(x0)
-              y0 = math.floor(y0)
-              x1 = math.floor(x1)
-              y1 = math.floor(y1)
-
-              if color == nil then
-                color = -1
-              end
-
-              color = math.floor(color)
+            info!("[PLUGIN][LUA][Unicorn][MAP] = {:?}", value);
 
-              UnicornObject:rectfill(x0, y0, x1, y1, color)
+            let value = lua_state.do_string(r#"time = function()
+                return unicorn_time()
               end
               "#);
-            info!("[PLUGIN][LUA][Unicorn][RECTFILL] = {:?}", value);
-
-            let value = lua_state.do_string(r#"circ = function(x, y, r, color)
-              x = math.floor(x)
-              y = math.floor(y)
-              r = math.floor(r)
-
-              if color == nil then
-                color = -1
-              end
-
-              color = math.floor(color)
+            info!("[PLUGIN][LUA][Unicorn][TIME] = {:?}", value);
 
-              UnicornObject:circ(x, y, r, color)
+            let value = lua_state.do_string(r#"flip = function()
               end
               "#);
-            info!("[PLUGIN][LUA][Unicorn][CIRC] = {:?}", value);
-
-            let value = lua_state.do_string(r#"circfill = function(x, y, r, color)
-              x = math.floor(x)
-              y = math.floor(y)
-              r = math.floor(r)
-
-              if color == nil then
-                color = -1
-              end
-
-              color = math.floor(color)
+            info!("[PLUGIN][LUA][Unicorn][FLIP] = {:?}", value);
 
-              UnicornObject:circfill(x, y, r, color)
+            let value = lua_state.do_string(r#"peek = function(addr)
+                return 0
               end
               "#);
-            info!("[PLUGIN][LUA][Unicorn][CIRCFILL] = {:?}", value);
-
-            let value = lua_state.do_string(r#"clip = function(x, y, w, h)
-              if x == nil then
-                x = -1
-              end
-              if y == nil then
-                y = -1
-              end
-              if w == nil then
-                w = -1
-              end
-              if h == nil then
-                h = -1
-              end
-
-              x = math.floor(x)
-              y = math.floor(y)
-              w = math.floor(w)
-              h = math.floor(h)
+            info!("[PLUGIN][LUA][Unicorn][PEEK] = {:?}", value);
 
-              UnicornObject:clip(x, y, w, h)
+            let value = lua_state.do_string(r#"poke = function(addr, val)
               end
               "#);
-            info!("[PLUGIN][LUA][Unicorn][CLIP] = {:?}", value);
-
-            let value = lua_state.do_string(r#"ellipse = function(x, y, rx, ry, color)
-              x = math.floor(x)
-              y = math.floor(y)
-              rx = math.floor(rx)
-              ry = math.floor(ry)
-
-              if color == nil then
-                color = -1
-              end
-
-              color = math.floor(color)
+            info!("[PLUGIN][LUA][Unicorn][POKE] = {:?}", value);
 
-              UnicornObject:ellipse(x, y, rx, ry, color)
+            /* CARTDATA */
+            let value = lua_state.do_string(r#"cartdata = function(x)
               end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][ELLIPSE] = {:?}", value);
-
-            let value = lua_state.do_string(r#"ellipsefill = function(x, y, rx, ry, color)
-              x = math.floor(x)
-              y = math.floor(y)
-              rx = math.floor(rx)
-              ry = math.floor(ry)
 
-              if color == nil then
-                color = -1
+              dget = function(x)
+                return 0
               end
 
-              color = math.floor(color)
-
-              UnicornObject:ellipsefill(x, y, rx, ry, color)
+              dset = function(x, y)
               end
               "#);
-            info!("[PLUGIN][LUA][Unicorn][ELLIPSEFILL] = {:?}", value);
-
-            let value = lua_state.do_string(r#"fget = function(idx, flag)
-              idx = math.floor(idx)
-              flag = math.floor(flag)
+            info!("[PLUGIN][LUA][Unicorn][CARTDATA] = {:?}", value);
 
-              if flag == nil then
-                return UnicornObject:fget_all(idx)
-              end
 
-              return UnicornObject:fget(idx, flag)
+            /* PICO8 compatible functions */
 
+            let value = lua_state.do_string(r#"
+              function warning(msg)
+                log(debug.traceback("WARNING: "..msg,3))
               end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][FGET] = {:?}", value);
 
-            let value = lua_state.do_string(r#"fset = function(idx, flag, value)
-              idx = math.floor(idx)
-              flag = math.floor(flag)
-
-              if value == nil then
-                UnicornObject:fset_all(idx, flag)
-              else
-                if value == true then
-                    UnicornObject:fset(idx, flag, 1)
-                else
-                    UnicornObject:fset(idx, flag, 0)
+              function add(a,v)
+                if a == nil then
+                  warning("add to nil")
+                  return
                 end
+                table.insert(a,v)
               end
 
+              function del(a,dv)
+                if a == nil then
+                  warning("del from nil")
+                  return
+                end
+                for i,v in ipairs(a) do
+                  if v==dv  then
+                    table.remove(a,i)
+                  end
+                end
               end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][FSET] = {:?}", value);
-
-            let value = lua_state.do_string(r#"line = function(x0, y0, x1, y1, color)
-
-              x0 = math.floor(x0)
-              y0 = math.floor(y0)
-              x1 = math.floor(x1)
-              y1 = math.floor(y1)
-
-              if color == nil then
-                color = -1
-              end
-
-              color = math.floor(color)
-
-              UnicornObject:line(x0, y0, x1, y1, color)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][LINE] = {:?}", value);
-
-            let value = lua_state.do_string(r#"trigon = function(x1, y1, x2, y2, x3, y3, color)
-              x1 = math.floor(x1)
-              y1 = math.floor(y1)
-              x2 = math.floor(x2)
-              y2 = math.floor(y2)
-              x3 = math.floor(x3)
-              y3 = math.floor(y3)
-
-              if color == nil then
-                color = -1
-              end
-
-              color = math.floor(color)
-
-              UnicornObject:trigon(x1, y1, x2, y2, x3, y3, color)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][TRIGON] = {:?}", value);
-
-            let value = lua_state.do_string(r#"rnd = function(x)
-              if x == nil then
-                x = 1
-              end
-
-              x = math.floor(x)
-              return UnicornObject:rnd(x)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][RND] = {:?}", value);
-
-            let value = lua_state.do_string(r#"add = function(t, v)
-              t[#t+1] = v
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][ADD] = {:?}", value);
-
-            let value = lua_state.do_string(r#"cls = function(v)
-              if v == nil then
-                v = -1
-              end
-
-              UnicornObject:cls(v)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][CLS] = {:?}", value);
-
-
-            let value = lua_state.do_string(r#"palt = function(c, t)
-              c = math.floor(c)
-
-              if t == true then
-                t = 1
-              else
-                t = 0
-              end
-
-              UnicornObject:palt(c, t)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][PALT] = {:?}", value);
-
-            let value = lua_state.do_string(r#"pal = function(c0, c1, p)
-              if c0 == nil then
-                c0 = -1
-              end
-
-              if c1 == nil then
-                c1 = -1
-              end
-
-              if p == nil then
-                p = -1
-              end
-
-              UnicornObject:pal(c0, c1, p)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][PAL] = {:?}", value);
-
-            let value = lua_state.do_string(r#"font = function(name)
-
-              if name == nil then
-                name = "pico8"
-              end
-
-              UnicornObject:font(name)
-
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][FONT] = {:?}", value);
-
-            let value = lua_state.do_string(r#"pset = function(x, y, color)
-              x = math.floor(x)
-              y = math.floor(y)
-
-              if color == nil then
-                color = -1
-              end
-
-              color = math.floor(color)
-
-              UnicornObject:pset(x, y, color)
-
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][PSET] = {:?}", value);
-
-            let value = lua_state.do_string(r#"pget = function(x, y)
-              x = math.floor(x)
-              y = math.floor(y)
-
-              return UnicornObject:pget(x, y)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][PGET] = {:?}", value);
-
-            let value = lua_state.do_string(r#"sget = function(x, y)
-              x = math.floor(x)
-              y = math.floor(y)
-
-              return UnicornObject:sget(x, y)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][SGET] = {:?}", value);
-
-            let value = lua_state.do_string(r#"sset = function(x, y, color)
-              x = math.floor(x)
-              y = math.floor(y)
-
-              if color == nil then
-                color = -1
-              end
-
-              color = math.floor(color)
-
-              UnicornObject:sset(x, y, c)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][SSET] = {:?}", value);
-
-            let value = lua_state.do_string(r#"noise = function(x, y, z)
-              return UnicornObject:noise(x, y, z)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][NOISE] = {:?}", value);
-
-            let value = lua_state.do_string(r#"noise_set_seed = function(seed)
-              return UnicornObject:noise_set_seed(seed)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][NOISE_SET_SEED] = {:?}", value);
-
-            let value = lua_state.do_string(r#"map = function(cel_x, cel_y, sx, sy, cel_w, cel_h, layer)
-
-              cel_x = math.floor(cel_x)
-              cel_y = math.floor(cel_y)
-              sx = math.floor(sx)
-              sy = math.floor(sy)
-              cel_w = math.floor(cel_w)
-              cel_h = math.floor(cel_h)
 
-              if layer == nil then
-                layer = 0
+              function foreach(a,f)
+                if not a then
+                  warning("foreach got a nil value")
+                  return
+                end
+                for i,v in ipairs(a) do
+                  f(v)
+                end
               end
 
-              UnicornObject:map(cel_x, cel_y, sx, sy, cel_w, cel_h, layer)
+              function count(a)
+                return #a
               end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][MAP] = {:?}", value);
 
-            let value = lua_state.do_string(r#"mapdraw = function(cel_x, cel_y, sx, sy, cel_w, cel_h, layer)
-                map(cel_x, cel_y, sx, sy, cel_w, cel_h, layer)
+              function all(a)
+                local i = 0
+                local n = #a
+                return function()
+                    i = i + 1
+                    if i <= n  then
+                        return a[i]
+                    end
                 end
-                "#);
-            info!("[PLUGIN][LUA][Unicorn][MAPDRAW] = {:?}", value);
-
-            let value = lua_state.do_string(r#"mget = function(x, y)
-              x = math.floor(x)
-              y = math.floor(y)
-
-              return UnicornObject:mget(x, y)
               end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][MGET] = {:?}", value);
-
-            let value = lua_state.do_string(r#"mset = function(x, y, v)
-              x = math.floor(x)
-              y = math.floor(y)
-              v = math.floor(v)
 
-              UnicornObject:mset(x, y, v)
-              end
+              sub = string.sub
               "#);
-            info!("[PLUGIN][LUA][Unicorn][MSET] = {:?}", value);
+            info!("[PLUGIN][LUA] LOADED LUA FUNCTIONS = {:?}", value);
 
-            let value = lua_state.do_string(r#"spr = function(n, x, y, w, h, flip_x, flip_y)
-
-              n = math.floor(n)
-              x = math.floor(x)
-              y = math.floor(y)
-              if w == nil then
-                w = 1
-              end
-              if h == nil then
-                h = 1
-              end
-              if flip_x == nil then
-                flip_x = false
-              end
-              if flip_y == nil then
-                flip_y = false
-              end
-
-              if flip_x == true then
-                flip_x = 1
-              else
-                flip_x = 0
-              end
-
-              if flip_y == true then
-                flip_y = 1
-              else
-                flip_y = 0
-              end
-
-              UnicornObject:spr(n, x, y, w, h, flip_x, flip_y)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][SPR] = {:?}", value);
-
-            let value = lua_state.do_string(r#"sspr = function(sx, sy, sw, sh, dx, dy, dw, dh, flip_x, flip_y)
-              sx = math.floor(sx)
-              sy = math.floor(sy)
-              sw = math.floor(sw)
-              sh = math.floor(sh)
-              dx = math.floor(dx)
-              dy = math.floor(dy)
-
-              if dw == nil then
-                dw = sw
-              end
-              if dh == nil then
-                dh = sh
-              end
-              if flip_x == nil then
-                flip_x = false
-              end
-              if flip_y == nil then
-                flip_y = false
-              end
-
-              if flip_x == true then
-                flip_x = 1
-              else
-                flip_x = 0
-              end
-
-              if flip_y == true then
-                flip_y = 1
-              else
-                flip_y = 0
-              end
-
-              UnicornObject:sspr(sx, sy, sw, sh, dx, dy, dw, dh, flip_x, flip_y)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][SSPR] = {:?}", value);
-
-            let value = lua_state.do_string(r#"print = function(str, x, y, col)
-              if x == nil then
-                x = -1
-              end
-
-              if y == nil then
-                y = -1
-              end
-
-              if col == nil then
-                col = -1
-              end
-
-              x = math.floor(x)
-              y = math.floor(y)
-              col = math.floor(col)
-
-              UnicornObject:print(str, x, y, col)
-
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][PRINT] = {:?}", value);
-
-            let value = lua_state.do_string(r#"time = function()
-                return UnicornObject:time()
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][TIME] = {:?}", value);
-
-            let value = lua_state.do_string(r#"sfx = function(n, channel, offset)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][SFX] = {:?}", value);
-
-            let value = lua_state.do_string(r#"music = function(id, filename, channel, loops, start_position)
-              if filename == nil then
-                filename = ""
-              end
-
-              if channel == nil then
-                channel = -1
-              end
-
-              if loops == nil then
-                loops = -1
-              end
-
-              if start_position == nil then
-                start_position = 0
-              end
-
-              UnicornObject:music(id, filename, channel, loops, start_position)
-
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][MUSIC] = {:?}", value);
-
-            let value = lua_state.do_string(r#"sfx = function(id, filename, channel, note, panning, rate, loops)
-              if filename == nil then
-                filename = ""
-              end
-
-              if channel == nil then
-                channel = -1
-              end
-
-              if note == nil then
-                note = 13312
-              end
-
-              if panning == nil then
-                panning = 64
-              end
-
-              if rate == nil then
-                rate = 50
-              end
-
-              if loops == nil then
-                loops = -1
-              end
-              
-              UnicornObject:sfx(id, filename, channel, note, panning, rate, loops)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][SFX] = {:?}", value);
-
-
-            let value = lua_state.do_string(r#"flip = function()
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][FLIP] = {:?}", value);
-
-            let value = lua_state.do_string(r#"color = function(c)
-                c = math.floor(c)
-                UnicornObject:color(c)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][COLOR] = {:?}", value);
-
-            let value = lua_state.do_string(r#"peek = function(addr)
-                return 0
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][PEEK] = {:?}", value);
-
-            let value = lua_state.do_string(r#"poke = function(addr, val)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][POKE] = {:?}", value);
-
-            let value = lua_state.do_string(r#"stat = function(x)
-                v = UnicornObject:stat(x)
-                return v
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][STAT] = {:?}", value);
-
-            /* CARTDATA */
-            let value = lua_state.do_string(r#"cartdata = function(x)
-              x = math.floor(x)
-              UnicornObject:cartdata(x)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][CARTDATA] = {:?}", value);
-
-            let value = lua_state.do_string(r#"dget = function(x)
-              x = math.floor(x)
-              return UnicornObject:dget(x)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][DGET] = {:?}", value);
-
-            let value = lua_state.do_string(r#"dset = function(x, y)
-              x = math.floor(x)
-              y = math.floor(y)
-
-              UnicornObject:dset(x, y)
-              end
-              "#);
-            info!("[PLUGIN][LUA][Unicorn][DSET] = {:?}", value);
-
-            /* PICO8 compatible functions */
-
-            let value = lua_state.do_string(r#"
-              function warning(msg)
-                log(debug.traceback("WARNING: "..msg,3))
-              end
-
-              function add(a,v)
-                if a == nil then
-                  warning("add to nil")
-                  return
-                end
-                table.insert(a,v)
-              end
-
-              function del(a,dv)
-                if a == nil then
-                  warning("del from nil")
-                  return
-                end
-                for i,v in ipairs(a) do
-                  if v==dv  then
-                    table.remove(a,i)
-                  end
-                end
-              end
-
-              function foreach(a,f)
-                if not a then
-                  warning("foreach got a nil value")
-                  return
-                end
-                for i,v in ipairs(a) do
-                  f(v)
-                end
-              end
-
-              function count(a)
-                return #a
-              end
-
-              function all(a)
-                local i = 0
-                local n = #a
-                return function()
-                    i = i + 1
-                    if i <= n  then
-                        return a[i]
-                    end
-                end
-              end
-
-              sub = string.sub
-              "#);
-            info!("[PLUGIN][LUA] LOADED LUA FUNCTIONS = {:?}", value);
-
-            let value = lua_state.do_string(r#"
-            function min(a,b)
-                if a == nil or b == nil then
-                        warning("min a or b are nil returning 0")
-                        return 0
-                end
-                if a < b then
-                    return a
-                end
-                return b
-            end
+            let value = lua_state.do_string(r#"
+            function min(a,b)
+                if a == nil or b == nil then
+                        warning("min a or b are nil returning 0")
+                        return 0
+                end
+                if a < b then
+                    return a
+                end
+                return b
+            end
 
             function max(a,b)
                 if a == nil or b == nil then
@@ -800,1312 +453,575 @@ pub mod plugin {
                     return a
                 end
                 return b
-            end
-
-            function mid(x,y,z)
-                x = x or 0
-                y = y or 0
-                z = z or 0
-                return x > y and x or y > z and z or y
-            end
-            function __pico_angle(a)
-              -- FIXME: why does this work?
-              return (((a - math.pi) / (math.pi*2)) + 0.25) % 1.0
-            end
-
-            flr = math.floor
-            ceil = math.ceil
-            cos = function(x) return math.cos((x or 0)*(math.pi*2)) end
-            sin = function(x) return math.sin(-(x or 0)*(math.pi*2)) end
-            atan2 = function(y,x) return __pico_angle(math.atan2(y,x)) end
-
-            sqrt = math.sqrt
-            abs = math.abs
-
-            sgn = function(x)
-                if x < 0 then
-                    return -1
-                else
-                    return 1
-                end
-            end
-
-            band = function(x, y)
-              x = math.floor(x)
-              y = math.floor(y)
-              return x & y
-            end
-
-            bor = function(x, y)
-              x = math.floor(x)
-              y = math.floor(y)
-              return x | y
-            end
-            bxor = function(x, y)
-              x = math.floor(x)
-              y = math.floor(y)
-              return x ~ y
-            end
-            bnot = function(x)
-              x = math.floor(x)
-              return ~x
-            end
-            shl = function(x, y)
-              x = math.floor(x)
-              y = math.floor(y)
-              return x << y
-            end
-            shr = function(x, y)
-              x = math.floor(x)
-              y = math.floor(y)
-              return x >> y
-            end
-            "#);
-            info!("[PLUGIN][LUA] LOADED MATH FUNCTIONS = {:?}", value);
-        }
-
-        pub fn init(&mut self) {
-            if !self.loaded_code {
-                return;
-            }
-
-            let mut lua_state = self.lua_state.lock().unwrap();
-
-            let value = lua_state.do_string("_init()");
-            if value != ThreadStatus::Ok {
-                error!("[PLUGIN][LUA] INIT = {:?}", value);
-            } else {
-                info!("[PLUGIN][LUA] INIT SUCCESS");
-            }
-        }
-
-        pub fn draw(&mut self) -> bool {
-            if !self.loaded_code {
-                return false;
-            }
-
-            let mut lua_state = self.lua_state.lock().unwrap();
-
-            let value = lua_state.do_string("_draw()");
-            if value != ThreadStatus::Ok {
-                error!("[PLUGIN][LUA] DRAW = {:?}", value);
-            }
-
-            return true;
-        }
-
-        pub fn update(&mut self) -> bool {
-            if !self.loaded_code {
-                return false;
-            }
-
-            let mut lua_state = self.lua_state.lock().unwrap();
-
-            let value = lua_state.do_string("_update()");
-            if value != ThreadStatus::Ok {
-                let value = lua_state.do_string("_update60()");
-                if value != ThreadStatus::Ok {
-                    error!("[PLUGIN][LUA] UPDATE = {:?}", value);
-                }
-            }
-
-            return true;
-
-        }
-
-        pub fn load_code(&mut self, data: String) -> bool {
-            info!("[PLUGIN][LUA] LOAD CODE");
-            let mut lua_state = self.lua_state.lock().unwrap();
-
-            self.loaded_code = true;
-
-            let value = lua_state.do_string(&data);
-            if value != ThreadStatus::Ok {
-                error!("[PLUGIN][LUA] LOAD CODE = {:?}", value);
-                self.loaded_code = false;
-            }
-
-            self.loaded_code
-        }
-    }
-
-    struct UnicornLua {}
-
-    impl UnicornLua {
-        fn new() -> UnicornLua {
-            return UnicornLua {};
-        }
-
-        #[allow(non_snake_case)]
-        unsafe extern "C" fn lua_new(lua_context: *mut lua_State) -> c_int {
-            let mut state = State::from_ptr(lua_context);
-
-            // construct new userdata in lua space and initialize it
-            *state.new_userdata_typed::<UnicornLua>() = UnicornLua::new();
-            // set the userdata's metatable so we can call methods on it
-            state.set_metatable_from_registry("UnicornLua");
-            // return the userdata on top of the stack
-            1
-        }
-
-        unsafe extern "C" fn lua_chiptune_music(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA CHIPTUNE MUSIC");
-
-            let mut state = State::from_ptr(lua_context);
-            let mut state2 = State::from_ptr(lua_context);
-
-            let id = state2.check_integer(2);
-            let filename = state.check_string(3);
-            let loops = state2.check_integer(4);
-            let start_position = state2.check_integer(5);
-            let channel = state2.check_integer(6);
-
-            let sound = state2.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.sound.clone()
-                                          });
-
-            sound
-                .lock()
-                .unwrap()
-                .music(id as i32, filename.to_string(), channel as i32, loops as i32, start_position as i32);
-
-            1
-        }
-
-        unsafe extern "C" fn lua_chiptune_sfx(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA CHIPTUNE SFX");
-
-            let mut state = State::from_ptr(lua_context);
-            let mut state2 = State::from_ptr(lua_context);
-
-            let id = state2.check_integer(2);
-            let filename = state.check_string(3);
-            let channel = state2.check_integer(4);
-            let note = state2.check_integer(5);
-            let panning = state2.check_integer(6);
-            let rate = state2.check_integer(7);
-            let loops = state2.check_integer(8);
-
-            let sound = state2.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.sound.clone()
-                                          });
-
-            sound
-                .lock()
-                .unwrap()
-                .sfx(id as i32, filename.to_string(), channel as i32, note as u16, panning as i32, rate as i32, loops as i32);
-
-            1
-        }
-
-        unsafe extern "C" fn lua_camera(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA CAMERA");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen.lock().unwrap().camera(x as i32, y as i32);
-
-            1
-        }
-
-        unsafe extern "C" fn lua_color(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA COLOR");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let value = state.check_integer(2);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen.lock().unwrap().color(value as i32);
-
-            1
-        }
-
-        unsafe extern "C" fn lua_btn(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA BTN");
-
-            let mut state = State::from_ptr(lua_context);
-            let mut state2 = State::from_ptr(lua_context);
-
-            let players = state2.with_extra(|extra| {
-                                                let data = extra
-                                                    .as_ref()
-                                                    .unwrap()
-                                                    .downcast_ref::<ExtraData>()
-                                                    .unwrap();
-                                                data.players.clone()
-                                            });
-
-            let player = state.check_integer(2);
-
-            let i = state.check_integer(3);
-
-            let players_data = players.lock().unwrap();
-
-            let value = players_data.get_value(player as u8, i as u8);
-
-            state.push_integer(value as i64);
-
-            1
-        }
-
-
-        unsafe extern "C" fn lua_btnp(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA BTN");
-
-            let mut state = State::from_ptr(lua_context);
-            let mut state2 = State::from_ptr(lua_context);
-
-            let players = state2.with_extra(|extra| {
-                                                let data = extra
-                                                    .as_ref()
-                                                    .unwrap()
-                                                    .downcast_ref::<ExtraData>()
-                                                    .unwrap();
-                                                data.players.clone()
-                                            });
-
-            let player = state.check_integer(2);
-            let i = state.check_integer(3);
-
-            let mut players_data = players.lock().unwrap();
-
-            let value = players_data.get_value_quick(player as u8, i as u8);
-
-            state.push_integer(value as i64);
-
-            1
-        }
-
-        unsafe extern "C" fn lua_cls(lua_context: *mut lua_State) -> c_int {
-            let mut state = State::from_ptr(lua_context);
-
-            let value = state.check_integer(2);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen.lock().unwrap().cls(value as i8);
-
-            1
-        }
-
-        #[allow(non_snake_case)]
-        unsafe extern "C" fn lua_rect(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA RECT");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x0 = state.check_integer(2);
-            let y0 = state.check_integer(3);
-            let x1 = state.check_integer(4);
-            let y1 = state.check_integer(5);
-            let col = state.check_integer(6);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen
-                .lock()
-                .unwrap()
-                .rect(x0 as i32, y0 as i32, x1 as i32, y1 as i32, col as i32);
-
-            1
-        }
-
-        #[allow(non_snake_case)]
-        unsafe extern "C" fn lua_rectfill(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA RECTFILL");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x0 = state.check_integer(2);
-            let y0 = state.check_integer(3);
-            let x1 = state.check_integer(4);
-            let y1 = state.check_integer(5);
-            let col = state.check_integer(6);
-
-            debug!("LUA RECTFILL x0:{:?} y0:{:?} x1:{:?} y1:{:?} col:{:?}",
-                   x0,
-                   y0,
-                   x1,
-                   y1,
-                   col);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-            screen
-                .lock()
-                .unwrap()
-                .rectfill(x0 as i32, y0 as i32, x1 as i32, y1 as i32, col as i32);
-
-            1
-        }
-
-        #[allow(non_snake_case)]
-        unsafe extern "C" fn lua_circ(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA CIRC");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
-            let r = state.check_integer(4);
-            let col = state.check_integer(5);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen
-                .lock()
-                .unwrap()
-                .circ(x as i32, y as i32, r as i32, col as i32);
-
-            1
-        }
-
-        #[allow(non_snake_case)]
-        unsafe extern "C" fn lua_circfill(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA CIRCFILL");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
-            let r = state.check_integer(4);
-            let col = state.check_integer(5);
-
-            debug!("LUA CIRCFILL {:?} {:?} {:?} {:?}", x, y, r, col);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen
-                .lock()
-                .unwrap()
-                .circfill(x as i32, y as i32, r as i32, col as i32);
-
-            1
-        }
-
-        #[allow(non_snake_case)]
-        unsafe extern "C" fn lua_clip(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA CLIP");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
-            let w = state.check_integer(4);
-            let h = state.check_integer(5);
-
-            debug!("LUA CLIP {:?} {:?} {:?} {:?}", x, y, w, h);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen
-                .lock()
-                .unwrap()
-                .clip(x as i32, y as i32, w as i32, h as i32);
-
-            1
-        }
-
-        #[allow(non_snake_case)]
-        unsafe extern "C" fn lua_ellipse(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA ELLIPSE");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
-            let rx = state.check_integer(4);
-            let ry = state.check_integer(5);
-            let col = state.check_integer(6);
-
-            debug!("LUA ELLIPSE x:{:?} y:{:?} rx:{:?} ry:{:?} col:{:?}",
-                   x,
-                   y,
-                   rx,
-                   ry,
-                   col);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-            screen
-                .lock()
-                .unwrap()
-                .ellipse(x as i32, y as i32, rx as i32, ry as i32, col as i32);
-
-            1
-        }
-
-        #[allow(non_snake_case)]
-        unsafe extern "C" fn lua_ellipsefill(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA ELLIPSEFILL");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
-            let rx = state.check_integer(4);
-            let ry = state.check_integer(5);
-            let col = state.check_integer(6);
-
-            debug!("LUA ELLIPSEFILL x:{:?} y:{:?} rx:{:?} ry:{:?} col:{:?}",
-                   x,
-                   y,
-                   rx,
-                   ry,
-                   col);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-            screen
-                .lock()
-                .unwrap()
-                .ellipsefill(x as i32, y as i32, rx as i32, ry as i32, col as i32);
-
-            1
-        }
-
-
-        #[allow(non_snake_case)]
-        unsafe extern "C" fn lua_trigon(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA TRIGON");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x1 = state.check_integer(2);
-            let y1 = state.check_integer(3);
-            let x2 = state.check_integer(4);
-            let y2 = state.check_integer(5);
-            let x3 = state.check_integer(6);
-            let y3 = state.check_integer(7);
-            let col = state.check_integer(8);
-
-            debug!("LUA TRIGON x1:{:?} y1:{:?} x2:{:?} y2:{:?} x3:{:?} y3:{:?} col:{:?}",
-                   x1,
-                   y1,
-                   x2,
-                   y2,
-                   x3,
-                   y3,
-                   col);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-            screen
-                .lock()
-                .unwrap()
-                .trigon(x1 as i32,
-                        y1 as i32,
-                        x2 as i32,
-                        y2 as i32,
-                        x3 as i32,
-                        y3 as i32,
-                        col as i32);
-
-            1
-        }
-
-        #[allow(non_snake_case)]
-        unsafe extern "C" fn lua_palt(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA PALT");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let c = state.check_integer(2);
-            let t = state.check_integer(3);
-
-            debug!("LUA PALT {:?} {:?}", c, t);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen.lock().unwrap().palt(c as i32, t == 1);
-
-            1
-        }
-
+            end
 
-        unsafe extern "C" fn lua_pal(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA PAL");
+            function mid(x,y,z)
+                x = x or 0
+                y = y or 0
+                z = z or 0
+                return x > y and x or y > z and z or y
+            end
+            function __pico_angle(a)
+              -- FIXME: why does this work?
+              return (((a - math.pi) / (math.pi*2)) + 0.25) % 1.0
+            end
 
-            let mut state = State::from_ptr(lua_context);
+            flr = math.floor
+            ceil = math.ceil
+            cos = function(x) return math.cos((x or 0)*(math.pi*2)) end
+            sin = function(x) return math.sin(-(x or 0)*(math.pi*2)) end
+            atan2 = function(y,x) return __pico_angle(math.atan2(y,x)) end
 
-            let c0 = state.check_integer(2);
-            let c1 = state.check_integer(3);
-            //            let p = state.check_integer(4);
+            sqrt = math.sqrt
+            abs = math.abs
 
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
+            sgn = function(x)
+                if x < 0 then
+                    return -1
+                else
+                    return 1
+                end
+            end
 
-            screen.lock().unwrap().pal(c0 as i32, c1 as i32);
+            band = function(x, y)
+              x = math.floor(x)
+              y = math.floor(y)
+              return x & y
+            end
 
-            1
+            bor = function(x, y)
+              x = math.floor(x)
+              y = math.floor(y)
+              return x | y
+            end
+            bxor = function(x, y)
+              x = math.floor(x)
+              y = math.floor(y)
+              return x ~ y
+            end
+            bnot = function(x)
+              x = math.floor(x)
+              return ~x
+            end
+            shl = function(x, y)
+              x = math.floor(x)
+              y = math.floor(y)
+              return x << y
+            end
+            shr = function(x, y)
+              x = math.floor(x)
+              y = math.floor(y)
+              return x >> y
+            end
+            "#);
+            info!("[PLUGIN][LUA] LOADED MATH FUNCTIONS = {:?}", value);
         }
 
-        unsafe extern "C" fn lua_pset(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA PSET");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
-            let col = state.check_integer(4);
+        /// Run the code of the cartridge
+        fn run(&mut self, code: &str) -> Result<(), ScriptError> {
+            let mut lua_state = self.lua_state.lock().unwrap();
 
-            if x < 0 || y < 0 {
-                return 1;
+            let chunk_name = format!("={}", CODE_FILENAME);
+            let value = lua_state.load_bufferx(code.as_bytes(), &chunk_name, "t");
+            if value != ThreadStatus::Ok {
+                let message = error_message(&mut lua_state, value);
+                return Err(ScriptError::from_lua(&message));
             }
 
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen.lock().unwrap().pset(x as i32, y as i32, col as i32);
-
-            1
+            protected_call(&mut lua_state, 0, 0)
         }
 
-        unsafe extern "C" fn lua_pget(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA PGET");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
+        /// Call the global function `name`, false if it is not defined
+        fn call(&mut self, name: &str) -> Result<bool, ScriptError> {
+            let mut lua_state = self.lua_state.lock().unwrap();
 
-            if x < 0 || y < 0 {
-                return 1;
+            lua_state.get_global(name);
+            if !lua_state.is_fn(-1) {
+                lua_state.pop(1);
+                return Ok(false);
             }
 
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            let value = screen.lock().unwrap().pget(x as u32, y as u32);
-
-            state.push_integer(value as i64);
-
-            1
+            protected_call(&mut lua_state, 0, 0).map(|_| true)
         }
 
+        /// Call `UnicornLua.name(arg)`, returning a string
+        fn call_helper(&mut self, name: &str, arg: Option<&str>) -> Result<String, ScriptError> {
+            let mut lua_state = self.lua_state.lock().unwrap();
 
-        unsafe extern "C" fn lua_sget(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA SGET");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
-
-            debug!("LUA SGET {:?} {:?}", x, y);
-
-            if x < 0 || y < 0 {
-                return 1;
-            }
-
-            if x as usize >= 128 || y as usize >= 128 {
-                return 1;
-            }
+            lua_state.get_global("UnicornLua");
+            lua_state.get_field(-1, name);
+            lua_state.remove(-2);
 
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
+            let nargs = match arg {
+                Some(arg) => {
+                    lua_state.push_string(arg);
+                    1
+                }
+                None => 0,
+            };
 
-            let value = screen.lock().unwrap().sget(x as u32, y as u32);
+            try!(protected_call(&mut lua_state, nargs, 1));
 
-            state.push_integer(value as i64);
+            let result = lua_state.to_str(-1).unwrap_or("").to_string();
+            lua_state.pop(1);
 
-            1
+            Ok(result)
         }
 
-        unsafe extern "C" fn lua_sset(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA SSET");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
-            let col = state.check_integer(4);
+        /// Modules of the cartridge, used by the next `load_code`
+        pub fn set_files(&mut self, files: HashMap<String, String>) {
+            self.files = files;
+        }
 
-            debug!("LUA SSET {:?} {:?} {:?}", x, y, col);
+        /// Replace the modules found by `require`
+        fn load_files(&mut self) -> Result<(), ScriptError> {
+            try!(self.call_helper("unload", None));
 
-            if x < 0 || y < 0 {
-                return 1;
-            }
+            let mut lua_state = self.lua_state.lock().unwrap();
 
-            if x as usize >= 128 || y as usize >= 128 {
-                return 1;
+            lua_state.get_global("UnicornLua");
+            lua_state.new_table();
+            for (filename, source) in &self.files {
+                lua_state.push_string(source);
+                lua_state.set_field(-2, filename);
             }
+            lua_state.set_field(-2, "files");
+            lua_state.pop(1);
 
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen.lock().unwrap().sset(x as u32, y as u32, col as i32);
-
-            1
+            Ok(())
         }
 
-        unsafe extern "C" fn lua_noise(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA NOISE");
-
-            let mut state = State::from_ptr(lua_context);
+        /// Resume the tasks of the scheduler waiting for this frame
+        fn resume_tasks(&mut self) -> Result<(), ScriptError> {
+            let mut lua_state = self.lua_state.lock().unwrap();
 
-            let x = state.check_number(2);
-            let y = state.check_number(3);
-            let z = state.check_number(4);
+            lua_state.get_global("UnicornLua");
+            lua_state.get_field(-1, "ready");
+            lua_state.remove(-2);
+            try!(protected_call(&mut lua_state, 0, 1));
 
-            debug!("LUA NOISE {:?} {:?} {:?}", x, y, z);
+            let mut idx = 1;
+            while lua_state.raw_geti(-1, idx) == Type::Table {
+                let result = resume_task(&mut lua_state);
+                lua_state.pop(1);
 
-            let noise = state.with_extra(|extra| {
-                                             let data = extra
-                                                 .as_ref()
-                                                 .unwrap()
-                                                 .downcast_ref::<ExtraData>()
-                                                 .unwrap();
-                                             data.noise.clone()
-                                         });
+                if result.is_err() {
+                    lua_state.pop(1);
+                    return result;
+                }
+                idx += 1;
+            }
 
-            let value = noise.lock().unwrap().get(x, y, z);
-            state.push_number(value);
+            // The end of the list, and the list
+            lua_state.pop(2);
 
-            1
+            Ok(())
         }
 
-        unsafe extern "C" fn lua_noise_set_seed(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA NOISE SET SEED");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let seed = state.check_integer(2);
-
-            debug!("LUA NOISE SET SEED {:?}", seed);
-
-            let noise = state.with_extra(|extra| {
-                                             let data = extra
-                                                 .as_ref()
-                                                 .unwrap()
-                                                 .downcast_ref::<ExtraData>()
-                                                 .unwrap();
-                                             data.noise.clone()
-                                         });
-
-            noise.lock().unwrap().set_seed(seed as u32);
-
-            1
+        /// Evaluate a line of the REPL, the results are separated by tabs
+        pub fn repl(&mut self, code: &str) -> Result<String, ScriptError> {
+            self.call_helper("repl", Some(code))
         }
 
-        unsafe extern "C" fn lua_line(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA LINE");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x0 = state.check_integer(2);
-            let y0 = state.check_integer(3);
-            let x1 = state.check_integer(4);
-            let y1 = state.check_integer(5);
-            let col = state.check_integer(6);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen
-                .lock()
-                .unwrap()
-                .line(x0 as i32, y0 as i32, x1 as i32, y1 as i32, col as i32);
-
-            1
+        /// Names of the global variables, completed by the REPL
+        pub fn globals(&mut self) -> Vec<String> {
+            match self.call_helper("globals", None) {
+                Ok(names) => names.split_whitespace().map(|name| name.to_string()).collect(),
+                Err(_) => Vec::new(),
+            }
         }
 
-        unsafe extern "C" fn lua_fget(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA FGET");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let idx = state.check_integer(2);
-            let flag = state.check_integer(3);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            state.push_bool(screen.lock().unwrap().fget(idx as u32, flag as u8));
-
-            1
+        /// Keep the error, displayed instead of the cartridge
+        fn report(&mut self, name: &str, error: ScriptError) {
+            error!("[PLUGIN][LUA] {} = {}", name, error.report());
+            self.error = Some(error);
         }
 
-        unsafe extern "C" fn lua_fget_all(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA FGET ALL");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let idx = state.check_integer(2);
+        pub fn take_error(&mut self) -> Option<ScriptError> {
+            self.error.take()
+        }
 
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
+        /// Memory of the virtual machine, in KB
+        pub fn memory(&mut self) -> f64 {
+            let mut lua_state = self.lua_state.lock().unwrap();
+            let kbytes = lua_state.gc(GcOption::Count, 0);
+            let bytes = lua_state.gc(GcOption::CountBytes, 0);
+            kbytes as f64 + bytes as f64 / 1024.0
+        }
 
-            state.push_integer(screen.lock().unwrap().fget_all(idx as u32) as i64);
+        pub fn tick(&mut self) {
+            if !self.loaded_code {
+                return;
+            }
 
-            1
+            if let Err(error) = self.resume_tasks() {
+                self.report("SCHEDULER", error);
+            }
         }
 
-        unsafe extern "C" fn lua_fset(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA FSET");
-
-            let mut state = State::from_ptr(lua_context);
+        pub fn init(&mut self) {
+            if !self.loaded_code {
+                return;
+            }
 
-            let idx = state.check_integer(2);
-            let flag = state.check_integer(3);
-            let value = state.check_integer(4);
+            match self.call("_init") {
+                Ok(_) => info!("[PLUGIN][LUA] INIT SUCCESS"),
+                Err(error) => self.report("INIT", error),
+            }
+        }
 
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
+        pub fn draw(&mut self) -> bool {
+            if !self.loaded_code {
+                return false;
+            }
 
-            screen
-                .lock()
-                .unwrap()
-                .fset(idx as u32, flag as u8, value == 1);
+            if let Err(error) = self.call("_draw") {
+                self.report("DRAW", error);
+            }
 
-            1
+            return true;
         }
 
-        unsafe extern "C" fn lua_fset_all(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA FSET ALL");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let idx = state.check_integer(2);
-            let flags = state.check_integer(3);
+        pub fn update(&mut self) -> bool {
+            if !self.loaded_code {
+                return false;
+            }
 
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
+            let result = match self.call("_update") {
+                Ok(false) => self.call("_update60"),
+                result => result,
+            };
+            if let Err(error) = result {
+                self.report("UPDATE", error);
+            }
 
-            screen.lock().unwrap().fset_all(idx as u32, flags as u8);
+            return true;
 
-            1
         }
 
-        unsafe extern "C" fn lua_rnd(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA RND");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
+        pub fn load_code(&mut self, data: String) -> bool {
+            info!("[PLUGIN][LUA] LOAD CODE");
 
-            let value: f64;
+            self.loaded_code = true;
+            self.error = None;
 
-            if x == 0 {
-                value = rand::thread_rng().gen_range(0.0, 1.0);
-            } else {
-                value = rand::thread_rng().gen_range(0.0, x as f64);
+            let result = self.call_helper("reset_tasks", None)
+                .and_then(|_| self.load_files())
+                .and_then(|_| self.run(&data));
+            if let Err(error) = result {
+                self.report("LOAD CODE", error);
+                self.loaded_code = false;
             }
 
-            state.push_number(value);
-
-            1
+            self.loaded_code
         }
+    }
 
-        // spr n x y [w h] [flip_x] [flip_y]
-        unsafe extern "C" fn lua_spr(lua_context: *mut lua_State) -> c_int {
-            let mut state = State::from_ptr(lua_context);
-
-            let n = state.check_integer(2);
-            let x = state.check_integer(3);
-            let y = state.check_integer(4);
-            let w = state.check_integer(5);
-            let h = state.check_integer(6);
-            let flip_x = state.check_integer(7);
-            let flip_y = state.check_integer(8);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen
-                .lock()
-                .unwrap()
-                .spr(n as u32,
-                     x as i32,
-                     y as i32,
-                     w as u32,
-                     h as u32,
-                     flip_x == 1,
-                     flip_y == 1);
+    /// Compile `code` in a new state without running it, to check its syntax
+    pub fn check_syntax(filename: &str, code: &str) -> Result<(), ScriptError> {
+        let mut lua_state = State::new();
 
-            1
+        let chunk_name = format!("={}", filename);
+        let value = lua_state.load_bufferx(code.as_bytes(), &chunk_name, "t");
+        if value != ThreadStatus::Ok {
+            let message = error_message(&mut lua_state, value);
+            return Err(ScriptError::from_lua(&message));
         }
 
-        // sspr sx sy sw sh dx dy [dw dh] [flip_x] [flip_y]
-        unsafe extern "C" fn lua_sspr(lua_context: *mut lua_State) -> c_int {
-            let mut state = State::from_ptr(lua_context);
+        Ok(())
+    }
 
-            let sx = state.check_integer(2);
-            let sy = state.check_integer(3);
-            let sw = state.check_integer(4);
-            let sh = state.check_integer(5);
-            let dx = state.check_integer(6);
-            let dy = state.check_integer(7);
-            let dw = state.check_integer(8);
-            let dh = state.check_integer(9);
-            let flip_x = state.check_integer(10);
-            let flip_y = state.check_integer(11);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen
-                .lock()
-                .unwrap()
-                .sspr(sx as u32,
-                      sy as u32,
-                      sw as u32,
-                      sh as u32,
-                      dx as i32,
-                      dy as i32,
-                      dw as u32,
-                      dh as u32,
-                      flip_x == 1,
-                      flip_y == 1);
+    /// Pop the error message of a failed call
+    fn error_message(lua_state: &mut State, value: ThreadStatus) -> String {
+        let message = lua_state
+            .to_str_in_place(-1)
+            .map(|message| message.to_string())
+            .unwrap_or(format!("{:?}", value));
+        lua_state.pop(1);
 
-            1
-        }
+        message
+    }
 
-        // map cel_x cel_y sx sy cel_w cel_h [layer]
-        unsafe extern "C" fn lua_map(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA MAP");
+    /// Message handler of the calls, adds the traceback to the error
+    unsafe extern "C" fn traceback_handler(lua_context: *mut lua_State) -> c_int {
+        let mut state = State::from_ptr(lua_context);
+        let mut thread = State::from_ptr(lua_context);
 
-            let mut state = State::from_ptr(lua_context);
+        let message = state
+            .to_str_in_place(1)
+            .unwrap_or("(error object is not a string)")
+            .to_string();
+        state.traceback(&mut thread, &message, 1);
 
-            let cel_x = state.check_integer(2);
-            let cel_y = state.check_integer(3);
-            let sx = state.check_integer(4);
-            let sy = state.check_integer(5);
-            let cel_w = state.check_integer(6);
-            let cel_h = state.check_integer(7);
-            let layer = state.check_integer(8);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen
-                .lock()
-                .unwrap()
-                .map(cel_x as u32,
-                     cel_y as u32,
-                     sx as i32,
-                     sy as i32,
-                     cel_w as u32,
-                     cel_h as u32,
-                     layer as u8);
+        1
+    }
 
+    /// Start the budget of a call, true if the hook must check it
+    fn start_budget(lua_state: &mut State) -> bool {
+        lua_state.with_extra_typed(|data: &mut ExtraData| {
+                                       data.budget = data.sandbox.budget();
+                                       data.sandbox.enabled
+                                   })
+    }
 
-            1
+    /// Call the function below its `nargs` arguments with a new budget, the
+    /// `nresults` results are left on the stack
+    fn protected_call(lua_state: &mut State,
+                      nargs: c_int,
+                      nresults: c_int)
+                      -> Result<(), ScriptError> {
+        if start_budget(lua_state) {
+            lua_state.set_hook(Some(budget_hook), MASKCOUNT, HOOK_COUNT);
         }
 
-        unsafe extern "C" fn lua_mget(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA MGET");
+        // The message handler, below the function
+        lua_state.push_fn(Some(traceback_handler));
+        lua_state.insert(-(nargs + 2));
+        let handler = lua_state.get_top() - nargs - 1;
 
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
+        let value = lua_state.pcall(nargs, nresults, handler);
+        let result = if value == ThreadStatus::Ok {
+            Ok(())
+        } else {
+            Err(ScriptError::from_lua(&error_message(lua_state, value)))
+        };
 
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
+        // The message handler
+        lua_state.remove(handler);
 
-            let value = screen.lock().unwrap().mget(x as i32, y as i32);
-
-            state.push_integer(value as i64);
+        result
+    }
 
-            1
+    /// Resume the coroutine of the task on the top of the stack, with a new
+    /// budget, until it waits or ends
+    fn resume_task(lua_state: &mut State) -> Result<(), ScriptError> {
+        lua_state.get_field(-1, "thread");
+        let thread = lua_state.to_thread(-1);
+        // Kept by the task
+        lua_state.pop(1);
+
+        let mut thread = match thread {
+            Some(thread) => thread,
+            None => return Ok(()),
+        };
+
+        if start_budget(lua_state) {
+            thread.set_hook(Some(budget_hook), MASKCOUNT, HOOK_COUNT);
         }
 
-
-        unsafe extern "C" fn lua_mset(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA MSET");
-
-            let mut state = State::from_ptr(lua_context);
-
-            let x = state.check_integer(2);
-            let y = state.check_integer(3);
-            let v = state.check_integer(4);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen.lock().unwrap().mset(x as i32, y as i32, v as u32);
-
-            1
+        match thread.resume(Some(&mut *lua_state), 0) {
+            ThreadStatus::Yield => {
+                // wait(frames)
+                let top = thread.get_top();
+                let frames = if top > 0 {
+                    thread.to_numberx(-1).unwrap_or(1.0)
+                } else {
+                    1.0
+                };
+                thread.pop(top);
+
+                lua_state.push_number(frames.ceil().max(1.0));
+                lua_state.set_field(-2, "wait");
+                Ok(())
+            }
+            ThreadStatus::Ok => {
+                lua_state.push_bool(true);
+                lua_state.set_field(-2, "done");
+                Ok(())
+            }
+            value => {
+                let message = thread
+                    .to_str(-1)
+                    .map(|message| message.to_string())
+                    .unwrap_or(format!("{:?}", value));
+                lua_state.traceback(&mut thread, &message, 0);
+                let traceback = error_message(lua_state, value);
+
+                lua_state.push_bool(true);
+                lua_state.set_field(-2, "done");
+                Err(ScriptError::from_lua(&traceback))
+            }
         }
+    }
 
-        unsafe extern "C" fn lua_print(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA PRINT");
-
-            let mut state = State::from_ptr(lua_context);
-            let mut state2 = State::from_ptr(lua_context);
-
-            let str_data = state2.check_string(2);
-            let x = state.check_integer(3);
-            let y = state.check_integer(4);
-            let col = state.check_integer(5);
-
-            let screen = state.with_extra(|extra| {
-                                              let data = extra
-                                                  .as_ref()
-                                                  .unwrap()
-                                                  .downcast_ref::<ExtraData>()
-                                                  .unwrap();
-                                              data.screen.clone()
-                                          });
-
-            screen
-                .lock()
-                .unwrap()
-                .print(str_data.to_string(), x as i32, y as i32, col as i32);
+    struct UnicornLua {}
 
-            1
+    impl UnicornLua {
+        fn new() -> UnicornLua {
+            return UnicornLua {};
         }
 
-        unsafe extern "C" fn lua_time(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA TIME");
-
+        #[allow(non_snake_case)]
+        unsafe extern "C" fn lua_new(lua_context: *mut lua_State) -> c_int {
             let mut state = State::from_ptr(lua_context);
 
-            let info = state.with_extra(|extra| {
-                                            let data = extra
-                                                .as_ref()
-                                                .unwrap()
-                                                .downcast_ref::<ExtraData>()
-                                                .unwrap();
-                                            data.info.clone()
-                                        });
-
-            state.push_integer(info.lock().unwrap().time());
-
+            // construct new userdata in lua space and initialize it
+            *state.new_userdata_typed::<UnicornLua>() = UnicornLua::new();
+            // set the userdata's metatable so we can call methods on it
+            state.set_metatable_from_registry("UnicornLua");
+            // return the userdata on top of the stack
             1
         }
 
-        unsafe extern "C" fn lua_stat(lua_context: *mut lua_State) -> c_int {
-            debug!("LUA STAT");
-
+        /// UnicornObject:api(idx, ...), called by the wrappers of `api::lua_bindings`
+        unsafe extern "C" fn lua_api(lua_context: *mut lua_State) -> c_int {
             let mut state = State::from_ptr(lua_context);
 
-            let value = state.check_integer(2);
+            let idx = state.check_integer(2) as usize;
+
+            let mut values = Vec::new();
+            for i in 3..state.get_top() + 1 {
+                values.push(UnicornLua::to_value(&mut state, i));
+            }
 
-            let players = state.with_extra(|extra| {
+            let context = state.with_extra(|extra| {
                                                let data = extra
                                                    .as_ref()
                                                    .unwrap()
                                                    .downcast_ref::<ExtraData>()
                                                    .unwrap();
-                                               data.players.clone()
+                                               data.context.clone()
                                            });
 
-            let players_data = players.lock().unwrap();
-
-            if value == 32 {
-                state.push_integer(players_data.mouse.x as i64);
-            } else if value == 33 {
-                state.push_integer(players_data.mouse.y as i64);
-            } else if value == 34 {
-                state.push_integer(players_data.mouse.state as i64);
-            } else {
-                state.push_integer(0);
+            match context.call(idx, values) {
+                ApiValue::Nil => state.push_nil(),
+                ApiValue::Int(v) => state.push_integer(v),
+                ApiValue::Float(v) => state.push_number(v),
+                ApiValue::Bool(v) => state.push_bool(v),
+                ApiValue::Str(v) => state.push_string(&v),
+                ApiValue::List(v) => {
+                    state.new_table();
+                    for (i, v) in v.iter().enumerate() {
+                        state.push_integer(*v);
+                        state.raw_seti(-2, (i + 1) as i64);
+                    }
+                }
             }
 
             1
         }
 
-
-        /***** CARTDATA *****/
-        unsafe extern "C" fn lua_cartdata(_lua_context: *mut lua_State) -> c_int {
-            debug!("LUA CARTDATA");
-
-            /*
+        /// UnicornObject:path(filename), the path of a file of the cartridge, or nil
+        unsafe extern "C" fn lua_path(lua_context: *mut lua_State) -> c_int {
             let mut state = State::from_ptr(lua_context);
-            let x = state.check_integer(2);
-            */
-
-            1
-        }
 
-        unsafe extern "C" fn lua_dget(_lua_context: *mut lua_State) -> c_int {
-            debug!("LUA DGET");
+            let filename = state.check_string(2).to_string();
 
-            /*
-            let mut state = State::from_ptr(lua_context);
-            let x = state.check_integer(2);
-            */
+            let path = state.with_extra_typed(|data: &mut ExtraData| {
+                                                  data.sandbox.resolve(&filename)
+                                              });
+            match path {
+                Some(path) => state.push_string(&path.to_string_lossy()),
+                None => state.push_nil(),
+            }
 
             1
         }
 
-
-        unsafe extern "C" fn lua_dset(_lua_context: *mut lua_State) -> c_int {
-            debug!("LUA DSET");
-
-            /*
-            let mut state = State::from_ptr(lua_context);
-            let x = state.check_integer(2);
-            */
-
-            1
+        fn to_value(state: &mut State, idx: i32) -> ApiValue {
+            match state.type_of(idx) {
+                Some(Type::Boolean) => ApiValue::Bool(state.to_bool(idx)),
+                Some(Type::Number) => {
+                    if state.is_integer(idx) {
+                        ApiValue::Int(state.to_integer(idx))
+                    } else {
+                        ApiValue::Float(state.to_number(idx))
+                    }
+                }
+                Some(Type::String) => {
+                    ApiValue::Str(state.to_str(idx).unwrap_or("").to_string())
+                }
+                Some(Type::Table) => {
+                    let mut list = Vec::new();
+                    for i in 1..state.raw_len(idx) as i64 + 1 {
+                        state.raw_geti(idx, i);
+                        list.push(state.to_number(-1).floor() as i64);
+                        state.pop(1);
+                    }
+                    ApiValue::List(list)
+                }
+                _ => ApiValue::Nil,
+            }
         }
     }
 
-    pub const UNICORN_LUA_LIB: [(&'static str, Function); 41] =
+    pub const UNICORN_LUA_LIB: [(&'static str, Function); 3] =
         [("new", Some(UnicornLua::lua_new)),
+         ("api", Some(UnicornLua::lua_api)),
+         ("path", Some(UnicornLua::lua_path))];
 
-         ("music", Some(UnicornLua::lua_chiptune_music)),
-         ("sfx", Some(UnicornLua::lua_chiptune_sfx)),
-
-         ("camera", Some(UnicornLua::lua_camera)),
-         ("color", Some(UnicornLua::lua_color)),
+    #[cfg(test)]
+    mod tests {
+        use std::fs::{self, File};
+        use std::io::Write;
+        use std::path::Path;
 
-         ("btn", Some(UnicornLua::lua_btn)),
-         ("btnp", Some(UnicornLua::lua_btnp)),
+        use tempdir::TempDir;
 
-         ("cls", Some(UnicornLua::lua_cls)),
+        use plugins::api;
+        use plugins::sandbox;
+        use plugins::sandbox::SandboxPolicy;
 
-         ("fget", Some(UnicornLua::lua_fget)),
-         ("fget_all", Some(UnicornLua::lua_fget_all)),
-         ("fset", Some(UnicornLua::lua_fset)),
-         ("fset_all", Some(UnicornLua::lua_fset_all)),
+        use super::*;
 
-         ("line", Some(UnicornLua::lua_line)),
+        fn plugin(root: &Path, code: &str) -> LuaPlugin {
+            let (context, _crecv) = api::test_context();
 
-         ("rect", Some(UnicornLua::lua_rect)),
-         ("rectfill", Some(UnicornLua::lua_rectfill)),
-         ("circ", Some(UnicornLua::lua_circ)),
-         ("circfill", Some(UnicornLua::lua_circfill)),
-         ("ellipse", Some(UnicornLua::lua_ellipse)),
-         ("ellipsefill", Some(UnicornLua::lua_ellipsefill)),
-         ("trigon", Some(UnicornLua::lua_trigon)),
+            let mut sandbox = SandboxPolicy::new(root);
+            sandbox.instructions = 100_000;
 
-         ("clip", Some(UnicornLua::lua_clip)),
-
-         ("spr", Some(UnicornLua::lua_spr)),
-         ("sspr", Some(UnicornLua::lua_sspr)),
-
-         ("map", Some(UnicornLua::lua_map)),
-         ("mget", Some(UnicornLua::lua_mget)),
-         ("mset", Some(UnicornLua::lua_mset)),
-
-         ("palt", Some(UnicornLua::lua_palt)),
-         ("pal", Some(UnicornLua::lua_pal)),
-
-         ("pget", Some(UnicornLua::lua_pget)),
-         ("pset", Some(UnicornLua::lua_pset)),
+            let mut plugin = LuaPlugin::new();
+            plugin.load(context, sandbox);
+            assert!(plugin.load_code(code.to_string()));
+            plugin
+        }
 
-         ("sget", Some(UnicornLua::lua_sget)),
-         ("sset", Some(UnicornLua::lua_sset)),
+        #[test]
+        fn budget_exceeded() {
+            let mut plugin = plugin(Path::new("."), "function _update() while true do end end");
 
-         ("noise", Some(UnicornLua::lua_noise)),
-         ("noise_set_seed", Some(UnicornLua::lua_noise_set_seed)),
+            plugin.update();
+            let error = plugin.take_error().unwrap();
+            assert!(error.message.contains(sandbox::BUDGET_EXCEEDED));
+        }
 
-         ("rnd", Some(UnicornLua::lua_rnd)),
+        #[test]
+        fn blocked_require_and_open() {
+            let dir = TempDir::new("unicorn").unwrap();
+            let root = dir.path().join("cart");
+            fs::create_dir(&root).unwrap();
+            File::create(dir.path().join("secret.txt")).unwrap().write_all(b"secret").unwrap();
 
-         ("print", Some(UnicornLua::lua_print)),
+            let mut plugin = plugin(&root,
+                                    r#"
+function _init() require("socket") end
+function _update() io.open("../secret.txt") end
+function _draw() os.execute("true") end
+"#);
 
-         ("time", Some(UnicornLua::lua_time)),
+            plugin.init();
+            assert!(plugin.take_error().unwrap().message.contains("module 'socket' not found"));
 
-         ("stat", Some(UnicornLua::lua_stat)),
+            plugin.update();
+            assert!(plugin.take_error().unwrap().message.contains("access denied"));
 
-         ("cartdata", Some(UnicornLua::lua_cartdata)),
-         ("dget", Some(UnicornLua::lua_dget)),
-         ("dset", Some(UnicornLua::lua_dset))];
+            plugin.draw();
+            assert!(plugin.take_error().unwrap().message.contains("execute"));
+        }
+    }
 }
 
 #[cfg(not(feature = "unicorn_plugin_lua"))]
 pub mod plugin {
-    use std::sync::{Arc, Mutex};
-
-    use config::Players;
+    use std::collections::HashMap;
 
-    use unicorn::noise::Noise;
-    use unicorn::info::Info;
-    use sound::sound::Sound;
-
-    use gfx::Screen;
+    use plugins::api::ApiContext;
+    use plugins::error::ScriptError;
+    use plugins::sandbox::SandboxPolicy;
 
+    pub fn check_syntax(_filename: &str, _code: &str) -> Result<(), ScriptError> {
+        Ok(())
+    }
 
     pub struct LuaPlugin {}
 
@@ -2115,17 +1031,26 @@ pub mod plugin {
         }
 
         // Keep the compatibility
-        pub fn load(&mut self,
-                    _players: Arc<Mutex<Players>>,
-                    _info: Arc<Mutex<Info>>,
-                    _screen: Arc<Mutex<Screen>>,
-                    _noise: Arc<Mutex<Noise>>,
-                    _sound: Arc<Mutex<Sound>>) {
+        pub fn load(&mut self, _context: ApiContext, _sandbox: SandboxPolicy) {
             panic!("LUA plugin disabled");
         }
+        pub fn take_error(&mut self) -> Option<ScriptError> {
+            None
+        }
+        pub fn repl(&mut self, _code: &str) -> Result<String, ScriptError> {
+            Err(ScriptError::new("LUA plugin disabled"))
+        }
+        pub fn globals(&mut self) -> Vec<String> {
+            Vec::new()
+        }
+        pub fn set_files(&mut self, _files: HashMap<String, String>) {}
         pub fn load_code(&mut self, _data: String) -> bool {
             false
         }
+        pub fn memory(&mut self) -> f64 {
+            0.0
+        }
+        pub fn tick(&mut self) {}
         pub fn init(&mut self) {}
         pub fn draw(&mut self) -> bool {
             false
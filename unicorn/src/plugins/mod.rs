@@ -1,3 +1,9 @@
+pub mod api;
+pub mod dylib_plugin;
+pub mod error;
 pub mod lua_plugin;
 pub mod python_plugin;
-pub mod javascript_plugin;
\ No newline at end of file
+pub mod javascript_plugin;
+pub mod wasm_plugin;
+pub mod sandbox;
+pub mod scheduler;
@@ -3,512 +3,340 @@
 pub mod plugin {
     use cpython::*;
 
-    use std::sync::{Arc, Mutex};
+    use std::collections::HashMap;
     use std::fs::File;
     use std::io::Read;
+    use std::sync::Mutex;
 
-    use config::Players;
-    use unicorn::info::Info;
-    use unicorn::Palettes;
-    use unicorn::noise::Noise;
-    use unicorn::UnicornConfig;
-    use gfx::Screen;
-    use sound::sound::Sound;
-
-    // Audio
-    py_class!(class UnicornAudio |py| {
-    data sound: Arc<Mutex<Sound>>;
-
-    // Audio
-    
-    def chiptune_music(&self, id: i32, filename: String, channel: i32, loops: i32, start_position: i32) -> PyResult<i32> {
-        self.sound(py).lock().unwrap().music(id, filename, channel, loops, start_position);
-        Ok(0)
-    }
-
-    def chiptune_sfx(&self, id: i32, filename: String, channel: i32, note: u16, panning: i32, rate: i32, loops: i32) -> PyResult<i32> {
-        self.sound(py).lock().unwrap().sfx(id, filename, channel, note, panning, rate, loops);
-        Ok(0)
-    }
+    use plugins::api;
+    use plugins::api::{ApiContext, ApiValue};
+    use plugins::error::{ScriptError, CODE_FILENAME};
+    use plugins::sandbox;
+    use plugins::sandbox::SandboxPolicy;
+    use plugins::scheduler;
 
-    def chiptune_stop(&self) -> PyResult<i32> {
-        self.sound(py).lock().unwrap().music_stop();
-        Ok(0)
-    }
-    
-    def chiptune_pause(&self) -> PyResult<i32> {
-        self.sound(py).lock().unwrap().music_pause();
-        Ok(0)
-    }
-    
-    def chiptune_resume(&self) -> PyResult<i32> {
-        self.sound(py).lock().unwrap().music_resume();
-        Ok(0)
+    lazy_static! {
+        /// Sandbox of the interpreter, created once while `open` and `__import__`
+        /// are the real ones, shared by the cartridges
+        static ref SANDBOX: Mutex<Option<PyObject>> = Mutex::new(None);
     }
 
-    def chiptune_volume(&self, volume: i32) -> PyResult<i32> {
-        self.sound(py).lock().unwrap().music_volume(volume);
-        Ok(0)
+    fn interpreter_sandbox(py: Python) -> Option<PyObject> {
+        SANDBOX.lock().unwrap().as_ref().map(|sandbox| sandbox.clone_ref(py))
     }
 
-    def chiptune_position(&self) -> PyResult<i32> {
-        Ok(self.sound(py).lock().unwrap().chiptune_get_position())
+    /// Import a module for the host, with the real `__import__` when the sandbox
+    /// rejects the imports of the cartridge
+    fn import(py: Python, name: &str) -> PyResult<PyObject> {
+        match interpreter_sandbox(py) {
+            Some(sandbox) => sandbox.call_method(py, "import_module", (name,), None),
+            None => py.import(name).map(|module| module.into_object()),
+        }
     }
 
-    });
-
-    // Palettes
-    py_class!(class UnicornPalette |py| {
-    data palettes: Arc<Mutex<Palettes>>;
+    // API, called by the functions of `api::python_bindings`
+    py_class!(class UnicornApi |py| {
+    data context: ApiContext;
 
-    def set_color(&self, color:u32, r: u8, g: u8, b: u8) -> PyResult<i32> {
-        self.palettes(py).lock().unwrap().set_color(color, r, g, b);
-        Ok(0)
-    }
-
-    def reset(&self) -> PyResult<i32> {
-        self.palettes(py).lock().unwrap().reset();
-        Ok(0)
-    }
+    def call(&self, idx: usize, args: PyList) -> PyResult<PyObject> {
+        let mut values = Vec::new();
+        for i in 0..args.len(py) {
+            values.push(to_value(py, &args.get_item(py, i)));
+        }
 
-    def switch(&self, name: String) -> PyResult<i32> {
-        self.palettes(py).lock().unwrap().switch_to_palette(&name);
-        Ok(0)
+        let result = self.context(py).call(idx, values);
+        Ok(match result {
+            ApiValue::Nil => py.None(),
+            ApiValue::Int(v) => v.to_py_object(py).into_object(),
+            ApiValue::Float(v) => v.to_py_object(py).into_object(),
+            ApiValue::Bool(v) => v.to_py_object(py).into_object(),
+            ApiValue::Str(v) => v.to_py_object(py).into_object(),
+            ApiValue::List(v) => v.to_py_object(py).into_object(),
+        })
     }
-
     });
 
-    // Cart Data
-
-    // Graphics
-    py_class!(class UnicornGraphic |py| {
-    data screen: Arc<Mutex<Screen>>;
-
-    def camera(&self, x: i32, y: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().camera(x, y);
-        Ok(0)
-    }
-
-    def circ(&self, x: i32, y: i32, r: i32, color: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().circ(x, y, r, color);
-        Ok(0)
-    }
-
-    def circfill(&self, x: i32, y: i32, r: i32, color: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().circfill(x, y, r, color);
-        Ok(0)
-    }
-
-    def clip(&self, x: i32, y: i32, w: i32, h: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().clip(x, y, w, h);
-        Ok(0)
-    }
-
-    def cls(&self, value: i8) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().cls(value);
-        Ok(0)
-    }
-
-    def color(&self, color:i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().color(color);
-        Ok(0)
-    }
-
-    def ellipse(&self, x: i32, y: i32, rx: i32, ry: i32, color: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().ellipse(x, y, rx, ry, color);
-        Ok(0)
-    }
-
-    def ellipsefill(&self, x: i32, y: i32, rx: i32, ry: i32, color: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().ellipsefill(x, y, rx, ry, color);
-        Ok(0)
-    }
-
-    def fget(&self, idx: u32, v: u8) -> PyResult<bool> {
-        Ok(self.screen(py).lock().unwrap().fget(idx, v))
-    }
-
-    def fget_all(&self, idx: u32) -> PyResult<u8> {
-        Ok(self.screen(py).lock().unwrap().fget_all(idx))
-    }
-
-    def font(&self, name: String) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().font(&name);
-        Ok(0)
-    }
-
-    def fset(&self, idx: u32, flag: u8, value: bool) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().fset(idx, flag, value);
-        Ok(0)
-    }
-
-    def fset_all(&self, idx: u32, flag: u8) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().fset_all(idx, flag);
-        Ok(0)
-    }
-
-    def flip(&self) -> PyResult<i32> {
-        Ok(0)
-    }
-
-    def line(&self, x1: i32, y1: i32, x2: i32, y2: i32, color: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().line(x1, y1, x2, y2, color);
-        Ok(0)
-    }
-
-    def mode_get_width(&self) -> PyResult<usize> {
-        Ok(self.screen(py).lock().unwrap().mode_width())
-    }
-
-    def mode_get_height(&self) -> PyResult<usize> {
-        Ok(self.screen(py).lock().unwrap().mode_height())
-    }
-
-    def palt(&self, c: i32, t: bool) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().palt(c, t);
-        Ok(0)
-    }
-
-    def pal(&self, c0: i32, c1: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().pal(c0, c1);
-        Ok(0)
-    }
-
-    def pset(&self, x: i32, y: i32, color: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().pset(x, y, color);
-        Ok(0)
-    }
-
-    def print(&self, str: String, x: i32, y: i32, color: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().print(str, x as i32, y as i32, color);
-        Ok(0)
-    }
-
-    def pget(&self, x: i32, y: i32) -> PyResult<u32> {
-        let value = self.screen(py).lock().unwrap().pget(x as u32, y as u32);
-        Ok(value)
-    }
-
-    def rect(&self, x1: i32, y1: i32, x2: i32, y2: i32, color: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().rect(x1, y1, x2, y2, color);
-        Ok(0)
-    }
-
-    def rectfill(&self, x1: i32, y1: i32, x2: i32, y2: i32, color: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().rectfill(x1, y1, x2, y2, color);
-        Ok(0)
-    }
-
-    def sget(&self, x: i32, y: i32) -> PyResult<u32> {
-        Ok(self.screen(py).lock().unwrap().sget(x as u32, y as u32))
-    }
-
-    def spr(&self, n: i32, x: i32, y: i32, w: i32, h: i32, flip_x: bool, flip_y: bool) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().spr(n as u32,
-                                            x as i32,
-                                            y as i32,
-                                            w as u32,
-                                            h as u32,
-                                            flip_x,
-                                            flip_y);
-
-        Ok(0)
-    }
-
-    def sset(&self, x: i32, y: i32, color: i32) -> PyResult<u8> {
-        self.screen(py).lock().unwrap().sset(x as u32, y as u32, color);
-        Ok(0)
-    }
-
-    def sspr(&self, sx: i32, sy: i32, sw: i32, sh: i32, dx: i32, dy: i32, dw: i32, dh: i32, flip_x: bool, flip_y: bool) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().sspr(sx as u32,
-                                             sy as u32,
-                                             sw as u32,
-                                             sh as u32,
-                                             dx as i32,
-                                             dy as i32,
-                                             dw as u32,
-                                             dh as u32,
-                                             flip_x,
-                                             flip_y);
-        Ok(0)
-    }
-
-    def sspr2(&self, sx: i32, sy: i32, sw: i32, sh: i32, dx: i32, dy: i32, angle: f64, zoom: f64, flip_x: bool, flip_y: bool) -> PyResult<PyList> {
-        let (dw, dh) = self.screen(py).lock().unwrap().sspr2(sx as u32,
-                                              sy as u32,
-                                              sw as u32,
-                                              sh as u32,
-                                              dx as i32,
-                                              dy as i32,
-                                              angle,
-                                              zoom,
-                                              flip_x,
-                                              flip_y);
-        
-        let v = vec![dw, dh];
-        let ret = v.to_py_object(py);
-        Ok(ret)
-    }
+    /// Message, location and traceback of an exception
+    fn script_error(py: Python, mut err: PyErr) -> ScriptError {
+        // Normalize the exception
+        err.instance(py);
+
+        let pvalue = err.pvalue.as_ref().map_or(py.None(), |v| v.clone_ref(py));
+        let ptraceback = err.ptraceback.as_ref().map_or(py.None(), |v| v.clone_ref(py));
+
+        let lines = import(py, "traceback")
+            .and_then(|traceback| {
+                          traceback.call_method(py,
+                                                "format_exception",
+                                                (err.ptype.clone_ref(py), pvalue, ptraceback),
+                                                None)
+                      })
+            .and_then(|lines| lines.extract::<Vec<String>>(py));
+
+        let mut error = match lines {
+            Ok(lines) => ScriptError::from_python(&lines),
+            Err(_) => ScriptError::new(&format!("{:?}", err)),
+        };
+
+        if error.message.starts_with("UnicornBudgetExceeded") {
+            error.message = sandbox::BUDGET_EXCEEDED.to_string();
+        }
 
-    def trigon(&self, x1: i32, y1: i32, x2: i32, y2: i32, x3: i32, y3: i32, color: i32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().trigon(x1, y1, x2, y2, x3, y3, color);
-        Ok(0)
+        error
     }
 
+    fn to_value(py: Python, obj: &PyObject) -> ApiValue {
+        if *obj == py.None() {
+            return ApiValue::Nil;
+        }
 
-    def polygon(&self, x: PyList, y: PyList, color: i32) -> PyResult<i32> {
-        if x.len(py) != y.len(py) {
-            return Ok(-1);
+        if let Ok(v) = obj.cast_as::<PyBool>(py) {
+            return ApiValue::Bool(v.is_true());
         }
 
-        if x.len(py) < 3 || y.len(py) < 3 {
-            return Ok(-1);
+        if let Ok(v) = obj.cast_as::<PyString>(py) {
+            return ApiValue::Str(v.to_string_lossy(py).into_owned());
         }
 
-        let mut vx: Vec<i32> = Vec::new();
-        let mut vy: Vec<i32> = Vec::new();
+        if let Ok(v) = obj.cast_as::<PyFloat>(py) {
+            return ApiValue::Float(v.value(py));
+        }
 
-        for lx in x.iter(py) {
-            vx.push(lx.extract::<i32>(py).unwrap());
+        if let Ok(v) = obj.extract::<i64>(py) {
+            return ApiValue::Int(v);
         }
 
-        for ly in y.iter(py) {
-            vy.push(ly.extract::<i32>(py).unwrap());
+        if let Ok(v) = obj.extract::<Vec<f64>>(py) {
+            return ApiValue::List(v.iter().map(|v| v.floor() as i64).collect());
         }
 
-        self.screen(py).lock().unwrap().polygon(vx, vy, color);
-        Ok(0)
+        warn!("[PLUGIN][PYTHON] Unsupported value {:?}", obj);
+        ApiValue::Nil
     }
 
-    });
 
-    // Input
-    py_class!(class UnicornInput |py| {
-    data players: Arc < Mutex < Players> >;
+    /// Compile `code` without running it, to check its syntax
+    pub fn check_syntax(filename: &str, code: &str) -> Result<(), ScriptError> {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
 
-    def btn(&self, x: i32, p: i32) -> PyResult<bool> {
-        let value = self.players(py).lock().unwrap().btn(p as u8, x as u8);
-        Ok(value)
-    }
+        let locals = PyDict::new(py);
+        locals.set_item(py, "unicorn_code", code).unwrap();
+        locals.set_item(py, "unicorn_code_filename", filename).unwrap();
 
-    def btn2(&self, x: i32) -> PyResult<bool> {
-        let value = self.players(py).lock().unwrap().btn2(x);
-        Ok(value)
+        py.run(r###"compile(unicorn_code, unicorn_code_filename, "exec")"###,
+               None,
+               Some(&locals))
+            .map_err(|err| script_error(py, err))
     }
 
-    def btnp(&self, x: i32, p: i32) -> PyResult<bool> {
-        let value = self.players(py).lock().unwrap().btnp(p as u8, x as u8);
-        Ok(value)
+    pub struct PythonPlugin {
+        pub mydict: PyDict,
+        pub loaded_code: bool,
+        pub sandboxed: bool,
+        /// Duration of a call in the sandbox, in seconds
+        budget: f64,
+        error: Option<ScriptError>,
+        /// Sources of the modules of the cartridge, by filename
+        files: HashMap<String, String>,
     }
 
-    def btnp2(&self, x: i32) -> PyResult<bool> {
-        let value = self.players(py).lock().unwrap().btnp2(x);
-        Ok(value)
-    }
+    impl PythonPlugin {
+        pub fn new() -> PythonPlugin {
+            let gil = Python::acquire_gil();
+            let py = gil.python();
 
-    def btn_mouse(&self, x: i32) -> PyResult<i32> {
-        let value = self.players(py).lock().unwrap().mouse_coordinate(x as u8);
-        Ok(value)
-    }
+            let d = PyDict::new(py);
 
-    def btn_mouse_state(&self) -> PyResult<u32> {
-        let value = self.players(py).lock().unwrap().mouse_state();
-        Ok(value)
-    }
+            PythonPlugin {
+                mydict: d,
+                loaded_code: false,
+                sandboxed: false,
+                budget: 0.0,
+                error: None,
+                files: HashMap::new(),
+            }
+        }
 
-    def btn_mouse_statep(&self) -> PyResult<u32> {
-        let value = self.players(py).lock().unwrap().mouse_state_quick();
-        Ok(value)
-    }
 
-    });
+        pub fn load(&mut self, context: ApiContext, sandbox: SandboxPolicy) {
+            info!("[PLUGIN][PYTHON] Init plugin");
 
-    // Map
-    py_class!(class UnicornMap |py| {
-    data screen: Arc < Mutex < Screen > >;
+            let gil = Python::acquire_gil();
+            let py = gil.python();
 
-    def spr_map(&self, cel_x: i32, cel_y: i32, sx: i32, sy: i32, cel_w: i32, cel_h: i32, layer: u8) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().map(cel_x as u32, cel_y as u32,
-                                            sx, sy,
-                                            cel_w as u32, cel_h as u32,
-                                            layer);
+            // The scripts of the plugin import their modules without the sandbox
+            if let Some(sandbox) = interpreter_sandbox(py) {
+                if let Err(v) = sandbox.call_method(py, "uninstall", NoArgs, None) {
+                    panic!("[PLUGIN][PYTHON] Failed to remove the sandbox = {:?}", v);
+                }
+            }
 
-        Ok(0)
-    }
+            let unicorn_api_obj = UnicornApi::create_instance(py, context).unwrap();
+            self.mydict.set_item(py, "unicorn_api", unicorn_api_obj).unwrap();
 
+            py.run(r###"globals()["unicorn_api"] = unicorn_api;"###,
+                     None,
+                     Some(&self.mydict))
+                .unwrap();
 
-    def mget(&self, x: i32, y: i32) -> PyResult<u32> {
-        let value = self.screen(py).lock().unwrap().mget(x, y);
-        Ok(value)
-    }
+            let result = py.run(&api::python_bindings(), None, None);
+            match result {
+                Err(v) => {
+                    panic!("[PLUGIN][PYTHON] Failed to load the API = {:?}", v);
+                }
+                Ok(v) => {
+                    info!("[PLUGIN][PYTHON] API loaded = {:?}", v);
+                }
+            }
 
-    def mset(&self, x: i32, y: i32, v: u32) -> PyResult<i32> {
-        self.screen(py).lock().unwrap().mset(x, y, v);
-        Ok(0)
-    }
+            let data = include_str!("../../sys/config/api.py").to_string();
 
-    });
+            let result = py.run(&data, None, None);
+            match result {
+                Err(v) => {
+                    panic!("[PLUGIN][PYTHON] Failed to load the plugin = {:?}", v);
+                }
+                Ok(v) => {
+                    info!("[PLUGIN][PYTHON] Successfully loaded = {:?}", v);
+                }
+            }
 
-    // Math
+            let data = include_str!("../../sys/config/repl.py").to_string();
+            if let Err(v) = py.run(&data, None, None) {
+                panic!("[PLUGIN][PYTHON] Failed to load the REPL = {:?}", v);
+            }
 
-    // Memory
-    py_class!(class UnicornMemory |py| {
-    data screen: Arc < Mutex < Screen > >;
+            let data = include_str!("../../sys/config/files.py").to_string();
+            if let Err(v) = py.run(&data, None, None) {
+                panic!("[PLUGIN][PYTHON] Failed to load the importer of the files = {:?}", v);
+            }
 
-    def memcpy(&self, dest_addr: u32, source_addr: u32, len: u32) -> PyResult<u32> {
-        self.screen(py).lock().unwrap().memcpy(dest_addr, source_addr, len);
-        Ok(0)
-    }
+            let data = include_str!("../../sys/config/scheduler.py").to_string();
+            if let Err(v) = py.run(&data, None, None) {
+                panic!("[PLUGIN][PYTHON] Failed to load the scheduler = {:?}", v);
+            }
 
-    });
+            self.sandboxed = sandbox.enabled;
+            if self.sandboxed {
+                let sandbox_obj = match interpreter_sandbox(py) {
+                    Some(sandbox_obj) => sandbox_obj,
+                    None => {
+                        // Out of the globals of the cartridges
+                        let globals = PyDict::new(py);
+                        globals.set_item(py, "__builtins__", py.import("builtins").unwrap()).unwrap();
+
+                        let data = include_str!("../../sys/config/sandbox.py").to_string();
+                        let result = py.run(&data, Some(&globals), None)
+                            .and_then(|_| py.eval(r###"unicorn_sandbox()"###, Some(&globals), None));
+                        match result {
+                            Err(v) => panic!("[PLUGIN][PYTHON] Failed to load the sandbox = {:?}", v),
+                            Ok(v) => {
+                                *SANDBOX.lock().unwrap() = Some(v.clone_ref(py));
+                                v
+                            }
+                        }
+                    }
+                };
 
-    // Noise
-    py_class!(class UnicornNoise |py| {
-    data _noise: Arc < Mutex < Noise > >;
-        def noise(&self, x: f64, y: f64, z: f64) -> PyResult<f64> {
-            Ok(self._noise(py).lock().unwrap().get(x, y, z))
-        }
+                let root = sandbox.root.to_string_lossy().into_owned();
+                self.budget = sandbox.time.as_secs() as f64 +
+                              sandbox.time.subsec_nanos() as f64 / 1000000000.0;
 
-        def noise_set_seed(&self, seed: u32) -> PyResult<u32> {
-            self._noise(py).lock().unwrap().set_seed(seed);
-            Ok(0)
+                let result = py.eval(r###"unicorn_file_exists"###, None, None)
+                    .and_then(|file_exists| sandbox_obj.call_method(py, "install", (root, file_exists), None));
+                info!("[PLUGIN][PYTHON] SANDBOX -> {:?}", result);
+            }
         }
-    });
 
+        /// Evaluate `code`, interrupted by the watchdog of the sandbox after the budget
+        fn eval(&mut self, py: Python, code: &str) -> Result<PyObject, ScriptError> {
+            let sandbox = if self.sandboxed { interpreter_sandbox(py) } else { None };
 
-    // Others
-    py_class!(class UnicornSys |py| {
-    data info: Arc < Mutex <Info > >;
-    data config: Arc<Mutex<UnicornConfig>>;
+            if let Some(ref sandbox) = sandbox {
+                let _ = sandbox.call_method(py, "start", (self.budget,), None);
+            }
+
+            let result = py.eval(code, None, Some(&self.mydict));
+
+            if let Some(ref sandbox) = sandbox {
+                let _ = sandbox.call_method(py, "stop", NoArgs, None);
+            }
 
-        def show_mouse(&self, value: bool) -> PyResult<u32> {
-            self.config(py).lock().unwrap().toggle_mouse(value);
-            Ok(0)
+            result.map_err(|err| script_error(py, err))
         }
 
-        def time(&self) -> PyResult<i64> {
-            Ok(self.info(py).lock().unwrap().time())
+        /// Keep the error, displayed instead of the cartridge
+        fn report(&mut self, name: &str, error: ScriptError) {
+            warn!("[PLUGIN][PYTHON] {} = {}", name, error.report());
+            self.error = Some(error);
         }
 
-        def time_sec(&self) -> PyResult<f64> {
-            Ok(self.info(py).lock().unwrap().time_sec())
+        pub fn take_error(&mut self) -> Option<ScriptError> {
+            self.error.take()
         }
-    });
 
-    pub struct PythonPlugin {
-        pub mydict: PyDict,
-        pub loaded_code: bool,
-    }
+        /// Modules of the cartridge, used by the next `load_code`
+        pub fn set_files(&mut self, files: HashMap<String, String>) {
+            self.files = files;
+        }
 
-    impl PythonPlugin {
-        pub fn new() -> PythonPlugin {
+        /// Evaluate a line of the REPL, the result is the `repr` of the value
+        pub fn repl(&mut self, code: &str) -> Result<String, ScriptError> {
             let gil = Python::acquire_gil();
             let py = gil.python();
 
-            let d = PyDict::new(py);
+            self.mydict.set_item(py, "unicorn_repl_code", code).unwrap();
 
-            PythonPlugin {
-                mydict: d,
-                loaded_code: false,
-            }
+            let value = try!(self.eval(py, r###"unicorn_repl(unicorn_repl_code)"###));
+            Ok(value.extract::<String>(py).unwrap_or("".to_string()))
         }
 
-
-        pub fn load(&mut self,
-                    palettes: Arc<Mutex<Palettes>>,
-                    players: Arc<Mutex<Players>>,
-                    info: Arc<Mutex<Info>>,
-                    screen: Arc<Mutex<Screen>>,
-                    sound: Arc<Mutex<Sound>>,
-                    noise: Arc<Mutex<Noise>>,
-                    config: Arc<Mutex<UnicornConfig>>) {
-            info!("[PLUGIN][PYTHON] Init plugin");
-
+        /// Names of the global variables, completed by the REPL
+        pub fn globals(&mut self) -> Vec<String> {
             let gil = Python::acquire_gil();
             let py = gil.python();
 
-            let unicorn_graphic_obj = UnicornGraphic::create_instance(py, screen.clone()).unwrap();
-            self.mydict
-                .set_item(py, "unicorn_graphic", unicorn_graphic_obj)
-                .unwrap();
-
-            let unicorn_palette_obj = UnicornPalette::create_instance(py, palettes.clone()).unwrap();
-            self.mydict
-                .set_item(py, "unicorn_palette", unicorn_palette_obj)
-                .unwrap();
-
-            let unicorn_audio_obj = UnicornAudio::create_instance(py, sound.clone()).unwrap();
-            self.mydict
-                .set_item(py, "unicorn_audio", unicorn_audio_obj)
-                .unwrap();
-
-            let unicorn_input_obj = UnicornInput::create_instance(py, players.clone()).unwrap();
-            self.mydict
-                .set_item(py, "unicorn_input", unicorn_input_obj)
-                .unwrap();
-
-            let unicorn_map_obj = UnicornMap::create_instance(py, screen.clone()).unwrap();
-            self.mydict.set_item(py, "unicorn_map", unicorn_map_obj).unwrap();
+            py.eval(r###"unicorn_globals()"###, None, Some(&self.mydict))
+                .and_then(|names| names.extract::<Vec<String>>(py))
+                .unwrap_or(Vec::new())
+        }
 
-            let unicorn_sys_obj = UnicornSys::create_instance(py, info.clone(), config.clone()).unwrap();
-            self.mydict.set_item(py, "unicorn_sys", unicorn_sys_obj).unwrap();
 
-            let unicorn_mem_obj = UnicornMemory::create_instance(py, screen.clone()).unwrap();
-            self.mydict.set_item(py, "unicorn_mem", unicorn_mem_obj).unwrap();
+        /// Size of the objects tracked by the garbage collector, in KB. Too slow
+        /// for each frame, it is measured when `stat(0)` asks it
+        pub fn memory() -> f64 {
+            let gil = Python::acquire_gil();
+            let py = gil.python();
 
-            let unicorn_noise_obj = UnicornNoise::create_instance(py, noise.clone()).unwrap();
-            self.mydict
-                .set_item(py, "unicorn_noise", unicorn_noise_obj)
-                .unwrap();
+            let locals = PyDict::new(py);
+            let bytes = import(py, "gc")
+                .and_then(|gc| locals.set_item(py, "gc", gc))
+                .and_then(|_| import(py, "sys"))
+                .and_then(|sys| locals.set_item(py, "sys", sys))
+                .and_then(|_| {
+                              py.eval(r###"sum(map(sys.getsizeof, gc.get_objects()))"###,
+                                      None,
+                                      Some(&locals))
+                          })
+                .and_then(|bytes| bytes.extract::<f64>(py));
+
+            bytes.map(|bytes| bytes / 1024.0).unwrap_or(0.0)
+        }
 
-            py.run(r###"globals()["unicorn_graphic"] = unicorn_graphic;"###,
-                     None,
-                     Some(&self.mydict))
-                .unwrap();
-            py.run(r###"globals()["unicorn_audio"] = unicorn_audio;"###,
-                     None,
-                     Some(&self.mydict))
-                .unwrap();
-            py.run(r###"globals()["unicorn_palette"] = unicorn_palette;"###,
-                     None,
-                     Some(&self.mydict))
-                .unwrap();
-            py.run(r###"globals()["unicorn_input"] = unicorn_input;"###,
-                     None,
-                     Some(&self.mydict))
-                .unwrap();
-            py.run(r###"globals()["unicorn_map"] = unicorn_map;"###,
-                     None,
-                     Some(&self.mydict))
-                .unwrap();
-            py.run(r###"globals()["unicorn_sys"] = unicorn_sys;"###,
-                     None,
-                     Some(&self.mydict))
-                .unwrap();
-            py.run(r###"globals()["unicorn_mem"] = unicorn_mem;"###,
-                     None,
-                     Some(&self.mydict))
-                .unwrap();
-            py.run(r###"globals()["unicorn_noise"] = unicorn_noise;"###,
-                     None,
-                     Some(&self.mydict))
-                .unwrap();
+        pub fn tick(&mut self) {
+            if !self.loaded_code {
+                return;
+            }
 
-            let data = include_str!("../../sys/config/api.py").to_string();
+            let gil = Python::acquire_gil();
+            let py = gil.python();
 
-            let result = py.run(&data, None, None);
-            match result {
-                Err(v) => {
-                    panic!("[PLUGIN][PYTHON] Failed to load the plugin = {:?}", v);
-                }
-                Ok(v) => {
-                    info!("[PLUGIN][PYTHON] Successfully loaded = {:?}", v);
-                }
+            if let Err(error) = self.eval(py, r###"unicorn_scheduler_tick()"###) {
+                self.report("SCHEDULER", error);
             }
         }
 
-
         pub fn init(&mut self) {
             info!("[PLUGIN][PYTHON] Call INIT");
 
@@ -519,8 +347,10 @@ pub mod plugin {
             let gil = Python::acquire_gil();
             let py = gil.python();
 
-            let result = py.run(r###"_init()"###, None, Some(&self.mydict));
-            info!("[PLUGIN][PYTHON] INIT -> {:?}", result);
+            match self.eval(py, r###"_init()"###) {
+                Ok(v) => info!("[PLUGIN][PYTHON] INIT -> {:?}", v),
+                Err(error) => self.report("INIT", error),
+            }
         }
 
         pub fn draw(&mut self) -> bool {
@@ -534,12 +364,12 @@ pub mod plugin {
             let gil = Python::acquire_gil();
             let py = gil.python();
 
-            let result = py.eval(r###"_draw()"###, None, Some(&self.mydict));
+            let result = self.eval(py, r###"_draw()"###);
 
             match result {
-                Err(v) => {
+                Err(error) => {
                     return_draw_value = false;
-                    warn!("[PLUGIN][PYTHON] DRAW = {:?}", v);
+                    self.report("DRAW", error);
                 }
                 Ok(v) => {
                     match v.extract(py) {
@@ -565,12 +395,12 @@ pub mod plugin {
             let gil = Python::acquire_gil();
             let py = gil.python();
 
-            let result = py.eval(r###"_update()"###, None, Some(&self.mydict));
+            let result = self.eval(py, r###"_update()"###);
 
             match result {
-                Err(v) => {
+                Err(error) => {
                     return_update_value = false;
-                    warn!("[PLUGIN][PYTHON] UPDATE = {:?}", v);
+                    self.report("UPDATE", error);
                 }
                 Ok(v) => {
                     match v.extract(py) {
@@ -592,7 +422,36 @@ pub mod plugin {
             let py = gil.python();
 
 
-            let result = py.run(&data, None, None);
+            self.error = None;
+
+            // Tasks of the previous code
+            self.mydict.set_item(py, "unicorn_scheduler_fps", scheduler::FRAMES_PER_SECOND).unwrap();
+            let _ = py.run(r###"unicorn_scheduler(unicorn_scheduler_fps)"###,
+                           None,
+                           Some(&self.mydict));
+
+            // Modules found by `import`
+            let files = PyDict::new(py);
+            for (filename, source) in &self.files {
+                files.set_item(py, filename.as_str(), source.as_str()).unwrap();
+            }
+            self.mydict.set_item(py, "unicorn_files_sources", files).unwrap();
+            let result = py.run(r###"unicorn_files(unicorn_files_sources)"###,
+                                None,
+                                Some(&self.mydict));
+            if let Err(err) = result {
+                self.report("LOAD FILES", script_error(py, err));
+                self.loaded_code = false;
+                return false;
+            }
+
+            // Compiled with a filename, for the locations of the errors
+            self.mydict.set_item(py, "unicorn_code", data).unwrap();
+            self.mydict.set_item(py, "unicorn_code_filename", CODE_FILENAME).unwrap();
+
+            let result = py.run(r###"exec(compile(unicorn_code, unicorn_code_filename, "exec"), globals())"###,
+                                None,
+                                Some(&self.mydict));
 
             match result {
                 Ok(_) => {
@@ -600,7 +459,7 @@ pub mod plugin {
                     self.loaded_code = true
                 }
                 Err(err) => {
-                    error!("[PLUGIN][PYTHON] Load code error => {:?}", err);
+                    self.report("LOAD CODE", script_error(py, err));
                     self.loaded_code = false
                 }
             }
@@ -608,21 +467,75 @@ pub mod plugin {
             self.loaded_code
         }
     }
+
+    #[cfg(test)]
+    mod tests {
+        use std::fs::{self, File};
+        use std::io::Write;
+        use std::time::Duration;
+
+        use tempdir::TempDir;
+
+        use plugins::api;
+        use plugins::sandbox;
+        use plugins::sandbox::SandboxPolicy;
+
+        use super::*;
+
+        /// A single test: the interpreter and its sandbox are shared
+        #[test]
+        fn sandbox() {
+            let dir = TempDir::new("unicorn").unwrap();
+            let root = dir.path().join("cart");
+            fs::create_dir(&root).unwrap();
+            File::create(dir.path().join("secret.txt")).unwrap().write_all(b"secret").unwrap();
+
+            let (context, _crecv) = api::test_context();
+            let mut policy = SandboxPolicy::new(&root);
+            policy.time = Duration::from_millis(100);
+
+            let mut plugin = PythonPlugin::new();
+            plugin.load(context, policy);
+            assert!(plugin.load_code(r#"
+def _init():
+    import socket
+
+def _update():
+    open("../secret.txt")
+
+def _draw():
+    while True:
+        pass
+"#
+                                             .to_string()));
+
+            plugin.init();
+            assert!(plugin.take_error().unwrap().message.contains("module socket is not available"));
+
+            plugin.update();
+            assert!(plugin.take_error().unwrap().message.contains("access denied"));
+
+            plugin.draw();
+            assert_eq!(plugin.take_error().unwrap().message, sandbox::BUDGET_EXCEEDED);
+
+            // The wrappers of the modules are created once
+            assert_eq!(plugin.repl("__import__('math') is __import__('math')").unwrap(),
+                       "True");
+        }
+    }
 }
 
 #[cfg(not(feature = "cpython"))]
 pub mod plugin {
-    use std::sync::{Arc, Mutex};
-
-    use config::Players;
+    use std::collections::HashMap;
 
-    use unicorn::info::Info;
+    use plugins::api::ApiContext;
+    use plugins::error::ScriptError;
+    use plugins::sandbox::SandboxPolicy;
 
-    use gfx::Screen;
-    use unicorn::Palettes;
-    use sound::sound::Sound;
-    use unicorn::noise::Noise;
-    use unicorn::UnicornConfig;
+    pub fn check_syntax(_filename: &str, _code: &str) -> Result<(), ScriptError> {
+        Ok(())
+    }
 
     pub struct PythonPlugin {}
 
@@ -632,16 +545,23 @@ pub mod plugin {
         }
 
 
-        pub fn load(&mut self,
-                    _palettes: Arc<Mutex<Palettes>>,
-                    _players: Arc<Mutex<Players>>,
-                    _info: Arc<Mutex<Info>>,
-                    _screen: Arc<Mutex<Screen>>,
-                    _sound: Arc<Mutex<Sound>>,
-                    _noise: Arc<Mutex<Noise>>,
-                    _config: Arc<Mutex<UnicornConfig>>) {
+        pub fn load(&mut self, _context: ApiContext, _sandbox: SandboxPolicy) {
             panic!("[PLUGIN][PYTHON] plugin disabled");
         }
+        pub fn take_error(&mut self) -> Option<ScriptError> {
+            None
+        }
+        pub fn repl(&mut self, _code: &str) -> Result<String, ScriptError> {
+            Err(ScriptError::new("Python plugin disabled"))
+        }
+        pub fn globals(&mut self) -> Vec<String> {
+            Vec::new()
+        }
+        pub fn set_files(&mut self, _files: HashMap<String, String>) {}
+        pub fn memory() -> f64 {
+            0.0
+        }
+        pub fn tick(&mut self) {}
         pub fn init(&mut self) {}
         pub fn draw(&mut self) -> bool {
             false
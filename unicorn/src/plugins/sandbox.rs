@@ -0,0 +1,170 @@
+//! Sandbox of the scripts of a cartridge: the part of the standard library they
+//! can use, the directory they can read, and how long `_init`, `_update` and
+//! `_draw` can run before being aborted.
+//!
+//! Each plugin applies the policy with the tools of its language:
+//! * Lua: whitelisted libraries, `require` and `io.open` limited to the
+//!   directory, and a count hook checking the budget
+//! * Javascript: duktape has no access to the files, the execution timeout
+//!   check of duktape aborts the long calls
+//! * WebAssembly: the modules only import the API, and their loops are
+//!   instrumented to check the budget
+//! * Python: whitelisted imports, `open` limited to the directory, and a
+//!   watchdog thread raising an exception in the long calls. This is only a
+//!   guard against the mistakes, Python can't be confined from the inside.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Message of the error raised in a script exceeding its budget
+pub const BUDGET_EXCEEDED: &'static str = "script budget exceeded";
+
+/// Number of virtual machine instructions of a call
+pub const DEFAULT_INSTRUCTIONS: u64 = 100_000_000;
+/// Duration of a call, in milliseconds
+pub const DEFAULT_TIME: u64 = 1000;
+
+#[derive(Clone, Debug)]
+pub struct SandboxPolicy {
+    /// Disabled for the trusted cartridges
+    pub enabled: bool,
+    /// Directory of the cartridge, the only one accessible by the scripts
+    pub root: PathBuf,
+    /// Number of instructions of a call, when the plugin can count them
+    pub instructions: u64,
+    /// Duration of a call
+    pub time: Duration,
+}
+
+impl SandboxPolicy {
+    pub fn new(root: &Path) -> SandboxPolicy {
+        SandboxPolicy {
+            enabled: true,
+            root: root.to_path_buf(),
+            instructions: DEFAULT_INSTRUCTIONS,
+            time: Duration::from_millis(DEFAULT_TIME),
+        }
+    }
+
+    pub fn unrestricted(root: &Path) -> SandboxPolicy {
+        SandboxPolicy { enabled: false, ..SandboxPolicy::new(root) }
+    }
+
+    /// Budget of a call of the script
+    pub fn budget(&self) -> Budget {
+        if !self.enabled {
+            return Budget::unlimited();
+        }
+
+        Budget {
+            deadline: Some(Instant::now() + self.time),
+            instructions: Some(self.instructions),
+        }
+    }
+
+    /// Path of a file of the script, relative to the directory of the cartridge.
+    /// `None` if the file is outside the directory.
+    pub fn resolve(&self, path: &str) -> Option<PathBuf> {
+        if !self.enabled {
+            return Some(PathBuf::from(path));
+        }
+
+        let mut resolved = self.root.clone();
+        for component in Path::new(path).components() {
+            match component {
+                Component::Normal(name) => resolved.push(name),
+                Component::ParentDir => {
+                    if resolved == self.root {
+                        return None;
+                    }
+                    resolved.pop();
+                }
+                Component::CurDir => (),
+                // Absolute paths
+                Component::RootDir | Component::Prefix(_) => return None,
+            }
+        }
+
+        // Follow the symbolic links of the existing files
+        if let (Ok(root), Ok(real)) = (fs::canonicalize(&self.root), fs::canonicalize(&resolved)) {
+            if !real.starts_with(root) {
+                return None;
+            }
+        }
+
+        Some(resolved)
+    }
+}
+
+/// Remaining time and instructions of a call
+#[derive(Clone, Debug)]
+pub struct Budget {
+    deadline: Option<Instant>,
+    instructions: Option<u64>,
+}
+
+impl Budget {
+    pub fn unlimited() -> Budget {
+        Budget {
+            deadline: None,
+            instructions: None,
+        }
+    }
+
+    pub fn deadline(&self) -> Option<Instant> {
+        self.deadline
+    }
+
+    /// Count `count` instructions, false when the budget is exceeded
+    pub fn spend(&mut self, count: u64) -> bool {
+        if let Some(ref mut instructions) = self.instructions {
+            if *instructions < count {
+                return false;
+            }
+            *instructions -= count;
+        }
+
+        match self.deadline {
+            Some(deadline) => Instant::now() < deadline,
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::{Path, PathBuf};
+
+    use super::*;
+
+    #[test]
+    fn resolve_inside_root() {
+        let policy = SandboxPolicy::new(Path::new("/nonexistent/cart"));
+
+        assert_eq!(policy.resolve("data/level.txt"),
+                   Some(PathBuf::from("/nonexistent/cart/data/level.txt")));
+        assert_eq!(policy.resolve("./a/../b.lua"),
+                   Some(PathBuf::from("/nonexistent/cart/b.lua")));
+        assert_eq!(policy.resolve("../other/cart.uni"), None);
+        assert_eq!(policy.resolve("a/../../b"), None);
+        assert_eq!(policy.resolve("/etc/passwd"), None);
+
+        let policy = SandboxPolicy::unrestricted(Path::new("/nonexistent/cart"));
+        assert_eq!(policy.resolve("/etc/passwd"),
+                   Some(PathBuf::from("/etc/passwd")));
+    }
+
+    #[test]
+    fn budget_instructions() {
+        let mut policy = SandboxPolicy::new(Path::new("."));
+        policy.instructions = 10;
+
+        let mut budget = policy.budget();
+        assert!(budget.spend(6));
+        assert!(!budget.spend(6));
+
+        let mut budget = Budget::unlimited();
+        assert!(budget.spend(u64::max_value()));
+    }
+}
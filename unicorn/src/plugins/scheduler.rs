@@ -0,0 +1,111 @@
+//! Scheduler of the scripts: tasks suspended for some frames, timers and
+//! tweens, resumed by `Unicorn::update` at each frame, before `_update`.
+//!
+//! The functions are the same in each language:
+//! * `spawn(fn)`: run `fn` as a task from the next frame, returns its id
+//! * `wait(frames = 1)`: suspend the current task
+//! * `after(seconds, fn)`: call `fn` once, as a task
+//! * `every(seconds, fn)`: call `fn` until it returns false
+//! * `tween(obj, field, to, duration, easing = "linear")`: move `obj[field]`
+//!   to `to` in `duration` seconds, the easing is a name of `EASINGS` or a
+//!   function
+//! * `cancel(id)`: stop a task, a timer or a tween
+//!
+//! Each plugin implements the tasks with the tools of its language:
+//! * Lua: coroutines, resumed by the plugin with `lua_resume`
+//! * Python: generators, `yield wait(frames)` suspends the task
+//! * Javascript: duktape has no generators, the tasks are `Duktape.Thread`
+//!   coroutines. `wait` can't suspend a task from a native callback, like the
+//!   function given to `Array.prototype.forEach`.
+
+use std::f64::consts::PI;
+
+/// Frames by second of the console, to convert the durations in frames
+pub const FRAMES_PER_SECOND: u32 = 60;
+
+/// Easing curves of `tween`
+pub const EASINGS: [&'static str; 12] = ["linear",
+                                         "in_quad",
+                                         "out_quad",
+                                         "in_out_quad",
+                                         "in_cubic",
+                                         "out_cubic",
+                                         "in_out_cubic",
+                                         "in_sine",
+                                         "out_sine",
+                                         "in_out_sine",
+                                         "out_back",
+                                         "out_bounce"];
+
+fn out_bounce(t: f64) -> f64 {
+    let n = 7.5625;
+    let d = 2.75;
+
+    if t < 1.0 / d {
+        n * t * t
+    } else if t < 2.0 / d {
+        let t = t - 1.5 / d;
+        n * t * t + 0.75
+    } else if t < 2.5 / d {
+        let t = t - 2.25 / d;
+        n * t * t + 0.9375
+    } else {
+        let t = t - 2.625 / d;
+        n * t * t + 0.984375
+    }
+}
+
+/// Progress of the easing curve `name` at `t`, both from 0.0 to 1.0
+pub fn ease(name: &str, t: f64) -> Option<f64> {
+    let t = t.max(0.0).min(1.0);
+
+    let value = match name {
+        "linear" => t,
+        "in_quad" => t * t,
+        "out_quad" => t * (2.0 - t),
+        "in_out_quad" => {
+            if t < 0.5 {
+                2.0 * t * t
+            } else {
+                -1.0 + (4.0 - 2.0 * t) * t
+            }
+        }
+        "in_cubic" => t * t * t,
+        "out_cubic" => (t - 1.0).powi(3) + 1.0,
+        "in_out_cubic" => {
+            if t < 0.5 {
+                4.0 * t * t * t
+            } else {
+                (t - 1.0) * (2.0 * t - 2.0) * (2.0 * t - 2.0) + 1.0
+            }
+        }
+        "in_sine" => 1.0 - (t * PI / 2.0).cos(),
+        "out_sine" => (t * PI / 2.0).sin(),
+        "in_out_sine" => -((PI * t).cos() - 1.0) / 2.0,
+        "out_back" => {
+            let c = 1.70158;
+            1.0 + (c + 1.0) * (t - 1.0).powi(3) + c * (t - 1.0).powi(2)
+        }
+        "out_bounce" => out_bounce(t),
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn easing_bounds() {
+        for name in EASINGS.iter() {
+            assert!(ease(name, 0.0).unwrap().abs() < 1e-9, "{} at 0", name);
+            assert!((ease(name, 1.0).unwrap() - 1.0).abs() < 1e-9, "{} at 1", name);
+        }
+
+        assert_eq!(ease("in_quad", 0.5), Some(0.25));
+        assert_eq!(ease("linear", 2.0), Some(1.0));
+        assert_eq!(ease("unknown", 0.5), None);
+    }
+}
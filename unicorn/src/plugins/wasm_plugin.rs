@@ -0,0 +1,676 @@
+//! WebAssembly cartridges: the `__wasm__` section contains the module encoded in base64.
+//!
+//! The module exports `_init`, `_update` and `_draw`, and a `memory`. The functions of
+//! `plugins::api` are imported from the `env` module, with these types:
+//! * `int` and `bool` are `i32`, `float` is `f64`
+//! * `str` is a pointer and a length in bytes (UTF-8)
+//! * `list` is a pointer and a number of `i32`
+//! * a `str` or `list` result is written to an extra pointer/capacity pair of arguments,
+//!   and the function returns the written length
+//! * every argument is required, -1 is used for the arguments that default to nil (the
+//!   length for a `str` or a `list`), the other negative lengths trap
+//!
+//! The module is instrumented when it is loaded: each loop calls the budget of the
+//! sandbox at every iteration, an iteration counts as one instruction. The budget is
+//! also checked when the API is called.
+
+#[cfg(feature = "wasm")]
+pub mod plugin {
+    use std::fmt;
+
+    use base64;
+    use byteorder::{ByteOrder, LittleEndian};
+    use parity_wasm::elements;
+    use parity_wasm::elements::{External, FunctionType, ImportEntry, ImportSection, Instruction,
+                                Internal, Section, Type};
+    use wasmi::{Error, Externals, FuncInstance, FuncRef, HostError, ImportsBuilder, MemoryRef,
+                Module, ModuleImportResolver, ModuleInstance, ModuleRef, RuntimeArgs,
+                RuntimeValue, Signature, Trap, TrapKind, ValueType};
+
+    use plugins::api;
+    use plugins::api::{ApiContext, ApiFunction, ApiType, ApiValue};
+    use plugins::error::ScriptError;
+    use plugins::sandbox;
+    use plugins::sandbox::{Budget, SandboxPolicy};
+
+    #[derive(Debug)]
+    struct BudgetExceeded;
+
+    impl fmt::Display for BudgetExceeded {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "{}", sandbox::BUDGET_EXCEEDED)
+        }
+    }
+
+    impl HostError for BudgetExceeded {}
+
+    /// Function imported by the instrumented modules, called by the loops
+    const BUDGET_FUNCTION: &'static str = "_budget";
+
+    /// Index of the budget function in the host functions, after the API
+    fn budget_index() -> usize {
+        api::FUNCTIONS.len()
+    }
+
+    /// Import the budget function, and call it at the start of each loop. The
+    /// indices of the functions of the module are shifted by the new import.
+    fn instrument(mut module: elements::Module) -> Result<elements::Module, String> {
+        if module.code_section().is_none() {
+            return Ok(module);
+        }
+
+        let hook_type = {
+            let types = match module.type_section_mut() {
+                Some(types) => types.types_mut(),
+                None => return Err("missing type section".to_string()),
+            };
+            types.push(Type::Function(FunctionType::new(Vec::new(), None)));
+            (types.len() - 1) as u32
+        };
+
+        let import = ImportEntry::new("env".to_string(),
+                                      BUDGET_FUNCTION.to_string(),
+                                      External::Function(hook_type));
+        let hook = match module.import_section_mut() {
+            Some(imports) => {
+                let functions = imports.functions() as u32;
+                imports.entries_mut().push(import);
+                functions
+            }
+            None => {
+                // The import section follows the type section
+                let sections = module.sections_mut();
+                let position = sections
+                    .iter()
+                    .position(|section| match *section {
+                                  Section::Type(_) => true,
+                                  _ => false,
+                              })
+                    .map(|position| position + 1)
+                    .unwrap_or(0);
+                sections.insert(position, Section::Import(ImportSection::with_entries(vec![import])));
+                0
+            }
+        };
+
+        let shift = |idx: &mut u32| if *idx >= hook {
+            *idx += 1;
+        };
+
+        for section in module.sections_mut().iter_mut() {
+            match *section {
+                Section::Code(ref mut code) => {
+                    for body in code.bodies_mut().iter_mut() {
+                        let instructions = body.code_mut().elements_mut();
+                        let mut instrumented = Vec::with_capacity(instructions.len());
+
+                        for mut instruction in instructions.drain(..) {
+                            if let Instruction::Call(ref mut idx) = instruction {
+                                shift(idx);
+                            }
+
+                            let is_loop = match instruction {
+                                Instruction::Loop(_) => true,
+                                _ => false,
+                            };
+
+                            instrumented.push(instruction);
+                            if is_loop {
+                                instrumented.push(Instruction::Call(hook));
+                            }
+                        }
+
+                        *instructions = instrumented;
+                    }
+                }
+                Section::Export(ref mut exports) => {
+                    for export in exports.entries_mut().iter_mut() {
+                        if let Internal::Function(ref mut idx) = *export.internal_mut() {
+                            shift(idx);
+                        }
+                    }
+                }
+                Section::Element(ref mut elements) => {
+                    for segment in elements.entries_mut().iter_mut() {
+                        for idx in segment.members_mut().iter_mut() {
+                            shift(idx);
+                        }
+                    }
+                }
+                Section::Start(ref mut idx) => shift(idx),
+                _ => (),
+            }
+        }
+
+        // The debug names refer to the old indices
+        module.sections_mut().retain(|section| match *section {
+                                         Section::Custom(ref custom) => custom.name() != "name",
+                                         Section::Name(_) => false,
+                                         _ => true,
+                                     });
+
+        Ok(module)
+    }
+
+    fn value_types(kind: ApiType) -> Vec<ValueType> {
+        match kind {
+            ApiType::Float => vec![ValueType::F64],
+            ApiType::Str | ApiType::List => vec![ValueType::I32, ValueType::I32],
+            _ => vec![ValueType::I32],
+        }
+    }
+
+    fn signature(function: &ApiFunction) -> Signature {
+        let mut params = Vec::new();
+        for arg in function.args.iter() {
+            params.extend(value_types(arg.kind));
+        }
+
+        let ret = match function.ret {
+            ApiType::Nil => None,
+            ApiType::Float => Some(ValueType::F64),
+            ApiType::Str | ApiType::List => {
+                // Output buffer
+                params.extend(value_types(function.ret));
+                Some(ValueType::I32)
+            }
+            _ => Some(ValueType::I32),
+        };
+
+        Signature::new(params, ret)
+    }
+
+    struct WasmResolver;
+
+    impl ModuleImportResolver for WasmResolver {
+        fn resolve_func(&self, field_name: &str, requested: &Signature) -> Result<FuncRef, Error> {
+            if field_name == BUDGET_FUNCTION {
+                return Ok(FuncInstance::alloc_host(Signature::new(Vec::new(), None), budget_index()));
+            }
+
+            let idx = match api::find(field_name) {
+                Some(idx) => idx,
+                None => {
+                    return Err(Error::Instantiation(format!("Unknown function {:?}",
+                                                            field_name)))
+                }
+            };
+
+            let expected = signature(&api::FUNCTIONS[idx]);
+            if *requested != expected {
+                return Err(Error::Instantiation(format!("{:?} has the signature {:?}, expected {:?}",
+                                                        field_name,
+                                                        requested,
+                                                        expected)));
+            }
+
+            Ok(FuncInstance::alloc_host(expected, idx))
+        }
+    }
+
+    struct WasmExternals {
+        context: ApiContext,
+        memory: Option<MemoryRef>,
+        budget: Budget,
+    }
+
+    /// Size in bytes of `len` items of `size` bytes
+    fn byte_length(len: i32, size: usize) -> Result<usize, Trap> {
+        if len < 0 {
+            return Err(Trap::new(TrapKind::MemoryAccessOutOfBounds));
+        }
+
+        (len as usize)
+            .checked_mul(size)
+            .ok_or_else(|| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+    }
+
+    impl WasmExternals {
+        fn read(&self, ptr: i32, len: usize) -> Result<Vec<u8>, Trap> {
+            match self.memory {
+                Some(ref memory) => {
+                    memory
+                        .get(ptr as u32, len)
+                        .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+                }
+                None => Err(Trap::new(TrapKind::MemoryAccessOutOfBounds)),
+            }
+        }
+
+        fn write(&self, ptr: i32, data: &[u8]) -> Result<(), Trap> {
+            match self.memory {
+                Some(ref memory) => {
+                    memory
+                        .set(ptr as u32, data)
+                        .map_err(|_| Trap::new(TrapKind::MemoryAccessOutOfBounds))
+                }
+                None => Err(Trap::new(TrapKind::MemoryAccessOutOfBounds)),
+            }
+        }
+    }
+
+    impl Externals for WasmExternals {
+        fn invoke_index(&mut self,
+                        index: usize,
+                        args: RuntimeArgs)
+                        -> Result<Option<RuntimeValue>, Trap> {
+            let count = if index == budget_index() { 1 } else { 0 };
+            if !self.budget.spend(count) {
+                return Err(Trap::new(TrapKind::Host(Box::new(BudgetExceeded))));
+            }
+
+            if index == budget_index() {
+                return Ok(None);
+            }
+
+            let function = &api::FUNCTIONS[index];
+
+            let mut values = Vec::new();
+            let mut pos = 0;
+
+            for arg in function.args.iter() {
+                let value = match arg.kind {
+                    ApiType::Float => {
+                        match try!(args.nth_value_checked(pos)) {
+                            RuntimeValue::F64(value) => ApiValue::Float(value.to_float()),
+                            _ => return Err(Trap::new(TrapKind::UnexpectedSignature)),
+                        }
+                    }
+                    ApiType::Str | ApiType::List => {
+                        let ptr = try!(args.nth_checked::<i32>(pos));
+                        let len = try!(args.nth_checked::<i32>(pos + 1));
+
+                        if len == -1 && arg.default == Some(ApiValue::Nil) {
+                            ApiValue::Nil
+                        } else if arg.kind == ApiType::Str {
+                            let data = try!(self.read(ptr, try!(byte_length(len, 1))));
+                            ApiValue::Str(String::from_utf8_lossy(&data).into_owned())
+                        } else {
+                            let data = try!(self.read(ptr, try!(byte_length(len, 4))));
+                            ApiValue::List(data.chunks(4)
+                                               .map(|v| LittleEndian::read_i32(v) as i64)
+                                               .collect())
+                        }
+                    }
+                    _ => {
+                        let value = try!(args.nth_checked::<i32>(pos));
+                        if value == -1 && arg.default == Some(ApiValue::Nil) {
+                            ApiValue::Nil
+                        } else {
+                            ApiValue::Int(value as i64)
+                        }
+                    }
+                };
+
+                pos += value_types(arg.kind).len();
+                values.push(value);
+            }
+
+            let result = self.context.call(index, values);
+
+            match function.ret {
+                ApiType::Nil => Ok(None),
+                ApiType::Str | ApiType::List => {
+                    let ptr = try!(args.nth_checked::<i32>(pos));
+                    let capacity = try!(byte_length(try!(args.nth_checked::<i32>(pos + 1)), 1));
+
+                    let (data, len) = match result {
+                        ApiValue::Str(v) => {
+                            let mut data = v.into_bytes();
+                            data.truncate(capacity);
+                            let len = data.len();
+                            (data, len)
+                        }
+                        ApiValue::List(mut v) => {
+                            v.truncate(capacity);
+                            let mut data = vec![0; v.len() * 4];
+                            for (i, value) in v.iter().enumerate() {
+                                LittleEndian::write_i32(&mut data[i * 4..], *value as i32);
+                            }
+                            (data, v.len())
+                        }
+                        _ => (Vec::new(), 0),
+                    };
+
+                    try!(self.write(ptr, &data));
+                    Ok(Some(RuntimeValue::I32(len as i32)))
+                }
+                _ => {
+                    Ok(Some(match result {
+                                ApiValue::Float(v) => RuntimeValue::F64(v.into()),
+                                ApiValue::Int(v) => RuntimeValue::I32(v as i32),
+                                ApiValue::Bool(v) => RuntimeValue::I32(v as i32),
+                                _ => RuntimeValue::I32(0),
+                            }))
+                }
+            }
+        }
+    }
+
+    pub struct WasmPlugin {
+        externals: Option<WasmExternals>,
+        instance: Option<ModuleRef>,
+        sandbox: Option<SandboxPolicy>,
+        loaded_code: bool,
+        error: Option<ScriptError>,
+    }
+
+    impl WasmPlugin {
+        pub fn new() -> WasmPlugin {
+            WasmPlugin {
+                externals: None,
+                instance: None,
+                sandbox: None,
+                loaded_code: false,
+                error: None,
+            }
+        }
+
+        pub fn load(&mut self, context: ApiContext, sandbox: SandboxPolicy) {
+            info!("[PLUGIN][WASM] Init plugin");
+
+            self.externals = Some(WasmExternals {
+                                      context: context,
+                                      memory: None,
+                                      budget: Budget::unlimited(),
+                                  });
+            self.sandbox = Some(sandbox);
+        }
+
+        pub fn take_error(&mut self) -> Option<ScriptError> {
+            self.error.take()
+        }
+
+        /// The modules can't evaluate code
+        pub fn repl(&mut self, _code: &str) -> Result<String, ScriptError> {
+            Err(ScriptError::new("no REPL for the WebAssembly cartridges"))
+        }
+
+        /// Nothing to complete
+        pub fn globals(&mut self) -> Vec<String> {
+            Vec::new()
+        }
+
+        pub fn load_code(&mut self, data: String) -> bool {
+            info!("[PLUGIN][WASM] LOAD CODE");
+
+            self.loaded_code = false;
+            self.instance = None;
+            self.error = None;
+
+            let externals = match self.externals {
+                Some(ref mut externals) => externals,
+                None => return false,
+            };
+
+            let data: String = data.chars().filter(|c| !c.is_whitespace()).collect();
+            let bytes = match base64::decode(&data) {
+                Ok(bytes) => bytes,
+                Err(err) => {
+                    error!("[PLUGIN][WASM] Invalid base64 data {:?}", err);
+                    return false;
+                }
+            };
+
+            let module = match elements::deserialize_buffer::<elements::Module>(&bytes)
+                      .map_err(|err| err.to_string())
+                      .and_then(instrument) {
+                Ok(module) => module,
+                Err(err) => {
+                    error!("[PLUGIN][WASM] Invalid module {:?}", err);
+                    return false;
+                }
+            };
+
+            let module = match Module::from_parity_wasm_module(module) {
+                Ok(module) => module,
+                Err(err) => {
+                    error!("[PLUGIN][WASM] Invalid module {:?}", err);
+                    return false;
+                }
+            };
+
+            let imports = ImportsBuilder::new().with_resolver("env", &WasmResolver);
+            let not_started = match ModuleInstance::new(&module, &imports) {
+                Ok(not_started) => not_started,
+                Err(err) => {
+                    error!("[PLUGIN][WASM] Impossible to instantiate the module {:?}", err);
+                    return false;
+                }
+            };
+
+            externals.memory = not_started
+                .not_started_instance()
+                .export_by_name("memory")
+                .and_then(|export| export.as_memory().cloned());
+
+            match not_started.run_start(externals) {
+                Ok(instance) => {
+                    self.instance = Some(instance);
+                    self.loaded_code = true;
+                }
+                Err(err) => {
+                    error!("[PLUGIN][WASM] Error in the start function {:?}", err);
+                    self.error = Some(ScriptError::new(&format!("start: {:?}", err)));
+                }
+            }
+
+            self.loaded_code
+        }
+
+        fn call(&mut self, name: &str) -> bool {
+            if !self.loaded_code {
+                return false;
+            }
+
+            if let (Some(instance), Some(externals)) =
+                (self.instance.as_ref(), self.externals.as_mut()) {
+                if instance.export_by_name(name).is_none() {
+                    return true;
+                }
+
+                externals.budget = match self.sandbox {
+                    Some(ref sandbox) => sandbox.budget(),
+                    None => Budget::unlimited(),
+                };
+
+                if let Err(err) = instance.invoke_export(name, &[], externals) {
+                    error!("[PLUGIN][WASM] {} = {:?}", name, err);
+
+                    let exceeded = err.as_host_error()
+                        .and_then(|err| err.downcast_ref::<BudgetExceeded>())
+                        .is_some();
+                    let message = if exceeded {
+                        format!("{}: {}", name, sandbox::BUDGET_EXCEEDED)
+                    } else {
+                        format!("{}: {:?}", name, err)
+                    };
+                    self.error = Some(ScriptError::new(&message));
+                }
+            }
+
+            true
+        }
+
+        pub fn init(&mut self) {
+            self.call("_init");
+        }
+
+        pub fn draw(&mut self) -> bool {
+            self.call("_draw")
+        }
+
+        pub fn update(&mut self) -> bool {
+            self.call("_update")
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::path::Path;
+
+        use base64;
+
+        use plugins::api;
+        use plugins::sandbox;
+        use plugins::sandbox::SandboxPolicy;
+
+        use wasmi::MemoryInstance;
+        use wasmi::memory_units::Pages;
+
+        use super::*;
+
+        /// Module importing `pset`, `_update` draws a pixel and `_draw` calls a
+        /// function looping forever
+        const MODULE: [u8; 114] =
+            [0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+             // Types: (i32, i32, i32) -> (), () -> ()
+             0x01, 0x0a, 0x02, 0x60, 0x03, 0x7f, 0x7f, 0x7f, 0x00, 0x60, 0x00, 0x00,
+             // Import env.pset
+             0x02, 0x0c, 0x01, 0x03, b'e', b'n', b'v', 0x04, b'p', b's', b'e', b't', 0x00, 0x00,
+             // Functions 1 to 4
+             0x03, 0x05, 0x04, 0x01, 0x01, 0x01, 0x01,
+             // Memory of one page
+             0x05, 0x03, 0x01, 0x00, 0x01,
+             // Exports: memory, _init, _update, _draw
+             0x07, 0x24, 0x04,
+             0x06, b'm', b'e', b'm', b'o', b'r', b'y', 0x02, 0x00,
+             0x05, b'_', b'i', b'n', b'i', b't', 0x00, 0x01,
+             0x07, b'_', b'u', b'p', b'd', b'a', b't', b'e', 0x00, 0x02,
+             0x05, b'_', b'd', b'r', b'a', b'w', 0x00, 0x03,
+             // Code
+             0x0a, 0x1c, 0x04,
+             // _init: nothing
+             0x02, 0x00, 0x0b,
+             // _update: pset(1, 2, 3)
+             0x0a, 0x00, 0x41, 0x01, 0x41, 0x02, 0x41, 0x03, 0x10, 0x00, 0x0b,
+             // _draw: call 4
+             0x04, 0x00, 0x10, 0x04, 0x0b,
+             // 4: loop br 0 end
+             0x07, 0x00, 0x03, 0x40, 0x0c, 0x00, 0x0b, 0x0b];
+
+        fn plugin(instructions: u64) -> (WasmPlugin, ApiContext) {
+            let (context, _crecv) = api::test_context();
+
+            let mut sandbox = SandboxPolicy::new(Path::new("."));
+            sandbox.instructions = instructions;
+
+            let mut plugin = WasmPlugin::new();
+            plugin.load(context.clone(), sandbox);
+            (plugin, context)
+        }
+
+        #[test]
+        fn load_module() {
+            let (mut plugin, _) = plugin(1000);
+
+            assert!(!plugin.load_code("not base64!".to_string()));
+            assert!(!plugin.load_code(base64::encode(&MODULE[..30])));
+
+            assert!(plugin.load_code(base64::encode(&MODULE[..])));
+            plugin.init();
+            assert!(plugin.take_error().is_none());
+        }
+
+        #[test]
+        fn update_calls_api() {
+            let (mut plugin, context) = plugin(1000);
+            assert!(plugin.load_code(base64::encode(&MODULE[..])));
+
+            assert!(plugin.update());
+            assert!(plugin.take_error().is_none());
+            assert_eq!(context.screen.lock().unwrap().pget(1, 2), 3);
+        }
+
+        fn externals() -> WasmExternals {
+            let (context, _crecv) = api::test_context();
+
+            WasmExternals {
+                context: context,
+                memory: Some(MemoryInstance::alloc(Pages(1), None).unwrap()),
+                budget: Budget::unlimited(),
+            }
+        }
+
+        #[test]
+        fn negative_lengths_trap() {
+            let mut externals = externals();
+            let print = api::find("print").unwrap();
+            let polygon = api::find("polygon").unwrap();
+
+            let args = [RuntimeValue::I32(0),
+                        RuntimeValue::I32(-1),
+                        RuntimeValue::I32(0),
+                        RuntimeValue::I32(0),
+                        RuntimeValue::I32(7)];
+            assert!(externals.invoke_index(print, RuntimeArgs::from(&args[..])).is_err());
+
+            let args = [RuntimeValue::I32(0),
+                        RuntimeValue::I32(2),
+                        RuntimeValue::I32(0),
+                        RuntimeValue::I32(i32::min_value()),
+                        RuntimeValue::I32(7)];
+            assert!(externals.invoke_index(polygon, RuntimeArgs::from(&args[..])).is_err());
+
+            let args = [RuntimeValue::I32(0),
+                        RuntimeValue::I32(0),
+                        RuntimeValue::I32(0),
+                        RuntimeValue::I32(0),
+                        RuntimeValue::I32(7)];
+            assert!(externals.invoke_index(polygon, RuntimeArgs::from(&args[..])).is_ok());
+        }
+
+        #[test]
+        fn draw_loop_is_aborted() {
+            let (mut plugin, _) = plugin(1000);
+            assert!(plugin.load_code(base64::encode(&MODULE[..])));
+
+            assert!(plugin.draw());
+            let error = plugin.take_error().unwrap();
+            assert!(error.message.contains(sandbox::BUDGET_EXCEEDED));
+
+            // The plugin can still be called
+            assert!(plugin.update());
+            assert!(plugin.take_error().is_none());
+        }
+    }
+}
+
+#[cfg(not(feature = "wasm"))]
+pub mod plugin {
+    use plugins::api::ApiContext;
+    use plugins::error::ScriptError;
+    use plugins::sandbox::SandboxPolicy;
+
+    pub struct WasmPlugin {}
+
+    impl WasmPlugin {
+        pub fn new() -> WasmPlugin {
+            WasmPlugin {}
+        }
+
+        // Keep the compatibility
+        pub fn load(&mut self, _context: ApiContext, _sandbox: SandboxPolicy) {
+            panic!("[PLUGIN][WASM] plugin disabled");
+        }
+        pub fn take_error(&mut self) -> Option<ScriptError> {
+            None
+        }
+        pub fn repl(&mut self, _code: &str) -> Result<String, ScriptError> {
+            Err(ScriptError::new("[PLUGIN][WASM] plugin disabled"))
+        }
+        pub fn globals(&mut self) -> Vec<String> {
+            Vec::new()
+        }
+        pub fn load_code(&mut self, _data: String) -> bool {
+            false
+        }
+        pub fn init(&mut self) {}
+        pub fn draw(&mut self) -> bool {
+            false
+        }
+        pub fn update(&mut self) -> bool {
+            false
+        }
+    }
+}
@@ -7,8 +7,231 @@ pub mod sound {
 
     use std::sync::{Arc, Mutex};
 
+    pub const CHANNELS: usize = 32;
+    pub const SFX_CHANNELS_START: usize = 16;
+    pub const MAX_VOLUME: i32 = 128;
+    pub const PAN_CENTER: i32 = 64;
+
+    pub const BUS_MASTER: i32 = 0;
+    pub const BUS_MUSIC: i32 = 1;
+    pub const BUS_SFX: i32 = 2;
+
+    /// Mixer state sitting on top of the chiptune player.
+    ///
+    /// Channels below `SFX_CHANNELS_START` belong to the music bus, the
+    /// others to the SFX bus. The effective volume of a channel is its own
+    /// volume scaled by its bus volume (and by the ducking level for the
+    /// music bus when a high priority SFX is playing).
+    pub struct Mixer {
+        pub bus_volume: [i32; 3],
+        pub channel_volume: [i32; CHANNELS],
+        pub channel_panning: [i32; CHANNELS],
+        pub reserved: [bool; CHANNELS],
+        pub priority: [i32; CHANNELS],
+        pub duck_volume: i32,
+        pub duck_priority: i32,
+        ducking: bool,
+        dirty: bool,
+    }
+
+    impl Mixer {
+        pub fn new() -> Mixer {
+            Mixer {
+                bus_volume: [MAX_VOLUME; 3],
+                channel_volume: [MAX_VOLUME; CHANNELS],
+                channel_panning: [-1; CHANNELS],
+                reserved: [false; CHANNELS],
+                priority: [0; CHANNELS],
+                duck_volume: MAX_VOLUME,
+                duck_priority: 0,
+                ducking: false,
+                dirty: true,
+            }
+        }
+
+        pub fn set_bus_volume(&mut self, bus: i32, volume: i32) {
+            if bus < BUS_MASTER || bus > BUS_SFX {
+                return;
+            }
+
+            self.bus_volume[bus as usize] = clamp_volume(volume);
+            self.dirty = true;
+        }
+
+        pub fn set_channel_volume(&mut self, chan: i32, volume: i32) {
+            if chan < 0 || chan as usize >= CHANNELS {
+                return;
+            }
+
+            self.channel_volume[chan as usize] = clamp_volume(volume);
+            self.dirty = true;
+        }
+
+        /// A negative panning gives the control back to the instrument/song
+        pub fn set_channel_panning(&mut self, chan: i32, panning: i32) {
+            if chan < 0 || chan as usize >= CHANNELS {
+                return;
+            }
+
+            self.channel_panning[chan as usize] = if panning < 0 { -1 } else { clamp_volume(panning) };
+            self.dirty = true;
+        }
+
+        /// Reserved channels are never picked automatically for a SFX,
+        /// they can only be used by asking for them explicitly.
+        pub fn set_reserved(&mut self, chan: i32, reserved: bool) {
+            if chan < 0 || chan as usize >= CHANNELS {
+                return;
+            }
+
+            self.reserved[chan as usize] = reserved;
+        }
+
+        pub fn set_ducking(&mut self, volume: i32, priority: i32) {
+            self.duck_volume = clamp_volume(volume);
+            self.duck_priority = priority;
+            self.dirty = true;
+        }
+
+        pub fn invalidate(&mut self) {
+            self.dirty = true;
+        }
+
+        pub fn bus(&self, chan: usize) -> usize {
+            if chan < SFX_CHANNELS_START {
+                BUS_MUSIC as usize
+            } else {
+                BUS_SFX as usize
+            }
+        }
+
+        pub fn effective_volume(&self, chan: usize) -> i32 {
+            let mut volume = self.channel_volume[chan] * self.bus_volume[self.bus(chan)] /
+                             MAX_VOLUME;
+
+            if self.ducking && self.bus(chan) == BUS_MUSIC as usize {
+                volume = volume * self.duck_volume / MAX_VOLUME;
+            }
+
+            volume
+        }
+
+        /// Select the channel used to play a SFX with the given priority.
+        ///
+        /// An explicit channel is always honored. Otherwise the first free
+        /// and unreserved SFX channel is used, and if there is none the
+        /// channel playing the lowest priority SFX is stolen. -1 is returned
+        /// when every candidate plays something more important.
+        pub fn pick_channel(&mut self,
+                            player: &mut chiptune::Chiptune,
+                            channel: i32,
+                            priority: i32)
+                            -> i32 {
+            let candidate = self.select_channel(channel,
+                                                priority,
+                                                |chan| player.is_channel_playing(chan as i32));
+
+            if channel < 0 && candidate >= 0 && player.is_channel_playing(candidate) {
+                debug!("[SOUND][MIXER] Steal channel {:?} (priority {:?} < {:?})",
+                       candidate,
+                       self.priority[candidate as usize],
+                       priority);
+                player.stop_chan(candidate);
+            }
+
+            candidate
+        }
+
+        /// Channel chosen by `pick_channel`, `is_playing` tells if a channel is busy
+        pub fn select_channel<F>(&self, channel: i32, priority: i32, mut is_playing: F) -> i32
+            where F: FnMut(usize) -> bool
+        {
+            if channel >= 0 {
+                return channel;
+            }
+
+            for chan in SFX_CHANNELS_START..CHANNELS {
+                if !self.reserved[chan] && !is_playing(chan) {
+                    return chan as i32;
+                }
+            }
+
+            let mut candidate = -1;
+            let mut lowest = priority;
+            for chan in SFX_CHANNELS_START..CHANNELS {
+                if !self.reserved[chan] && self.priority[chan] < lowest {
+                    lowest = self.priority[chan];
+                    candidate = chan as i32;
+                }
+            }
+
+            candidate
+        }
+
+        pub fn started(&mut self, chan: i32, priority: i32) {
+            if chan >= 0 && (chan as usize) < CHANNELS {
+                self.priority[chan as usize] = priority;
+                self.dirty = true;
+            }
+        }
+
+        pub fn update(&mut self, player: &mut chiptune::Chiptune) {
+            self.update_ducking(|chan| player.is_channel_playing(chan as i32));
+
+            if !self.dirty {
+                return;
+            }
+
+            player.set_volume(self.bus_volume[BUS_MASTER as usize]);
+
+            for chan in 0..CHANNELS {
+                player.set_channel_volume(chan as i32, self.effective_volume(chan));
+                if self.channel_panning[chan] >= 0 {
+                    player.set_channel_panning(chan as i32, self.channel_panning[chan]);
+                }
+            }
+
+            self.dirty = false;
+        }
+
+        /// Forget the priorities of the stopped SFX, and duck the music while
+        /// a SFX with the ducking priority is playing
+        pub fn update_ducking<F>(&mut self, mut is_playing: F)
+            where F: FnMut(usize) -> bool
+        {
+            let mut ducking = false;
+
+            for chan in SFX_CHANNELS_START..CHANNELS {
+                if is_playing(chan) {
+                    if self.priority[chan] >= self.duck_priority {
+                        ducking = true;
+                    }
+                } else {
+                    self.priority[chan] = 0;
+                }
+            }
+
+            ducking = ducking && self.duck_volume < MAX_VOLUME;
+            if ducking != self.ducking {
+                self.ducking = ducking;
+                self.dirty = true;
+            }
+        }
+    }
+
+    fn clamp_volume(value: i32) -> i32 {
+        if value < 0 {
+            0
+        } else if value > MAX_VOLUME {
+            MAX_VOLUME
+        } else {
+            value
+        }
+    }
+
     pub struct SoundInternal {
         pub player: chiptune::Chiptune,
+        pub mixer: Mixer,
         pub csend: mpsc::Sender<Vec<u8>>,
         pub crecv: mpsc::Receiver<Vec<u8>>,
     }
@@ -19,6 +242,7 @@ pub mod sound {
 
             SoundInternal {
                 player: chiptune::Chiptune::new(),
+                mixer: Mixer::new(),
                 csend: csend,
                 crecv: crecv,
             }
@@ -121,28 +345,51 @@ pub mod sound {
                     }
                 }
 
-                match cartridge.sound_tracks.get_mut(&filename) {
-                    Some(mut sound) => {
-                        res = self.player.play_sound(&mut sound, channel, note, panning, rate);
-                    }
-                    None => {}
-                }
+                res = self.play_sound(cartridge, filename.clone(), channel, note, panning, rate, 0);
             }
 
-            if id >= 0 {
-                match cartridge.sound_tracks.get_mut(&cartridge.sound_tracks_name[id as usize]) {
-                    Some(mut sound) => {
-                        res = self.player.play_sound(&mut sound, channel, note, panning, rate);
-                    }
-                    None => {}
-                }
+            if id >= 0 && id < cartridge.sound_tracks_name.len() as i32 {
+                let name = cartridge.sound_tracks_name[id as usize].clone();
+                res = self.play_sound(cartridge, name, channel, note, panning, rate, 0);
             }
 
             res
         }
 
+        fn play_sound(&mut self,
+                      cartridge: &mut UnicornCartridge,
+                      name: String,
+                      channel: i32,
+                      note: u16,
+                      panning: i32,
+                      rate: i32,
+                      priority: i32)
+                      -> i32 {
+            match cartridge.sound_tracks.get_mut(&name) {
+                Some(mut sound) => {
+                    let chan = self.mixer.pick_channel(&mut self.player, channel, priority);
+                    if chan < 0 {
+                        debug!("[SOUND][MIXER] No channel available for {:?}", name);
+                        return -1;
+                    }
+
+                    let mut panning = panning;
+                    if (chan as usize) < CHANNELS && self.mixer.channel_panning[chan as usize] >= 0 {
+                        panning = self.mixer.channel_panning[chan as usize];
+                    }
+
+                    let res = self.player.play_sound(&mut sound, chan, note, panning, rate);
+                    self.mixer.started(res, priority);
+                    res
+                }
+                None => -1,
+            }
+        }
+
         pub fn update(&mut self, cartridge: &mut UnicornCartridge, sound: Arc<Mutex<Sound>>) {
-            for sound_packet in self.crecv.try_iter() {
+            // The packets are taken first, `play_sound` borrows all the sound
+            let packets: Vec<Vec<u8>> = self.crecv.try_iter().collect();
+            for sound_packet in packets {
                 debug!("[SOUND] PACKET {:?}", sound_packet);
                 match packet::read_packet(sound_packet).unwrap() {
                     packet::Packet::ChiptuneMusic(res) => {
@@ -181,6 +428,9 @@ pub mod sound {
                             }
                             None => {}
                         }
+
+                        // The song resets the channel volumes
+                        self.mixer.invalidate();
                     }
                     packet::Packet::ChiptuneLoadSFX(res) => {
                         info!("LOAD SFX {:?}", res);
@@ -216,30 +466,24 @@ pub mod sound {
                                 }
                             }
 
-                            match cartridge.sound_tracks.get_mut(&filename) {
-                                Some(mut sound) => {
-                                    self.player.play_sound(&mut sound,
-                                                           res.channel,
-                                                           res.note,
-                                                           res.panning,
-                                                           res.rate);
-                                }
-                                None => {}
-                            }
+                            self.play_sound(cartridge,
+                                            filename,
+                                            res.channel,
+                                            res.note,
+                                            res.panning,
+                                            res.rate,
+                                            res.priority);
                         }
 
                         if res.id >= 0 && res.id < cartridge.sound_tracks_name.len() as i32 {
-                            match cartridge.sound_tracks
-                                .get_mut(&cartridge.sound_tracks_name[res.id as usize]) {
-                                Some(mut sound) => {
-                                    self.player.play_sound(&mut sound,
-                                                           res.channel,
-                                                           res.note,
-                                                           res.panning,
-                                                           res.rate);
-                                }
-                                None => {}
-                            }
+                            let name = cartridge.sound_tracks_name[res.id as usize].clone();
+                            self.play_sound(cartridge,
+                                            name,
+                                            res.channel,
+                                            res.note,
+                                            res.panning,
+                                            res.rate,
+                                            res.priority);
                         }
                     }
                     packet::Packet::ChiptuneMusicState(res) => {
@@ -256,19 +500,38 @@ pub mod sound {
                         }
                     }
 
-                    packet::Packet::ChiptuneVolume(res) => {
-                        self.player.set_volume(res.volume);
+                    packet::Packet::ChiptuneBusVolume(res) => {
+                        self.mixer.set_bus_volume(res.bus, res.volume);
+                    }
+                    packet::Packet::ChiptuneChannelVolume(res) => {
+                        self.mixer.set_channel_volume(res.chan, res.volume);
+                    }
+                    packet::Packet::ChiptuneChannelPanning(res) => {
+                        self.mixer.set_channel_panning(res.chan, res.panning);
+                    }
+                    packet::Packet::ChiptuneChannelReserve(res) => {
+                        self.mixer.set_reserved(res.chan, res.reserved);
+                    }
+                    packet::Packet::ChiptuneDucking(res) => {
+                        self.mixer.set_ducking(res.volume, res.priority);
                     }
                 }
             }
 
-            sound.lock().unwrap().chiptune_position = self.player.get_music_position();
+            self.mixer.update(&mut self.player);
+
+            let mut sound = sound.lock().unwrap();
+            sound.chiptune_position = self.player.get_music_position();
+            for chan in 0..CHANNELS {
+                sound.channels_playing[chan] = self.player.is_channel_playing(chan as i32);
+            }
         }
     }
 
     pub struct Sound {
         csend: mpsc::Sender<Vec<u8>>,
         chiptune_position: i32,
+        channels_playing: [bool; CHANNELS],
     }
 
     impl Sound {
@@ -276,6 +539,7 @@ pub mod sound {
             Sound {
                 csend: csend,
                 chiptune_position: 0,
+                channels_playing: [false; CHANNELS],
             }
         }
 
@@ -305,7 +569,19 @@ pub mod sound {
                    panning: i32,
                    rate: i32,
                    loops: i32) {
-            debug!("[SOUND] Chiptune SFX Play {:?}", id);
+            self.sfx_priority(id, filename, channel, note, panning, rate, loops, 0);
+        }
+
+        pub fn sfx_priority(&mut self,
+                            id: i32,
+                            filename: String,
+                            channel: i32,
+                            note: u16,
+                            panning: i32,
+                            rate: i32,
+                            loops: i32,
+                            priority: i32) {
+            debug!("[SOUND] Chiptune SFX Play {:?} priority {:?}", id, priority);
             let p = packet::ChiptuneSFX {
                 id: id,
                 filename: filename,
@@ -314,6 +590,7 @@ pub mod sound {
                 note: note,
                 panning: panning,
                 rate: rate,
+                priority: priority,
             };
             self.csend.send(packet::write_packet(p).unwrap()).unwrap();
         }
@@ -372,13 +649,117 @@ pub mod sound {
         }
 
         pub fn music_volume(&mut self, volume: i32) {
-            debug!("[SOUND] Chiptune volume");
-            let p = packet::ChiptuneVolume { volume: volume };
+            self.bus_volume(BUS_MUSIC, volume);
+        }
+
+        pub fn sfx_volume(&mut self, volume: i32) {
+            self.bus_volume(BUS_SFX, volume);
+        }
+
+        pub fn master_volume(&mut self, volume: i32) {
+            self.bus_volume(BUS_MASTER, volume);
+        }
+
+        pub fn bus_volume(&mut self, bus: i32, volume: i32) {
+            debug!("[SOUND] Mixer bus {:?} volume {:?}", bus, volume);
+            let p = packet::ChiptuneBusVolume {
+                bus: bus,
+                volume: volume,
+            };
+            self.csend.send(packet::write_packet(p).unwrap()).unwrap();
+        }
+
+        pub fn channel_volume(&mut self, chan: i32, volume: i32) {
+            debug!("[SOUND] Mixer channel {:?} volume {:?}", chan, volume);
+            let p = packet::ChiptuneChannelVolume {
+                chan: chan,
+                volume: volume,
+            };
+            self.csend.send(packet::write_packet(p).unwrap()).unwrap();
+        }
+
+        pub fn channel_panning(&mut self, chan: i32, panning: i32) {
+            debug!("[SOUND] Mixer channel {:?} panning {:?}", chan, panning);
+            let p = packet::ChiptuneChannelPanning {
+                chan: chan,
+                panning: panning,
+            };
+            self.csend.send(packet::write_packet(p).unwrap()).unwrap();
+        }
+
+        pub fn channel_reserve(&mut self, chan: i32, reserved: bool) {
+            debug!("[SOUND] Mixer channel {:?} reserved {:?}", chan, reserved);
+            let p = packet::ChiptuneChannelReserve {
+                chan: chan,
+                reserved: reserved,
+            };
+            self.csend.send(packet::write_packet(p).unwrap()).unwrap();
+        }
+
+        pub fn music_ducking(&mut self, volume: i32, priority: i32) {
+            debug!("[SOUND] Mixer ducking {:?} from priority {:?}", volume, priority);
+            let p = packet::ChiptuneDucking {
+                volume: volume,
+                priority: priority,
+            };
             self.csend.send(packet::write_packet(p).unwrap()).unwrap();
         }
 
         pub fn chiptune_get_position(&mut self) -> i32 {
             self.chiptune_position
         }
+
+        pub fn is_channel_playing(&self, chan: i32) -> bool {
+            chan >= 0 && (chan as usize) < CHANNELS && self.channels_playing[chan as usize]
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn pick_free_then_lowest_priority() {
+            let mut mixer = Mixer::new();
+            mixer.set_reserved(SFX_CHANNELS_START as i32, true);
+
+            // An explicit channel, then the first free channel not reserved
+            assert_eq!(mixer.select_channel(3, 0, |_| true), 3);
+            assert_eq!(mixer.select_channel(-1, 0, |chan| chan < 20), 20);
+
+            // Every channel is busy: the lowest priority below the new one is stolen
+            for chan in SFX_CHANNELS_START..CHANNELS {
+                mixer.started(chan as i32, 5);
+            }
+            mixer.started(SFX_CHANNELS_START as i32, 0);
+            mixer.started(24, 2);
+            mixer.started(25, 1);
+            assert_eq!(mixer.select_channel(-1, 3, |_| true), 25);
+
+            // Nothing is less important, a reserved channel is never stolen
+            assert_eq!(mixer.select_channel(-1, 1, |_| true), -1);
+        }
+
+        #[test]
+        fn duck_the_music() {
+            let mut mixer = Mixer::new();
+            mixer.set_bus_volume(BUS_MUSIC, 64);
+            mixer.set_ducking(32, 2);
+
+            let sfx = SFX_CHANNELS_START;
+            mixer.started(sfx as i32, 1);
+            mixer.update_ducking(|chan| chan == sfx);
+            assert_eq!(mixer.effective_volume(0), 64);
+
+            mixer.started(sfx as i32, 2);
+            mixer.update_ducking(|chan| chan == sfx);
+            assert_eq!(mixer.effective_volume(0), 16);
+            assert_eq!(mixer.effective_volume(sfx), MAX_VOLUME);
+
+            // The stopped SFX loses its priority, and the music comes back
+            mixer.update_ducking(|_| false);
+            assert_eq!(mixer.priority[sfx], 0);
+            assert_eq!(mixer.effective_volume(0), 64);
+        }
     }
-}
\ No newline at end of file
+}
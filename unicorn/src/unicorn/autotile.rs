@@ -0,0 +1,259 @@
+use std::collections::HashMap;
+use std::fmt;
+
+/// Neighbors of a cell, as bits of the masks
+const NORTH: u8 = 1;
+const NORTH_EAST: u8 = 2;
+const EAST: u8 = 4;
+const SOUTH_EAST: u8 = 8;
+const SOUTH: u8 = 16;
+const SOUTH_WEST: u8 = 32;
+const WEST: u8 = 64;
+const NORTH_WEST: u8 = 128;
+
+/// Offset of each neighbor, in the order of the bits
+static NEIGHBORS: [(i32, i32); 8] = [(0, -1), (1, -1), (1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1)];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum AutotileStyle {
+    /// 47 sprites, the corners are used when both of their sides are the
+    /// same terrain
+    Blob47,
+    /// 16 sprites, only the sides are used
+    Wang16,
+}
+
+impl AutotileStyle {
+    pub fn parse(name: &str) -> Option<AutotileStyle> {
+        match name {
+            "blob47" => Some(AutotileStyle::Blob47),
+            "wang16" => Some(AutotileStyle::Wang16),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            AutotileStyle::Blob47 => "blob47",
+            AutotileStyle::Wang16 => "wang16",
+        }
+    }
+
+    /// Number of sprites of a rule
+    pub fn len(&self) -> usize {
+        match *self {
+            AutotileStyle::Blob47 => 47,
+            AutotileStyle::Wang16 => 16,
+        }
+    }
+
+    /// Index of the sprite for the neighbors of the same terrain
+    pub fn index(&self, neighbors: u8) -> usize {
+        match *self {
+            AutotileStyle::Blob47 => {
+                // The 47 masks are sorted, this one is after the smaller ones
+                let mask = blob_mask(neighbors);
+                (0..mask).filter(|m| blob_mask(*m) == *m).count()
+            }
+            AutotileStyle::Wang16 => {
+                // North 1, east 2, south 4, west 8
+                ((neighbors & NORTH != 0) as usize) | ((neighbors & EAST != 0) as usize) << 1 |
+                ((neighbors & SOUTH != 0) as usize) << 2 | ((neighbors & WEST != 0) as usize) << 3
+            }
+        }
+    }
+}
+
+/// The corners without both of their sides are not used by the blob style
+fn blob_mask(neighbors: u8) -> u8 {
+    let mut mask = neighbors;
+    for &(corner, side1, side2) in &[(NORTH_EAST, NORTH, EAST),
+                                     (SOUTH_EAST, SOUTH, EAST),
+                                     (SOUTH_WEST, SOUTH, WEST),
+                                     (NORTH_WEST, NORTH, WEST)] {
+        if neighbors & side1 == 0 || neighbors & side2 == 0 {
+            mask &= !corner;
+        }
+    }
+    mask
+}
+
+/// Sprites of a terrain, chosen with its neighbors
+#[derive(Clone, Debug, PartialEq)]
+pub struct AutotileRule {
+    pub terrain: u32,
+    pub style: AutotileStyle,
+    /// Sprite of each index of the style
+    pub sprites: Vec<u32>,
+}
+
+impl AutotileRule {
+    pub fn new(terrain: u32, style: AutotileStyle, sprites: Vec<u32>) -> AutotileRule {
+        AutotileRule {
+            terrain: terrain,
+            style: style,
+            sprites: sprites,
+        }
+    }
+
+    /// A rule by line of the cartridge: `terrain style sprite...`
+    pub fn parse(line: &str) -> Option<AutotileRule> {
+        let mut words = line.split_whitespace();
+
+        let terrain = match words.next().and_then(|word| word.parse::<u32>().ok()) {
+            Some(terrain) => terrain,
+            None => return None,
+        };
+
+        let style = match words.next().and_then(AutotileStyle::parse) {
+            Some(style) => style,
+            None => return None,
+        };
+
+        let sprites: Vec<u32> = words.filter_map(|word| word.parse::<u32>().ok()).collect();
+        if sprites.len() != style.len() {
+            return None;
+        }
+
+        Some(AutotileRule::new(terrain, style, sprites))
+    }
+
+    pub fn sprite(&self, neighbors: u8) -> u32 {
+        self.sprites[self.style.index(neighbors)]
+    }
+
+    /// Sprite surrounded by the same terrain
+    pub fn full(&self) -> u32 {
+        self.sprite(0xff)
+    }
+}
+
+impl fmt::Display for AutotileRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} {}", self.terrain, self.style.name()));
+        for sprite in &self.sprites {
+            try!(write!(f, " {}", sprite));
+        }
+        Ok(())
+    }
+}
+
+/// Rule with the sprites of the sprite sheet selected from `sprite`, by lines
+/// of `sprites_per_line`: 4x4 sprites for the wang style, 8x6 for the blob
+/// style (the last one is not used). The sprites are in the order of the
+/// masks of the neighbors.
+pub fn rule_from_sheet(terrain: u32,
+                       sprite: u32,
+                       size: [u32; 2],
+                       sprites_per_line: u32)
+                       -> Option<AutotileRule> {
+    let style = match (size[0], size[1]) {
+        (4, 4) => AutotileStyle::Wang16,
+        (8, 6) => AutotileStyle::Blob47,
+        _ => return None,
+    };
+
+    let sprites = (0..style.len() as u32)
+        .map(|idx| sprite + idx % size[0] + (idx / size[0]) * sprites_per_line)
+        .collect();
+
+    Some(AutotileRule::new(terrain, style, sprites))
+}
+
+/// Choose again the sprites of the terrains in the region of the map, and
+/// around it. The cells out of the map are the same terrain as their
+/// neighbors.
+pub fn resolve(map: &mut [u32],
+               width: i32,
+               height: i32,
+               rules: &[AutotileRule],
+               rect: (i32, i32, i32, i32)) {
+    if rules.is_empty() {
+        return;
+    }
+
+    let mut terrains = HashMap::new();
+    for (idx, rule) in rules.iter().enumerate() {
+        for sprite in &rule.sprites {
+            terrains.entry(*sprite).or_insert(idx);
+        }
+    }
+
+    let terrain = |map: &[u32], x: i32, y: i32| -> Option<usize> {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return None;
+        }
+        terrains.get(&map[(x + y * width) as usize]).cloned()
+    };
+
+    for y in rect.1 - 1..rect.1 + rect.3 + 1 {
+        for x in rect.0 - 1..rect.0 + rect.2 + 1 {
+            if x < 0 || y < 0 || x >= width || y >= height {
+                continue;
+            }
+
+            let rule = match terrain(map, x, y) {
+                Some(rule) => rule,
+                None => continue,
+            };
+
+            let mut neighbors = 0;
+            for (bit, &(dx, dy)) in NEIGHBORS.iter().enumerate() {
+                let (nx, ny) = (x + dx, y + dy);
+                let outside = nx < 0 || ny < 0 || nx >= width || ny >= height;
+                if outside || terrain(map, nx, ny) == Some(rule) {
+                    neighbors |= 1 << bit;
+                }
+            }
+
+            map[(x + y * width) as usize] = rules[rule].sprite(neighbors);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn neighbors_index() {
+        let masks = (0..256).filter(|m| blob_mask(*m as u8) == *m as u8).count();
+        assert_eq!(masks, 47);
+
+        assert_eq!(AutotileStyle::Wang16.index(0), 0);
+        assert_eq!(AutotileStyle::Wang16.index(NORTH | NORTH_EAST | SOUTH), 5);
+        assert_eq!(AutotileStyle::Wang16.index(0xff), 15);
+
+        // A corner alone is the same as nothing
+        assert_eq!(AutotileStyle::Blob47.index(NORTH_EAST), 0);
+        assert_eq!(AutotileStyle::Blob47.index(NORTH | EAST), AutotileStyle::Blob47.index(NORTH | EAST | SOUTH_WEST));
+        assert!(AutotileStyle::Blob47.index(NORTH | EAST | NORTH_EAST) != AutotileStyle::Blob47.index(NORTH | EAST));
+        assert_eq!(AutotileStyle::Blob47.index(0xff), 46);
+    }
+
+    #[test]
+    fn parse_rules() {
+        let rule = rule_from_sheet(2, 10, [4, 4], 50).unwrap();
+        assert_eq!(rule.sprites[..6], [10, 11, 12, 13, 60, 61]);
+        assert_eq!(AutotileRule::parse(&rule.to_string()), Some(rule));
+
+        assert_eq!(rule_from_sheet(2, 10, [8, 6], 50).unwrap().sprites.len(), 47);
+        assert_eq!(rule_from_sheet(2, 10, [2, 2], 50), None);
+        assert_eq!(AutotileRule::parse("1 wang16 1 2 3"), None);
+        assert_eq!(AutotileRule::parse("1 hexa"), None);
+    }
+
+    #[test]
+    fn resolve_region() {
+        let rule = AutotileRule::new(1, AutotileStyle::Wang16, (100..116).collect());
+        let mut map = vec![0, 0, 0, 0,
+                           0, 100, 100, 0,
+                           0, 0, 100, 0];
+        resolve(&mut map, 4, 3, &[rule], (1, 1, 1, 1));
+
+        // North 1, east 2, south 4, west 8, the bottom is out of the map
+        assert_eq!(map, vec![0, 0, 0, 0,
+                             0, 102, 112, 0,
+                             0, 0, 105, 0]);
+    }
+}
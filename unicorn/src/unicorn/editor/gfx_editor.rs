@@ -3,14 +3,20 @@ use config::Players;
 use std::sync::{Arc, Mutex};
 use std::cmp::{max, min};
 use std::collections::HashMap;
+use std::path::Path;
 
-use unicorn::UnicornConfig;
+use unicorn::{UnicornConfig, RGB};
 
 use unicorn::editor::Widget;
 use unicorn::editor::State;
 use unicorn::editor::point_in_rect;
 use unicorn::editor::Button;
+use unicorn::editor::history::Snapshot;
 use unicorn;
+use unicorn::autotile;
+use unicorn::sheet;
+use unicorn::tiled::TiledMap;
+use config::scancode::Scancode;
 
 pub struct PalettePicker {
     state: Arc<Mutex<State>>,
@@ -33,51 +39,10 @@ impl PalettePicker {
         }
     }
 
-    pub fn update(&mut self, screen: &mut Screen) {
+    pub fn update(&mut self, _screen: &mut Screen) {
         if self.state.lock().unwrap().mouse_statep == 1 {
             let mouse_x = self.state.lock().unwrap().mouse_x;
             let mouse_y = self.state.lock().unwrap().mouse_y;
-            let idx_x_zoom_sprite = self.state.lock().unwrap().idx_x_zoom_sprite;
-            let idx_y_zoom_sprite = self.state.lock().unwrap().idx_y_zoom_sprite;
-            let zoom_sprite = self.state.lock().unwrap().zoom_sprite;
-
-            if point_in_rect(mouse_x,
-                             mouse_y,
-                             idx_x_zoom_sprite as i32,
-                             idx_y_zoom_sprite as i32,
-                             (idx_x_zoom_sprite + 128) as i32,
-                             (idx_y_zoom_sprite + 128) as i32) {
-                let idx_x = ((mouse_x - idx_x_zoom_sprite as i32) as f64 *
-                             zoom_sprite as f64 / 16.)
-                        .floor() as u32;
-                let idx_y = ((mouse_y - idx_y_zoom_sprite as i32) as f64 *
-                             zoom_sprite as f64 / 16.)
-                        .floor() as u32;
-
-                let x_zoom_sprite = self.state.lock().unwrap().x_zoom_sprite;
-                let y_zoom_sprite = self.state.lock().unwrap().y_zoom_sprite;
-
-                let fill_action = self.state.lock().unwrap().fill_action;
-
-                if fill_action {
-                    let color_to_replace = screen.sget(x_zoom_sprite + idx_x,  y_zoom_sprite + idx_y);
-
-                    for x in 0..8 * zoom_sprite {
-                        for y in 0..8 * zoom_sprite {
-                            let current_color = screen.sget(x_zoom_sprite + x,  y_zoom_sprite + y);
-                            if color_to_replace == current_color {
-                                screen.sset(x_zoom_sprite + x,
-                                            y_zoom_sprite + y,
-                                            self.current_color as i32);
-                            }
-                        }
-                    }
-                } else {
-                    screen.sset(x_zoom_sprite + idx_x as u32,
-                                y_zoom_sprite + idx_y as u32,
-                                self.current_color as i32);
-                }
-            }
 
             if point_in_rect(mouse_x,
                              mouse_y,
@@ -95,6 +60,13 @@ impl PalettePicker {
         }
     }
 
+    /// Select the color `col`, from the color picker tool
+    pub fn set_color(&mut self, col: u32) {
+        self.current_color = col;
+        self.current_selection_x = (col % 4) as i32;
+        self.current_selection_y = (col / 4) as i32;
+    }
+
     pub fn draw(&mut self, screen: &mut Screen) {
         let mut idx = 0;
         let x = self.idx_x;
@@ -205,131 +177,431 @@ pub enum EditorState {
     MapEditor,
 }
 
+/// Tools of the map editor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MapTool {
+    Brush,
+    Fill,
+    RectFill,
+    Picker,
+    /// Paint the terrain of the current sprite, with the autotile rules
+    Terrain,
+}
+
+/// Tools of the map editor with their widget name, position and hotkey
+static MAP_TOOLS: [(MapTool, &'static str, u32, Scancode); 5] = [
+    (MapTool::Brush, "BRUSH", 210, Scancode::B),
+    (MapTool::Fill, "FILL", 220, Scancode::F),
+    (MapTool::RectFill, "RECT FILL", 230, Scancode::R),
+    (MapTool::Picker, "PICKER", 240, Scancode::I),
+    (MapTool::Terrain, "TERRAIN", 250, Scancode::T),
+];
+
+static TERRAIN_ICON: [u8; 64] = [
+    5, 5, 5, 5, 5, 5, 5, 5,
+    5, 5, 5, 5, 5, 5, 5, 5,
+    5, 5, 5, 6, 6, 5, 5, 5,
+    5, 5, 6, 6, 6, 6, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 5,
+    5, 6, 6, 6, 6, 6, 6, 5,
+    5, 6, 6, 6, 6, 6, 6, 5,
+    5, 5, 5, 5, 5, 5, 5, 5,
+];
+
+fn map_tool_icon(tool: MapTool) -> Vec<u8> {
+    match tool {
+        MapTool::Brush => TOOL_ICONS[0].to_vec(),
+        MapTool::Fill => TOOL_ICONS[1].to_vec(),
+        MapTool::RectFill => TOOL_ICONS[4].to_vec(),
+        MapTool::Picker => TOOL_ICONS[8].to_vec(),
+        MapTool::Terrain => TERRAIN_ICON.to_vec(),
+    }
+}
+
+static GRID_ICON: [u8; 64] = [
+    5, 5, 5, 5, 5, 5, 5, 5,
+    5, 6, 6, 6, 6, 6, 6, 6,
+    5, 6, 5, 5, 6, 5, 5, 6,
+    5, 6, 5, 5, 6, 5, 5, 6,
+    5, 6, 6, 6, 6, 6, 6, 6,
+    5, 6, 5, 5, 6, 5, 5, 6,
+    5, 6, 5, 5, 6, 5, 5, 6,
+    5, 6, 6, 6, 6, 6, 6, 6,
+];
+
+/// Number of sprites on a line of the sprite sheet
+const SPRITES_PER_LINE: u32 = 50;
+
+/// Sprite of a stamp of `size` sprites of the sheet, starting at `sprite`,
+/// for the cell at (`dx`, `dy`) from where it is put. The stamp is repeated
+/// over larger areas.
+pub fn stamp_sprite(sprite: u32, size: [u32; 2], dx: i32, dy: i32) -> u32 {
+    let (w, h) = (max(1, size[0]) as i32, max(1, size[1]) as i32);
+    let x = (dx % w + w) % w;
+    let y = (dy % h + h) % h;
+    sprite + x as u32 + y as u32 * SPRITES_PER_LINE
+}
+
 pub struct MapEditor {
     state: Arc<Mutex<State>>,
+    /// Area of the map on the screen
     coord: [i32; 4],
+    /// First visible cell of the map
     offset_x: i32,
     offset_y: i32,
-    available_zooms: [f32; 3],
+    available_zooms: [f32; 4],
     idx_zoom: u32,
     zoom: f32,
-    cache: [u32; unicorn::MAP_WIDTH * unicorn::MAP_HEIGHT],
-    select_field: [i32; 2],
     size_sprite: i32,
+    /// Cell under the mouse
     current_sprite: [u32; 2],
-    sprites_per_x: f32,
-    sprites_per_y: f32,
+    tools: Vec<(MapTool, Arc<Mutex<Widget>>)>,
+    tool: MapTool,
+    grid: Arc<Mutex<Widget>>,
+    /// Cells where the mouse was pressed, and where it is
+    drag: Option<((i32, i32), (i32, i32))>,
+    /// Area of the minimap on the screen, and cells by pixel
+    minimap: [i32; 4],
+    minimap_scale: i32,
 }
 
 impl MapEditor {
     pub fn new(state: Arc<Mutex<State>>) -> MapEditor {
+        let mut highlight = HashMap::new();
+        highlight.insert(6, 10);
+
+        let tools = MAP_TOOLS.iter()
+            .map(|&(tool, name, x, _)| {
+                let widget = Widget::new(state.clone(),
+                                         name.to_string(),
+                                         x, 40, 8, 8,
+                                         map_tool_icon(tool),
+                                         highlight.clone(),
+                                         tool == MapTool::Brush, true);
+                (tool, Arc::new(Mutex::new(widget)))
+            })
+            .collect();
+
+        let grid = Widget::new(state.clone(),
+                               "GRID".to_string(),
+                               270, 40, 8, 8,
+                               GRID_ICON.to_vec(),
+                               highlight.clone(),
+                               false, true);
+
+        // The whole map in at most 200 pixels of width
+        let minimap_scale = max(1, (unicorn::MAP_WIDTH as i32 + 199) / 200);
+        let minimap_w = unicorn::MAP_WIDTH as i32 / minimap_scale;
+        let minimap_h = unicorn::MAP_HEIGHT as i32 / minimap_scale;
+        let minimap_y = max(60, 160 - minimap_h);
+
         MapEditor {
             state: state.clone(),
-            coord: [0, 8, 200, 182],
+            coord: [0, 16, 200, 152],
             offset_x: 0,
             offset_y: 0,
-            available_zooms: [1., 0.5, 0.25],
+            available_zooms: [1., 0.5, 0.25, 2.],
             idx_zoom: 0,
             zoom: 1.,
-            cache: [0; unicorn::MAP_WIDTH * unicorn::MAP_HEIGHT],
-            select_field: [0, 8],
             size_sprite: 8,
             current_sprite: [0, 0],
-            sprites_per_x: 25.,
-            sprites_per_y: 22.,
+            tools: tools,
+            tool: MapTool::Brush,
+            grid: Arc::new(Mutex::new(grid)),
+            drag: None,
+            minimap: [200, minimap_y, 200 + minimap_w, minimap_y + minimap_h],
+            minimap_scale: minimap_scale,
         }
     }
 
-    pub fn init(&mut self, screen: &mut Screen) {
+    pub fn init(&mut self, _screen: &mut Screen) {
         info!("[EDITOR][GFX][MAP] Init");
+        self.drag = None;
+    }
 
-        for y in 0..unicorn::MAP_HEIGHT {
-            for x in 0..unicorn::MAP_WIDTH {
-                self.cache[x + y * unicorn::MAP_WIDTH] = screen.mget(x as i32, y as i32);
-            }
+    pub fn set_tool(&mut self, tool: MapTool) {
+        info!("[EDITOR][GFX][MAP] Tool {:?}", tool);
+
+        self.tool = tool;
+        self.drag = None;
+        for &(widget_tool, ref widget) in &self.tools {
+            widget.lock().unwrap().set_click(widget_tool == tool);
         }
     }
 
-    pub fn update(&mut self, players: Arc<Mutex<Players>>, screen: &mut Screen) {
-        if players.lock().unwrap().btnp(0, 0) {
-            self.offset_x -= 8;
-            self.offset_x = max(0, self.offset_x);
+    fn set_zoom(&mut self, idx_zoom: u32) {
+        self.idx_zoom = idx_zoom;
+        self.zoom = self.available_zooms[idx_zoom as usize];
+        self.size_sprite = (8. * self.zoom).floor() as i32;
+        self.scroll(0, 0);
+    }
+
+    /// Number of cells visible in the map area
+    fn visible(&self) -> (i32, i32) {
+        ((self.coord[2] - self.coord[0]) / self.size_sprite,
+         (self.coord[3] - self.coord[1]) / self.size_sprite)
+    }
+
+    /// Move the view by some cells, it stays in the map
+    fn scroll(&mut self, dx: i32, dy: i32) {
+        let (visible_x, visible_y) = self.visible();
+        self.offset_x = max(0, min(unicorn::MAP_WIDTH as i32 - visible_x, self.offset_x + dx));
+        self.offset_y = max(0, min(unicorn::MAP_HEIGHT as i32 - visible_y, self.offset_y + dy));
+    }
+
+    /// Cell of the map under the mouse
+    fn mouse_cell(&self) -> Option<(i32, i32)> {
+        let (mouse_x, mouse_y) = {
+            let state = self.state.lock().unwrap();
+            (state.mouse_x, state.mouse_y)
+        };
+
+        if !point_in_rect(mouse_x, mouse_y, self.coord[0], self.coord[1], self.coord[2], self.coord[3]) {
+            return None;
         }
 
-        if players.lock().unwrap().btnp(0, 1) {
-            self.offset_x += 8;
-            self.offset_x = min(((unicorn::MAP_WIDTH as f32 / self.zoom - self.sprites_per_x * self.zoom) *
-                                 self.zoom)
-                                        .floor() as i32,
-                                self.offset_x);
+        let x = self.offset_x + (mouse_x - self.coord[0]) / self.size_sprite;
+        let y = self.offset_y + (mouse_y - self.coord[1]) / self.size_sprite;
+        if x < unicorn::MAP_WIDTH as i32 && y < unicorn::MAP_HEIGHT as i32 {
+            Some((x, y))
+        } else {
+            None
         }
+    }
 
-        if players.lock().unwrap().btnp(0, 2) {
-            self.offset_y -= 8;
-            self.offset_y = max(0, self.offset_y);
+    /// Sprite and size of the stamp selected in the sprite sheet
+    fn stamp(&self) -> (u32, [u32; 2]) {
+        let state = self.state.lock().unwrap();
+        (state.current_sprite, state.stamp)
+    }
+
+    /// Repeat the stamp over the rectangle of cells, from its corner
+    fn fill_rect(&self, screen: &mut Screen, rect: (i32, i32, i32, i32)) {
+        let (sprite, size) = self.stamp();
+        for y in 0..rect.3 {
+            for x in 0..rect.2 {
+                screen.mset(rect.0 + x, rect.1 + y, stamp_sprite(sprite, size, x, y));
+            }
         }
+    }
 
-        if players.lock().unwrap().btnp(0, 3) {
-            self.offset_y += 8;
-            self.offset_y = min(((unicorn::MAP_HEIGHT as f32 / self.zoom - self.sprites_per_y * self.zoom) *
-                                 self.zoom)
-                                        .floor() as i32,
-                                self.offset_y);
+    /// Repeat the stamp over the cells connected to (`x`, `y`) with the
+    /// same sprite
+    fn fill(&self, screen: &mut Screen, x: i32, y: i32) {
+        let (width, height) = (unicorn::MAP_WIDTH as i32, unicorn::MAP_HEIGHT as i32);
+
+        let mut cells = Vec::with_capacity((width * height) as usize);
+        for cell_y in 0..height {
+            for cell_x in 0..width {
+                cells.push(screen.mget(cell_x, cell_y));
+            }
         }
 
+        // No sprite has this index, it marks the cells to fill
+        let mark = u32::max_value();
+        flood_fill(&mut cells, width, height, x, y, mark);
 
-        if players.lock().unwrap().btnp(0, 4) {
-            self.idx_zoom = (self.idx_zoom + 1) % self.available_zooms.len() as u32;
-            self.zoom = self.available_zooms[self.idx_zoom as usize];
-            self.size_sprite = (8. * self.zoom).floor() as i32;
+        let (sprite, size) = self.stamp();
+        for (idx, cell) in cells.iter().enumerate() {
+            if *cell == mark {
+                let (cell_x, cell_y) = (idx as i32 % width, idx as i32 / width);
+                screen.mset(cell_x, cell_y, stamp_sprite(sprite, size, cell_x - x, cell_y - y));
+            }
         }
+    }
+
+    /// Put the terrain of the current sprite, or the sprite if it is not in
+    /// a rule, and choose the sprites of the terrains around
+    fn paint_terrain(&self, screen: &mut Screen, x: i32, y: i32) {
+        let (sprite, _) = self.stamp();
+        let current = screen.mget(x, y);
 
-        let mouse_x = self.state.lock().unwrap().mouse_x;
-        let mouse_y = self.state.lock().unwrap().mouse_y;
+        let (sprite, painted) = match screen.autotiles.iter().find(|rule| rule.sprites.contains(&sprite)) {
+            Some(rule) => (rule.full(), rule.sprites.contains(&current)),
+            None => (sprite, current == sprite),
+        };
 
-        if point_in_rect(mouse_x,
-                         mouse_y,
-                         self.coord[0],
-                         self.coord[1],
-                         self.coord[2],
-                         self.coord[3]) {
-            let mouse_statep = self.state.lock().unwrap().mouse_statep;
+        if !painted {
+            screen.mset(x, y, sprite);
+            screen.autotile(x, y, 1, 1);
+        }
+    }
 
-            let select_field_x = min(192, mouse_x - mouse_x % self.size_sprite);
-            let select_field_y = min(176, mouse_y - mouse_y % self.size_sprite);
+    /// New autotile rule with the sprites of the stamp, 4x4 for a wang rule
+    /// and 8x6 for a blob rule
+    fn new_rule(&self, screen: &mut Screen) {
+        let (sprite, size) = self.stamp();
+        let terrain = screen.autotiles.iter().map(|rule| rule.terrain + 1).max().unwrap_or(1);
 
-            let new_x = ((select_field_x + self.offset_x * self.size_sprite) as f64 /
-                         self.size_sprite as f64)
-                    .floor() as u32;
-            let new_y = ((select_field_y - self.coord[1] + self.offset_y * self.size_sprite) as
-                         f64 /
-                         self.size_sprite as f64)
-                    .floor() as u32;
-            if new_x < unicorn::MAP_WIDTH as u32 && new_y < unicorn::MAP_HEIGHT as u32 {
-                self.select_field[0] = select_field_x;
-                self.select_field[1] = select_field_y;
+        match autotile::rule_from_sheet(terrain, sprite, size, SPRITES_PER_LINE) {
+            Some(rule) => {
+                info!("[EDITOR][GFX][MAP] New autotile rule {}", rule);
+                screen.autotiles.push(rule);
+            }
+            None => {
+                warn!("[EDITOR][GFX][MAP] Select 4x4 (wang) or 8x6 (blob) sprites for a rule, not {:?}",
+                      size);
+            }
+        }
+    }
 
-                if mouse_statep == 1 {
-                    let zoom_sprite = self.state.lock().unwrap().zoom_sprite;
+    /// Take the sprite of a cell as the stamp
+    fn pick(&self, screen: &mut Screen, x: i32, y: i32) {
+        let sprite = screen.mget(x, y);
 
-                    for x in 0u32..zoom_sprite as u32 {
-                        for y in 0u32..zoom_sprite as u32 {
-                            let current_sprite = self.state.lock().unwrap().current_sprite + x +
-                                                 y * 16;
+        let mut state = self.state.lock().unwrap();
+        state.current_sprite = sprite;
+        state.stamp = [1, 1];
+        state.x_zoom_sprite = (sprite % SPRITES_PER_LINE) * 8;
+        state.y_zoom_sprite = (sprite / SPRITES_PER_LINE) * 8;
+    }
 
-                            let idx = ((new_x + x) as f64 + (new_y + y) as f64 * unicorn::MAP_WIDTH as f64).floor() as
-                                      usize;
-                            self.cache[idx] = current_sprite;
-                            screen.mset((new_x + x) as i32, (new_y + y) as i32, current_sprite);
+    /// Center the view on the cell under the mouse in the minimap
+    fn update_minimap(&mut self) {
+        let (mouse_x, mouse_y, mouse_statep) = {
+            let state = self.state.lock().unwrap();
+            (state.mouse_x, state.mouse_y, state.mouse_statep)
+        };
+
+        if mouse_statep == 1 &&
+           point_in_rect(mouse_x, mouse_y, self.minimap[0], self.minimap[1], self.minimap[2], self.minimap[3]) {
+            let (visible_x, visible_y) = self.visible();
+            let x = (mouse_x - self.minimap[0]) * self.minimap_scale;
+            let y = (mouse_y - self.minimap[1]) * self.minimap_scale;
+
+            self.offset_x = x - visible_x / 2;
+            self.offset_y = y - visible_y / 2;
+            self.scroll(0, 0);
+        }
+    }
+
+    /// Apply the current tool with the mouse
+    fn update_tool(&mut self, screen: &mut Screen) {
+        let (mouse_state, mouse_statep) = {
+            let state = self.state.lock().unwrap();
+            (state.mouse_state, state.mouse_statep)
+        };
+
+        let cell = self.mouse_cell();
+        if let Some((x, y)) = cell {
+            self.current_sprite = [x as u32, y as u32];
+        }
+
+        if mouse_statep != 1 {
+            if let Some((start, end)) = self.drag.take() {
+                info!("[EDITOR][GFX][MAP] Fill {:?} {:?}", start, end);
+                self.fill_rect(screen, rect_between(start, end));
+            }
+            return;
+        }
+
+        match self.tool {
+            MapTool::Brush => {
+                if let Some((x, y)) = cell {
+                    let (_, size) = self.stamp();
+                    self.fill_rect(screen, (x, y, size[0] as i32, size[1] as i32));
+                }
+            }
+            MapTool::Fill => {
+                if let Some((x, y)) = cell {
+                    if mouse_state == 1 {
+                        self.fill(screen, x, y);
+                    }
+                }
+            }
+            MapTool::Picker => {
+                if let Some((x, y)) = cell {
+                    self.pick(screen, x, y);
+                }
+            }
+            MapTool::Terrain => {
+                if let Some((x, y)) = cell {
+                    self.paint_terrain(screen, x, y);
+                }
+            }
+            MapTool::RectFill => {
+                match self.drag {
+                    Some((start, _)) => {
+                        if let Some(end) = cell {
+                            self.drag = Some((start, end));
                         }
                     }
+                    None => self.drag = cell.map(|start| (start, start)),
                 }
-                self.current_sprite[0] = new_x;
-                self.current_sprite[1] = new_y;
             }
         }
     }
 
+    pub fn update(&mut self, players: Arc<Mutex<Players>>, screen: &mut Screen) {
+        let ctrl = self.state.lock().unwrap().ctrl;
+
+        for &(button, dx, dy) in &[(0, -8, 0), (1, 8, 0), (2, 0, -8), (3, 0, 8)] {
+            if players.lock().unwrap().btnp(0, button) {
+                self.scroll(dx, dy);
+            }
+        }
+
+        if !ctrl && players.lock().unwrap().btnp(0, 4) {
+            let idx_zoom = (self.idx_zoom + 1) % self.available_zooms.len() as u32;
+            self.set_zoom(idx_zoom);
+        }
+
+        // Zoom in and out, the zooms are not sorted
+        let zoom = self.zoom;
+        let zoom_in = players.lock().unwrap().btnp3(Scancode::Equals);
+        let zoom_out = players.lock().unwrap().btnp3(Scancode::Minus);
+        if zoom_in || zoom_out {
+            let next = (0..self.available_zooms.len())
+                .filter(|idx| if zoom_in {
+                    self.available_zooms[*idx] > zoom
+                } else {
+                    self.available_zooms[*idx] < zoom
+                })
+                .min_by_key(|idx| ((self.available_zooms[*idx] - zoom).abs() * 100.) as i32);
+            if let Some(idx_zoom) = next {
+                self.set_zoom(idx_zoom as u32);
+            }
+        }
+
+        if !ctrl {
+            let mut hotkey = None;
+            for tool in MAP_TOOLS.iter() {
+                if players.lock().unwrap().btnp3(tool.3) {
+                    hotkey = Some(tool.0);
+                }
+            }
+            if let Some(tool) = hotkey {
+                self.set_tool(tool);
+            }
+
+            if players.lock().unwrap().btnp3(Scancode::G) {
+                let mut grid = self.grid.lock().unwrap();
+                let clicked = grid.is_click();
+                grid.set_click(!clicked);
+            }
+
+            if players.lock().unwrap().btnp3(Scancode::N) {
+                self.new_rule(screen);
+            }
+        }
+
+        let clicked = self.tools
+            .iter()
+            .find(|&&(_, ref widget)| widget.lock().unwrap().is_clickable())
+            .map(|&(tool, _)| tool);
+        if let Some(tool) = clicked {
+            self.set_tool(tool);
+        }
+
+        // The grid widget is a toggle
+        if self.grid.lock().unwrap().is_clickable() {
+            self.grid.lock().unwrap().update();
+        }
+
+        self.update_minimap();
+        self.update_tool(screen);
+    }
+
     pub fn draw(&mut self, screen: &mut Screen) {
         // clean screen
         screen.rectfill(self.coord[0],
@@ -340,70 +612,290 @@ impl MapEditor {
         screen.rectfill(self.coord[2], self.coord[1], 240, self.coord[3], 5);
 
         // draw map
-        let mut idx_y = 0;
-        for y in self.offset_y as u32..
-                 min(unicorn::MAP_HEIGHT as u32,
-                     self.offset_y as u32 + (self.sprites_per_y / self.zoom).floor() as u32) {
-            let mut idx_x = 0;
-
-            for x in self.offset_x as u32..
-                     min(unicorn::MAP_WIDTH as u32,
-                         self.offset_x as u32 + (self.sprites_per_x / self.zoom).floor() as u32) {
-                let offset = x + y * unicorn::MAP_WIDTH as u32;
-
-                let sprite_number = self.cache[offset as usize];
-                if sprite_number != 0 {
-                    let sprite_x = (sprite_number % 16) * 8;
-                    let sprite_y = (sprite_number as f32 / 16.).floor() as i32 * 8;
-
-                    let dx = idx_x * ((8. * self.zoom).floor() as i32);
-                    let dy = idx_y * ((8. * self.zoom).floor() as i32) + 9;
-                    screen.sspr(sprite_x as u32,
-                                sprite_y as u32,
+        let size = self.size_sprite;
+        let (visible_x, visible_y) = self.visible();
+        for y in 0..min(visible_y, unicorn::MAP_HEIGHT as i32 - self.offset_y) {
+            for x in 0..min(visible_x, unicorn::MAP_WIDTH as i32 - self.offset_x) {
+                let sprite_number = screen.mget(self.offset_x + x, self.offset_y + y);
+                if sprite_number != 0 && (sprite_number as usize) < screen.sprites.len() {
+                    let sprite_x = (sprite_number % SPRITES_PER_LINE) * 8;
+                    let sprite_y = (sprite_number / SPRITES_PER_LINE) * 8;
+
+                    screen.sspr(sprite_x,
+                                sprite_y,
                                 8,
                                 8,
-                                dx,
-                                dy,
-                                (self.zoom * 8.).floor() as u32,
-                                (self.zoom * 8.).floor() as u32,
+                                self.coord[0] + x * size,
+                                self.coord[1] + y * size,
+                                size as u32,
+                                size as u32,
                                 false,
                                 false);
                 }
-
-                idx_x += 1
             }
+        }
 
-            idx_y += 1
+        let grid = self.grid.lock().unwrap().is_click();
+        if grid && size >= 4 {
+            let (x2, y2) = (self.coord[0] + visible_x * size, self.coord[1] + visible_y * size);
+            for x in 0..visible_x + 1 {
+                let x = self.coord[0] + x * size;
+                screen.line(x, self.coord[1], x, y2, 1);
+            }
+            for y in 0..visible_y + 1 {
+                let y = self.coord[1] + y * size;
+                screen.line(self.coord[0], y, x2, y, 1);
+            }
         }
 
+        // draw the area of the tool, or the stamp under the mouse
+        let (_, stamp) = self.stamp();
+        let area = match self.drag {
+            Some((start, end)) => Some(rect_between(start, end)),
+            None => self.mouse_cell().map(|(x, y)| {
+                if self.tool == MapTool::Brush {
+                    (x, y, stamp[0] as i32, stamp[1] as i32)
+                } else {
+                    (x, y, 1, 1)
+                }
+            }),
+        };
+        if let Some((x, y, w, h)) = area {
+            let x1 = self.coord[0] + (x - self.offset_x) * size;
+            let y1 = self.coord[1] + (y - self.offset_y) * size;
+            screen.rect(x1, y1, x1 + w * size, y1 + h * size, 7);
+        }
 
-        // draw selected sprites
-        let zoom_sprite = self.state.lock().unwrap().zoom_sprite;
+        self.draw_minimap(screen);
 
-        screen.rect(self.select_field[0],
-                    self.select_field[1],
-                    self.select_field[0] + (8. * self.zoom * zoom_sprite as f32).floor() as i32,
-                    self.select_field[1] + (8. * self.zoom * zoom_sprite as f32).floor() as i32,
-                    7);
+        for &(_, ref widget) in &self.tools {
+            widget.lock().unwrap().draw(screen);
+        }
+        self.grid.lock().unwrap().draw(screen);
 
         // Draw info
-        screen.print(format!("{:?} {:?}: {:?}",
-                             self.current_sprite[0],
-                             self.current_sprite[1],
-                             self.cache[(self.current_sprite[0] + self.current_sprite[1] * 128) as
-                             usize]),
+        let (x, y) = (self.current_sprite[0] as i32, self.current_sprite[1] as i32);
+        let sprite = screen.mget(x, y);
+        screen.print(format!("{:?} {:?}: {:?} x{}", x, y, sprite, self.zoom),
                      210,
                      25,
                      7);
     }
+
+    /// The whole map, a pixel by cell (or a few cells), with the viewed area
+    fn draw_minimap(&mut self, screen: &mut Screen) {
+        let scale = self.minimap_scale;
+        screen.rectfill(self.minimap[0], self.minimap[1], self.minimap[2] - 1, self.minimap[3] - 1, 0);
+
+        for y in 0..self.minimap[3] - self.minimap[1] {
+            for x in 0..self.minimap[2] - self.minimap[0] {
+                let sprite = screen.mget(x * scale, y * scale) as usize;
+                if sprite != 0 && sprite < screen.sprites.len() {
+                    // The middle pixel of the sprite
+                    let col = screen.sprites[sprite].data[36] as i32;
+                    screen.pset(self.minimap[0] + x, self.minimap[1] + y, col);
+                }
+            }
+        }
+
+        let (visible_x, visible_y) = self.visible();
+        let x1 = self.minimap[0] + self.offset_x / scale;
+        let y1 = self.minimap[1] + self.offset_y / scale;
+        let x2 = min(self.minimap[2] - 1, x1 + visible_x / scale);
+        let y2 = min(self.minimap[3] - 1, y1 + visible_y / scale);
+        screen.rect(x1, y1, x2, y2, 7);
+    }
+}
+
+/// Drawing tools of the sprite editor
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Tool {
+    Pencil,
+    Fill,
+    Line,
+    Rect,
+    RectFill,
+    Ellipse,
+    EllipseFill,
+    Select,
+    Picker,
+}
+
+/// Tools with their widget name, position and hotkey. Pressing the hotkey of
+/// the current tool selects the next tool with the same key (filled shapes).
+static TOOLS: [(Tool, &'static str, u32, Scancode); 9] = [
+    (Tool::Pencil, "PENCIL", 160, Scancode::B),
+    (Tool::Fill, "FILL", 170, Scancode::F),
+    (Tool::Line, "LINE", 180, Scancode::L),
+    (Tool::Rect, "RECT", 190, Scancode::R),
+    (Tool::RectFill, "RECT FILL", 200, Scancode::R),
+    (Tool::Ellipse, "ELLIPSE", 210, Scancode::E),
+    (Tool::EllipseFill, "ELLIPSE FILL", 220, Scancode::E),
+    (Tool::Select, "SELECT", 230, Scancode::S),
+    (Tool::Picker, "PICKER", 240, Scancode::I),
+];
+
+/// Icons of the tools, in the order of `TOOLS`
+static TOOL_ICONS: [[u8; 64]; 9] = [
+    [5, 5, 5, 5, 5, 5, 6, 5,
+     5, 5, 5, 5, 5, 6, 6, 6,
+     5, 5, 5, 5, 6, 6, 6, 5,
+     5, 5, 5, 6, 6, 6, 5, 5,
+     5, 5, 6, 6, 6, 5, 5, 5,
+     5, 6, 6, 6, 5, 5, 5, 5,
+     5, 6, 6, 5, 5, 5, 5, 5,
+     5, 5, 5, 5, 5, 5, 5, 5],
+    [5, 5, 5, 5, 5, 5, 5, 5,
+     5, 5, 6, 6, 6, 6, 6, 5,
+     5, 5, 6, 6, 6, 6, 6, 5,
+     5, 5, 6, 6, 6, 6, 6, 5,
+     5, 5, 6, 6, 6, 6, 6, 5,
+     5, 6, 5, 5, 5, 5, 5, 5,
+     5, 6, 6, 5, 5, 5, 5, 5,
+     5, 6, 6, 5, 5, 5, 5, 5],
+    [5, 5, 5, 5, 5, 5, 5, 5,
+     5, 5, 5, 5, 5, 5, 6, 5,
+     5, 5, 5, 5, 5, 6, 5, 5,
+     5, 5, 5, 5, 6, 5, 5, 5,
+     5, 5, 5, 6, 5, 5, 5, 5,
+     5, 5, 6, 5, 5, 5, 5, 5,
+     5, 6, 5, 5, 5, 5, 5, 5,
+     5, 5, 5, 5, 5, 5, 5, 5],
+    [5, 5, 5, 5, 5, 5, 5, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 6, 5, 5, 5, 5, 6, 5,
+     5, 6, 5, 5, 5, 5, 6, 5,
+     5, 6, 5, 5, 5, 5, 6, 5,
+     5, 6, 5, 5, 5, 5, 6, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 5, 5, 5, 5, 5, 5, 5],
+    [5, 5, 5, 5, 5, 5, 5, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 5, 5, 5, 5, 5, 5, 5],
+    [5, 5, 5, 5, 5, 5, 5, 5,
+     5, 5, 6, 6, 6, 6, 5, 5,
+     5, 6, 5, 5, 5, 5, 6, 5,
+     5, 6, 5, 5, 5, 5, 6, 5,
+     5, 6, 5, 5, 5, 5, 6, 5,
+     5, 6, 5, 5, 5, 5, 6, 5,
+     5, 5, 6, 6, 6, 6, 5, 5,
+     5, 5, 5, 5, 5, 5, 5, 5],
+    [5, 5, 5, 5, 5, 5, 5, 5,
+     5, 5, 6, 6, 6, 6, 5, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 6, 6, 6, 6, 6, 6, 5,
+     5, 5, 6, 6, 6, 6, 5, 5,
+     5, 5, 5, 5, 5, 5, 5, 5],
+    [5, 5, 5, 5, 5, 5, 5, 5,
+     5, 6, 5, 6, 5, 6, 5, 5,
+     5, 5, 5, 5, 5, 5, 5, 5,
+     5, 6, 5, 5, 5, 6, 5, 5,
+     5, 5, 5, 5, 5, 5, 5, 5,
+     5, 6, 5, 6, 5, 6, 5, 5,
+     5, 5, 5, 5, 5, 5, 5, 5,
+     5, 5, 5, 5, 5, 5, 5, 5],
+    [5, 5, 5, 5, 5, 5, 6, 6,
+     5, 5, 5, 5, 5, 6, 6, 6,
+     5, 5, 5, 5, 6, 6, 6, 5,
+     5, 5, 5, 5, 5, 6, 5, 5,
+     5, 5, 5, 6, 5, 5, 5, 5,
+     5, 5, 6, 5, 5, 5, 5, 5,
+     5, 6, 5, 5, 5, 5, 5, 5,
+     6, 5, 5, 5, 5, 5, 5, 5],
+];
+
+/// Fill the area of `col` connected to (`x`, `y`) with `new_col`
+pub fn flood_fill(pixels: &mut [u32], width: i32, height: i32, x: i32, y: i32, new_col: u32) {
+    if x < 0 || y < 0 || x >= width || y >= height {
+        return;
+    }
+
+    let col = pixels[(x + y * width) as usize];
+    if col == new_col {
+        return;
+    }
+
+    let mut stack = vec![(x, y)];
+    while let Some((x, y)) = stack.pop() {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            continue;
+        }
+
+        let idx = (x + y * width) as usize;
+        if pixels[idx] != col {
+            continue;
+        }
+
+        pixels[idx] = new_col;
+        stack.push((x - 1, y));
+        stack.push((x + 1, y));
+        stack.push((x, y - 1));
+        stack.push((x, y + 1));
+    }
+}
+
+/// Pixels mirrored left to right, or top to bottom
+pub fn flip(pixels: &[u32], width: i32, height: i32, horizontal: bool) -> Vec<u32> {
+    let mut flipped = Vec::with_capacity(pixels.len());
+    for y in 0..height {
+        for x in 0..width {
+            let (x, y) = if horizontal { (width - 1 - x, y) } else { (x, height - 1 - y) };
+            flipped.push(pixels[(x + y * width) as usize]);
+        }
+    }
+    flipped
+}
+
+/// Pixels moved by (`dx`, `dy`), the ones going out come back on the other side
+pub fn shift(pixels: &[u32], width: i32, height: i32, dx: i32, dy: i32) -> Vec<u32> {
+    let mut shifted = vec![0; pixels.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let to_x = ((x + dx) % width + width) % width;
+            let to_y = ((y + dy) % height + height) % height;
+            shifted[(to_x + to_y * width) as usize] = pixels[(x + y * width) as usize];
+        }
+    }
+    shifted
+}
+
+/// Rectangle (x, y, width, height) between two corners
+fn rect_between(start: (i32, i32), end: (i32, i32)) -> (i32, i32, i32, i32) {
+    (min(start.0, end.0),
+     min(start.1, end.1),
+     (start.0 - end.0).abs() + 1,
+     (start.1 - end.1).abs() + 1)
+}
+
+fn in_rect(x: i32, y: i32, rect: (i32, i32, i32, i32)) -> bool {
+    x >= rect.0 && y >= rect.1 && x < rect.0 + rect.2 && y < rect.1 + rect.3
 }
 
 pub struct SpriteEditor {
     state: Arc<Mutex<State>>,
     pp: PalettePicker,
     widgets: Vec<Arc<Mutex<Widget>>>,
+    /// Widgets of the tools, one is selected
+    tools: Vec<(Tool, Arc<Mutex<Widget>>)>,
     buffer_copy: Vec<u32>,
     buffer_copy_size: [u32; 2],
+    tool: Tool,
+    /// Pixels of the sprite where the mouse was pressed, and where it is
+    drag: Option<((i32, i32), (i32, i32))>,
+    /// Marquee selection (x, y, width, height), in pixels of the sprite
+    selection: Option<(i32, i32, i32, i32)>,
+    /// Position in the sprite sheet and size of the edited pixels, the
+    /// selection is dropped when they change
+    area: (u32, u32, u32),
+    /// Shapes are drawn here by `Screen` before going to the sprites
+    canvas: Screen,
 }
 
 impl SpriteEditor {
@@ -484,25 +976,321 @@ impl SpriteEditor {
                                                      HashMap::new(),
                                                      false, false))));
         widgets.push(Arc::new(Mutex::new(Widget::new(state.clone(),
-                                                     "FILL".to_string(),
+                                                     "FLIP H".to_string(),
                                                      210, 90, 8, 8,
                                                      vec![5, 5, 5, 5, 5, 5, 5, 5,
-                                                          5, 5, 6, 6, 6, 6, 6, 5,
-                                                          5, 5, 6, 6, 6, 6, 6, 5,
-                                                          5, 5, 6, 6, 6, 6, 6, 5,
-                                                          5, 5, 6, 6, 6, 6, 6, 5,
-                                                          5, 6, 5, 5, 5, 5, 5, 5,
-                                                          5, 6, 6, 5, 5, 5, 5, 5,
-                                                          5, 6, 6, 5, 5, 5, 5, 5],
-                                                     highlight.clone(),
-                                                     false, true))));
+                                                          5, 5, 5, 5, 5, 5, 5, 5,
+                                                          5, 6, 5, 5, 5, 5, 6, 5,
+                                                          6, 6, 6, 6, 6, 6, 6, 6,
+                                                          5, 6, 5, 5, 5, 5, 6, 5,
+                                                          5, 5, 5, 5, 5, 5, 5, 5,
+                                                          5, 5, 5, 5, 5, 5, 5, 5,
+                                                          5, 5, 5, 5, 5, 5, 5, 5],
+                                                     HashMap::new(),
+                                                     false, false))));
+        widgets.push(Arc::new(Mutex::new(Widget::new(state.clone(),
+                                                     "FLIP V".to_string(),
+                                                     220, 90, 8, 8,
+                                                     vec![5, 5, 5, 6, 5, 5, 5, 5,
+                                                          5, 5, 6, 6, 6, 5, 5, 5,
+                                                          5, 5, 5, 6, 5, 5, 5, 5,
+                                                          5, 5, 5, 6, 5, 5, 5, 5,
+                                                          5, 5, 5, 6, 5, 5, 5, 5,
+                                                          5, 5, 5, 6, 5, 5, 5, 5,
+                                                          5, 5, 6, 6, 6, 5, 5, 5,
+                                                          5, 5, 5, 6, 5, 5, 5, 5],
+                                                     HashMap::new(),
+                                                     false, false))));
+
+        let tools = TOOLS.iter()
+            .zip(TOOL_ICONS.iter())
+            .map(|(&(tool, name, x, _), icon)| {
+                let widget = Widget::new(state.clone(),
+                                         name.to_string(),
+                                         x, 100, 8, 8,
+                                         icon.to_vec(),
+                                         highlight.clone(),
+                                         tool == Tool::Pencil, true);
+                (tool, Arc::new(Mutex::new(widget)))
+            })
+            .collect();
+
+        let mut canvas = Screen::new(64, 64);
+        canvas.init();
 
         SpriteEditor {
             state: state.clone(),
             pp: PalettePicker::new(state.clone()),
             widgets: widgets,
+            tools: tools,
             buffer_copy: Vec::new(),
             buffer_copy_size: [0, 0],
+            tool: Tool::Pencil,
+            drag: None,
+            selection: None,
+            area: (0, 0, 0),
+            canvas: canvas,
+        }
+    }
+
+    pub fn set_tool(&mut self, tool: Tool) {
+        info!("[Unicorn][EDITOR] Tool {:?}", tool);
+
+        self.tool = tool;
+        self.drag = None;
+        for &(widget_tool, ref widget) in &self.tools {
+            widget.lock().unwrap().set_click(widget_tool == tool);
+        }
+    }
+
+    /// Tool of the hotkey pressed, the next one with the same key if the
+    /// current tool has it. The keys with Ctrl are for the editor.
+    fn hotkey_tool(&self, players: &Arc<Mutex<Players>>) -> Option<Tool> {
+        if self.state.lock().unwrap().ctrl {
+            return None;
+        }
+
+        let mut players = players.lock().unwrap();
+        let key = match TOOLS.iter().map(|tool| tool.3).find(|key| players.btnp3(*key)) {
+            Some(key) => key,
+            None => return None,
+        };
+
+        let tools: Vec<Tool> = TOOLS.iter().filter(|tool| tool.3 == key).map(|tool| tool.0).collect();
+        match tools.iter().position(|tool| *tool == self.tool) {
+            Some(idx) => Some(tools[(idx + 1) % tools.len()]),
+            None => tools.first().cloned(),
+        }
+    }
+
+    /// Edited pixels: position in the sprite sheet and size
+    fn current_area(&self) -> (u32, u32, u32) {
+        let state = self.state.lock().unwrap();
+        (state.x_zoom_sprite, state.y_zoom_sprite, 8 * state.zoom_sprite)
+    }
+
+    /// Selection, or all the edited pixels
+    fn region(&self) -> (i32, i32, i32, i32) {
+        let size = self.area.2 as i32;
+        self.selection.unwrap_or((0, 0, size, size))
+    }
+
+    fn read(&self, screen: &mut Screen, rect: (i32, i32, i32, i32)) -> Vec<u32> {
+        let mut pixels = Vec::new();
+        for y in rect.1..rect.1 + rect.3 {
+            for x in rect.0..rect.0 + rect.2 {
+                pixels.push(screen.sget(self.area.0 + x as u32, self.area.1 + y as u32));
+            }
+        }
+        pixels
+    }
+
+    /// Write `pixels` in `rect`, without the ones out of the edited pixels
+    fn write(&self, screen: &mut Screen, rect: (i32, i32, i32, i32), pixels: &[u32]) {
+        let size = self.area.2 as i32;
+        for y in 0..rect.3 {
+            for x in 0..rect.2 {
+                let (to_x, to_y) = (rect.0 + x, rect.1 + y);
+                if to_x >= 0 && to_y >= 0 && to_x < size && to_y < size {
+                    screen.sset(self.area.0 + to_x as u32,
+                                self.area.1 + to_y as u32,
+                                pixels[(x + y * rect.2) as usize] as i32);
+                }
+            }
+        }
+    }
+
+    /// Pixel of the sprite under the mouse, the nearest one outside while
+    /// dragging
+    fn mouse_pixel(&self, clamp: bool) -> Option<(i32, i32)> {
+        let state = self.state.lock().unwrap();
+        let x = state.mouse_x - state.idx_x_zoom_sprite as i32;
+        let y = state.mouse_y - state.idx_y_zoom_sprite as i32;
+
+        // 128 pixels on the screen for 8 * zoom pixels of the sprite
+        let size = 8 * state.zoom_sprite as i32;
+        let (x, y) = ((x as f64 * size as f64 / 128.).floor() as i32,
+                      (y as f64 * size as f64 / 128.).floor() as i32);
+
+        if clamp {
+            Some((max(0, min(size - 1, x)), max(0, min(size - 1, y))))
+        } else if x >= 0 && y >= 0 && x < size && y < size {
+            Some((x, y))
+        } else {
+            None
+        }
+    }
+
+    /// Pixels of the shape of the current tool between two corners, drawn
+    /// by the primitives of `Screen`
+    fn shape(&mut self, start: (i32, i32), end: (i32, i32)) -> Vec<(i32, i32)> {
+        let size = self.area.2 as i32;
+
+        for pixel in self.canvas.frame_buffer.iter_mut() {
+            *pixel = 0;
+        }
+
+        let (x, y, w, h) = rect_between(start, end);
+        match self.tool {
+            Tool::Line => self.canvas.line(start.0, start.1, end.0, end.1, 1),
+            Tool::Rect => self.canvas.rect(x, y, x + w - 1, y + h - 1, 1),
+            Tool::RectFill => self.canvas.rectfill(x, y, x + w - 1, y + h - 1, 1),
+            Tool::Ellipse | Tool::EllipseFill => {
+                let (rx, ry) = ((w - 1) / 2, (h - 1) / 2);
+                if rx == 0 || ry == 0 {
+                    self.canvas.rectfill(x, y, x + w - 1, y + h - 1, 1);
+                } else if self.tool == Tool::Ellipse {
+                    self.canvas.ellipse(x + rx, y + ry, rx, ry, 1);
+                } else {
+                    self.canvas.ellipsefill(x + rx, y + ry, rx, ry, 1);
+                }
+            }
+            _ => {}
+        }
+
+        let mut pixels = Vec::new();
+        for y in 0..size {
+            for x in 0..size {
+                if self.canvas.pget(x as u32, y as u32) == 1 {
+                    pixels.push((x, y));
+                }
+            }
+        }
+        pixels
+    }
+
+    pub fn flip(&mut self, screen: &mut Screen, horizontal: bool) {
+        info!("[Unicorn][EDITOR] Flip {}", if horizontal { "H" } else { "V" });
+
+        let region = self.region();
+        let pixels = self.read(screen, region);
+        self.write(screen, region, &flip(&pixels, region.2, region.3, horizontal));
+    }
+
+    /// Shift the selection, or the sprite, by one pixel with a wrap around
+    pub fn shift(&mut self, screen: &mut Screen, dx: i32, dy: i32) {
+        let region = self.region();
+        let pixels = self.read(screen, region);
+        self.write(screen, region, &shift(&pixels, region.2, region.3, dx, dy));
+    }
+
+    /// Apply the current tool with the mouse
+    fn update_tool(&mut self, screen: &mut Screen) {
+        let mouse_statep = self.state.lock().unwrap().mouse_statep;
+        let col = self.pp.current_color;
+
+        if mouse_statep != 1 {
+            if let Some((start, end)) = self.drag.take() {
+                self.finish_drag(screen, start, end);
+            }
+            return;
+        }
+
+        match self.tool {
+            Tool::Pencil => {
+                if let Some((x, y)) = self.mouse_pixel(false) {
+                    screen.sset(self.area.0 + x as u32, self.area.1 + y as u32, col as i32);
+                }
+            }
+            Tool::Fill => {
+                if let Some((x, y)) = self.mouse_pixel(false) {
+                    let region = self.region();
+                    if in_rect(x, y, region) {
+                        let mut pixels = self.read(screen, region);
+                        flood_fill(&mut pixels, region.2, region.3, x - region.0, y - region.1, col);
+                        self.write(screen, region, &pixels);
+                    }
+                }
+            }
+            Tool::Picker => {
+                if let Some((x, y)) = self.mouse_pixel(false) {
+                    let col = screen.sget(self.area.0 + x as u32, self.area.1 + y as u32);
+                    self.pp.set_color(col);
+                }
+            }
+            _ => {
+                match self.drag {
+                    Some((start, _)) => {
+                        let end = self.mouse_pixel(true);
+                        self.drag = end.map(|end| (start, end));
+                    }
+                    None => self.drag = self.mouse_pixel(false).map(|start| (start, start)),
+                }
+            }
+        }
+    }
+
+    /// Draw the shape, or select and move, at the release of the mouse
+    fn finish_drag(&mut self, screen: &mut Screen, start: (i32, i32), end: (i32, i32)) {
+        if self.tool != Tool::Select {
+            let col = self.pp.current_color as i32;
+            for (x, y) in self.shape(start, end) {
+                screen.sset(self.area.0 + x as u32, self.area.1 + y as u32, col);
+            }
+            return;
+        }
+
+        let selection = self.selection;
+        match selection {
+            // Move the selected pixels, their place is erased
+            Some(selection) if in_rect(start.0, start.1, selection) => {
+                let pixels = self.read(screen, selection);
+                self.write(screen, selection, &vec![0; pixels.len()]);
+
+                let (x, y) = (selection.0 + end.0 - start.0, selection.1 + end.1 - start.1);
+                self.write(screen, (x, y, selection.2, selection.3), &pixels);
+
+                // The selection stays on the moved pixels in the sprite
+                let size = self.area.2 as i32;
+                let (x1, y1) = (max(0, x), max(0, y));
+                let (x2, y2) = (min(size, x + selection.2), min(size, y + selection.3));
+                self.selection = Some((x1, y1, x2 - x1, y2 - y1));
+            }
+            _ => {
+                self.selection = if start == end {
+                    None
+                } else {
+                    Some(rect_between(start, end))
+                };
+            }
+        }
+    }
+
+    /// Shape being drawn, selection and moved pixels, over the zoomed sprite
+    fn draw_tool(&mut self, screen: &mut Screen) {
+        let (x, y) = {
+            let state = self.state.lock().unwrap();
+            (state.idx_x_zoom_sprite as i32, state.idx_y_zoom_sprite as i32)
+        };
+        let scale = 128 / max(1, self.area.2 as i32);
+        let col = self.pp.current_color as i32;
+
+        let mut selection = self.selection;
+        let drag = self.drag;
+        if let Some((start, end)) = drag {
+            match self.tool {
+                Tool::Select => {
+                    match selection {
+                        Some(rect) if in_rect(start.0, start.1, rect) => {
+                            selection = Some((rect.0 + end.0 - start.0, rect.1 + end.1 - start.1, rect.2, rect.3));
+                        }
+                        _ => selection = Some(rect_between(start, end)),
+                    }
+                }
+                _ => {
+                    for (px, py) in self.shape(start, end) {
+                        let (px, py) = (x + px * scale, y + py * scale);
+                        screen.rectfill(px, py, px + scale - 1, py + scale - 1, col);
+                    }
+                }
+            }
+        }
+
+        if let Some((sx, sy, sw, sh)) = selection {
+            let (x1, y1) = (x + sx * scale, y + sy * scale);
+            let (x2, y2) = (x1 + sw * scale - 1, y1 + sh * scale - 1);
+            screen.rect(x1 - 1, y1 - 1, x2 + 1, y2 + 1, 0);
+            screen.rect(x1, y1, x2, y2, 7);
         }
     }
 
@@ -574,6 +1362,34 @@ impl SpriteEditor {
     pub fn update(&mut self, players: Arc<Mutex<Players>>, screen: &mut Screen) {
         self.pp.update(screen);
 
+        // The selection is for the pixels where it was made
+        let area = self.current_area();
+        if area != self.area {
+            self.area = area;
+            self.selection = None;
+            self.drag = None;
+        }
+
+        if let Some(tool) = self.hotkey_tool(&players) {
+            self.set_tool(tool);
+        }
+
+        let clicked = self.tools
+            .iter()
+            .find(|&&(_, ref widget)| widget.lock().unwrap().is_clickable())
+            .map(|&(tool, _)| tool);
+        if let Some(tool) = clicked {
+            self.set_tool(tool);
+        }
+
+        if players.lock().unwrap().btnp3(Scancode::H) {
+            self.flip(screen, true);
+        }
+
+        if players.lock().unwrap().btnp3(Scancode::V) {
+            self.flip(screen, false);
+        }
+
         if players.lock().unwrap().btnp2(1073741948) {
             self.copy(screen);
         }
@@ -595,14 +1411,17 @@ impl SpriteEditor {
         let x_zoom_sprite = self.state.lock().unwrap().x_zoom_sprite;
         let y_zoom_sprite = self.state.lock().unwrap().y_zoom_sprite;
 
-        self.state.lock().unwrap().fill_action = false;
         for widget in &self.widgets.clone() {
             let is_click = widget.lock().unwrap().is_click();
 
             if is_click {
                 let name = widget.lock().unwrap().name.clone();
-                if name == "FILL" {
-                    self.state.lock().unwrap().fill_action = true;
+                if name == "FLIP H" {
+                    self.flip(screen, true);
+                }
+
+                if name == "FLIP V" {
+                    self.flip(screen, false);
                 }
 
                 if name == "ERASE" {
@@ -694,7 +1513,8 @@ impl SpriteEditor {
             }
         }
 
-        if players.lock().unwrap().btnp(0, 4) {
+        let ctrl = self.state.lock().unwrap().ctrl;
+        if !ctrl && players.lock().unwrap().btnp(0, 4) {
             let idx_zoom_sprite = self.state.lock().unwrap().idx_zoom_sprite;
             let sprite_available_zooms = self.state.lock().unwrap().sprite_available_zooms;
 
@@ -702,133 +1522,19 @@ impl SpriteEditor {
             self.state.lock().unwrap().idx_zoom_sprite = new_idx_zoom_sprite;
 
             self.state.lock().unwrap().zoom_sprite = sprite_available_zooms[new_idx_zoom_sprite as usize];
-        }
-
-        // shift sprite
-        // shift left
-        if players.lock().unwrap().btnp(0, 0) {
-            let mut buffer_copy = Vec::new();
-
-            for _ in 0..8 * zoom_sprite {
-                for _ in 0..8 * zoom_sprite {
-                    buffer_copy.push(0);
-                }
-            }
-
-            for x in 0..8 * zoom_sprite {
-                for y in 0..8 * zoom_sprite {
-                    buffer_copy[(y + x * 8 * zoom_sprite) as usize] =
-                        screen.sget(x_zoom_sprite + x, y_zoom_sprite + y);
-                }
-            }
-
-            let (a, b) = buffer_copy.split_at(8 * zoom_sprite as usize);
-            let mut spun_vector: Vec<u32> = vec![];
-            spun_vector.extend_from_slice(b);
-            spun_vector.extend_from_slice(a);
 
-            for x in 0..8 * zoom_sprite {
-                for y in 0..8 * zoom_sprite {
-                    screen.sset(x_zoom_sprite + x,
-                                y_zoom_sprite + y,
-                                spun_vector[(y + x * 8 * zoom_sprite) as usize] as i32);
-                }
-            }
-        }
-        // shift right
-        if players.lock().unwrap().btnp(0, 1) {
-            let mut buffer_copy = Vec::new();
-
-            for _ in 0..8 * zoom_sprite {
-                for _ in 0..8 * zoom_sprite {
-                    buffer_copy.push(0);
-                }
-            }
-
-            for x in 0..8 * zoom_sprite {
-                for y in 0..8 * zoom_sprite {
-                    buffer_copy[(y + x * 8 * zoom_sprite) as usize] =
-                        screen.sget(x_zoom_sprite + x, y_zoom_sprite + y);
-                }
-            }
-
-            let max_size = (8 * zoom_sprite * 8 * zoom_sprite) - (8 * zoom_sprite);
-            let (a, b) = buffer_copy.split_at(max_size as usize);
-            let mut spun_vector: Vec<u32> = vec![];
-            spun_vector.extend_from_slice(b);
-            spun_vector.extend_from_slice(a);
-
-            for x in 0..8 * zoom_sprite {
-                for y in 0..8 * zoom_sprite {
-                    screen.sset(x_zoom_sprite + x,
-                                y_zoom_sprite + y,
-                                spun_vector[(y + x * 8 * zoom_sprite) as usize] as i32);
-                }
-            }
+            let zoom_sprite = self.state.lock().unwrap().zoom_sprite;
+            self.state.lock().unwrap().stamp = [zoom_sprite, zoom_sprite];
         }
 
-        // shift down
-        if players.lock().unwrap().btnp(0, 2) {
-            let mut buffer_copy = Vec::new();
-
-            for _ in 0..8 * zoom_sprite {
-                for _ in 0..8 * zoom_sprite {
-                    buffer_copy.push(0);
-                }
-            }
-
-            for x in 0..8 * zoom_sprite {
-                for y in 0..8 * zoom_sprite {
-                    buffer_copy[(x + y * 8 * zoom_sprite) as usize] =
-                        screen.sget(x_zoom_sprite + x, y_zoom_sprite + y);
-                }
-            }
-
-            let (a, b) = buffer_copy.split_at(8 * zoom_sprite as usize);
-            let mut spun_vector: Vec<u32> = vec![];
-            spun_vector.extend_from_slice(b);
-            spun_vector.extend_from_slice(a);
-
-            for x in 0..8 * zoom_sprite {
-                for y in 0..8 * zoom_sprite {
-                    screen.sset(x_zoom_sprite + x,
-                                y_zoom_sprite + y,
-                                spun_vector[(x + y * 8 * zoom_sprite) as usize] as i32);
-                }
+        // Shift the selection or the sprite with the arrows
+        for &(button, dx, dy) in &[(0, -1, 0), (1, 1, 0), (2, 0, -1), (3, 0, 1)] {
+            if players.lock().unwrap().btnp(0, button) {
+                self.shift(screen, dx, dy);
             }
         }
 
-        // shift up
-        if players.lock().unwrap().btnp(0, 3) {
-            let mut buffer_copy = Vec::new();
-
-            for _ in 0..8 * zoom_sprite {
-                for _ in 0..8 * zoom_sprite {
-                    buffer_copy.push(0);
-                }
-            }
-
-            for x in 0..8 * zoom_sprite {
-                for y in 0..8 * zoom_sprite {
-                    buffer_copy[(x + y * 8 * zoom_sprite) as usize] =
-                        screen.sget(x_zoom_sprite + x, y_zoom_sprite + y);
-                }
-            }
-
-            let max_size = (8 * zoom_sprite * 8 * zoom_sprite) - (8 * zoom_sprite);
-            let (a, b) = buffer_copy.split_at(max_size as usize);
-            let mut spun_vector: Vec<u32> = vec![];
-            spun_vector.extend_from_slice(b);
-            spun_vector.extend_from_slice(a);
-
-            for x in 0..8 * zoom_sprite {
-                for y in 0..8 * zoom_sprite {
-                    screen.sset(x_zoom_sprite + x,
-                                y_zoom_sprite + y,
-                                spun_vector[(x + y * 8 * zoom_sprite) as usize] as i32);
-                }
-            }
-        }
+        self.update_tool(screen);
     }
 
     pub fn draw(&mut self, screen: &mut Screen) {
@@ -858,15 +1564,24 @@ impl SpriteEditor {
                     false,
                     false);
 
+        self.draw_tool(screen);
+
         for widget in &self.widgets {
             widget.lock().unwrap().draw(screen);
         }
+
+        for &(_, ref widget) in &self.tools {
+            widget.lock().unwrap().draw(screen);
+        }
     }
 }
 
 pub struct SpritesMap {
     state: Arc<Mutex<State>>,
     flags: Flags,
+    /// Sprite where the mouse was pressed, the stamp goes to the sprite
+    /// under the mouse
+    drag: Option<u32>,
 }
 
 impl SpritesMap {
@@ -876,6 +1591,7 @@ impl SpritesMap {
         SpritesMap {
             state: state.clone(),
             flags: Flags::new(state.clone()),
+            drag: None,
         }
     }
 
@@ -900,6 +1616,37 @@ impl SpritesMap {
                 let current_sprite = self.state.lock().unwrap().current_sprite;
                 self.state.lock().unwrap().x_zoom_sprite = (current_sprite % 50) * 8;
                 self.state.lock().unwrap().y_zoom_sprite = (current_sprite as f64 / 50.).floor() as u32 * 8;
+
+                let zoom_sprite = self.state.lock().unwrap().zoom_sprite;
+                self.state.lock().unwrap().stamp = [zoom_sprite, zoom_sprite];
+                self.drag = Some(current_sprite);
+            }
+        }
+
+        // Select several sprites for a stamp of the map editor
+        if self.state.lock().unwrap().mouse_statep != 1 {
+            self.drag = None;
+        }
+
+        if let Some(start) = self.drag {
+            let mouse_x = self.state.lock().unwrap().mouse_x;
+            let mouse_y = self.state.lock().unwrap().mouse_y;
+
+            let idx_sprites_batch_x = self.state.lock().unwrap().idx_sprites_batch_x;
+            let idx_sprites_batch_y = self.state.lock().unwrap().idx_sprites_batch_y;
+
+            let x = max(0, min(49, (mouse_x - idx_sprites_batch_x) / 8)) as u32;
+            let y = max(0, min(7, (mouse_y - idx_sprites_batch_y) / 8)) as u32;
+            let (start_x, start_y) = (start % 50, start / 50);
+
+            if (x, y) != (start_x, start_y) {
+                let current_sprite = min(x, start_x) + min(y, start_y) * 50;
+                let mut state = self.state.lock().unwrap();
+                state.current_sprite = current_sprite;
+                state.x_zoom_sprite = (current_sprite % 50) * 8;
+                state.y_zoom_sprite = (current_sprite / 50) * 8;
+                state.stamp = [max(x, start_x) - min(x, start_x) + 1,
+                               max(y, start_y) - min(y, start_y) + 1];
             }
         }
 
@@ -918,7 +1665,6 @@ impl SpritesMap {
     }
 
     pub fn draw_sprite_map(&mut self, screen: &mut Screen) {
-        let zoom = self.state.lock().unwrap().zoom_sprite as i32;
         let mut current_sprite_x = 0;
         let mut current_sprite_y = 0;
 
@@ -950,16 +1696,21 @@ impl SpritesMap {
             }
         }
 
+        // The stamp of the map editor, the same as the edited sprites unless
+        // several sprites are selected
+        let stamp = self.state.lock().unwrap().stamp;
+        let (w, h) = (stamp[0] as i32, stamp[1] as i32);
+
         current_sprite_x -= 1;
         screen.rect(current_sprite_x,
                     current_sprite_y,
-                    current_sprite_x + 8 * zoom,
-                    current_sprite_y + 8 * zoom,
+                    current_sprite_x + 8 * w,
+                    current_sprite_y + 8 * h,
                     7);
         screen.rect(current_sprite_x - 1,
                     current_sprite_y - 1,
-                    current_sprite_x + 1 + 8 * zoom,
-                    current_sprite_y + 1 + 8 * zoom,
+                    current_sprite_x + 1 + 8 * w,
+                    current_sprite_y + 1 + 8 * h,
                     0);
     }
 
@@ -981,6 +1732,11 @@ impl SpritesMap {
     }
 }
 
+/// File next to the cartridge, with the extension replaced by `suffix`
+fn export_filename(filename: &str, suffix: &str) -> String {
+    Path::new(filename).with_extension(suffix).to_string_lossy().into_owned()
+}
+
 pub struct GFXEditor {
     state: Arc<Mutex<State>>,
     state_editor: EditorState,
@@ -988,6 +1744,7 @@ pub struct GFXEditor {
     me: MapEditor,
     se: SpriteEditor,
     widgets: Vec<Arc<Mutex<Widget>>>,
+    snapshot: Snapshot,
 }
 
 impl GFXEditor {
@@ -1052,11 +1809,74 @@ impl GFXEditor {
             me: MapEditor::new(state.clone()),
             se: SpriteEditor::new(state.clone()),
             widgets: widgets,
+            snapshot: Snapshot::new(),
         }
     }
 
-    pub fn init(&mut self, _config: Arc<Mutex<UnicornConfig>>, _screen: &mut Screen) {
+    pub fn init(&mut self, _config: Arc<Mutex<UnicornConfig>>, screen: &mut Screen) {
         info!("[EDITOR][GFX] Init");
+        self.sync(screen);
+    }
+
+    /// Forget the changes of the sprites and the map made out of the editors
+    pub fn sync(&mut self, screen: &mut Screen) {
+        self.snapshot.sync(screen);
+    }
+
+    /// Save the sprite sheet and the map in PNG, and the map for Tiled, next
+    /// to the cartridge
+    pub fn export(&mut self, filename: &str, colors: &[RGB], screen: &Screen) {
+        let sheet_filename = export_filename(filename, "sheet.png");
+        if let Err(e) = sheet::export_sheet_png(&sheet_filename, &screen.sprites, colors) {
+            warn!("[EDITOR][GFX] Error to export the sprite sheet {:?}", e);
+        }
+
+        if let Err(e) = sheet::export_map_png(&export_filename(filename, "map.png"),
+                                              &screen.sprites,
+                                              &screen.map,
+                                              unicorn::MAP_WIDTH,
+                                              unicorn::MAP_HEIGHT,
+                                              colors) {
+            warn!("[EDITOR][GFX] Error to export the map {:?}", e);
+        }
+
+        // The tileset is the sprite sheet next to the map
+        let image = Path::new(&sheet_filename)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or(sheet_filename.clone());
+        let map = TiledMap::new(&screen.map, unicorn::MAP_WIDTH, unicorn::MAP_HEIGHT, &screen.sprites);
+        if let Err(e) = map.save(&export_filename(filename, "map.tmx"), &image) {
+            warn!("[EDITOR][GFX] Error to export the map for Tiled {:?}", e);
+        }
+    }
+
+    /// Load the sprite sheet and the Tiled map saved by `export`, if they
+    /// exist. The changes are in the history like the other edits.
+    pub fn import(&mut self, filename: &str, colors: &[RGB], screen: &mut Screen) {
+        let sheet_filename = export_filename(filename, "sheet.png");
+        if Path::new(&sheet_filename).exists() {
+            if let Err(e) = sheet::import_sheet_png(&sheet_filename, &mut screen.sprites, colors) {
+                warn!("[EDITOR][GFX] Error to import the sprite sheet {:?}", e);
+            }
+        }
+
+        for extension in &["map.tmx", "map.json"] {
+            let map_filename = export_filename(filename, extension);
+            if !Path::new(&map_filename).exists() {
+                continue;
+            }
+
+            match TiledMap::load(&map_filename) {
+                Ok(map) => {
+                    map.apply(&mut screen.map, unicorn::MAP_WIDTH, unicorn::MAP_HEIGHT, &mut screen.sprites);
+                }
+                Err(e) => warn!("[EDITOR][GFX] Error to import the map {:?}", e),
+            }
+            break;
+        }
+
+        screen.edited = true;
     }
 
     pub fn update(&mut self, screen: &mut Screen, players: Arc<Mutex<Players>>) -> bool {
@@ -1100,6 +1920,14 @@ impl GFXEditor {
             }
         }
 
+        let changes = self.snapshot.update(screen);
+        if !changes.is_empty() {
+            let mut state = self.state.lock().unwrap();
+            for change in changes {
+                state.history.record(change);
+            }
+        }
+
         let width = screen.mode_width() as i32;
         let height = screen.mode_height() as i32;
 
@@ -1127,3 +1955,89 @@ impl GFXEditor {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fill_connected_pixels() {
+        let mut pixels = vec![0, 0, 1, 0,
+                              0, 1, 0, 0,
+                              1, 0, 0, 2,
+                              0, 0, 2, 0];
+        flood_fill(&mut pixels, 4, 4, 3, 0, 3);
+        assert_eq!(pixels, vec![0, 0, 1, 3,
+                                0, 1, 3, 3,
+                                1, 3, 3, 2,
+                                3, 3, 2, 0]);
+
+        // Out of the pixels, or the same color
+        flood_fill(&mut pixels, 4, 4, 4, 0, 5);
+        flood_fill(&mut pixels, 4, 4, 0, 0, 0);
+        assert_eq!(pixels[0], 0);
+    }
+
+    #[test]
+    fn flip_and_shift() {
+        let pixels = vec![1, 2, 3,
+                          4, 5, 6];
+        assert_eq!(flip(&pixels, 3, 2, true), vec![3, 2, 1, 6, 5, 4]);
+        assert_eq!(flip(&pixels, 3, 2, false), vec![4, 5, 6, 1, 2, 3]);
+        assert_eq!(shift(&pixels, 3, 2, -1, 0), vec![2, 3, 1, 5, 6, 4]);
+        assert_eq!(shift(&pixels, 3, 2, 1, 1), vec![6, 4, 5, 3, 1, 2]);
+        assert_eq!(rect_between((5, 1), (2, 3)), (2, 1, 4, 3));
+    }
+
+    #[test]
+    fn repeat_stamp() {
+        // 2x2 sprites from the sprite 3 of the sheet
+        assert_eq!(stamp_sprite(3, [2, 2], 0, 0), 3);
+        assert_eq!(stamp_sprite(3, [2, 2], 1, 0), 4);
+        assert_eq!(stamp_sprite(3, [2, 2], 0, 1), 53);
+        assert_eq!(stamp_sprite(3, [2, 2], 3, 2), 4);
+        assert_eq!(stamp_sprite(3, [2, 2], -1, -1), 54);
+        assert_eq!(stamp_sprite(7, [0, 0], 5, 5), 7);
+    }
+
+    /// Map editor stamping `stamp` sprites from `sprite`
+    fn map_editor(sprite: u32, stamp: [u32; 2]) -> MapEditor {
+        let state = Arc::new(Mutex::new(State::new()));
+        {
+            let mut state = state.lock().unwrap();
+            state.current_sprite = sprite;
+            state.stamp = stamp;
+        }
+        MapEditor::new(state)
+    }
+
+    #[test]
+    fn fill_the_map() {
+        // The map of the cartridge is missing, mset makes it
+        let mut screen = Screen::new(64, 64);
+        map_editor(3, [2, 2]).fill_rect(&mut screen, (1, 1, 3, 2));
+
+        assert_eq!(screen.map.len(), unicorn::MAP_WIDTH * unicorn::MAP_HEIGHT);
+        assert!(screen.edited);
+        let rows: Vec<Vec<u32>> = (0..4).map(|y| (0..5).map(|x| screen.mget(x, y)).collect()).collect();
+        assert_eq!(rows,
+                   vec![vec![0, 0, 0, 0, 0],
+                        vec![0, 3, 4, 3, 0],
+                        vec![0, 53, 54, 53, 0],
+                        vec![0, 0, 0, 0, 0]]);
+
+        // The cells left of a wall, the stamp starts at the filled cell
+        let mut screen = Screen::new(64, 64);
+        for y in 0..unicorn::MAP_HEIGHT as i32 {
+            screen.mset(2, y, 9);
+        }
+        screen.edited = false;
+        map_editor(5, [2, 1]).fill(&mut screen, 1, 0);
+
+        assert!(screen.edited);
+        for y in 0..unicorn::MAP_HEIGHT as i32 {
+            assert_eq!((screen.mget(0, y), screen.mget(1, y), screen.mget(2, y), screen.mget(3, y)),
+                       (6, 5, 9, 0));
+        }
+    }
+}
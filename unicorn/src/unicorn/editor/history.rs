@@ -0,0 +1,367 @@
+use gfx::Screen;
+
+/// Default number of operations that can be undone
+pub const HISTORY_DEPTH: usize = 100;
+
+/// A reversible edit of the cartridge data
+#[derive(Clone, Debug, PartialEq)]
+pub enum Change {
+    /// Pixel `idx` of the sprite `sprite`
+    Pixel {
+        sprite: usize,
+        idx: usize,
+        before: u8,
+        after: u8,
+    },
+    Flags {
+        sprite: usize,
+        before: u8,
+        after: u8,
+    },
+    /// Cell `idx` of the map
+    Map {
+        idx: usize,
+        before: u32,
+        after: u32,
+    },
+    /// Whole map, when its size changed
+    MapData {
+        before: Vec<u32>,
+        after: Vec<u32>,
+    },
+    /// Step `position` of the program of the sound named `sfx`
+    SoundProgram {
+        sfx: String,
+        position: u32,
+        before: u16,
+        after: u16,
+    },
+}
+
+impl Change {
+    /// The change that cancels this one
+    pub fn reverse(&self) -> Change {
+        match *self {
+            Change::Pixel { sprite, idx, before, after } => {
+                Change::Pixel { sprite: sprite, idx: idx, before: after, after: before }
+            }
+            Change::Flags { sprite, before, after } => {
+                Change::Flags { sprite: sprite, before: after, after: before }
+            }
+            Change::Map { idx, before, after } => {
+                Change::Map { idx: idx, before: after, after: before }
+            }
+            Change::MapData { ref before, ref after } => {
+                Change::MapData { before: after.clone(), after: before.clone() }
+            }
+            Change::SoundProgram { ref sfx, position, before, after } => {
+                Change::SoundProgram {
+                    sfx: sfx.clone(),
+                    position: position,
+                    before: after,
+                    after: before,
+                }
+            }
+        }
+    }
+
+    /// Apply the sprite, flag and map changes to the screen, the sound
+    /// changes are left to the caller
+    pub fn apply(&self, screen: &mut Screen) {
+        match *self {
+            Change::Pixel { sprite, idx, after, .. } => {
+                if sprite < screen.sprites.len() && idx < 64 {
+                    screen.sprites[sprite].data[idx] = after;
+                }
+            }
+            Change::Flags { sprite, after, .. } => {
+                if sprite < screen.sprites.len() {
+                    screen.sprites[sprite].flags = after;
+                }
+            }
+            Change::Map { idx, after, .. } => {
+                if idx < screen.map.len() {
+                    screen.map[idx] = after;
+                }
+            }
+            Change::MapData { ref after, .. } => screen.map = after.clone(),
+            Change::SoundProgram { .. } => {}
+        }
+    }
+}
+
+/// Undo stack shared by the GFX, map and SFX editors
+///
+/// The changes are recorded in a pending operation until `commit`, so a
+/// whole stroke of the mouse is undone at once.
+#[derive(Clone)]
+pub struct History {
+    undo: Vec<Vec<Change>>,
+    redo: Vec<Vec<Change>>,
+    pending: Vec<Change>,
+    depth: usize,
+}
+
+impl History {
+    pub fn new(depth: usize) -> History {
+        History {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            pending: Vec::new(),
+            depth: depth,
+        }
+    }
+
+    /// Change the number of operations kept, the oldest are dropped
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        self.truncate();
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub fn record(&mut self, change: Change) {
+        self.pending.push(change);
+    }
+
+    /// Close the pending operation
+    pub fn commit(&mut self) {
+        if self.pending.is_empty() {
+            return;
+        }
+
+        let operation = ::std::mem::replace(&mut self.pending, Vec::new());
+        self.undo.push(operation);
+        self.redo.clear();
+        self.truncate();
+    }
+
+    /// Changes to apply to cancel the last operation
+    pub fn undo(&mut self) -> Option<Vec<Change>> {
+        self.commit();
+
+        match self.undo.pop() {
+            Some(operation) => {
+                let changes = operation.iter().rev().map(|change| change.reverse()).collect();
+                self.redo.push(operation);
+                Some(changes)
+            }
+            None => None,
+        }
+    }
+
+    /// Changes to apply to do again the last undone operation
+    pub fn redo(&mut self) -> Option<Vec<Change>> {
+        self.commit();
+
+        match self.redo.pop() {
+            Some(operation) => {
+                let changes = operation.clone();
+                self.undo.push(operation);
+                Some(changes)
+            }
+            None => None,
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo.is_empty() || !self.pending.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo.is_empty()
+    }
+
+    fn truncate(&mut self) {
+        if self.undo.len() > self.depth {
+            let extra = self.undo.len() - self.depth;
+            self.undo.drain(..extra);
+        }
+    }
+}
+
+/// Copy of the sprites and the map, the edits of the editors are found by
+/// comparing the screen with it, after the functions setting `Screen::edited`
+pub struct Snapshot {
+    pixels: Vec<[u8; 64]>,
+    flags: Vec<u8>,
+    map: Vec<u32>,
+}
+
+impl Snapshot {
+    pub fn new() -> Snapshot {
+        Snapshot {
+            pixels: Vec::new(),
+            flags: Vec::new(),
+            map: Vec::new(),
+        }
+    }
+
+    /// Take the current data of the screen, without any change
+    pub fn sync(&mut self, screen: &mut Screen) {
+        self.pixels = screen.sprites.iter().map(|sprite| sprite.data).collect();
+        self.flags = screen.sprites.iter().map(|sprite| sprite.flags).collect();
+        self.map = screen.map.clone();
+        screen.edited = false;
+    }
+
+    /// Changes of the screen since the last call, the snapshot follows them
+    pub fn update(&mut self, screen: &mut Screen) -> Vec<Change> {
+        let mut changes = Vec::new();
+
+        if !screen.edited {
+            return changes;
+        }
+        screen.edited = false;
+
+        if self.pixels.len() != screen.sprites.len() {
+            // Not an edit, the sprites of another cartridge
+            self.pixels = screen.sprites.iter().map(|sprite| sprite.data).collect();
+            self.flags = screen.sprites.iter().map(|sprite| sprite.flags).collect();
+        }
+
+        for (sprite, data) in screen.sprites.iter().enumerate() {
+            if self.pixels[sprite] != data.data {
+                for idx in 0..64 {
+                    let before = self.pixels[sprite][idx];
+                    if before != data.data[idx] {
+                        changes.push(Change::Pixel {
+                            sprite: sprite,
+                            idx: idx,
+                            before: before,
+                            after: data.data[idx],
+                        });
+                    }
+                }
+                self.pixels[sprite] = data.data;
+            }
+
+            if self.flags[sprite] != data.flags {
+                changes.push(Change::Flags {
+                    sprite: sprite,
+                    before: self.flags[sprite],
+                    after: data.flags,
+                });
+                self.flags[sprite] = data.flags;
+            }
+        }
+
+        // A short map is resized by `mset` and `autotile`
+        if self.map.len() != screen.map.len() {
+            let before = ::std::mem::replace(&mut self.map, screen.map.clone());
+            changes.push(Change::MapData {
+                before: before,
+                after: screen.map.clone(),
+            });
+            return changes;
+        }
+
+        for (idx, value) in screen.map.iter().enumerate() {
+            if self.map[idx] != *value {
+                changes.push(Change::Map {
+                    idx: idx,
+                    before: self.map[idx],
+                    after: *value,
+                });
+                self.map[idx] = *value;
+            }
+        }
+
+        changes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use unicorn;
+
+    use super::*;
+
+    fn pixel(idx: usize, before: u8, after: u8) -> Change {
+        Change::Pixel { sprite: 0, idx: idx, before: before, after: after }
+    }
+
+    #[test]
+    fn undo_redo_operations() {
+        let mut history = History::new(10);
+        assert_eq!(history.undo(), None);
+
+        history.record(pixel(0, 0, 1));
+        history.record(pixel(1, 0, 2));
+        history.commit();
+        history.record(Change::Map { idx: 3, before: 4, after: 5 });
+
+        // The pending change is an operation of its own
+        assert_eq!(history.undo(), Some(vec![Change::Map { idx: 3, before: 5, after: 4 }]));
+        assert_eq!(history.undo(), Some(vec![pixel(1, 2, 0), pixel(0, 1, 0)]));
+        assert!(!history.can_undo());
+
+        assert_eq!(history.redo(), Some(vec![pixel(0, 0, 1), pixel(1, 0, 2)]));
+        assert!(history.can_redo());
+
+        // A new operation forgets the undone ones
+        history.record(pixel(2, 0, 3));
+        history.commit();
+        assert!(!history.can_redo());
+        assert_eq!(history.redo(), None);
+    }
+
+    #[test]
+    fn snapshot_of_the_edits() {
+        let mut screen = Screen::new(64, 64);
+        screen.map = vec![0; 10];
+
+        let mut snapshot = Snapshot::new();
+        snapshot.sync(&mut screen);
+
+        // Only compared after an edit
+        screen.map[1] = 5;
+        assert_eq!(snapshot.update(&mut screen), vec![]);
+        screen.edited = true;
+        assert_eq!(snapshot.update(&mut screen), vec![Change::Map { idx: 1, before: 0, after: 5 }]);
+        assert!(!screen.edited);
+
+        // The short map is resized with the edit
+        screen.mset(2, 1, 7);
+        let changes = snapshot.update(&mut screen);
+        let mut after = vec![0; unicorn::MAP_WIDTH * unicorn::MAP_HEIGHT];
+        after[1] = 5;
+        after[2 + unicorn::MAP_WIDTH] = 7;
+        assert_eq!(changes,
+                   vec![Change::MapData {
+                            before: {
+                                let mut before = vec![0; 10];
+                                before[1] = 5;
+                                before
+                            },
+                            after: after,
+                        }]);
+
+        // Undone to the short map, like the editors
+        changes[0].reverse().apply(&mut screen);
+        snapshot.sync(&mut screen);
+        assert_eq!(screen.map.len(), 10);
+        assert_eq!(screen.map[1], 5);
+
+        screen.mset(3, 0, 1);
+        assert_eq!(snapshot.update(&mut screen).len(), 1);
+        screen.mset(3, 0, 2);
+        assert_eq!(snapshot.update(&mut screen), vec![Change::Map { idx: 3, before: 1, after: 2 }]);
+    }
+
+    #[test]
+    fn history_depth() {
+        let mut history = History::new(3);
+        for idx in 0..5 {
+            history.record(pixel(idx, 0, 1));
+            history.commit();
+        }
+
+        history.set_depth(2);
+        assert_eq!(history.undo(), Some(vec![pixel(4, 1, 0)]));
+        assert_eq!(history.undo(), Some(vec![pixel(3, 1, 0)]));
+        assert_eq!(history.undo(), None);
+    }
+}
@@ -1,9 +1,11 @@
 pub mod gfx_editor;
+pub mod history;
 pub mod music_editor;
 pub mod text_editor;
 
 use gfx::Screen;
 use config::Players;
+use config::scancode::Scancode;
 use std::sync::{Arc, Mutex};
 use std::collections::HashMap;
 use time;
@@ -12,6 +14,8 @@ use sound::sound::{SoundInternal, Sound};
 
 use unicorn::{UnicornCartridge, UnicornConfig, Palettes};
 
+use self::history::{Change, History, HISTORY_DEPTH};
+
 #[derive(Clone)]
 pub struct Button {
     x1: i32,
@@ -163,18 +167,22 @@ pub fn point_in_rect(x: i32,
     (coord_x1 <= x && x < coord_x2) && (coord_y1 <= y && y < coord_y2)
 }
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
 pub struct State {
     mouse_x: i32,
     mouse_y: i32,
     mouse_state: u32,
     mouse_statep: u32,
+    ctrl: bool,
 
     idx_sprites_batch_x: i32,
     idx_sprites_batch_y: i32,
     idx_sprite_info: [i32; 2],
     idx_flag: [i32; 2],
     current_sprite: u32,
+    /// Sprites selected in the sprite sheet from `current_sprite`, the
+    /// brush of the map editor
+    stamp: [u32; 2],
 
     x_zoom_sprite: u32,
     y_zoom_sprite: u32,
@@ -191,7 +199,7 @@ pub struct State {
     on_current_sprite_y: u32,
     on_current_sprite: bool,
 
-    fill_action: bool,
+    history: History,
 }
 
 impl State {
@@ -201,6 +209,7 @@ impl State {
             mouse_y: 0,
             mouse_state: 0,
             mouse_statep: 0,
+            ctrl: false,
 
             idx_sprites_batch_x: 0,
             idx_sprites_batch_y: 170,
@@ -208,6 +217,7 @@ impl State {
 
             idx_flag: [40, 160],
             current_sprite: 0,
+            stamp: [1, 1],
 
             x_zoom_sprite: 0,
             y_zoom_sprite: 0,
@@ -224,7 +234,7 @@ impl State {
             on_current_sprite_y: 0,
             on_current_sprite: false,
 
-            fill_action: false,
+            history: History::new(HISTORY_DEPTH),
         }
     }
 
@@ -233,6 +243,15 @@ impl State {
         self.mouse_statep = players.lock().unwrap().mouse_state_quick();
         self.mouse_x = players.lock().unwrap().mouse_coordinate(0);
         self.mouse_y = players.lock().unwrap().mouse_coordinate(1);
+
+        let players = players.lock().unwrap();
+        self.ctrl = players.akeys.get(&Scancode::LCtrl).cloned().unwrap_or(false) ||
+                    players.akeys.get(&Scancode::RCtrl).cloned().unwrap_or(false);
+
+        // A stroke of the mouse is one operation of the history
+        if self.mouse_statep == 0 {
+            self.history.commit();
+        }
     }
 }
 
@@ -287,6 +306,10 @@ impl Widget {
         self.clicked
     }
 
+    pub fn set_click(&mut self, clicked: bool) {
+        self.clicked = clicked;
+    }
+
     pub fn update(&mut self) {
         let mouse_state = self.state.lock().unwrap().mouse_state;
 
@@ -354,6 +377,19 @@ impl Widget {
     }
 }
 
+#[cfg(not(feature = "libksnd"))]
+fn apply_sound_change(_change: &Change, _cartridge: &mut UnicornCartridge, _sound_internal: Arc<Mutex<SoundInternal>>) {
+}
+
+#[cfg(feature = "libksnd")]
+fn apply_sound_change(change: &Change, cartridge: &mut UnicornCartridge, sound_internal: Arc<Mutex<SoundInternal>>) {
+    if let Change::SoundProgram { ref sfx, position, after, .. } = *change {
+        if let Some(sound) = cartridge.sound_tracks.get(sfx) {
+            sound_internal.lock().unwrap().player.set_sound_program(*sound, after, position);
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum STATE {
     GfxEditor,
@@ -463,16 +499,19 @@ impl Editor {
         }
     }
 
-    pub fn init(&mut self, config: Arc<Mutex<UnicornConfig>>, palettes: Arc<Mutex<Palettes>>, screen: &mut Screen, filename: String, code: String) {
+    pub fn init(&mut self, config: Arc<Mutex<UnicornConfig>>, palettes: Arc<Mutex<Palettes>>, screen: &mut Screen, filename: String, code: String, files: Vec<(String, String)>, language: String) {
         info!("[EDITOR] Init {:?}", filename);
         self.filename = filename.clone();
         config.lock().unwrap().toggle_mouse(true);
 
+        let history_depth = config.lock().unwrap().history_depth;
+        self.state.lock().unwrap().history.set_depth(history_depth);
+
         palettes.lock().unwrap().switch_to_palette("pico-8");
         screen.font("pico-8");
 
         self.gfx.init(config.clone(), screen);
-        self.txt.init(config.clone(), screen, filename.clone(), code);
+        self.txt.init(config.clone(), screen, filename.clone(), code, files, language);
         self.music.init(config.clone(), screen);
     }
 
@@ -480,7 +519,37 @@ impl Editor {
         self.txt.get_buffer()
     }
 
-    pub fn update(&mut self, cartridge: &mut UnicornCartridge, screen: &mut Screen, players: Arc<Mutex<Players>>, sound_internal: Arc<Mutex<SoundInternal>>, sound: Arc<Mutex<Sound>>) -> bool {
+    /// Lines of the other code files, by name
+    pub fn get_files(&mut self) -> Vec<(String, Vec<String>)> {
+        self.txt.get_files()
+    }
+
+    /// Show `file` in the text editor with the cursor on `line`, counted from 1
+    pub fn goto_line(&mut self, file: &str, line: usize) {
+        self.show_text_editor();
+        self.txt.goto_line(file, line);
+    }
+
+    /// Check the syntax of the code, the first error is shown in the text editor
+    pub fn check_syntax(&mut self) -> bool {
+        if self.txt.check_syntax() {
+            return true;
+        }
+
+        self.show_text_editor();
+        false
+    }
+
+    fn show_text_editor(&mut self) {
+        for widget in &self.widgets {
+            let mut widget = widget.lock().unwrap();
+            widget.clicked = widget.name == "TEXT";
+        }
+
+        self.state_editor = STATE::TextEditor;
+    }
+
+    pub fn update(&mut self, cartridge: &mut UnicornCartridge, screen: &mut Screen, players: Arc<Mutex<Players>>, palettes: Arc<Mutex<Palettes>>, sound_internal: Arc<Mutex<SoundInternal>>, sound: Arc<Mutex<Sound>>) -> bool {
         self.state.lock().unwrap().update(players.clone());
 
         let mut is_clickable = false;
@@ -510,8 +579,31 @@ impl Editor {
             }
         }
 
+        match self.state_editor {
+            STATE::GfxEditor | STATE::MusicEditor => {
+                let ctrl = self.state.lock().unwrap().ctrl;
+                if ctrl && players.lock().unwrap().btnp3(Scancode::Z) {
+                    let changes = self.state.lock().unwrap().history.undo();
+                    self.apply_changes(changes, cartridge, screen, sound_internal.clone());
+                } else if ctrl && players.lock().unwrap().btnp3(Scancode::Y) {
+                    let changes = self.state.lock().unwrap().history.redo();
+                    self.apply_changes(changes, cartridge, screen, sound_internal.clone());
+                }
+            }
+            STATE::TextEditor => {}
+        }
+
         match self.state_editor {
             STATE::GfxEditor => {
+                let ctrl = self.state.lock().unwrap().ctrl;
+                if ctrl && players.lock().unwrap().btnp3(Scancode::E) {
+                    let colors = palettes.lock().unwrap().colors();
+                    self.gfx.export(&self.filename, &colors, screen);
+                } else if ctrl && players.lock().unwrap().btnp3(Scancode::I) {
+                    let colors = palettes.lock().unwrap().colors();
+                    self.gfx.import(&self.filename, &colors, screen);
+                }
+
                 self.gfx.update(screen, players.clone());
             }
             STATE::TextEditor => {
@@ -525,6 +617,28 @@ impl Editor {
         true
     }
 
+    /// Apply the changes of an undo or a redo
+    fn apply_changes(&mut self, changes: Option<Vec<Change>>, cartridge: &mut UnicornCartridge, screen: &mut Screen, sound_internal: Arc<Mutex<SoundInternal>>) {
+        let changes = match changes {
+            Some(changes) => changes,
+            None => return,
+        };
+
+        info!("[EDITOR] Apply {:?} changes of the history", changes.len());
+
+        for change in &changes {
+            match *change {
+                Change::SoundProgram { .. } => {
+                    apply_sound_change(change, cartridge, sound_internal.clone());
+                }
+                _ => change.apply(screen),
+            }
+        }
+
+        // The editors must not see these changes as new edits
+        self.gfx.sync(screen);
+    }
+
     pub fn draw(&mut self, players: Arc<Mutex<Players>>, palettes: Arc<Mutex<Palettes>>, screen: &mut Screen) -> f64 {
         let current_time = time::now();
 
@@ -551,6 +665,10 @@ impl Editor {
         // Print current filename
         screen.print(self.filename.clone(), 0, 2, 7);
 
+        if let STATE::TextEditor = self.state_editor {
+            self.txt.draw_tabs(screen);
+        }
+
         for widget in &self.widgets {
             widget.lock().unwrap().draw(screen);
         }
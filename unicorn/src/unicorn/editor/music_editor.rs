@@ -12,6 +12,8 @@ use config::scancode::Scancode;
 
 use sound::sound::{SoundInternal, Sound};
 use unicorn::editor::{Button, ButtonSlider};
+#[cfg(feature = "libksnd")]
+use unicorn::editor::history::Change;
 use chiptune::chiptune;
 
 static KEYS_NOTE: [Scancode; 29] = [
@@ -493,6 +495,7 @@ impl TrackEditor {
 }
 
 pub struct SFXEditor {
+    state: Arc<Mutex<State>>,
     idx_sfx: u32,
     num_sfx: u32,
     name: String,
@@ -514,6 +517,7 @@ pub struct SFXEditor {
 impl SFXEditor {
     pub fn new(state: Arc<Mutex<State>>) -> SFXEditor {
         SFXEditor {
+            state: state,
             idx_sfx: 0,
             num_sfx: 0,
             name: "".to_string(),
@@ -664,10 +668,22 @@ impl SFXEditor {
 
         let mut define_value = false;
 
+        let program = sound_internal.player.get_sound_program(current_sfx);
+
         let mut idx_prog = 0;
         for value in self.p_values.iter_mut() {
             if value.higlight() {
-                sound_internal.player.set_sound_program(current_sfx, value.get(), idx_prog);
+                let after = value.get();
+                if program[idx_prog as usize] != after {
+                    self.state.lock().unwrap().history.record(Change::SoundProgram {
+                        sfx: self.name.clone(),
+                        position: idx_prog,
+                        before: program[idx_prog as usize],
+                        after: after,
+                    });
+                }
+
+                sound_internal.player.set_sound_program(current_sfx, after, idx_prog);
                 define_value = true;
             }
             idx_prog += 1;
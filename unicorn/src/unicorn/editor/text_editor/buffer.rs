@@ -579,6 +579,16 @@ impl Buffer {
         }
     }
 
+    /// Keep `mark` in the text, with its line updated, after changes made
+    /// around another mark
+    pub fn refresh_mark(&mut self, mark: Mark) {
+        let absolute = self.marks.get(&mark).map(|mark_pos| mark_pos.absolute);
+        if let Some(absolute) = absolute {
+            let idx = cmp::min(absolute, self.text.len());
+            self.set_mark(mark, idx);
+        }
+    }
+
     // Remove the chars in the range from start to end
     pub fn remove_range(&mut self, start: usize, end: usize) -> Option<Vec<u8>> {
         self.dirty = true;
@@ -594,6 +604,34 @@ impl Buffer {
         Some(vec)
     }
 
+    /// Replace the ranges, sorted by start, with their data, undone at once
+    pub fn replace_ranges(&mut self, ranges: &[(usize, usize, Vec<u8>)]) {
+        let start = match ranges.first() {
+            Some(&(start, _, _)) => start,
+            None => return,
+        };
+
+        self.dirty = true;
+        let text = &mut self.text;
+        let mut transaction = self.log.start(start);
+        for &(start, end, ref data) in ranges.iter().rev() {
+            for idx in (start..end).rev() {
+                if let Some(ch) = text.remove(idx) {
+                    transaction.log(Change::Remove(idx, ch), idx);
+                }
+            }
+            for (offset, ch) in data.iter().enumerate() {
+                text.insert(start + offset, *ch);
+                transaction.log(Change::Insert(start + offset, *ch), start);
+            }
+        }
+    }
+
+    /// Whole text of the buffer
+    pub fn text(&self) -> Vec<u8> {
+        (0..self.text.len()).map(|idx| self.text[idx]).collect()
+    }
+
     // Remove the chars between mark and object
     pub fn remove_from_mark_to_object(&mut self, mark: Mark, object: TextObject) -> Option<Vec<u8>> {
 
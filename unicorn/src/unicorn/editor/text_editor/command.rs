@@ -4,6 +4,7 @@ use unicorn::editor::text_editor::buffer::Mark;
 use unicorn::editor::text_editor::textobject::{ TextObject, Offset, Kind, Anchor };
 use unicorn::editor::text_editor::overlay::OverlayType;
 use unicorn::editor::text_editor::modes::ModeType;
+use unicorn::editor::text_editor::view::Split;
 
 /// Instructions for the Editor.
 /// These do NOT alter the text, but may change editor/view state
@@ -18,6 +19,16 @@ pub enum Instruction {
     SetMode(ModeType),
     ShowMessage(&'static str),
     SwitchToLastBuffer,
+    /// Move to the next match of the last search, `true` in the other direction
+    SearchNext(bool),
+    /// Move the cursor to a line counted from 1, the last one for 0
+    GotoLine(usize),
+    /// Repeat the last change of the Vi mode
+    Repeat,
+    /// Show two views of the buffers, or a single one for None
+    SplitView(Option<Split>),
+    /// Move the focus to the other view of the split
+    SwitchView,
     None,
 }
 
@@ -34,6 +45,20 @@ pub enum Operation {
 
     Undo,         // rewind buffer transaction log
     Redo,         // replay buffer transaction log
+
+    Apply(Operator),                // apply an operator from the cursor to some object
+    ApplyLines(Operator, usize),    // apply an operator to some lines from the cursor
+    ApplySelection(Operator),       // apply an operator to the visual selection
+    Paste(Anchor),                  // insert the register before/after the cursor
+    OpenLine(Anchor),               // insert a line before/after the cursor line
+}
+
+/// Operators of the Vi mode, the text goes to the register
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Operator {
+    Delete,
+    Change,
+    Yank,
 }
 
 /// Fragments that can be combined to specify a command
@@ -96,6 +121,60 @@ impl Command {
         }
     }
 
+    /// Shortcut to create a SetMode command, moving the cursor to `object` first
+    pub fn set_mode_at(mode: ModeType, object: TextObject) -> Command {
+        Command {
+            action: Action::Instruction(Instruction::SetMode(mode)),
+            number: 0,
+            object: Some(object),
+        }
+    }
+
+    /// Shortcut to create an Apply command
+    pub fn apply(operator: Operator, object: TextObject) -> Command {
+        Command {
+            number: 1,
+            action: Action::Operation(Operation::Apply(operator)),
+            object: Some(object),
+        }
+    }
+
+    /// Shortcut to create an ApplyLines command
+    pub fn apply_lines(operator: Operator, count: usize) -> Command {
+        Command {
+            number: 1,
+            action: Action::Operation(Operation::ApplyLines(operator, count)),
+            object: None,
+        }
+    }
+
+    /// Shortcut to create an ApplySelection command
+    pub fn apply_selection(operator: Operator) -> Command {
+        Command {
+            number: 1,
+            action: Action::Operation(Operation::ApplySelection(operator)),
+            object: None,
+        }
+    }
+
+    /// Shortcut to create a SetOverlay command
+    pub fn set_overlay(overlay_type: OverlayType) -> Command {
+        Command {
+            action: Action::Instruction(Instruction::SetOverlay(overlay_type)),
+            number: 0,
+            object: None,
+        }
+    }
+
+    /// Shortcut to create a SearchNext command
+    pub fn search_next(reverse: bool) -> Command {
+        Command {
+            action: Action::Instruction(Instruction::SearchNext(reverse)),
+            number: 0,
+            object: None,
+        }
+    }
+
     /// Shortcut to create an Insert command
     pub fn insert_char(c: char) -> Command {
         Command {
@@ -144,6 +223,42 @@ impl Command {
         }
     }
 
+    /// Shortcut to create a GotoLine command
+    pub fn goto_line(line: usize) -> Command {
+        Command {
+            number: 0,
+            action: Action::Instruction(Instruction::GotoLine(line)),
+            object: None,
+        }
+    }
+
+    /// Shortcut to create a Repeat command
+    pub fn repeat() -> Command {
+        Command {
+            number: 0,
+            action: Action::Instruction(Instruction::Repeat),
+            object: None,
+        }
+    }
+
+    /// Shortcut to create a SplitView command
+    pub fn split_view(split: Option<Split>) -> Command {
+        Command {
+            number: 0,
+            action: Action::Instruction(Instruction::SplitView(split)),
+            object: None,
+        }
+    }
+
+    /// Shortcut to create a SwitchView command
+    pub fn switch_view() -> Command {
+        Command {
+            number: 0,
+            action: Action::Instruction(Instruction::SwitchView),
+            object: None,
+        }
+    }
+
     pub fn noop() -> Command {
         Command {
             number: 0,
@@ -0,0 +1,227 @@
+//! Completion of the identifiers, and signature hints of the API functions.
+//!
+//! The candidates are the API functions of the cartridge language, from the
+//! binding tables of `plugins::api`, and the identifiers of the code files.
+
+/// Shortest word completed
+const MIN_WORD: usize = 2;
+
+/// Candidates shown at once
+pub const MAX_CANDIDATES: usize = 8;
+
+/// Longest part of the text searched for the call around the cursor
+const MAX_CALL: usize = 1024;
+
+fn is_identifier(ch: u8) -> bool {
+    (ch as char).is_alphanumeric() || ch == b'_'
+}
+
+/// Identifier ending at `idx`
+pub fn word_before(text: &[u8], idx: usize) -> String {
+    let idx = ::std::cmp::min(idx, text.len());
+    let start = text[..idx]
+        .iter()
+        .rposition(|ch| !is_identifier(*ch))
+        .map_or(0, |pos| pos + 1);
+    String::from_utf8_lossy(&text[start..idx]).into_owned()
+}
+
+/// Identifiers of the text, without the numbers
+pub fn identifiers(text: &[u8]) -> Vec<String> {
+    let mut words: Vec<String> = text.split(|ch| !is_identifier(*ch))
+        .filter(|word| word.len() > MIN_WORD && !(word[0] as char).is_digit(10))
+        .map(|word| String::from_utf8_lossy(word).into_owned())
+        .collect();
+    words.sort();
+    words.dedup();
+    words
+}
+
+/// Name of the function called around `idx`, and the index of the argument
+/// at `idx`
+pub fn enclosing_call(text: &[u8], idx: usize) -> Option<(String, usize)> {
+    let idx = ::std::cmp::min(idx, text.len());
+    let first = idx.saturating_sub(MAX_CALL);
+    let mut depth = 0;
+    let mut argument = 0;
+
+    for pos in (first..idx).rev() {
+        match text[pos] {
+            b')' | b']' | b'}' => depth += 1,
+            b'[' | b'{' => {
+                if depth == 0 {
+                    return None;
+                }
+                depth -= 1;
+            }
+            b'(' => {
+                if depth == 0 {
+                    let end = text[..pos]
+                        .iter()
+                        .rposition(|ch| *ch != b' ')
+                        .map_or(0, |end| end + 1);
+                    let name = word_before(text, end);
+                    return if name.is_empty() { None } else { Some((name, argument)) };
+                }
+                depth -= 1;
+            }
+            b',' if depth == 0 => argument += 1,
+            b';' => return None,
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Completion popup of the code editor
+pub struct Completion {
+    /// Name and signature of the API functions
+    api: Vec<(String, String)>,
+    /// Candidates for the word before the cursor
+    pub candidates: Vec<String>,
+    pub selected: usize,
+    /// Word being completed
+    word: String,
+}
+
+impl Completion {
+    pub fn new() -> Completion {
+        Completion {
+            api: Vec::new(),
+            candidates: Vec::new(),
+            selected: 0,
+            word: String::new(),
+        }
+    }
+
+    pub fn set_api(&mut self, api: Vec<(String, String)>) {
+        self.api = api;
+        self.close();
+    }
+
+    pub fn is_open(&self) -> bool {
+        !self.candidates.is_empty()
+    }
+
+    pub fn close(&mut self) {
+        self.candidates.clear();
+        self.selected = 0;
+        self.word.clear();
+    }
+
+    /// Candidates starting with `word`, from the API then from the code
+    pub fn update(&mut self, word: &str, texts: &[Vec<u8>]) {
+        self.close();
+        if word.len() < MIN_WORD {
+            return;
+        }
+
+        let mut candidates: Vec<String> = self.api
+            .iter()
+            .map(|&(ref name, _)| name.clone())
+            .filter(|name| name.starts_with(word) && name != word)
+            .collect();
+
+        for text in texts {
+            for identifier in identifiers(text) {
+                if identifier.starts_with(word) && identifier != word &&
+                   !candidates.contains(&identifier) {
+                    candidates.push(identifier);
+                }
+            }
+        }
+
+        candidates.truncate(MAX_CANDIDATES);
+        self.candidates = candidates;
+        self.word = word.to_string();
+    }
+
+    pub fn select(&mut self, delta: isize) {
+        let len = self.candidates.len() as isize;
+        if len > 0 {
+            self.selected = (((self.selected as isize + delta) % len + len) % len) as usize;
+        }
+    }
+
+    /// Rest of the selected candidate, after the word, and close the popup
+    pub fn accept(&mut self) -> Option<String> {
+        let rest = self.candidates
+            .get(self.selected)
+            .map(|candidate| candidate[self.word.len()..].to_string());
+        self.close();
+        rest
+    }
+
+    /// Signature of the API function called around `idx`, and the index of the
+    /// argument at `idx`
+    pub fn signature(&self, text: &[u8], idx: usize) -> Option<(&str, usize)> {
+        let (name, argument) = match enclosing_call(text, idx) {
+            Some(call) => call,
+            None => return None,
+        };
+
+        self.api
+            .iter()
+            .find(|&&(ref function, _)| *function == name)
+            .map(|&(_, ref signature)| (signature.as_str(), argument))
+    }
+}
+
+/// Bounds of the argument `argument` in `signature`, to highlight it
+pub fn argument_bounds(signature: &str, argument: usize) -> Option<(usize, usize)> {
+    let open = match signature.find('(') {
+        Some(open) => open + 1,
+        None => return None,
+    };
+    let close = signature.rfind(')').unwrap_or(signature.len());
+
+    let mut start = open;
+    for (idx, arg) in signature[open..close].split(", ").enumerate() {
+        if idx == argument && !arg.is_empty() {
+            return Some((start, start + arg.len()));
+        }
+        start += arg.len() + 2;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_call() {
+        let text = b"spr(n, flr(x / 8), ";
+        assert_eq!(enclosing_call(text, text.len()), Some(("spr".to_string(), 2)));
+        assert_eq!(enclosing_call(text, 13), Some(("flr".to_string(), 0)));
+        assert_eq!(enclosing_call(b"x = 1", 5), None);
+        assert_eq!(enclosing_call(b"print (", 7), Some(("print".to_string(), 0)));
+        assert_eq!(enclosing_call(b"t = {1, ", 8), None);
+    }
+
+    #[test]
+    fn complete_words() {
+        let mut completion = Completion::new();
+        completion.set_api(vec![("spr".to_string(), "spr(n: int, x: int, y: int)".to_string()),
+                                ("sspr".to_string(), "sspr()".to_string())]);
+
+        let texts = vec![b"sprite_x = 1\nsprite_y = sp".to_vec()];
+        assert_eq!(word_before(&texts[0], texts[0].len()), "sp");
+
+        completion.update("sp", &texts);
+        assert_eq!(completion.candidates, vec!["spr", "sprite_x", "sprite_y"]);
+
+        completion.select(-1);
+        assert_eq!(completion.accept(), Some("rite_y".to_string()));
+        assert!(!completion.is_open());
+
+        completion.update("s", &texts);
+        assert!(!completion.is_open());
+
+        assert_eq!(completion.signature(b"spr(1, ", 7),
+                   Some(("spr(n: int, x: int, y: int)", 1)));
+        assert_eq!(argument_bounds("spr(n: int, x: int, y: int)", 1), Some((12, 18)));
+    }
+}
@@ -0,0 +1,148 @@
+//! Syntax check of the code files, compiled without running them by the plugin
+//! of the cartridge language, in the background after each pause in typing.
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use plugins::error::ScriptError;
+use plugins::javascript_plugin;
+use plugins::lua_plugin;
+use plugins::python_plugin;
+
+/// Pause in typing before the check of the code
+const PAUSE_MS: u64 = 500;
+
+/// Syntax error of `code`, compiled by the plugin of `language`
+pub fn check_syntax(language: &str, filename: &str, code: &str) -> Result<(), ScriptError> {
+    match language {
+        "lua" => lua_plugin::plugin::check_syntax(filename, code),
+        "python" => python_plugin::plugin::check_syntax(filename, code),
+        "javascript" => javascript_plugin::plugin::check_syntax(filename, code),
+        _ => Ok(()),
+    }
+}
+
+/// Code file to check: its index, language, name and code
+type Request = (usize, String, String, String);
+
+pub struct Diagnostics {
+    requests: Sender<Request>,
+    results: Receiver<(usize, Option<ScriptError>)>,
+    language: String,
+    /// Name, last code checked and its error, by code file
+    files: Vec<(String, String, Option<ScriptError>)>,
+    /// Time of the last key typed, until the next check
+    typed: Option<Instant>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        let (requests, receiver) = channel::<Request>();
+        let (sender, results) = channel();
+
+        thread::spawn(move || {
+            for (idx, language, filename, code) in receiver {
+                let error = check_syntax(&language, &filename, &code).err();
+                if sender.send((idx, error)).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Diagnostics {
+            requests: requests,
+            results: results,
+            language: String::new(),
+            files: Vec::new(),
+            typed: None,
+        }
+    }
+
+    /// Code files named `names` of a cartridge in `language`
+    pub fn reset(&mut self, language: &str, names: Vec<String>) {
+        self.language = language.to_string();
+        self.files = names.into_iter().map(|name| (name, String::new(), None)).collect();
+        self.typed = None;
+    }
+
+    pub fn typed(&mut self) {
+        self.typed = Some(Instant::now());
+    }
+
+    /// The code stopped changing since the last key
+    pub fn is_due(&self) -> bool {
+        self.typed.map_or(false, |typed| typed.elapsed() >= Duration::from_millis(PAUSE_MS))
+    }
+
+    /// Check the code file `idx` in the background, unless it is unchanged
+    pub fn check(&mut self, idx: usize, code: String) {
+        self.typed = None;
+
+        let request = match self.files.get_mut(idx) {
+            Some(file) => {
+                if file.1 == code {
+                    return;
+                }
+                file.1 = code.clone();
+                (idx, self.language.clone(), file.0.clone(), code)
+            }
+            None => return,
+        };
+
+        if self.requests.send(request).is_err() {
+            warn!("[EDITOR][TXT] The syntax check is stopped");
+        }
+    }
+
+    /// Check the code file `idx` now
+    pub fn check_now(&mut self, idx: usize, code: String) -> Option<&ScriptError> {
+        let error = match self.files.get(idx) {
+            Some(&(ref name, _, _)) => check_syntax(&self.language, name, &code).err(),
+            None => return None,
+        };
+
+        self.files[idx].1 = code;
+        self.files[idx].2 = error;
+        self.files[idx].2.as_ref()
+    }
+
+    /// Keep the results of the background checks
+    pub fn poll(&mut self) {
+        while let Ok((idx, error)) = self.results.try_recv() {
+            if let Some(file) = self.files.get_mut(idx) {
+                file.2 = error;
+            }
+        }
+    }
+
+    pub fn error(&self, idx: usize) -> Option<&ScriptError> {
+        self.files.get(idx).and_then(|file| file.2.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[cfg(feature = "unicorn_plugin_lua")]
+    fn lua_syntax_error() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.reset("lua", vec!["main".to_string(), "enemies.lua".to_string()]);
+
+        assert!(diagnostics.check_now(0, "x = 1\n".to_string()).is_none());
+        let error = diagnostics.check_now(1, "x = 1\ny = = 2\n".to_string()).cloned().unwrap();
+        assert_eq!(error.file, Some("enemies.lua".to_string()));
+        assert_eq!(error.line, Some(2));
+    }
+
+    #[test]
+    fn unknown_language() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.reset("wasm", vec!["main".to_string()]);
+
+        assert!(diagnostics.check_now(0, "(module".to_string()).is_none());
+        assert!(diagnostics.error(1).is_none());
+    }
+}
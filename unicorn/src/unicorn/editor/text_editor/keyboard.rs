@@ -28,8 +28,28 @@ impl Key {
             Scancode::Return => Some(Key::Enter),
             Scancode::KpEnter => Some(Key::Enter),
             Scancode::Tab => Some(Key::Tab),
+            Scancode::Escape => Some(Key::Esc),
 
             _ => None,
         }
     }
+
+    /// Key pressed with Ctrl held, the letters are `Key::Ctrl`
+    pub fn from_ctrl_code(scancode: Scancode) -> Option<Key> {
+        // The frontend gives Ctrl+C/V/X as the clipboard keys
+        match scancode {
+            Scancode::Copy => return Some(Key::Ctrl('c')),
+            Scancode::Paste => return Some(Key::Ctrl('v')),
+            Scancode::Cut => return Some(Key::Ctrl('x')),
+            _ => {}
+        }
+
+        let mut chars = scancode.name().chars();
+        match (chars.next(), chars.next()) {
+            (Some(c), None) if c.is_alphabetic() => {
+                Some(Key::Ctrl(c.to_lowercase().next().unwrap_or(c)))
+            }
+            _ => Key::from_special_code(scancode),
+        }
+    }
 }
\ No newline at end of file
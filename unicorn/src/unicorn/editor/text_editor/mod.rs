@@ -1,14 +1,17 @@
 mod input;
 mod utils;
-mod buffer;
+pub mod buffer;
 mod keyboard;
 mod keymap;
-mod view;
+pub mod view;
 mod log;
 mod modes;
 mod overlay;
-mod command;
-mod textobject;
+mod search;
+mod completion;
+mod diagnostics;
+pub mod command;
+pub mod textobject;
 mod iterators;
 
 use std::path::PathBuf;
@@ -18,13 +21,16 @@ use std::sync::mpsc::channel;
 use std::env;
 use std::rc::Rc;
 use std::char;
+use std::mem;
 
 
 use syntect::highlighting::ThemeSet;
 use syntect::parsing::SyntaxSet;
 
+use plugins::api;
+use plugins::error::CODE_FILENAME;
 use unicorn::UnicornConfig;
-use unicorn::editor::State;
+use unicorn::editor::{State, point_in_rect};
 use config::scancode::Scancode;
 
 use gfx::Screen;
@@ -32,17 +38,27 @@ use config::Players;
 
 use unicorn::editor::text_editor::input::Input;
 use unicorn::editor::text_editor::keyboard::Key;
-use unicorn::editor::text_editor::view::View;
-use unicorn::editor::text_editor::modes::{Mode, ModeType, InsertMode, NormalMode, StandardMode};
-use unicorn::editor::text_editor::overlay::{Overlay, OverlayEvent};
-use unicorn::editor::text_editor::buffer::Buffer;
+use unicorn::editor::text_editor::view::{Split, View, GUTTER};
+use unicorn::editor::text_editor::modes::{Mode, ModeType, InsertMode, NormalMode, StandardMode, VisualMode};
+use unicorn::editor::text_editor::overlay::{Overlay, OverlayEvent, OverlayType};
+use unicorn::editor::text_editor::diagnostics::Diagnostics;
+use unicorn::editor::text_editor::search::Replace;
+use unicorn::editor::text_editor::buffer::{Buffer, Mark};
 use unicorn::editor::text_editor::command::Command;
-use unicorn::editor::text_editor::command::{Action, BuilderEvent, Operation, Instruction};
+use unicorn::editor::text_editor::command::{Action, BuilderEvent, Operation, Operator, Instruction};
 use unicorn::Palettes;
 
 
+/// Position of the file tabs, in the top bar of the editor
+const TABS_Y: i32 = 9;
+
 pub struct TextEditor {
     editor: Editor,
+    state: Arc<Mutex<State>>,
+    /// Names of the main code then the code files, the first buffers
+    files: Vec<String>,
+    /// Language of the code, for the extension of the new files
+    language: String,
 }
 
 impl TextEditor {
@@ -53,6 +69,9 @@ impl TextEditor {
             editor: Editor::new(Input::Code("".to_string()),
                                 Box::new(StandardMode::new()),
                                 options),
+            state: state,
+            files: Vec::new(),
+            language: String::new(),
         }
     }
 
@@ -60,17 +79,156 @@ impl TextEditor {
                 config: Arc<Mutex<UnicornConfig>>,
                 screen: &mut Screen,
                 filename: String,
-                code: String) {
-        info!("[EDITOR][TXT] Init");
+                code: String,
+                files: Vec<(String, String)>,
+                language: String) {
+        info!("[EDITOR][TXT] Init {:?}", language);
+
+        let mut codes = vec![(CODE_FILENAME.to_string(), code)];
+        codes.extend(files);
+        self.files = codes.iter().map(|&(ref name, _)| name.clone()).collect();
 
         self.editor.reset();
-        self.editor.set_buffer(filename.clone(), code.clone());
+        self.editor.set_buffers(codes);
+        self.editor.set_completions(api::signatures(&language));
+        self.editor.set_diagnostics(&language, self.files.clone());
+        self.language = language;
+    }
+
+    /// Check the syntax of all the code files, the first error is shown
+    pub fn check_syntax(&mut self) -> bool {
+        match self.editor.check_syntax() {
+            Some((idx, line)) => {
+                self.switch_file(idx);
+                if let Some(line) = line {
+                    self.editor.goto_line(line);
+                }
+                self.editor.show_message("Syntax error, Shift+F6 to run anyway");
+                false
+            }
+            None => true,
+        }
+    }
+
+    /// Horizontal bounds of the tabs, one by buffer
+    fn tabs(&self) -> Vec<(i32, i32)> {
+        let mut x = 0;
+        self.editor
+            .names()
+            .iter()
+            .map(|name| {
+                     // 4 pixels by character with the pico-8 font
+                     let x1 = x;
+                     x += (name.len() as i32) * 4 + 4;
+                     (x1, x - 2)
+                 })
+            .collect()
+    }
+
+    /// Horizontal bounds of the buttons adding a code file and deleting the
+    /// current one, after the tabs
+    fn buttons(&self) -> ((i32, i32), (i32, i32)) {
+        let x = self.tabs().last().map_or(0, |&(_, x2)| x2 + 4);
+        ((x, x + 5), (x + 8, x + 13))
+    }
+
+    /// Index of the current buffer, when it is a code file other than the main code
+    fn current_code_file(&self) -> Option<usize> {
+        match self.editor.current_buffer() {
+            Some(idx) if idx > 0 && idx < self.files.len() => Some(idx),
+            _ => None,
+        }
+    }
+
+    /// Add an empty code file, shown in a new tab
+    fn new_file(&mut self) {
+        let extension = match self.language.as_str() {
+            "python" => "py",
+            "javascript" => "js",
+            _ => "lua",
+        };
+        let name = (1..)
+            .map(|n| format!("module{}.{}", n, extension))
+            .find(|name| !self.files.contains(name))
+            .unwrap();
+        info!("[EDITOR][TXT] New file {:?}", name);
+
+        let idx = self.files.len();
+        self.files.push(name.clone());
+        self.editor.insert_buffer(idx, name, String::new());
+        self.editor.set_diagnostics(&self.language, self.files.clone());
+        self.switch_file(idx);
+    }
+
+    /// Ask to delete the code file of the current tab, the main code is kept
+    fn delete_file(&mut self) {
+        if let Some(idx) = self.current_code_file() {
+            self.editor.confirm_delete(idx);
+        }
+    }
+
+    /// Delete the code file `idx`, once confirmed
+    fn remove_file(&mut self, idx: usize) {
+        if idx == 0 || idx >= self.files.len() {
+            return;
+        }
+        info!("[EDITOR][TXT] Delete file {:?}", self.files[idx]);
+
+        self.files.remove(idx);
+        self.editor.remove_buffer(idx);
+        self.editor.set_diagnostics(&self.language, self.files.clone());
     }
 
     pub fn update(&mut self, players: Arc<Mutex<Players>>) -> bool {
+        if let Some(idx) = self.editor.take_deleted() {
+            self.remove_file(idx);
+        }
+
+        let (mouse_statep, mouse_x, mouse_y) = {
+            let state = self.state.lock().unwrap();
+            (state.mouse_statep, state.mouse_x, state.mouse_y)
+        };
+
+        if mouse_statep == 1 {
+            let clicked = self.tabs()
+                .iter()
+                .position(|&(x1, x2)| point_in_rect(mouse_x, mouse_y, x1, TABS_Y - 1, x2, TABS_Y + 6));
+            let (new, delete) = self.buttons();
+            if let Some(idx) = clicked {
+                self.switch_file(idx);
+            } else if point_in_rect(mouse_x, mouse_y, new.0, TABS_Y - 1, new.1, TABS_Y + 6) {
+                self.new_file();
+            } else if point_in_rect(mouse_x, mouse_y, delete.0, TABS_Y - 1, delete.1, TABS_Y + 6) {
+                self.delete_file();
+            }
+        }
+
         true
     }
 
+    fn switch_file(&mut self, idx: usize) {
+        self.editor.switch_buffer(idx);
+    }
+
+    /// Draw the tabs of the buffers in the top bar
+    pub fn draw_tabs(&mut self, screen: &mut Screen) {
+        let current = self.editor.current_buffer();
+        for (idx, (x1, x2)) in self.tabs().into_iter().enumerate() {
+            let name = self.editor.names()[idx].clone();
+            if Some(idx) == current {
+                screen.rectfill(x1, TABS_Y - 1, x2, TABS_Y + 5, 7);
+                screen.print(name, x1 + 1, TABS_Y, 11);
+            } else {
+                screen.print(name, x1 + 1, TABS_Y, 7);
+            }
+        }
+
+        let (new, delete) = self.buttons();
+        screen.print("+".to_string(), new.0 + 1, TABS_Y, 11);
+        let color = if self.current_code_file().is_some() { 8 } else { 5 };
+        screen.print("x".to_string(), delete.0 + 1, TABS_Y, color);
+    }
+
     pub fn draw(&mut self,
                 players: Arc<Mutex<Players>>,
                 palettes: Arc<Mutex<Palettes>>,
@@ -78,8 +236,27 @@ impl TextEditor {
         self.editor.render(screen, palettes.clone(), players.clone());
     }
 
+    /// Lines of the main code
     pub fn get_buffer(&mut self) -> Vec<String> {
-        self.editor.get_buffer()
+        self.editor.get_buffer_at(0)
+    }
+
+    /// Lines of the code files, by name
+    pub fn get_files(&mut self) -> Vec<(String, Vec<String>)> {
+        let mut files = Vec::new();
+        for idx in 1..self.files.len() {
+            files.push((self.files[idx].clone(), self.editor.get_buffer_at(idx)));
+        }
+        files
+    }
+
+    /// Show `file`, with the cursor on `line` counted from 1
+    pub fn goto_line(&mut self, file: &str, line: usize) {
+        let idx = self.files.iter().position(|name| name == file);
+        if let Some(idx) = idx {
+            self.switch_file(idx);
+            self.editor.goto_line(line);
+        }
     }
 }
 
@@ -100,11 +277,33 @@ impl Default for Options {
 /// This is the top-most structure in Iota.
 pub struct Editor {
     buffers: Vec<Arc<Mutex<Buffer>>>,
+    /// Names of the buffers, shown in the tabs and the buffer list
+    names: Vec<String>,
+    /// Scratch and notes buffers, kept after the code files
+    notes: Vec<(String, Arc<Mutex<Buffer>>)>,
     view: View,
+    /// Second view of a split, it does not take the keys
+    other: Option<View>,
+    split: Option<Split>,
+    /// The focused view is the top or left one
+    focus_first: bool,
     running: bool,
     mode: Box<Mode>,
+    mode_type: ModeType,
     options: Options,
 
+    /// Buffer whose deletion was confirmed, removed by the text editor
+    deleted: Option<usize>,
+
+    /// Commands of the last change of the Vi mode, repeated by `.`
+    last_change: Vec<Command>,
+    /// The commands of the insert mode are added to `last_change`
+    recording: bool,
+    replaying: bool,
+
+    /// Syntax errors of the buffers
+    diagnostics: Diagnostics,
+
     command_queue: Receiver<Command>,
     command_sender: Sender<Command>,
 }
@@ -128,6 +327,14 @@ impl Editor {
         };
         buffers.push(Arc::new(Mutex::new(buffer)));
 
+        let notes = vec![(String::from("scratch"), Arc::new(Mutex::new(Buffer::new_with_syntax_raw(String::new(), &ps)))),
+                         (String::from("notes"), Arc::new(Mutex::new(Buffer::from(String::new()))))];
+        let mut names = vec![CODE_FILENAME.to_string()];
+        for &(ref name, ref buffer) in &notes {
+            names.push(name.clone());
+            buffers.push(buffer.clone());
+        }
+
         // NOTE: this will only work on linux
         // TODO: make this more cross-platform friendly
         let mut subl_config = env::home_dir().unwrap();
@@ -140,16 +347,31 @@ impl Editor {
             (String::from("base16-eighties.dark"), Rc::new(ThemeSet::load_defaults()))
         };
 
-        let view = View::new(buffers[0].clone(), ts.clone(), theme_name, 133, 34);
+        // Resized to the screen resolution when drawn
+        let (_, _, width, height) = view::layout(None, 400, 240)[0];
+        let view = View::new(buffers[0].clone(), ts.clone(), theme_name, width, height);
 
         Editor {
             buffers: buffers,
+            names: names,
+            notes: notes,
             view: view,
+            other: None,
+            split: None,
+            focus_first: true,
             running: true,
 
             mode: mode,
+            mode_type: ModeType::Standard,
             options: opts,
 
+            deleted: None,
+            last_change: Vec::new(),
+            recording: false,
+            replaying: false,
+
+            diagnostics: Diagnostics::new(),
+
             command_queue: recv,
             command_sender: snd,
         }
@@ -163,13 +385,220 @@ impl Editor {
         self.view.get_buffer()
     }
 
-    pub fn set_buffer(&mut self, filename: String, code: String) {
+    pub fn goto_line(&mut self, line: usize) {
+        self.view.move_cursor_to_line(line);
+    }
+
+    /// One buffer by code file, by name, then the scratch and notes buffers,
+    /// the first one is shown in a single view
+    pub fn set_buffers(&mut self, files: Vec<(String, String)>) {
+        let mut ps = SyntaxSet::load_defaults_nonewlines();
+        ps.link_syntaxes();
+
+        self.buffers.clear();
+        self.names.clear();
+        for (name, code) in files {
+            self.names.push(name);
+            self.buffers.push(Arc::new(Mutex::new(Buffer::new_with_syntax_raw(code, &ps))));
+        }
+        for &(ref name, ref buffer) in &self.notes {
+            self.names.push(name.clone());
+            self.buffers.push(buffer.clone());
+        }
+
+        self.split_view(None);
+        if let Some(buffer) = self.buffers.first().cloned() {
+            self.view.set_buffer(buffer);
+        }
+    }
+
+    /// Add the code file `name` at `idx`, before the scratch and notes buffers
+    pub fn insert_buffer(&mut self, idx: usize, name: String, code: String) {
         let mut ps = SyntaxSet::load_defaults_nonewlines();
         ps.link_syntaxes();
 
-        let buffer = Buffer::new_with_syntax_raw(code, &ps);
+        self.names.insert(idx, name);
+        self.buffers.insert(idx, Arc::new(Mutex::new(Buffer::new_with_syntax_raw(code, &ps))));
+    }
+
+    /// Remove the buffer `idx`, the first buffer is shown in a single view
+    pub fn remove_buffer(&mut self, idx: usize) {
+        if idx >= self.buffers.len() {
+            return;
+        }
+
+        self.names.remove(idx);
+        self.buffers.remove(idx);
+
+        self.split_view(None);
+        self.switch_buffer(0);
+        self.view.last_buffer = None;
+    }
+
+    /// Ask the confirmation of the deletion of the buffer `idx`
+    pub fn confirm_delete(&mut self, idx: usize) {
+        self.view.overlay = Overlay::ConfirmDelete {
+            prefix: "Delete the file? (y)es (n)o",
+            idx: idx,
+        };
+    }
+
+    /// Buffer whose deletion was confirmed since the last call
+    pub fn take_deleted(&mut self) -> Option<usize> {
+        self.deleted.take()
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
 
-        self.view.set_buffer(Arc::new(Mutex::new(buffer)));
+    /// Functions of the API, with their signature, completed in the code
+    pub fn set_completions(&mut self, api: Vec<(String, String)>) {
+        self.view.completion.set_api(api);
+    }
+
+    /// Complete the word before the cursor with the identifiers of all the
+    /// code files
+    fn update_completion(&mut self) {
+        let texts: Vec<Vec<u8>> = self.buffers
+            .iter()
+            .map(|buffer| buffer.lock().unwrap().text())
+            .collect();
+        self.view.update_completion(&texts);
+    }
+
+    /// Check the code files, named `names`, with the plugin of `language`
+    pub fn set_diagnostics(&mut self, language: &str, names: Vec<String>) {
+        self.diagnostics.reset(language, names);
+        for idx in 0..self.buffers.len() {
+            let code = self.buffer_text(idx);
+            self.diagnostics.check(idx, code);
+        }
+    }
+
+    /// Check the current buffer after a pause in typing
+    fn update_diagnostics(&mut self) {
+        let idx = match self.current_buffer() {
+            Some(idx) => idx,
+            None => return,
+        };
+
+        if self.diagnostics.is_due() {
+            let code = self.buffer_text(idx);
+            self.diagnostics.check(idx, code);
+        }
+        self.diagnostics.poll();
+        self.view.diagnostic = self.diagnostics.error(idx).cloned();
+
+        let error = match self.other {
+            Some(ref other) => {
+                self.buffer_index(&other.buffer).and_then(|idx| self.diagnostics.error(idx).cloned())
+            }
+            None => None,
+        };
+        if let Some(ref mut other) = self.other {
+            other.diagnostic = error;
+        }
+    }
+
+    /// Check all the buffers now, the first one with an error and its line
+    pub fn check_syntax(&mut self) -> Option<(usize, Option<usize>)> {
+        for idx in 0..self.buffers.len() {
+            let code = self.buffer_text(idx);
+            if let Some(error) = self.diagnostics.check_now(idx, code) {
+                return Some((idx, error.line));
+            }
+        }
+
+        None
+    }
+
+    pub fn show_message(&mut self, message: &'static str) {
+        self.view.show_message(message);
+    }
+
+    /// Index of the buffer of the focused view
+    pub fn current_buffer(&self) -> Option<usize> {
+        self.buffer_index(&self.view.buffer)
+    }
+
+    fn buffer_index(&self, buffer: &Arc<Mutex<Buffer>>) -> Option<usize> {
+        self.buffers.iter().position(|b| Arc::ptr_eq(b, buffer))
+    }
+
+    fn buffer_text(&self, idx: usize) -> String {
+        String::from_utf8_lossy(&self.buffers[idx].lock().unwrap().text()).into_owned()
+    }
+
+    pub fn switch_buffer(&mut self, idx: usize) {
+        if let Some(buffer) = self.buffers.get(idx).cloned() {
+            self.view.switch_buffer(buffer);
+        }
+    }
+
+    /// Show the next buffer, or the previous one for a negative `delta`
+    fn cycle_buffer(&mut self, delta: isize) {
+        let len = self.buffers.len() as isize;
+        if let Some(idx) = self.current_buffer() {
+            self.switch_buffer((((idx as isize + delta) % len + len) % len) as usize);
+        }
+    }
+
+    /// List the buffers in an overlay, the current one selected
+    fn select_buffer(&mut self) {
+        self.view.overlay = Overlay::SelectBuffer {
+            names: self.names.clone(),
+            selected: self.current_buffer().unwrap_or(0),
+        };
+    }
+
+    /// Split the screen in two views of the current buffer, or come back to
+    /// the focused view for None
+    fn split_view(&mut self, split: Option<Split>) {
+        match split {
+            Some(_) => {
+                if self.other.is_none() {
+                    self.other = Some(self.view.split(1));
+                }
+            }
+            None => {
+                self.other = None;
+                self.focus_first = true;
+            }
+        }
+        self.split = split;
+    }
+
+    /// Move the focus, and the keys, to the other view of the split
+    fn switch_view(&mut self) {
+        let mut other = match self.other.take() {
+            Some(other) => other,
+            None => return,
+        };
+
+        // The selection stays in the view left
+        match self.mode_type {
+            ModeType::Visual | ModeType::VisualLine => self.set_mode(ModeType::Normal),
+            _ => {}
+        }
+
+        other.take_focus(&mut self.view);
+        self.other = Some(mem::replace(&mut self.view, other));
+        self.focus_first = !self.focus_first;
+    }
+
+    /// Lines of the buffer `idx`, like `get_buffer`
+    pub fn get_buffer_at(&mut self, idx: usize) -> Vec<String> {
+        match self.buffers.get(idx) {
+            Some(buffer) => {
+                buffer.lock()
+                    .unwrap()
+                    .lines()
+                    .map(|line| String::from_utf8(line).unwrap())
+                    .collect()
+            }
+            None => Vec::new(),
+        }
     }
     /// Handle key events
     ///
@@ -189,28 +618,39 @@ impl Editor {
             Some(k) => k,
             None => return,
         };
+        self.diagnostics.typed();
+
+        // The popup of the completion takes the keys to choose a candidate
+        if self.view.completion.is_open() {
+            match key {
+                Key::Up => return self.view.completion.select(-1),
+                Key::Down => return self.view.completion.select(1),
+                Key::Tab | Key::Enter => return self.view.accept_completion(),
+                Key::Esc => return self.view.completion.close(),
+                _ => {}
+            }
+        }
 
-        let mut remove_overlay = false;
         let command = match self.view.overlay {
             Overlay::None => self.mode.handle_key_event(key),
             _ => {
                 let event = self.view.overlay.handle_key_event(key);
                 match event {
                     OverlayEvent::Finished(response) => {
-                        remove_overlay = true;
-                        self.handle_overlay_response(rb, response)
+                        // The response may open another overlay
+                        let overlay = mem::replace(&mut self.view.overlay, Overlay::None);
+                        self.view.clear(rb);
+                        self.handle_overlay_response(rb, overlay, response)
                     }
 
-                    _ => BuilderEvent::Incomplete,
+                    _ => {
+                        self.view.preview_search();
+                        BuilderEvent::Incomplete
+                    }
                 }
             }
         };
 
-        if remove_overlay {
-            self.view.overlay = Overlay::None;
-            self.view.clear(rb);
-        }
-
         if let BuilderEvent::Complete(c) = command {
             let _ = self.command_sender.send(c);
         }
@@ -223,68 +663,164 @@ impl Editor {
     /// first, such as in the case of Overlay::SavePrompt.
     fn handle_overlay_response(&mut self,
                                rb: &mut Screen,
+                               overlay: Overlay,
                                response: Option<String>)
                                -> BuilderEvent {
+        let data = match response {
+            Some(data) => data,
+            None => {
+                match overlay {
+                    Overlay::Search { .. } => self.view.cancel_search(),
+                    Overlay::Confirm { .. } => self.view.answer_replace(None),
+                    _ => {}
+                }
+                return BuilderEvent::Incomplete;
+            }
+        };
+
         // FIXME: This entire method neext to be updated
-        match response {
-            Some(data) => {
-                match self.view.overlay {
-
-                    // FIXME: this is just a temporary fix
-                    Overlay::Prompt { ref data, .. } => {
-                        match &**data {
-                            // FIXME: need to find a better system for these commands
-                            //        They should be chainable
-                            //          ie: wq - save & quit
-                            //        They should also take arguments
-                            //          ie w file.txt - write buffer to file.txt
-                            "q" | "quit" => BuilderEvent::Complete(Command::exit_editor()),
-                            "w" | "write" => BuilderEvent::Complete(Command::save_buffer()),
-
-                            _ => BuilderEvent::Incomplete,
-                        }
+        match overlay {
+
+            // FIXME: this is just a temporary fix
+            Overlay::Prompt { .. } => {
+                match &*data {
+                    // FIXME: need to find a better system for these commands
+                    //        They should be chainable
+                    //          ie: wq - save & quit
+                    //        They should also take arguments
+                    //          ie w file.txt - write buffer to file.txt
+                    "q" | "quit" => BuilderEvent::Complete(Command::exit_editor()),
+                    "w" | "write" => BuilderEvent::Complete(Command::save_buffer()),
+                    "wq" | "x" => {
+                        let _ = self.command_sender.send(Command::save_buffer());
+                        BuilderEvent::Complete(Command::exit_editor())
+                    }
+                    "set novi" => BuilderEvent::Complete(Command::set_mode(ModeType::Standard)),
+                    "sp" | "split" => BuilderEvent::Complete(Command::split_view(Some(Split::Horizontal))),
+                    "vs" | "vsplit" => BuilderEvent::Complete(Command::split_view(Some(Split::Vertical))),
+                    "only" => BuilderEvent::Complete(Command::split_view(None)),
+                    "b" | "ls" | "buffers" => {
+                        self.select_buffer();
+                        BuilderEvent::Complete(Command::noop())
+                    }
+                    "bn" | "bnext" => {
+                        self.cycle_buffer(1);
+                        BuilderEvent::Complete(Command::noop())
+                    }
+                    "bp" | "bprevious" => {
+                        self.cycle_buffer(-1);
+                        BuilderEvent::Complete(Command::noop())
                     }
 
-                    Overlay::SavePrompt { .. } => {
-                        if data.is_empty() {
-                            BuilderEvent::Invalid
+                    _ => {
+                        if data.parse::<usize>().is_ok() {
+                            self.view.goto_line(&data);
+                            BuilderEvent::Complete(Command::noop())
+                        } else if let Some(substitute) = search::parse_substitute(&data) {
+                            self.view.substitute(substitute);
+                            BuilderEvent::Complete(Command::noop())
                         } else {
-                            let path = PathBuf::from(&*data);
-                            self.view.buffer.lock().unwrap().file_path = Some(path);
-                            BuilderEvent::Complete(Command::save_buffer())
+                            BuilderEvent::Incomplete
                         }
                     }
+                }
+            }
 
-                    Overlay::SelectFile { .. } => {
-                        let path = PathBuf::from(data);
-                        let buffer = Arc::new(Mutex::new(Buffer::from(path)));
-                        self.buffers.push(buffer.clone());
-                        self.view.set_buffer(buffer.clone());
-                        self.view.clear(rb);
-                        BuilderEvent::Complete(Command::noop())
-                    }
+            Overlay::SavePrompt { .. } => {
+                if data.is_empty() {
+                    BuilderEvent::Invalid
+                } else {
+                    let path = PathBuf::from(&*data);
+                    self.view.buffer.lock().unwrap().file_path = Some(path);
+                    BuilderEvent::Complete(Command::save_buffer())
+                }
+            }
+
+            Overlay::SelectFile { .. } => {
+                self.names.push(data.clone());
+                let path = PathBuf::from(data);
+                let buffer = Arc::new(Mutex::new(Buffer::from(path)));
+                self.buffers.push(buffer.clone());
+                self.view.set_buffer(buffer.clone());
+                self.view.clear(rb);
+                BuilderEvent::Complete(Command::noop())
+            }
 
-                    _ => BuilderEvent::Incomplete,
+            Overlay::Search { backward, regex, replace, .. } => {
+                if self.view.finish_search(&data, regex, backward) && replace {
+                    self.view.ask_replacement();
                 }
+                BuilderEvent::Complete(Command::noop())
             }
-            None => BuilderEvent::Incomplete,
-        }
-    }
 
-    /// Handle resize events
-    ///
-    /// width and height represent the new height of the window.
-    fn handle_resize_event(&mut self, width: usize, height: usize) {
-        self.view.resize(width, height);
+            Overlay::Replace { .. } => {
+                let cursor = self.view.cursor_idx();
+                self.view.start_replace(cursor, Replace::all(data));
+                BuilderEvent::Complete(Command::noop())
+            }
+
+            Overlay::Confirm { .. } => {
+                self.view.answer_replace(Some(data));
+                BuilderEvent::Complete(Command::noop())
+            }
+
+            Overlay::ConfirmDelete { idx, .. } => {
+                if data == "y" {
+                    self.deleted = Some(idx);
+                }
+                BuilderEvent::Complete(Command::noop())
+            }
+
+            Overlay::GotoLine { .. } => {
+                self.view.goto_line(&data);
+                BuilderEvent::Complete(Command::noop())
+            }
+
+            Overlay::SelectBuffer { .. } => {
+                if let Ok(idx) = data.parse::<usize>() {
+                    self.switch_buffer(idx);
+                }
+                BuilderEvent::Complete(Command::noop())
+            }
+
+            _ => BuilderEvent::Incomplete,
+        }
     }
 
-    /// Draw the current view to the frontend
+    /// Draw the views to the frontend, sized to the screen resolution
     fn draw(&mut self, rb: &mut Screen, palettes: Arc<Mutex<Palettes>>) {
-        self.view.draw(rb, palettes.clone(), self.options.syntax_enabled);
+        let layout = view::layout(self.split, rb.mode_width(), rb.mode_height());
+        let syntax_enabled = self.options.syntax_enabled;
+
+        for (idx, &(x, y, width, height)) in layout.iter().enumerate() {
+            let view = if (idx == 0) == self.focus_first {
+                &mut self.view
+            } else {
+                match self.other {
+                    Some(ref mut other) => other,
+                    None => continue,
+                }
+            };
+
+            view.resize(width, height);
+            if !view.focused {
+                view.refresh_marks();
+            }
+
+            // Each view draws from the top left corner of the text area
+            rb.camera(-x, -y);
+            rb.clip(x, 16 + y, GUTTER + width as i32 * 4, height as i32 * 7);
+            view.draw(rb, palettes.clone(), syntax_enabled);
+        }
+
+        rb.camera(0, 0);
+        rb.clip(-1, -1, -1, -1);
     }
 
     /// Handle the given command, performing the associated action
     fn handle_command(&mut self, rb: &mut Screen, command: Command) {
+        self.record_change(command);
+
         let repeat = if command.number > 0 {
             command.number
         } else {
@@ -296,6 +832,91 @@ impl Editor {
                 Action::Operation(o) => self.handle_operation(o, command),
             }
         }
+
+        // The completion follows the word being typed
+        match command.action {
+            Action::Operation(Operation::Insert(c)) if c.is_alphanumeric() || c == '_' => {
+                self.update_completion()
+            }
+            Action::Operation(Operation::DeleteFromMark(_)) if self.view.completion.is_open() => {
+                self.update_completion()
+            }
+            _ => self.view.completion.close(),
+        }
+    }
+
+    /// Keep the commands of the last change of the Vi mode: the change, and
+    /// what is typed in the insert mode after it
+    fn record_change(&mut self, command: Command) {
+        if self.replaying {
+            return;
+        }
+
+        match self.mode_type {
+            ModeType::Normal => {
+                let change = match command.action {
+                    Action::Operation(Operation::Undo) |
+                    Action::Operation(Operation::Redo) |
+                    Action::Operation(Operation::Apply(Operator::Yank)) |
+                    Action::Operation(Operation::ApplyLines(Operator::Yank, _)) => false,
+                    Action::Operation(_) => true,
+                    Action::Instruction(Instruction::SetMode(ModeType::Insert)) => true,
+                    _ => false,
+                };
+
+                if change {
+                    self.last_change = vec![command];
+                    self.recording = true;
+                }
+            }
+            ModeType::Insert => {
+                if self.recording {
+                    self.last_change.push(command);
+                }
+                if let Action::Instruction(Instruction::SetMode(_)) = command.action {
+                    self.recording = false;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn repeat_change(&mut self, rb: &mut Screen) {
+        self.replaying = true;
+        for command in self.last_change.clone() {
+            self.handle_command(rb, command);
+        }
+        self.replaying = false;
+    }
+
+    fn set_mode(&mut self, mode: ModeType) {
+        match mode {
+            ModeType::Visual | ModeType::VisualLine => {}
+            _ => self.view.clear_selection(),
+        }
+
+        self.mode = match mode {
+            ModeType::Insert => Box::new(InsertMode::new()),
+            ModeType::Normal => Box::new(NormalMode::new()),
+            ModeType::Visual => Box::new(VisualMode::new(false)),
+            ModeType::VisualLine => Box::new(VisualMode::new(true)),
+            ModeType::Standard => Box::new(StandardMode::new()),
+        };
+        self.mode_type = mode;
+
+        match mode {
+            ModeType::Insert => self.view.show_message("-- INSERT --"),
+            ModeType::Visual => {
+                self.view.start_selection(false);
+                self.view.show_message("-- VISUAL --");
+            }
+            ModeType::VisualLine => {
+                self.view.start_selection(true);
+                self.view.show_message("-- VISUAL LINE --");
+            }
+            ModeType::Normal => self.view.show_message("-- NORMAL --"),
+            ModeType::Standard => self.view.show_message("-- STANDARD --"),
+        }
     }
 
 
@@ -314,18 +935,25 @@ impl Editor {
                     self.view.move_mark(mark, object)
                 }
             }
+            Instruction::SetOverlay(OverlayType::SelectBuffer) => self.select_buffer(),
             Instruction::SetOverlay(overlay_type) => self.view.set_overlay(overlay_type),
             Instruction::SetMode(mode) => {
-                match mode {
-                    ModeType::Insert => self.mode = Box::new(InsertMode::new()),
-                    ModeType::Normal => self.mode = Box::new(NormalMode::new()),
+                // `a`, `A` and `I` move the cursor before the insert mode
+                if let Some(object) = command.object {
+                    self.view.move_mark(Mark::Cursor(0), object)
                 }
+                self.set_mode(mode);
             }
             Instruction::SwitchToLastBuffer => {
                 self.view.switch_last_buffer();
                 self.view.clear(rb);
             }
             Instruction::ShowMessage(msg) => self.view.show_message(msg),
+            Instruction::SearchNext(reverse) => self.view.search_next(reverse),
+            Instruction::GotoLine(line) => self.view.goto_line_number(line),
+            Instruction::Repeat => self.repeat_change(rb),
+            Instruction::SplitView(split) => self.split_view(split),
+            Instruction::SwitchView => self.switch_view(),
 
             _ => {}
         }
@@ -350,6 +978,35 @@ impl Editor {
             }
             Operation::Undo => self.view.undo(),
             Operation::Redo => self.view.redo(),
+            Operation::Apply(operator) => {
+                if let Some(obj) = command.object {
+                    self.view.apply(operator, obj);
+                    self.after_operator(operator);
+                }
+            }
+            Operation::ApplyLines(operator, count) => {
+                self.view.apply_lines(operator, count);
+                self.after_operator(operator);
+            }
+            Operation::ApplySelection(operator) => {
+                self.view.apply_selection(operator);
+                if operator != Operator::Change {
+                    self.set_mode(ModeType::Normal);
+                }
+                self.after_operator(operator);
+            }
+            Operation::Paste(anchor) => self.view.paste(anchor),
+            Operation::OpenLine(anchor) => {
+                self.view.open_line(anchor);
+                self.set_mode(ModeType::Insert);
+            }
+        }
+    }
+
+    /// The change operator continues in the insert mode
+    fn after_operator(&mut self, operator: Operator) {
+        if operator == Operator::Change {
+            self.set_mode(ModeType::Insert);
         }
     }
 
@@ -363,9 +1020,16 @@ impl Editor {
         self.view.maybe_clear_message();
         // if players
 
+        let ctrl = players.akeys.get(&Scancode::LCtrl).cloned().unwrap_or(false) ||
+                   players.akeys.get(&Scancode::RCtrl).cloned().unwrap_or(false);
+
         for (key, value) in &players.akeys_quick {
             if *value {
-                let k = Key::from_special_code(*key);
+                let k = if ctrl {
+                    Key::from_ctrl_code(*key)
+                } else {
+                    Key::from_special_code(*key)
+                };
                 self.handle_key_event(screen, k);
             }
         }
@@ -379,5 +1043,56 @@ impl Editor {
             self.handle_command(screen, message)
         }
 
+        self.update_diagnostics();
+
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use gfx::Screen;
+
+    use super::*;
+
+    /// Editor of `code` in the Vi mode
+    fn vi_editor(code: &str) -> Editor {
+        let mut editor = Editor::new(Input::Code(String::new()),
+                                     Box::new(NormalMode::new()),
+                                     Options::default());
+        editor.set_buffers(vec![(CODE_FILENAME.to_string(), code.to_string())]);
+        editor.set_mode(ModeType::Normal);
+        editor
+    }
+
+    /// Type `keys`, `\x1b` for Esc, and run their commands
+    fn type_keys(editor: &mut Editor, keys: &str) {
+        let mut screen = Screen::new(64, 64);
+        for c in keys.chars() {
+            let key = if c == '\x1b' { Key::Esc } else { Key::Char(c) };
+            editor.handle_key_event(&mut screen, Some(key));
+            while let Ok(command) = editor.command_queue.try_recv() {
+                editor.handle_command(&mut screen, command);
+            }
+        }
+    }
+
+    fn code(editor: &mut Editor) -> String {
+        editor.get_buffer_at(0).concat()
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn vi_counts_and_repeat() {
+        let mut editor = vi_editor("one\ntwo\nthree\nfour\n");
+        type_keys(&mut editor, "3dd");
+        assert_eq!(code(&mut editor), "four\n");
+
+        let mut editor = vi_editor("one two three four\n");
+        type_keys(&mut editor, "2dw");
+        assert_eq!(code(&mut editor), "three four\n");
+
+        // `.` changes the next word the same way
+        let mut editor = vi_editor("one two three\n");
+        type_keys(&mut editor, "cwx\x1bw.");
+        assert_eq!(code(&mut editor), "x x three\n");
+    }
+}
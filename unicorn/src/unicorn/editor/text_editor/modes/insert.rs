@@ -1,6 +1,8 @@
 use unicorn::editor::text_editor::keyboard::Key;
 use unicorn::editor::text_editor::keymap::{KeyMap, KeyMapState};
-use unicorn::editor::text_editor::command::{BuilderEvent, Command};
+use unicorn::editor::text_editor::buffer::Mark;
+use unicorn::editor::text_editor::command::{Action, BuilderEvent, Command, Operation};
+use unicorn::editor::text_editor::textobject::{Anchor, Kind, Offset, TextObject};
 
 use super::{Mode, ModeType};
 
@@ -25,6 +27,30 @@ impl InsertMode {
 
         keymap.bind_key(Key::Esc, Command::set_mode(ModeType::Normal));
 
+        keymap.bind_key(Key::Up, Command::movement(Offset::Backward(1, Mark::Cursor(0)), Kind::Line(Anchor::Same)));
+        keymap.bind_key(Key::Down, Command::movement(Offset::Forward(1, Mark::Cursor(0)), Kind::Line(Anchor::Same)));
+        keymap.bind_key(Key::Left, Command::movement(Offset::Backward(1, Mark::Cursor(0)), Kind::Char));
+        keymap.bind_key(Key::Right, Command::movement(Offset::Forward(1, Mark::Cursor(0)), Kind::Char));
+
+        keymap.bind_key(Key::Tab, Command::insert_tab());
+        keymap.bind_key(Key::Enter, Command::insert_char('\n'));
+        keymap.bind_key(Key::Backspace, Command {
+            number: 1,
+            action: Action::Operation(Operation::DeleteFromMark(Mark::Cursor(0))),
+            object: Some(TextObject {
+                kind: Kind::Char,
+                offset: Offset::Backward(1, Mark::Cursor(0))
+            })
+        });
+        keymap.bind_key(Key::Delete, Command {
+            number: 1,
+            action: Action::Operation(Operation::DeleteFromMark(Mark::Cursor(0))),
+            object: Some(TextObject {
+                kind: Kind::Char,
+                offset: Offset::Forward(1, Mark::Cursor(0))
+            })
+        });
+
         keymap
     }
 
@@ -4,15 +4,21 @@ use unicorn::editor::text_editor::command::BuilderEvent;
 pub use self::standard::StandardMode;
 pub use self::normal::NormalMode;
 pub use self::insert::InsertMode;
+pub use self::visual::VisualMode;
 
 mod standard;
 mod normal;
 mod insert;
+mod visual;
 
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum ModeType {
     Normal,
     Insert,
+    Visual,
+    VisualLine,
+    /// Non modal mode, without the Vi keys
+    Standard,
 }
 
 /// The concept of Iota's modes are taken from Vi.
@@ -1,14 +1,62 @@
 use unicorn::editor::text_editor::keyboard::Key;
 use unicorn::editor::text_editor::keymap::{KeyMap, KeyMapState};
-use unicorn::editor::text_editor::command::{Builder, BuilderEvent, Command};
+use unicorn::editor::text_editor::buffer::Mark;
+use unicorn::editor::text_editor::command::{Action, BuilderEvent, Command, Instruction, Operation, Operator};
+use unicorn::editor::text_editor::textobject::{Anchor, Kind, Offset, TextObject};
+use unicorn::editor::text_editor::overlay::OverlayType;
+use unicorn::editor::text_editor::view::Split;
 
 use super::{Mode, ModeType};
 
 
+/// Largest count of a command, the digits after it are ignored
+pub const MAX_COUNT: usize = 9999;
+
+/// Text object reached by a Vi motion repeated `count` times
+pub fn motion(key: Key, count: usize) -> Option<TextObject> {
+    let cursor = Mark::Cursor(0);
+
+    let (kind, offset) = match key {
+        Key::Char('h') | Key::Left => (Kind::Char, Offset::Backward(count, cursor)),
+        Key::Char('l') | Key::Right | Key::Char(' ') => (Kind::Char, Offset::Forward(count, cursor)),
+        Key::Char('j') | Key::Down => (Kind::Line(Anchor::Same), Offset::Forward(count, cursor)),
+        Key::Char('k') | Key::Up => (Kind::Line(Anchor::Same), Offset::Backward(count, cursor)),
+        Key::Char('w') => (Kind::Word(Anchor::Start), Offset::Forward(count, cursor)),
+        Key::Char('b') => (Kind::Word(Anchor::Start), Offset::Backward(count, cursor)),
+        Key::Char('0') => (Kind::Line(Anchor::Start), Offset::Backward(0, cursor)),
+        Key::Char('$') => (Kind::Line(Anchor::End), Offset::Forward(0, cursor)),
+        _ => return None,
+    };
+
+    Some(TextObject {
+        kind: kind,
+        offset: offset,
+    })
+}
+
+/// Operator of a key
+pub fn operator(key: Key) -> Option<Operator> {
+    match key {
+        Key::Char('d') => Some(Operator::Delete),
+        Key::Char('c') => Some(Operator::Change),
+        Key::Char('y') => Some(Operator::Yank),
+        _ => None,
+    }
+}
+
+
 /// `NormalMode` mimics Vi's Normal mode.
+///
+/// A command is an optional count, then a motion, a command key, or an
+/// operator followed by a motion (`d2w`), or by itself for whole lines (`3dd`).
 pub struct NormalMode {
     keymap: KeyMap<Command>,
-    builder: Builder,
+    /// Count being typed
+    count: Option<usize>,
+    /// Operator waiting for its motion, with the count typed before it
+    operator: Option<(Operator, usize)>,
+    /// A key sequence of the keymap is started, like `gg`
+    match_in_progress: bool,
 }
 
 impl NormalMode {
@@ -17,7 +65,9 @@ impl NormalMode {
     pub fn new() -> NormalMode {
         NormalMode {
             keymap: NormalMode::key_defaults(),
-            builder: Builder::new(),
+            count: None,
+            operator: None,
+            match_in_progress: false,
         }
     }
 
@@ -27,41 +77,160 @@ impl NormalMode {
 
         keymap.bind_key(Key::Char('u'), Command::undo());
         keymap.bind_key(Key::Ctrl('r'), Command::redo());
+        keymap.bind_key(Key::Char('.'), Command::repeat());
+
+        // Insert
         keymap.bind_key(Key::Char('i'), Command::set_mode(ModeType::Insert));
+        keymap.bind_key(Key::Char('a'), Command::set_mode_at(ModeType::Insert, TextObject {
+            kind: Kind::Char,
+            offset: Offset::Forward(1, Mark::Cursor(0))
+        }));
+        keymap.bind_key(Key::Char('A'), Command::set_mode_at(ModeType::Insert, TextObject {
+            kind: Kind::Line(Anchor::End),
+            offset: Offset::Forward(0, Mark::Cursor(0))
+        }));
+        keymap.bind_key(Key::Char('I'), Command::set_mode_at(ModeType::Insert, TextObject {
+            kind: Kind::Line(Anchor::Start),
+            offset: Offset::Backward(0, Mark::Cursor(0))
+        }));
+        keymap.bind_key(Key::Char('o'), Command {
+            number: 1,
+            action: Action::Operation(Operation::OpenLine(Anchor::After)),
+            object: None
+        });
+        keymap.bind_key(Key::Char('O'), Command {
+            number: 1,
+            action: Action::Operation(Operation::OpenLine(Anchor::Before)),
+            object: None
+        });
+
+        // Register
+        keymap.bind_key(Key::Char('p'), Command {
+            number: 1,
+            action: Action::Operation(Operation::Paste(Anchor::After)),
+            object: None
+        });
+        keymap.bind_key(Key::Char('P'), Command {
+            number: 1,
+            action: Action::Operation(Operation::Paste(Anchor::Before)),
+            object: None
+        });
+
+        // Visual
+        keymap.bind_key(Key::Char('v'), Command::set_mode(ModeType::Visual));
+        keymap.bind_key(Key::Char('V'), Command::set_mode(ModeType::VisualLine));
+
+        // Lines
+        keymap.bind_keys(&[Key::Char('g'), Key::Char('g')], Command::goto_line(1));
+        keymap.bind_key(Key::Char('G'), Command::goto_line(0));
+        keymap.bind_key(Key::Char(':'), Command::set_overlay(OverlayType::Prompt));
+
+        // Search
+        keymap.bind_key(Key::Char('/'), Command::set_overlay(OverlayType::VimSearch));
+        keymap.bind_key(Key::Char('?'), Command::set_overlay(OverlayType::VimSearchBackward));
+        keymap.bind_key(Key::Char('n'), Command::search_next(false));
+        keymap.bind_key(Key::Char('N'), Command::search_next(true));
+
+        // Windows
+        keymap.bind_keys(&[Key::Ctrl('w'), Key::Char('s')], Command::split_view(Some(Split::Horizontal)));
+        keymap.bind_keys(&[Key::Ctrl('w'), Key::Char('v')], Command::split_view(Some(Split::Vertical)));
+        keymap.bind_keys(&[Key::Ctrl('w'), Key::Char('o')], Command::split_view(None));
+        keymap.bind_keys(&[Key::Ctrl('w'), Key::Char('w')], Command::switch_view());
 
         keymap
     }
 
-    fn check_keymap(&mut self, key: Key) -> BuilderEvent {
-        if let KeyMapState::Match(c) = self.keymap.check_key(key) {
-            BuilderEvent::Complete(c)
+    /// Second key of an operator: a motion, or the same operator for the lines
+    fn complete_operator(&mut self, operator: Operator, count: usize, key: Key) -> BuilderEvent {
+        if self::operator(key) == Some(operator) {
+            BuilderEvent::Complete(Command::apply_lines(operator, count))
+        } else if let Some(object) = motion(key, count) {
+            BuilderEvent::Complete(Command::apply(operator, object))
         } else {
-            BuilderEvent::Incomplete
+            BuilderEvent::Invalid
         }
     }
 
-}
+    fn check_key(&mut self, key: Key, count: Option<usize>) -> BuilderEvent {
+        if self.match_in_progress {
+            return self.check_keymap(key, count);
+        }
 
-impl Mode for NormalMode {
-    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
-        match self.builder.check_key(key) {
-            // builder gives us a full command, return that
-            BuilderEvent::Complete(cmd) => BuilderEvent::Complete(cmd),
+        if let Some(operator) = operator(key) {
+            self.operator = Some((operator, count.unwrap_or(1)));
+            return BuilderEvent::Incomplete;
+        }
 
-            // no command from the builder, check the internal keymap
-            BuilderEvent::Incomplete => { self.check_keymap(key) }
+        let n = count.unwrap_or(1);
+        let command = match key {
+            Key::Char('x') => motion(Key::Char('l'), n).map(|o| Command::apply(Operator::Delete, o)),
+            Key::Char('X') => motion(Key::Char('h'), n).map(|o| Command::apply(Operator::Delete, o)),
+            Key::Char('D') => motion(Key::Char('$'), n).map(|o| Command::apply(Operator::Delete, o)),
+            Key::Char('C') => motion(Key::Char('$'), n).map(|o| Command::apply(Operator::Change, o)),
+            Key::Char('Y') => Some(Command::apply_lines(Operator::Yank, n)),
+            // `5G` goes to the fifth line
+            Key::Char('G') if count.is_some() => Some(Command::goto_line(n)),
+            _ => motion(key, n).map(|o| Command::movement(o.offset, o.kind)),
+        };
+        if let Some(command) = command {
+            return BuilderEvent::Complete(command);
+        }
+
+        self.check_keymap(key, count)
+    }
 
-            // invalid result from builder, return invalid if the internal
-            // keymap doesn't give a match
-            BuilderEvent::Invalid => {
-                let val = self.check_keymap(key);
+    fn check_keymap(&mut self, key: Key, count: Option<usize>) -> BuilderEvent {
+        let n = count.unwrap_or(1);
+        self.match_in_progress = false;
 
-                if let BuilderEvent::Incomplete = val {
-                    BuilderEvent::Invalid 
+        match self.keymap.check_key(key) {
+            KeyMapState::Match(c) => {
+                if let Action::Instruction(Instruction::GotoLine(_)) = c.action {
+                    BuilderEvent::Complete(c)
                 } else {
-                    val
+                    // The count repeats the command
+                    BuilderEvent::Complete(Command { number: n as i32, ..c })
                 }
             }
+            KeyMapState::Continue => {
+                self.match_in_progress = true;
+                self.count = count;
+                BuilderEvent::Incomplete
+            }
+            KeyMapState::None => BuilderEvent::Invalid,
+        }
+    }
+
+}
+
+impl Mode for NormalMode {
+    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        if let Key::Char(c) = key {
+            // '0' is bound (start of line), and cannot be the start of a count
+            if let Some(digit) = c.to_digit(10) {
+                if digit > 0 || self.count.is_some() {
+                    let count = self.count.unwrap_or(0).saturating_mul(10).saturating_add(digit as usize);
+                    self.count = Some(count.min(MAX_COUNT));
+                    return BuilderEvent::Incomplete;
+                }
+            }
+        }
+
+        if key == Key::Esc {
+            self.match_in_progress = false;
+            self.count = None;
+            self.operator = None;
+            return BuilderEvent::Incomplete;
+        }
+
+        let count = self.count.take();
+        match self.operator.take() {
+            // `2d3w` deletes 6 words
+            Some((operator, first)) => {
+                let count = first.saturating_mul(count.unwrap_or(1)).min(MAX_COUNT);
+                self.complete_operator(operator, count, key)
+            }
+            None => self.check_key(key, count),
         }
     }
 }
@@ -71,3 +240,98 @@ impl Default for NormalMode {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use unicorn::editor::text_editor::keyboard::Key;
+    use unicorn::editor::text_editor::command::{Action, BuilderEvent, Command, Instruction, Operation, Operator};
+    use unicorn::editor::text_editor::modes::Mode;
+    use unicorn::editor::text_editor::textobject::{Kind, Offset};
+
+    use super::*;
+
+    /// Command of the keys `keys`, all the keys before the last one are incomplete
+    fn command(mode: &mut NormalMode, keys: &str) -> Command {
+        let mut event = BuilderEvent::Incomplete;
+        for (idx, c) in keys.chars().enumerate() {
+            if idx > 0 {
+                match event {
+                    BuilderEvent::Incomplete => {}
+                    _ => panic!("{:?} completed before {:?}", keys, c),
+                }
+            }
+            event = mode.handle_key_event(Key::Char(c));
+        }
+
+        match event {
+            BuilderEvent::Complete(command) => command,
+            _ => panic!("{:?} is not complete", keys),
+        }
+    }
+
+    fn forward(command: &Command) -> Option<usize> {
+        match command.object {
+            Some(object) => {
+                match object.offset {
+                    Offset::Forward(count, _) => Some(count),
+                    _ => None,
+                }
+            }
+            None => None,
+        }
+    }
+
+    #[test]
+    fn operators_and_counts() {
+        let mut mode = NormalMode::new();
+
+        match command(&mut mode, "3dd").action {
+            Action::Operation(Operation::ApplyLines(Operator::Delete, 3)) => {}
+            action => panic!("{:?}", action),
+        }
+
+        // `2d3w` deletes 6 words
+        let c = command(&mut mode, "2d3w");
+        match c.action {
+            Action::Operation(Operation::Apply(Operator::Delete)) => {}
+            action => panic!("{:?}", action),
+        }
+        assert_eq!(forward(&c), Some(6));
+        match c.object.unwrap().kind {
+            Kind::Word(_) => {}
+            kind => panic!("{:?}", kind),
+        }
+
+        // The count of a motion, and `0` at the start of the line
+        assert_eq!(forward(&command(&mut mode, "10l")), Some(10));
+        match command(&mut mode, "0").object.unwrap().kind {
+            Kind::Line(_) => {}
+            kind => panic!("{:?}", kind),
+        }
+
+        // Repeated keymap commands, and `5G` to the fifth line
+        assert_eq!(command(&mut mode, "3u").number, 3);
+        match command(&mut mode, "5G").action {
+            Action::Instruction(Instruction::GotoLine(5)) => {}
+            action => panic!("{:?}", action),
+        }
+
+        // Esc cancels the count and the operator
+        assert!(match mode.handle_key_event(Key::Char('2')) { BuilderEvent::Incomplete => true, _ => false });
+        assert!(match mode.handle_key_event(Key::Char('d')) { BuilderEvent::Incomplete => true, _ => false });
+        mode.handle_key_event(Key::Esc);
+        assert_eq!(forward(&command(&mut mode, "l")), Some(1));
+    }
+
+    #[test]
+    fn large_counts() {
+        let mut mode = NormalMode::new();
+
+        assert_eq!(forward(&command(&mut mode, "99999999999999999999999l")), Some(MAX_COUNT));
+        assert_eq!(forward(&command(&mut mode, "9999d9999w")), Some(MAX_COUNT));
+        match command(&mut mode, "123456dd").action {
+            Action::Operation(Operation::ApplyLines(Operator::Delete, MAX_COUNT)) => {}
+            action => panic!("{:?}", action),
+        }
+    }
+}
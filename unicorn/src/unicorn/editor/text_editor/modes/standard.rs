@@ -4,8 +4,9 @@ use unicorn::editor::text_editor::buffer::Mark;
 use unicorn::editor::text_editor::command::{BuilderEvent, Operation, Instruction, Command, Action};
 use unicorn::editor::text_editor::textobject::{Anchor, Kind, TextObject, Offset};
 use unicorn::editor::text_editor::overlay::OverlayType;
+use unicorn::editor::text_editor::view::Split;
 
-use super::Mode;
+use super::{Mode, ModeType};
 
 
 
@@ -50,7 +51,6 @@ impl StandardMode {
         keymap.bind_key(Key::Ctrl('p'), Command::movement(Offset::Backward(1, Mark::Cursor(0)), Kind::Line(Anchor::Same)));
         keymap.bind_key(Key::Ctrl('n'), Command::movement(Offset::Forward(1, Mark::Cursor(0)), Kind::Line(Anchor::Same)));
         keymap.bind_key(Key::Ctrl('b'), Command::movement(Offset::Backward(1, Mark::Cursor(0)), Kind::Char));
-        keymap.bind_key(Key::Ctrl('f'), Command::movement(Offset::Forward(1, Mark::Cursor(0)), Kind::Char));
         keymap.bind_key(Key::Ctrl('e'), Command::movement(Offset::Forward(0, Mark::Cursor(0)), Kind::Line(Anchor::End)));
         keymap.bind_key(Key::Ctrl('a'), Command::movement(Offset::Backward(0, Mark::Cursor(0)), Kind::Line(Anchor::Start)));
 
@@ -73,14 +73,6 @@ impl StandardMode {
                 offset: Offset::Forward(1, Mark::Cursor(0))
             })
         });
-        keymap.bind_key(Key::Ctrl('h'), Command {
-            number: 1,
-            action: Action::Operation(Operation::DeleteFromMark(Mark::Cursor(0))),
-            object: Some(TextObject {
-                kind: Kind::Char,
-                offset: Offset::Backward(1, Mark::Cursor(0))
-            })
-        });
         keymap.bind_key(Key::Ctrl('d'), Command {
             number: 1,
             action: Action::Operation(Operation::DeleteFromMark(Mark::Cursor(0))),
@@ -94,11 +86,25 @@ impl StandardMode {
             action: Action::Instruction(Instruction::SetOverlay(OverlayType::SelectFile)),
             object: None
         });
+        keymap.bind_keys(&[Key::Ctrl('x'), Key::Char('v')], Command::set_mode(ModeType::Normal));
         keymap.bind_keys(&[Key::Ctrl('x'), Key::Ctrl('b')], Command {
             number: 1,
             action: Action::Instruction(Instruction::SwitchToLastBuffer),
             object: None
         });
+        keymap.bind_keys(&[Key::Ctrl('x'), Key::Char('b')], Command::set_overlay(OverlayType::SelectBuffer));
+
+        // Views
+        keymap.bind_keys(&[Key::Ctrl('x'), Key::Char('1')], Command::split_view(None));
+        keymap.bind_keys(&[Key::Ctrl('x'), Key::Char('2')], Command::split_view(Some(Split::Horizontal)));
+        keymap.bind_keys(&[Key::Ctrl('x'), Key::Char('3')], Command::split_view(Some(Split::Vertical)));
+        keymap.bind_keys(&[Key::Ctrl('x'), Key::Char('o')], Command::switch_view());
+
+        // Search
+        keymap.bind_key(Key::Ctrl('f'), Command::set_overlay(OverlayType::Search));
+        keymap.bind_key(Key::Ctrl('r'), Command::set_overlay(OverlayType::SearchBackward));
+        keymap.bind_key(Key::Ctrl('h'), Command::set_overlay(OverlayType::Replace));
+        keymap.bind_key(Key::Ctrl('g'), Command::set_overlay(OverlayType::GotoLine));
 
         // History
         keymap.bind_key(Key::Ctrl('z'), Command::undo());
@@ -0,0 +1,59 @@
+use unicorn::editor::text_editor::keyboard::Key;
+use unicorn::editor::text_editor::command::{BuilderEvent, Command, Operator};
+
+use super::{Mode, ModeType};
+use super::normal::{motion, operator};
+
+
+/// `VisualMode` mimics Vi's Visual and Visual Line modes.
+///
+/// The motions extend the selection from where the mode started, and the
+/// operators apply to the selection.
+pub struct VisualMode {
+    /// Whole lines are selected
+    line: bool,
+    count: Option<usize>,
+}
+
+impl VisualMode {
+
+    /// Create a new instance of `VisualMode`
+    pub fn new(line: bool) -> VisualMode {
+        VisualMode {
+            line: line,
+            count: None,
+        }
+    }
+
+}
+
+impl Mode for VisualMode {
+    fn handle_key_event(&mut self, key: Key) -> BuilderEvent {
+        if let Key::Char(c) = key {
+            if let Some(digit) = c.to_digit(10) {
+                if digit > 0 || self.count.is_some() {
+                    self.count = Some(self.count.unwrap_or(0) * 10 + digit as usize);
+                    return BuilderEvent::Incomplete;
+                }
+            }
+        }
+
+        let count = self.count.take().unwrap_or(1);
+        if let Some(operator) = operator(key) {
+            return BuilderEvent::Complete(Command::apply_selection(operator));
+        }
+        if let Some(object) = motion(key, count) {
+            return BuilderEvent::Complete(Command::movement(object.offset, object.kind));
+        }
+
+        match key {
+            Key::Char('x') => BuilderEvent::Complete(Command::apply_selection(Operator::Delete)),
+            Key::Char('v') if !self.line => BuilderEvent::Complete(Command::set_mode(ModeType::Normal)),
+            Key::Char('V') if self.line => BuilderEvent::Complete(Command::set_mode(ModeType::Normal)),
+            Key::Char('v') => BuilderEvent::Complete(Command::set_mode(ModeType::Visual)),
+            Key::Char('V') => BuilderEvent::Complete(Command::set_mode(ModeType::VisualLine)),
+            Key::Esc => BuilderEvent::Complete(Command::set_mode(ModeType::Normal)),
+            _ => BuilderEvent::Invalid,
+        }
+    }
+}
@@ -2,6 +2,7 @@ use unicode_width::UnicodeWidthChar;
 use gfx::Screen;
 
 use unicorn::editor::text_editor::keyboard::Key;
+use unicorn::editor::text_editor::view::{print_char, GUTTER};
 
 /// State for the overlay
 pub enum OverlayEvent {
@@ -13,6 +14,15 @@ pub enum OverlayEvent {
 pub enum OverlayType {
     Prompt,
     SelectFile,
+    Search,
+    SearchBackward,
+    /// Regex search of the Vi mode, `/` and `?`
+    VimSearch,
+    VimSearchBackward,
+    Replace,
+    GotoLine,
+    /// List of the open buffers
+    SelectBuffer,
 }
 
 
@@ -39,26 +49,98 @@ pub enum Overlay {
         prefix: &'static str,
     },
 
+    /// Incremental search, the cursor follows the first match of `data`
+    Search {
+        cursor_x: usize,
+        data: String,
+        prefix: &'static str,
+        backward: bool,
+        regex: bool,
+        /// Ask the replacement of the pattern after it
+        replace: bool,
+    },
+
+    /// Replacement of the matches of the last search
+    Replace {
+        cursor_x: usize,
+        data: String,
+        prefix: &'static str,
+    },
+
+    GotoLine {
+        cursor_x: usize,
+        data: String,
+        prefix: &'static str,
+    },
+
+    /// Question answered by a single key, the answer is None for Esc
+    Confirm {
+        prefix: &'static str,
+    },
+
+    /// Confirmation of the deletion of the buffer `idx`, deleted for `y`
+    ConfirmDelete {
+        prefix: &'static str,
+        idx: usize,
+    },
+
+    /// Choice of a buffer in a list, the answer is its index
+    SelectBuffer {
+        names: Vec<String>,
+        selected: usize,
+    },
+
     None,
 }
 
 impl Overlay {
-    pub fn draw(&self, rb: &mut Screen) {
+    /// Draw the prompt on the `row` line of the view
+    pub fn draw(&self, rb: &mut Screen, row: i32) {
         match *self {
             Overlay::SelectFile     {prefix, ref data, ..} |
             Overlay::Prompt         {prefix, ref data, ..} |
-            Overlay::SavePrompt     {prefix, ref data, ..} => {
-                let height = (rb.height - 1) as i32;
+            Overlay::SavePrompt     {prefix, ref data, ..} |
+            Overlay::Search         {prefix, ref data, ..} |
+            Overlay::Replace        {prefix, ref data, ..} |
+            Overlay::GotoLine       {prefix, ref data, ..} => {
                 let offset = prefix.len();
 
                 // draw the given prefix
                 for (index, ch) in prefix.chars().enumerate() {
-                    rb.print_char(ch, index as i32, height, 7);
+                    print_char(rb, ch, index as i32, row, 7);
                 }
 
                 // draw the overlay data
                 for (index, ch) in data.chars().enumerate() {
-                    rb.print_char(ch, index as i32 + offset as i32, height, 7);
+                    print_char(rb, ch, index as i32 + offset as i32, row, 7);
+                }
+            }
+
+            Overlay::Confirm { prefix } |
+            Overlay::ConfirmDelete { prefix, .. } => {
+                for (index, ch) in prefix.chars().enumerate() {
+                    print_char(rb, ch, index as i32, row, 7);
+                }
+            }
+
+            Overlay::SelectBuffer { ref names, selected } => {
+                let prefix = "Buffer (1-9, Up/Down, Enter): ";
+                for (index, ch) in prefix.chars().enumerate() {
+                    print_char(rb, ch, index as i32, row, 7);
+                }
+
+                // The list is above the prompt, one buffer by line
+                let width = names.iter().map(|name| name.len()).max().unwrap_or(0) as i32 + 3;
+                let top = row - names.len() as i32;
+                for (idx, name) in names.iter().enumerate() {
+                    let y = top + idx as i32;
+                    let background = if idx == selected { 13 } else { 1 };
+                    rb.rectfill(GUTTER, 16 + y * 7, GUTTER + width * 4, 16 + y * 7 + 6, background);
+
+                    let line = format!("{} {}", idx + 1, name);
+                    for (index, ch) in line.chars().enumerate() {
+                        print_char(rb, ch, index as i32 + 1, y, 7);
+                    }
                 }
             }
 
@@ -66,15 +148,18 @@ impl Overlay {
         }
     }
 
-    pub fn draw_cursor(&mut self, rb: &mut Screen) {
+    pub fn draw_cursor(&mut self, rb: &mut Screen, row: i32) {
         match *self {
             Overlay::SelectFile     {cursor_x, ..} |
             Overlay::Prompt         {cursor_x, ..} |
-            Overlay::SavePrompt     {cursor_x, ..} => {
-                // Prompt is always on the bottom, so we can use the
-                // height given by the frontend here
-                let height = (rb.height - 1) as i32;
-                //rb.set_cursor(cursor_x as isize, height as isize)
+            Overlay::SavePrompt     {cursor_x, ..} |
+            Overlay::Search         {cursor_x, ..} |
+            Overlay::Replace        {cursor_x, ..} |
+            Overlay::GotoLine       {cursor_x, ..} => {
+                // Same cell size as the cursor of the view, after the prefix
+                let x = GUTTER + (cursor_x * 4) as i32;
+                let y = 16 + row * 7;
+                rb.rectfill(x, y, x + 4, y + 7, 3);
             },
 
             _ => {}
@@ -85,7 +170,10 @@ impl Overlay {
         match *self {
             Overlay::SelectFile {ref mut cursor_x, ref mut data, ..} |
             Overlay::Prompt     {ref mut cursor_x, ref mut data, ..} |
-            Overlay::SavePrompt {ref mut cursor_x, ref mut data, ..} => {
+            Overlay::SavePrompt {ref mut cursor_x, ref mut data, ..} |
+            Overlay::Search     {ref mut cursor_x, ref mut data, ..} |
+            Overlay::Replace    {ref mut cursor_x, ref mut data, ..} |
+            Overlay::GotoLine   {ref mut cursor_x, ref mut data, ..} => {
                 match key {
                     Key::Esc => return OverlayEvent::Finished(None),
                     Key::Backspace => {
@@ -110,6 +198,34 @@ impl Overlay {
                 }
             }
 
+            Overlay::Confirm { .. } |
+            Overlay::ConfirmDelete { .. } => {
+                match key {
+                    Key::Esc => return OverlayEvent::Finished(None),
+                    Key::Char(c) => return OverlayEvent::Finished(Some(c.to_string())),
+                    _ => {}
+                }
+            }
+
+            Overlay::SelectBuffer { ref names, ref mut selected } => {
+                let len = names.len();
+                match key {
+                    Key::Esc => return OverlayEvent::Finished(None),
+                    Key::Up if len > 0 => *selected = (*selected + len - 1) % len,
+                    Key::Down if len > 0 => *selected = (*selected + 1) % len,
+                    Key::Enter => return OverlayEvent::Finished(Some(selected.to_string())),
+                    Key::Char(c) => {
+                        match c.to_digit(10) {
+                            Some(digit) if digit > 0 && (digit as usize) <= len => {
+                                return OverlayEvent::Finished(Some((digit - 1).to_string()))
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {}
+                }
+            }
+
             _ => {}
         }
         OverlayEvent::Ok
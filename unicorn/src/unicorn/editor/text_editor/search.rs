@@ -0,0 +1,332 @@
+//! Search in the text of a buffer, with a plain text or a regex pattern.
+//!
+//! A pattern between slashes, like `/[a-z]+/`, is always a regex. The other
+//! patterns are plain text, except for the Vi searches with `/` and `?`.
+
+use regex;
+use regex::bytes::Regex;
+
+#[derive(Clone, Debug)]
+pub struct Search {
+    regex: Regex,
+    /// Plain text pattern, the replacement has no `$1` groups
+    literal: bool,
+    /// Direction of the search, `N` goes the other way
+    pub backward: bool,
+}
+
+impl Search {
+    pub fn new(pattern: &str, regex: bool, backward: bool) -> Result<Search, String> {
+        let (source, literal) = if pattern.len() > 2 && pattern.starts_with('/') &&
+                                   pattern.ends_with('/') {
+            (pattern[1..pattern.len() - 1].to_string(), false)
+        } else if regex {
+            (pattern.to_string(), false)
+        } else {
+            (regex::escape(pattern), true)
+        };
+
+        Search::build(&source, literal, backward)
+    }
+
+    /// Search of the regex `source`, even between slashes
+    pub fn regex(source: &str, backward: bool) -> Result<Search, String> {
+        Search::build(source, false, backward)
+    }
+
+    fn build(source: &str, literal: bool, backward: bool) -> Result<Search, String> {
+        let regex = try!(Regex::new(source).map_err(|e| e.to_string()));
+
+        Ok(Search {
+               regex: regex,
+               literal: literal,
+               backward: backward,
+           })
+    }
+
+    /// Start and end of the matches in `text`, without the empty ones
+    pub fn matches(&self, text: &[u8]) -> Vec<(usize, usize)> {
+        self.regex
+            .find_iter(text)
+            .map(|m| (m.start(), m.end()))
+            .filter(|&(start, end)| end > start)
+            .collect()
+    }
+
+    /// First match starting at `from` or after
+    pub fn next(&self, text: &[u8], from: usize) -> Option<(usize, usize)> {
+        self.matches(text).into_iter().find(|&(start, _)| start >= from)
+    }
+
+    /// Last match starting before `from`
+    pub fn previous(&self, text: &[u8], from: usize) -> Option<(usize, usize)> {
+        self.matches(text).into_iter().rev().find(|&(start, _)| start < from)
+    }
+
+    /// `next` or `previous`, wrapping around the text
+    pub fn find(&self, text: &[u8], from: usize, backward: bool) -> Option<(usize, usize)> {
+        let matches = self.matches(text);
+        if backward {
+            matches.iter().rev().find(|m| m.0 < from).or(matches.last()).cloned()
+        } else {
+            matches.iter().find(|m| m.0 >= from).or(matches.first()).cloned()
+        }
+    }
+
+    /// Matches from `from` to the end of the text, with their replacement by
+    /// `replace`, where `$1` is the first group of a regex
+    pub fn replacements(&self,
+                        text: &[u8],
+                        from: usize,
+                        replace: &str)
+                        -> Vec<(usize, usize, Vec<u8>)> {
+        let mut replacements = Vec::new();
+
+        for captures in self.regex.captures_iter(text) {
+            let (start, end) = match captures.get(0) {
+                Some(m) => (m.start(), m.end()),
+                None => continue,
+            };
+            if start < from || end == start {
+                continue;
+            }
+
+            let mut data = Vec::new();
+            if self.literal {
+                data.extend_from_slice(replace.as_bytes());
+            } else {
+                captures.expand(replace.as_bytes(), &mut data);
+            }
+            replacements.push((start, end, data));
+        }
+
+        replacements
+    }
+}
+
+/// Replacement of the matches of a search, after the cursor
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replace {
+    pub data: String,
+    /// Last line of the replaced matches, counted from 0
+    pub last_line: usize,
+    /// All the matches of a line, or only the first one
+    pub global: bool,
+}
+
+impl Replace {
+    /// Every match until the end of the buffer
+    pub fn all(data: String) -> Replace {
+        Replace {
+            data: data,
+            last_line: usize::max_value(),
+            global: true,
+        }
+    }
+
+    /// Replacements of the matches of `search` from `from`, on the lines up to
+    /// `last_line`, the first match of each line unless `global`
+    pub fn matches(&self, search: &Search, text: &[u8], from: usize) -> Vec<(usize, usize, Vec<u8>)> {
+        let mut replacements = Vec::new();
+        let mut line = 0;
+        let mut pos = 0;
+        let mut last = None;
+
+        for replacement in search.replacements(text, from, &self.data) {
+            line += text[pos..replacement.0].iter().filter(|&&c| c == b'\n').count();
+            pos = replacement.0;
+            if line > self.last_line {
+                break;
+            }
+            if !self.global && last == Some(line) {
+                continue;
+            }
+
+            last = Some(line);
+            replacements.push(replacement);
+        }
+
+        replacements
+    }
+
+    /// Where the next match is searched after `idx`, the next line when only
+    /// the first match of a line is replaced
+    pub fn resume(&self, text: &[u8], idx: usize) -> usize {
+        if self.global {
+            return idx;
+        }
+
+        match text.iter().skip(idx).position(|&c| c == b'\n') {
+            Some(offset) => idx + offset + 1,
+            None => text.len() + 1,
+        }
+    }
+}
+
+/// Vi substitution, `s/pattern/replace/flags`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Substitute {
+    pub pattern: String,
+    pub replace: String,
+    /// `%s`, the whole buffer instead of the line of the cursor
+    pub whole: bool,
+    /// `g` flag, all the matches of a line instead of the first one
+    pub global: bool,
+}
+
+impl Substitute {
+    /// Index of the first match and replacement of the substitution, from the
+    /// line of `cursor`
+    pub fn start(&self, text: &[u8], cursor: usize) -> (usize, Replace) {
+        let cursor = cursor.min(text.len());
+        let (from, last_line) = if self.whole {
+            (0, usize::max_value())
+        } else {
+            let from = text[..cursor].iter().rposition(|&c| c == b'\n').map_or(0, |idx| idx + 1);
+            (from, text[..cursor].iter().filter(|&&c| c == b'\n').count())
+        };
+
+        (from,
+         Replace {
+             data: self.replace.clone(),
+             last_line: last_line,
+             global: self.global,
+         })
+    }
+}
+
+/// Vi substitution, `s/pattern/replace/` on the line of the cursor, `%s` for
+/// the whole buffer and the `g` flag for all the matches of a line. `\/` is a
+/// slash in the pattern or the replacement.
+pub fn parse_substitute(command: &str) -> Option<Substitute> {
+    let (command, whole) = if command.starts_with('%') {
+        (&command[1..], true)
+    } else {
+        (command, false)
+    };
+
+    if !command.starts_with("s/") {
+        return None;
+    }
+
+    let mut parts = vec![String::new()];
+    let mut chars = command[2..].chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '/' if parts.len() < 3 => parts.push(String::new()),
+            '\\' => {
+                let part = parts.last_mut().unwrap();
+                match chars.next() {
+                    Some('/') => part.push('/'),
+                    Some(next) => {
+                        part.push('\\');
+                        part.push(next);
+                    }
+                    None => part.push('\\'),
+                }
+            }
+            _ => parts.last_mut().unwrap().push(c),
+        }
+    }
+
+    if parts.len() < 2 || parts[0].is_empty() {
+        return None;
+    }
+    let flags = parts.get(2).cloned().unwrap_or_default();
+
+    Some(Substitute {
+             pattern: parts[0].clone(),
+             replace: parts[1].clone(),
+             whole: whole,
+             global: flags.contains('g'),
+         })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn find_wraps_around() {
+        let search = Search::new("ab", false, false).unwrap();
+        let text = b"ab cd ab";
+
+        assert_eq!(search.matches(text), vec![(0, 2), (6, 8)]);
+        assert_eq!(search.find(text, 1, false), Some((6, 8)));
+        assert_eq!(search.find(text, 7, false), Some((0, 2)));
+        assert_eq!(search.find(text, 6, true), Some((0, 2)));
+        assert_eq!(search.find(text, 0, true), Some((6, 8)));
+        assert_eq!(search.next(text, 7), None);
+    }
+
+    #[test]
+    fn plain_text_and_regex() {
+        assert_eq!(Search::new("a.c", false, false).unwrap().matches(b"abc a.c"),
+                   vec![(4, 7)]);
+        assert_eq!(Search::new("/a.c/", false, false).unwrap().matches(b"abc a.c"),
+                   vec![(0, 3), (4, 7)]);
+        assert!(Search::new("(", true, false).is_err());
+    }
+
+    #[test]
+    fn replace_groups() {
+        let search = Search::new("/(\\w+)=(\\w+)/", false, false).unwrap();
+        let text = b"x=1 y=2";
+        assert_eq!(search.replacements(text, 1, "$2=$1"),
+                   vec![(4, 7, b"2=y".to_vec())]);
+
+        let search = Search::new("$x", false, false).unwrap();
+        assert_eq!(search.replacements(b"$x", 0, "$1"), vec![(0, 2, b"$1".to_vec())]);
+
+    }
+
+    fn substitute(pattern: &str, replace: &str, whole: bool, global: bool) -> Option<Substitute> {
+        Some(Substitute {
+                 pattern: pattern.to_string(),
+                 replace: replace.to_string(),
+                 whole: whole,
+                 global: global,
+             })
+    }
+
+    #[test]
+    fn parse_substitutions() {
+        assert_eq!(parse_substitute("%s/a/b/g"), substitute("a", "b", true, true));
+        assert_eq!(parse_substitute("s/a/b"), substitute("a", "b", false, false));
+        assert_eq!(parse_substitute("s/a/"), substitute("a", "", false, false));
+        assert_eq!(parse_substitute(r"s/a\/b/c\/d/g"), substitute("a/b", "c/d", false, true));
+        assert_eq!(parse_substitute(r"s/\w\\/x/"), substitute(r"\w\\", "x", false, false));
+        assert_eq!(parse_substitute("s//b/"), None);
+        assert_eq!(parse_substitute("w"), None);
+    }
+
+    #[test]
+    fn substitute_lines() {
+        let text = b"a a\na a\na a\n";
+        let search = Search::regex("a", false).unwrap();
+        let starts = |replace: &Replace, from| -> Vec<usize> {
+            replace.matches(&search, text, from).iter().map(|m| m.0).collect()
+        };
+
+        // First match of the line of the cursor
+        let (from, replace) = parse_substitute("s/a/b/").unwrap().start(text, 5);
+        assert_eq!(from, 4);
+        assert_eq!(starts(&replace, from), vec![4]);
+        assert_eq!(replace.resume(text, 5), 8);
+
+        let (from, replace) = parse_substitute("s/a/b/g").unwrap().start(text, 5);
+        assert_eq!(starts(&replace, from), vec![4, 6]);
+        assert_eq!(replace.resume(text, 5), 5);
+
+        // First match of each line
+        let (from, replace) = parse_substitute("%s/a/b/").unwrap().start(text, 5);
+        assert_eq!(starts(&replace, from), vec![0, 4, 8]);
+
+        let (from, replace) = parse_substitute("%s/a/b/g").unwrap().start(text, 5);
+        assert_eq!(starts(&replace, from), vec![0, 2, 4, 6, 8, 10]);
+
+        // Slashes in a regex substitution
+        let search = Search::regex("/x/", false).unwrap();
+        assert_eq!(search.matches(b"a/x/"), vec![(1, 4)]);
+    }
+}
@@ -7,6 +7,8 @@ use std::fs::{File, rename};
 use std::sync::{Mutex, Arc};
 use std::time::SystemTime;
 use std::rc::Rc;
+use std::cell::RefCell;
+use std::mem;
 
 
 use tempdir::TempDir;
@@ -17,10 +19,44 @@ use std::collections::HashMap;
 
 use gfx::Screen;
 use unicorn::editor::text_editor::buffer::{Buffer, Mark};
+use unicorn::editor::text_editor::command::Operator;
+use unicorn::editor::text_editor::completion::{self, Completion};
 use unicorn::editor::text_editor::overlay::{Overlay, OverlayType};
+use unicorn::editor::text_editor::search::{Replace, Search, Substitute};
 use unicorn::editor::text_editor::utils;
 use unicorn::editor::text_editor::textobject::{Anchor, TextObject, Kind, Offset};
 use unicorn::Palettes;
+use plugins::error::ScriptError;
+
+/// Width of the gutter on the left of the text, in pixels
+pub const GUTTER: i32 = 4;
+
+/// Two views of the editor, one above the other or side by side
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Split {
+    Horizontal,
+    Vertical,
+}
+
+/// Origin in pixels, width and height in characters of the views, for a
+/// screen of `width` x `height` pixels
+pub fn layout(split: Option<Split>, width: usize, height: usize) -> Vec<(i32, i32, usize, usize)> {
+    // Rows of 7 pixels between the top bar (16 pixels) and the bottom bar (8 pixels)
+    let rows = height.saturating_sub(24) / 7;
+    let columns = |width: usize| width.saturating_sub(GUTTER as usize) / 4;
+
+    match split {
+        None => vec![(0, 0, columns(width), rows)],
+        Some(Split::Horizontal) => {
+            let top = rows / 2;
+            vec![(0, 0, columns(width), top), (0, (top * 7) as i32, columns(width), rows - top)]
+        }
+        Some(Split::Vertical) => {
+            let left = width / 2;
+            vec![(0, 0, columns(left), rows), (left as i32, 0, columns(width - left), rows)]
+        }
+    }
+}
 
 
 /// A View is an abstract Window (into a Buffer).
@@ -54,26 +90,65 @@ pub struct View {
     /// was displayed.
     message: Option<(&'static str, SystemTime)>,
 
+    /// Last search, its matches are highlighted
+    search: Option<Search>,
+
+    /// Search before the one being typed, restored if it is cancelled
+    last_search: Option<Search>,
+
+    /// Index of the cursor when the search prompt was opened
+    search_origin: usize,
+
+    /// Replacement of the matches, while asking a confirmation for each one
+    replace: Option<Replace>,
+
+    /// Text of the last yank or delete of the Vi mode, and whether it is whole lines
+    register: (Vec<u8>, bool),
+
+    /// Start of the visual selection, and whether it selects whole lines
+    selection: Option<(usize, bool)>,
+
+    /// Completion popup of the word before the cursor
+    pub completion: Completion,
+
+    /// Syntax error of the buffer, marked in the gutter
+    pub diagnostic: Option<ScriptError>,
+
     themes: Rc<ThemeSet>,
 
     theme_name: String,
 
-    colors: HashMap<String, i32>,
+    /// Colors of the syntax highlighting in the palette, shared by the views
+    colors: Rc<RefCell<HashMap<String, i32>>>,
+
+    /// The view takes the keys, the other view of a split only shows its buffer
+    pub focused: bool,
 }
 
 impl View {
 
     pub fn new(buffer: Arc<Mutex<Buffer>>, themes: Rc<ThemeSet>, theme_name: String, width: usize, height: usize) -> View {
-        let cursor = Mark::Cursor(0);
-        let top_line = Mark::DisplayMark(0);
-
         {
             let mut b = buffer.lock().unwrap();
 
-            b.set_mark(cursor, 0);
-            b.set_mark(top_line, 0);
+            b.set_mark(Mark::Cursor(0), 0);
+            b.set_mark(Mark::DisplayMark(0), 0);
         }
 
+        View::with_marks(buffer, themes, theme_name, width, height, 0)
+    }
+
+    /// View using the cursor and top line marks `id` of the buffer
+    fn with_marks(buffer: Arc<Mutex<Buffer>>,
+                  themes: Rc<ThemeSet>,
+                  theme_name: String,
+                  width: usize,
+                  height: usize,
+                  id: usize)
+                  -> View {
+        let cursor = Mark::Cursor(id);
+        let top_line = Mark::DisplayMark(id);
+
         View {
             buffer: buffer,
             last_buffer: None,
@@ -83,16 +158,84 @@ impl View {
             overlay: Overlay::None,
             threshold: 5,
             message: None,
+            search: None,
+            last_search: None,
+            search_origin: 0,
+            replace: None,
+            register: (Vec::new(), false),
+            selection: None,
+            completion: Completion::new(),
+            diagnostic: None,
             themes: themes,
             theme_name: theme_name,
             height: height,
             width: width,
-            colors: HashMap::new(),
+            colors: Rc::new(RefCell::new(HashMap::new())),
+            focused: true,
         }
     }
 
     pub fn reset(&mut self) {
-        self.colors.clear();
+        self.colors.borrow_mut().clear();
+    }
+
+    /// Second view of the buffer at the same position, with the marks `id`
+    pub fn split(&self, id: usize) -> View {
+        let mut view = View::with_marks(self.buffer.clone(),
+                                        self.themes.clone(),
+                                        self.theme_name.clone(),
+                                        self.width,
+                                        self.height,
+                                        id);
+        view.colors = self.colors.clone();
+        view.focused = false;
+        view.set_marks(id, self.position());
+        view
+    }
+
+    /// Index of the cursor and of the top line
+    fn position(&self) -> (usize, usize) {
+        let buffer = self.buffer.lock().unwrap();
+        (buffer.get_mark_idx(self.cursor).unwrap_or(0),
+         buffer.get_mark_idx(self.top_line).unwrap_or(0))
+    }
+
+    /// Use the marks `id` of the buffer, moved to `position`
+    fn set_marks(&mut self, id: usize, position: (usize, usize)) {
+        self.cursor = Mark::Cursor(id);
+        self.top_line = Mark::DisplayMark(id);
+
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.set_mark(self.cursor, position.0);
+        buffer.set_mark(self.top_line, position.1);
+    }
+
+    /// Take the focus from `other`, with the marks moved by the commands, the
+    /// register, the last search and the completion
+    pub fn take_focus(&mut self, other: &mut View) {
+        let (position, other_position) = (self.position(), other.position());
+        let id = match self.cursor {
+            Mark::Cursor(id) => id,
+            Mark::DisplayMark(id) => id,
+        };
+        other.set_marks(id, other_position);
+        self.set_marks(0, position);
+
+        self.register = mem::replace(&mut other.register, (Vec::new(), false));
+        self.search = other.search.clone();
+        self.focused = true;
+        other.focused = false;
+        other.overlay = Overlay::None;
+        mem::swap(&mut self.completion, &mut other.completion);
+        other.completion.close();
+        other.clear_selection();
+    }
+
+    /// Keep the marks in the text after the changes of the other view
+    pub fn refresh_marks(&mut self) {
+        let mut buffer = self.buffer.lock().unwrap();
+        buffer.refresh_mark(self.cursor);
+        buffer.refresh_mark(self.top_line);
     }
     
     pub fn set_buffer(&mut self, buffer: Arc<Mutex<Buffer>>) {
@@ -108,6 +251,18 @@ impl View {
         self.buffer = buffer;
     }
 
+    /// Show `buffer` where it was left, from its start the first time
+    pub fn switch_buffer(&mut self, buffer: Arc<Mutex<Buffer>>) {
+        let shown = buffer.lock().unwrap().get_mark_idx(self.cursor).is_some();
+        if !shown {
+            self.set_buffer(buffer);
+            return;
+        }
+
+        self.last_buffer = Some(self.buffer.clone());
+        self.buffer = buffer;
+    }
+
     pub fn switch_last_buffer(&mut self) {
         let buffer = self.buffer.clone();
         let last_buffer = match self.last_buffer.clone() {
@@ -151,7 +306,11 @@ impl View {
     }
 
     pub fn draw(&mut self, rb: &mut Screen, palettes: Arc<Mutex<Palettes>>, syntax_enabled: bool) {
-        self.draw_cursor(rb);
+        self.draw_matches(rb);
+        self.draw_selection(rb);
+        if self.focused {
+            self.draw_cursor(rb);
+        }
 
        // self.clear(rb);
         {
@@ -177,13 +336,14 @@ impl View {
                           //                   style.background.r, style.background.g, style.background.b);
                            // let bg = Color::Byte(utils::rgb_to_short(&*bg) as u16);
 
-                           if !self.colors.contains_key(&fg) {
-                               let color: u32 = self.colors.len() as u32 + 16;
-                               self.colors.insert(fg.clone(), color as i32);
+                           let mut colors = self.colors.borrow_mut();
+                           if !colors.contains_key(&fg) {
+                               let color: u32 = colors.len() as u32 + 16;
+                               colors.insert(fg.clone(), color as i32);
                                palettes.lock().unwrap().set_color(color, style.foreground.r, style.foreground.g, style.foreground.b);
                            }
 
-                           let color = self.colors[&fg];
+                           let color = colors[&fg];
 
                             for ch in text.chars().skip(self.left_col) {
                                 match ch {
@@ -230,7 +390,14 @@ impl View {
         }
 
 
+        self.draw_diagnostic(rb);
         self.draw_status(rb);
+        self.draw_completion(rb);
+
+        // The prompt replaces the message
+        let row = self.get_height() as i32;
+        self.overlay.draw_cursor(rb, row);
+        self.overlay.draw(rb, row);
     }
 
     #[cfg_attr(feature="clippy", allow(needless_range_loop))]
@@ -244,20 +411,56 @@ impl View {
         let width = self.get_width();
         let height = self.get_height() - 1;
 
+        // Inside a call of the API, its signature replaces the status
+        let hint = match buffer.get_mark_idx(self.cursor) {
+            Some(idx) => {
+                self.completion
+                    .signature(&buffer.text(), idx)
+                    .map(|(signature, argument)| (signature.to_string(), argument))
+            }
+            None => None,
+        };
 
-        for index in 0..width*4 {
-            let ch: char = if index < status_text_len {
-                status_text[index] as char
-            } else { ' ' };
-            print_char(rb, ch, index as i32 * 4, height as i32 * 7, 9);
-        }
+        let diagnostic = match self.diagnostic {
+            Some(ref error) => Some(error.to_string()),
+            None => None,
+        };
 
-        if buffer.dirty {
-            let data = ['[', '*', ']'];
-            for (idx, ch) in data.iter().enumerate() {
-                print_char(rb, *ch, (status_text_len + idx + 1) as i32, height as i32 * 7, 8);
+        if let Some((signature, argument)) = hint {
+            let bounds = completion::argument_bounds(&signature, argument);
+            for index in 0..width {
+                let ch = signature.as_bytes().get(index).map_or(' ', |ch| *ch as char);
+                let col = match bounds {
+                    Some((start, end)) if index >= start && index < end => 10,
+                    _ => 6,
+                };
+                print_char(rb, ch, index as i32, height as i32, col);
+            }
+        } else if let Some(diagnostic) = diagnostic {
+            for index in 0..width {
+                let ch = diagnostic.as_bytes().get(index).map_or(' ', |ch| *ch as char);
+                print_char(rb, ch, index as i32, height as i32, 8);
+            }
+        } else {
+            for index in 0..width {
+                let ch: char = if index < status_text_len {
+                    status_text[index] as char
+                } else { ' ' };
+                print_char(rb, ch, index as i32, height as i32, if self.focused { 9 } else { 6 });
+            }
+
+            if buffer.dirty {
+                let data = ['[', '*', ']'];
+                for (idx, ch) in data.iter().enumerate() {
+                    print_char(rb, *ch, (status_text_len + idx + 1) as i32, height as i32, 8);
+                }
             }
         }
+
+        match self.overlay {
+            Overlay::None => {}
+            _ => return,
+        }
         if let Some((message, _time)) = self.message {
             for (offset, ch) in message.chars().enumerate() {
                 print_char(rb, ch, offset as i32, height as i32 + 1, 7);
@@ -265,6 +468,64 @@ impl View {
         }
     }
 
+    /// Mark the line of the syntax error in the gutter
+    fn draw_diagnostic(&mut self, rb: &mut Screen) {
+        let line = match self.diagnostic {
+            Some(ScriptError { line: Some(line), .. }) => line,
+            _ => return,
+        };
+        let top_line = match self.buffer.lock().unwrap().get_mark_display_coords(self.top_line) {
+            Some((_, top_line)) => top_line,
+            None => return,
+        };
+
+        // The lines of the errors are counted from 1
+        let row = line as i32 - 1 - top_line as i32;
+        if row >= 0 && row < self.get_height() as i32 - 1 {
+            let y1 = 16 + row * 7;
+            rb.rectfill(0, y1, GUTTER - 2, y1 + 5, 8);
+        }
+    }
+
+    /// Candidates of the completion, under the cursor or above it at the
+    /// bottom of the view
+    fn draw_completion(&mut self, rb: &mut Screen) {
+        if !self.completion.is_open() {
+            return;
+        }
+
+        let (x, y) = {
+            let buffer = self.buffer.lock().unwrap();
+            match (buffer.get_mark_display_coords(self.top_line),
+                   buffer.get_mark_display_coords(self.cursor)) {
+                (Some(top_line), Some((x, y))) => {
+                    (x as i32 - self.left_col as i32, y as i32 - top_line.1 as i32)
+                }
+                _ => return,
+            }
+        };
+
+        let candidates = &self.completion.candidates;
+        let len = candidates.len() as i32;
+        let longest = candidates.iter().map(|candidate| candidate.len()).max().unwrap_or(0) as i32;
+
+        let x = cmp::max(0, cmp::min(x, self.get_width() as i32 - longest));
+        let top = if y + 1 + len < self.get_height() as i32 - 1 { y + 1 } else { y - len };
+
+        let x1 = GUTTER + x * 4;
+        let x2 = GUTTER + (x + longest) * 4;
+        rb.rectfill(x1 - 1, 16 + top * 7 - 1, x2, 16 + (top + len) * 7 - 1, 1);
+        for (idx, candidate) in candidates.iter().enumerate() {
+            let row = top + idx as i32;
+            if idx == self.completion.selected {
+                rb.rectfill(x1 - 1, 16 + row * 7 - 1, x2, 16 + row * 7 + 5, 13);
+            }
+            for (offset, ch) in candidate.chars().enumerate() {
+                print_char(rb, ch, x + offset as i32, row, 7);
+            }
+        }
+    }
+
     fn draw_cursor(&mut self, rb: &mut Screen) {
         let buffer = self.buffer.lock().unwrap();
         if let Some(top_line) = buffer.get_mark_display_coords(self.top_line) {
@@ -272,7 +533,7 @@ impl View {
                 let mut x1 = (x - self.left_col) as i32;
                 let mut y1 = y as i32 - top_line.1 as i32;
 
-                x1 = x1 * 4;
+                x1 = GUTTER + x1 * 4;
                 y1 = (y1 * 7);
                 
                 rb.rectfill(x1, 16+y1, x1+4, 16+y1+7, 3);
@@ -280,6 +541,78 @@ impl View {
         }
     }
 
+    /// Highlight the matches of the search in the visible lines
+    fn draw_matches(&mut self, rb: &mut Screen) {
+        let search = match self.search {
+            Some(ref search) => search,
+            None => return,
+        };
+        let buffer = self.buffer.lock().unwrap();
+        let lines = match buffer.lines_from(self.top_line) {
+            Some(lines) => lines,
+            None => return,
+        };
+
+        for (y, line) in lines.take(self.get_height() - 1).enumerate() {
+            // Column of each char, with the tabs expanded like `draw_line`
+            let mut columns = Vec::with_capacity(line.len() + 1);
+            let mut x = 0;
+            for (idx, ch) in line.iter().enumerate() {
+                columns.push(x);
+                if idx >= self.left_col {
+                    x += if *ch == b'\t' { 4 - x % 4 } else { 1 };
+                }
+            }
+            columns.push(x);
+
+            for (start, end) in search.matches(&line) {
+                let x1 = GUTTER + (columns[start] * 4) as i32;
+                let x2 = GUTTER + (columns[end] * 4) as i32;
+                if x2 > x1 {
+                    let y1 = 16 + (y as i32) * 7;
+                    rb.rectfill(x1, y1, x2 - 1, y1 + 7, 2);
+                }
+            }
+        }
+    }
+
+    /// Highlight the visual selection in the visible lines
+    fn draw_selection(&mut self, rb: &mut Screen) {
+        let (start, end) = match self.selection_range() {
+            Some(range) => range,
+            None => return,
+        };
+        let buffer = self.buffer.lock().unwrap();
+        let top = match buffer.get_mark_idx(self.top_line) {
+            Some(top) => top,
+            None => return,
+        };
+        let lines = match buffer.lines_from(self.top_line) {
+            Some(lines) => lines,
+            None => return,
+        };
+
+        let mut line_start = top;
+        for (y, line) in lines.take(self.get_height() - 1).enumerate() {
+            let mut x = 0;
+            for (idx, ch) in line.iter().enumerate() {
+                let width = if *ch == b'\t' { 4 - x % 4 } else { 1 };
+                if idx >= self.left_col {
+                    if line_start + idx >= start && line_start + idx < end {
+                        let y1 = 16 + (y as i32) * 7;
+                        rb.rectfill(GUTTER + (x * 4) as i32,
+                                    y1,
+                                    GUTTER + ((x + width) * 4) as i32 - 1,
+                                    y1 + 7,
+                                    1);
+                    }
+                    x += width;
+                }
+            }
+            line_start += line.len();
+        }
+    }
+
     pub fn set_overlay(&mut self, overlay_type: OverlayType) {
         match overlay_type {
             OverlayType::Prompt => {
@@ -299,6 +632,257 @@ impl View {
                     data: String::new(),
                 };
             }
+
+            // The editor lists its buffers
+            OverlayType::SelectBuffer => {}
+
+            OverlayType::GotoLine => {
+                let prefix = "Go to line: ";
+
+                self.overlay = Overlay::GotoLine {
+                    cursor_x: prefix.len(),
+                    prefix: prefix,
+                    data: String::new(),
+                };
+            }
+
+            _ => {
+                let (prefix, backward, regex) = match overlay_type {
+                    OverlayType::SearchBackward => ("Search backward: ", true, false),
+                    OverlayType::VimSearch => ("/", false, true),
+                    OverlayType::VimSearchBackward => ("?", true, true),
+                    OverlayType::Replace => ("Replace: ", false, false),
+                    _ => ("Search: ", false, false),
+                };
+
+                self.search_origin = self.cursor_idx();
+                self.last_search = self.search.take();
+                self.overlay = Overlay::Search {
+                    cursor_x: prefix.len(),
+                    prefix: prefix,
+                    data: String::new(),
+                    backward: backward,
+                    regex: regex,
+                    replace: overlay_type == OverlayType::Replace,
+                };
+            }
+        }
+    }
+
+    /// Index of the cursor in the buffer
+    pub fn cursor_idx(&self) -> usize {
+        self.buffer.lock().unwrap().get_mark_idx(self.cursor).unwrap_or(0)
+    }
+
+    /// Move the cursor to `idx`, and the screen with it
+    fn set_cursor(&mut self, idx: usize) {
+        self.buffer.lock().unwrap().set_mark(self.cursor, idx);
+        self.maybe_move_screen();
+    }
+
+    /// Move the cursor to the match of the search from `from`, wrapping
+    /// around the buffer
+    fn find_from(&mut self, from: usize, backward: bool) -> bool {
+        let found = match self.search {
+            Some(ref search) => search.find(&self.buffer.lock().unwrap().text(), from, backward),
+            None => None,
+        };
+
+        match found {
+            Some((start, _)) => {
+                self.set_cursor(start);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Move the cursor to the first match of the pattern being typed
+    pub fn preview_search(&mut self) {
+        let search = match self.overlay {
+            Overlay::Search { ref data, backward, regex, .. } => {
+                if data.is_empty() {
+                    None
+                } else {
+                    Search::new(data, regex, backward).ok()
+                }
+            }
+            _ => return,
+        };
+
+        let backward = search.as_ref().map_or(false, |search| search.backward);
+        let origin = self.search_origin;
+        self.search = search;
+        if !self.find_from(origin, backward) {
+            self.set_cursor(origin);
+        }
+    }
+
+    /// Back to the cursor and the search before the prompt
+    pub fn cancel_search(&mut self) {
+        self.search = self.last_search.take();
+        let origin = self.search_origin;
+        self.set_cursor(origin);
+    }
+
+    /// Search `pattern` from the cursor of the prompt, the last search again
+    /// if it is empty
+    pub fn finish_search(&mut self, pattern: &str, regex: bool, backward: bool) -> bool {
+        let search = if pattern.is_empty() {
+            self.last_search.take().map(|mut search| {
+                search.backward = backward;
+                search
+            })
+        } else {
+            match Search::new(pattern, regex, backward) {
+                Ok(search) => Some(search),
+                Err(_) => {
+                    self.show_message("Invalid pattern");
+                    None
+                }
+            }
+        };
+
+        let found = search.is_some();
+        let origin = self.search_origin;
+        self.search = search;
+        if !found {
+            self.set_cursor(origin);
+            return false;
+        }
+
+        // Past the match at the cursor when the last search is repeated
+        let from = if pattern.is_empty() && !backward {
+            origin + 1
+        } else {
+            origin
+        };
+        if !self.find_from(from, backward) {
+            self.show_message("Pattern not found");
+            self.set_cursor(origin);
+            return false;
+        }
+
+        true
+    }
+
+    /// Move the cursor to the next match of the last search, in the other
+    /// direction for `reverse`
+    pub fn search_next(&mut self, reverse: bool) {
+        let backward = match self.search.as_ref().map(|search| search.backward != reverse) {
+            Some(backward) => backward,
+            None => {
+                self.show_message("No previous search");
+                return;
+            }
+        };
+
+        let cursor = self.cursor_idx();
+        let from = if backward { cursor } else { cursor + 1 };
+        if !self.find_from(from, backward) {
+            self.show_message("Pattern not found");
+        }
+    }
+
+    /// Ask the replacement of the matches of the search
+    pub fn ask_replacement(&mut self) {
+        let prefix = "With: ";
+
+        self.overlay = Overlay::Replace {
+            cursor_x: prefix.len(),
+            prefix: prefix,
+            data: String::new(),
+        };
+    }
+
+    /// Replace the matches of the search from `from` with `replace`, after
+    /// the confirmation of each one
+    pub fn start_replace(&mut self, from: usize, replace: Replace) {
+        let found = match self.search {
+            Some(ref search) => {
+                let text = self.buffer.lock().unwrap().text();
+                replace.matches(search, &text, from).first().map(|&(start, _, _)| start)
+            }
+            None => None,
+        };
+
+        match found {
+            Some(start) => {
+                self.set_cursor(start);
+                self.replace = Some(replace);
+                self.overlay = Overlay::Confirm { prefix: "Replace? (y)es (n)o (a)ll (q)uit" };
+            }
+            None => self.show_message("No more matches"),
+        }
+    }
+
+    /// Answer to the confirmation of the replacement at the cursor
+    pub fn answer_replace(&mut self, answer: Option<String>) {
+        let replace = match self.replace.take() {
+            Some(replace) => replace,
+            None => return,
+        };
+        let cursor = self.cursor_idx();
+
+        match answer.and_then(|answer| answer.chars().next()) {
+            Some('y') => {
+                if let Some(next) = self.replace_matches(cursor, &replace, false) {
+                    let next = replace.resume(&self.buffer.lock().unwrap().text(), next);
+                    self.start_replace(next, replace);
+                }
+            }
+            Some('n') => {
+                let next = replace.resume(&self.buffer.lock().unwrap().text(), cursor + 1);
+                self.start_replace(next, replace);
+            }
+            Some('a') => {
+                self.replace_matches(cursor, &replace, true);
+                self.show_message("Replaced all the matches");
+            }
+            Some('q') | None => {}
+            _ => {
+                // Ask again
+                self.replace = Some(replace);
+                self.overlay = Overlay::Confirm { prefix: "Replace? (y)es (n)o (a)ll (q)uit" };
+            }
+        }
+    }
+
+    /// Replace the match at `from`, or all the matches after it, the index
+    /// after the first replacement
+    fn replace_matches(&mut self, from: usize, replace: &Replace, all: bool) -> Option<usize> {
+        let mut buffer = self.buffer.lock().unwrap();
+        let mut replacements = match self.search {
+            Some(ref search) => replace.matches(search, &buffer.text(), from),
+            None => Vec::new(),
+        };
+        if !all {
+            replacements.truncate(1);
+        }
+
+        buffer.replace_ranges(&replacements);
+        replacements.first().map(|&(start, _, ref data)| start + data.len())
+    }
+
+    /// Vi substitution `s/pattern/replace/`, on the line of the cursor or on
+    /// the whole buffer for `%s`
+    pub fn substitute(&mut self, substitute: Substitute) {
+        match Search::regex(&substitute.pattern, false) {
+            Ok(search) => {
+                self.search = Some(search);
+                let cursor = self.cursor_idx();
+                let (from, replace) = substitute.start(&self.buffer.lock().unwrap().text(), cursor);
+                self.start_replace(from, replace);
+            }
+            Err(_) => self.show_message("Invalid pattern"),
+        }
+    }
+
+    /// Answer of the "go to line" prompt
+    pub fn goto_line(&mut self, line: &str) {
+        match line.trim().parse::<usize>() {
+            Ok(line) => self.move_cursor_to_line(line),
+            Err(_) => self.show_message("Invalid line number"),
         }
     }
 
@@ -326,6 +910,19 @@ impl View {
         self.maybe_move_screen();
     }
 
+    /// Move the cursor to the start of `line`, counted from 1
+    pub fn move_cursor_to_line(&mut self, line: usize) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let idx = buffer.lines()
+                .take(line.saturating_sub(1))
+                .map(|line| line.len())
+                .sum();
+            buffer.set_mark(self.cursor, idx);
+        }
+        self.maybe_move_screen();
+    }
+
     /// Update the top_line mark if necessary to keep the cursor on the screen.
     fn maybe_move_screen(&mut self) {
         let mut buffer = self.buffer.lock().unwrap();
@@ -397,6 +994,252 @@ impl View {
         }
     }
 
+    /// Complete the word before the cursor, with the API and the identifiers
+    /// of `texts`
+    pub fn update_completion(&mut self, texts: &[Vec<u8>]) {
+        let word = {
+            let buffer = self.buffer.lock().unwrap();
+            match buffer.get_mark_idx(self.cursor) {
+                Some(idx) => completion::word_before(&buffer.text(), idx),
+                None => String::new(),
+            }
+        };
+        self.completion.update(&word, texts);
+    }
+
+    /// Insert the rest of the selected candidate
+    pub fn accept_completion(&mut self) {
+        let rest = match self.completion.accept() {
+            Some(rest) => rest,
+            None => return,
+        };
+
+        let idx = self.cursor_idx();
+        self.buffer.lock().unwrap().replace_ranges(&[(idx, idx, rest.clone().into_bytes())]);
+        self.set_cursor(idx + rest.len());
+    }
+
+    /// Apply `operator` from the cursor to `object`, on whole lines for the
+    /// vertical motions
+    pub fn apply(&mut self, operator: Operator, object: TextObject) {
+        let range = {
+            let buffer = self.buffer.lock().unwrap();
+            let cursor = buffer.get_mark_idx(self.cursor);
+            let target = buffer.get_object_index(object).map(|pos| pos.absolute);
+            match (cursor, target) {
+                (Some(cursor), Some(target)) => {
+                    let text = buffer.text();
+                    let start = cmp::min(cursor, target);
+                    let end = cmp::min(cmp::max(cursor, target), text.len());
+
+                    match object.kind {
+                        Kind::Line(Anchor::Same) => {
+                            Some((line_bounds(&text, start).0, line_bounds(&text, end).1, true))
+                        }
+                        Kind::Word(_) if operator == Operator::Change => {
+                            // `cw` keeps the spaces after the word, like `ce`
+                            let mut end = end;
+                            while end > start + 1 && (text[end - 1] as char).is_whitespace() {
+                                end -= 1;
+                            }
+                            Some((start, end, false))
+                        }
+                        _ => Some((start, end, false)),
+                    }
+                }
+                _ => None,
+            }
+        };
+
+        if let Some((start, end, lines)) = range {
+            self.operate(operator, start, end, lines);
+        }
+    }
+
+    /// Apply `operator` to `count` lines from the cursor line
+    pub fn apply_lines(&mut self, operator: Operator, count: usize) {
+        let (start, end) = {
+            let buffer = self.buffer.lock().unwrap();
+            let text = buffer.text();
+            let cursor = buffer.get_mark_idx(self.cursor).unwrap_or(0);
+
+            let start = line_bounds(&text, cursor).0;
+            let mut end = start;
+            for _ in 0..cmp::max(count, 1) {
+                end = line_bounds(&text, end).1;
+            }
+            (start, end)
+        };
+
+        self.operate(operator, start, end, true);
+    }
+
+    /// Apply `operator` to the visual selection, and stop the selection
+    pub fn apply_selection(&mut self, operator: Operator) {
+        let lines = match self.selection {
+            Some((_, lines)) => lines,
+            None => return,
+        };
+
+        if let Some((start, end)) = self.selection_range() {
+            self.operate(operator, start, end, lines);
+        }
+        self.selection = None;
+    }
+
+    /// Start and end of the selection, the char under the cursor included
+    fn selection_range(&self) -> Option<(usize, usize)> {
+        let (anchor, lines) = match self.selection {
+            Some(selection) => selection,
+            None => return None,
+        };
+
+        let buffer = self.buffer.lock().unwrap();
+        let text = buffer.text();
+        let cursor = buffer.get_mark_idx(self.cursor).unwrap_or(0);
+        let start = cmp::min(anchor, cursor);
+        let end = cmp::min(cmp::max(anchor, cursor) + 1, text.len());
+
+        if lines {
+            Some((line_bounds(&text, start).0, line_bounds(&text, end.saturating_sub(1)).1))
+        } else {
+            Some((start, end))
+        }
+    }
+
+    /// Select from the cursor, or keep the start of the current selection
+    pub fn start_selection(&mut self, lines: bool) {
+        let anchor = match self.selection {
+            Some((anchor, _)) => anchor,
+            None => self.cursor_idx(),
+        };
+        self.selection = Some((anchor, lines));
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.selection = None;
+    }
+
+    /// Copy the text from start to end in the register, and remove it for
+    /// `Delete` and `Change`
+    fn operate(&mut self, operator: Operator, start: usize, end: usize, lines: bool) {
+        {
+            let mut buffer = self.buffer.lock().unwrap();
+            let text = buffer.text();
+            if start >= end || end > text.len() {
+                return;
+            }
+            let mut data = text[start..end].to_vec();
+            if lines && data.last() != Some(&b'\n') {
+                // The last line of the buffer
+                data.push(b'\n');
+            }
+            self.register = (data, lines);
+
+            // Changed lines are replaced by an empty line
+            let end = if operator == Operator::Change && lines && text[end - 1] == b'\n' {
+                end - 1
+            } else {
+                end
+            };
+
+            if operator != Operator::Yank && end > start {
+                buffer.remove_range(start, end);
+            }
+        }
+
+        // The cursor stays in the text, even after the last line is deleted
+        let len = self.buffer.lock().unwrap().len();
+        let idx = if start >= len { len.saturating_sub(1) } else { start };
+        let idx = if lines && operator == Operator::Delete {
+            line_bounds(&self.buffer.lock().unwrap().text(), idx).0
+        } else {
+            idx
+        };
+        self.set_cursor(idx);
+    }
+
+    /// Insert the register before or after the cursor, or its line for whole lines
+    pub fn paste(&mut self, anchor: Anchor) {
+        let (data, lines) = self.register.clone();
+        if data.is_empty() {
+            return;
+        }
+
+        let cursor = {
+            let mut buffer = self.buffer.lock().unwrap();
+            let text = buffer.text();
+            let cursor = buffer.get_mark_idx(self.cursor).unwrap_or(0);
+
+            let (idx, data, cursor) = match (lines, anchor) {
+                (true, Anchor::Before) => {
+                    let idx = line_bounds(&text, cursor).0;
+                    (idx, data, idx)
+                }
+                (true, _) => {
+                    let idx = line_bounds(&text, cursor).1;
+                    if idx == text.len() && text.last() != Some(&b'\n') {
+                        // After the last line, without a newline at its end
+                        let mut line = vec![b'\n'];
+                        line.extend_from_slice(&data[..data.len() - 1]);
+                        (idx, line, idx + 1)
+                    } else {
+                        (idx, data, idx)
+                    }
+                }
+                (false, Anchor::Before) => (cursor, data.clone(), cursor + data.len() - 1),
+                (false, _) => {
+                    // Not after the end of the line
+                    let idx = match text.get(cursor) {
+                        Some(&b'\n') | None => cursor,
+                        Some(_) => cursor + 1,
+                    };
+                    (idx, data.clone(), idx + data.len() - 1)
+                }
+            };
+
+            buffer.replace_ranges(&[(idx, idx, data)]);
+            cursor
+        };
+
+        self.set_cursor(cursor);
+    }
+
+    /// Insert an empty line before or after the line of the cursor, the cursor
+    /// goes to it
+    pub fn open_line(&mut self, anchor: Anchor) {
+        let idx = {
+            let mut buffer = self.buffer.lock().unwrap();
+            let text = buffer.text();
+            let cursor = buffer.get_mark_idx(self.cursor).unwrap_or(0);
+            let (start, end) = line_bounds(&text, cursor);
+
+            let idx = match anchor {
+                Anchor::Before => start,
+                // Before the newline of the line
+                _ => if end > start && text[end - 1] == b'\n' { end - 1 } else { end },
+            };
+            buffer.replace_ranges(&[(idx, idx, vec![b'\n'])]);
+
+            match anchor {
+                Anchor::Before => idx,
+                _ => idx + 1,
+            }
+        };
+
+        self.set_cursor(idx);
+    }
+
+    /// Move the cursor to `line`, counted from 1, or to the last line for 0
+    pub fn goto_line_number(&mut self, line: usize) {
+        let line = if line == 0 {
+            self.buffer.lock().unwrap().lines().count()
+        } else {
+            line
+        };
+        self.move_cursor_to_line(line);
+    }
+
     pub fn undo(&mut self) {
         {
             let mut buffer = self.buffer.lock().unwrap();
@@ -504,6 +1347,14 @@ impl View {
 
 }
 
+/// Start of the line of `idx`, and the start of the next line
+fn line_bounds(text: &[u8], idx: usize) -> (usize, usize) {
+    let idx = cmp::min(idx, text.len());
+    let start = text[..idx].iter().rposition(|ch| *ch == b'\n').map_or(0, |pos| pos + 1);
+    let end = text[idx..].iter().position(|ch| *ch == b'\n').map_or(text.len(), |pos| idx + pos + 1);
+    (start, end)
+}
+
 pub fn draw_line(rb: &mut Screen, line: &[u8], idx: usize, left: usize) {
     let width = rb.width - 1;
     let mut x = 0;
@@ -543,7 +1394,7 @@ pub fn draw_line(rb: &mut Screen, line: &[u8], idx: usize, left: usize) {
 
 
 pub fn print_char(screen: &mut Screen, data: char, x: i32, y: i32, col: i32) {
-    screen.print_char(data, x * 4, 16 + y * 7, col);
+    screen.print_char(data, GUTTER + x * 4, 16 + y * 7, col);
 }
 
 #[cfg(test)]
@@ -554,8 +1405,12 @@ mod tests {
 
     use syntect::highlighting::ThemeSet;
 
-    use view::View;
+    use view::{layout, Split, View};
     use buffer::Buffer;
+    use command::Operator;
+    use textobject::{Anchor, Kind, Offset, TextObject};
+    use buffer::Mark;
+    use unicorn::editor::text_editor::search::parse_substitute;
 
     fn setup_view(testcase: &'static str) -> View {
         let theme_name = String::from("base16-eighties.dark");
@@ -581,4 +1436,185 @@ mod tests {
             assert_eq!(buffer.lines().next().unwrap(), b"ttest\n");
         }
     }
+
+    #[test]
+    fn test_move_cursor_to_line() {
+        let mut view = setup_view("first\nsecond\nthird");
+        view.move_cursor_to_line(3);
+        view.insert_char('x');
+
+        {
+            let mut buffer = view.buffer.lock().unwrap();
+            assert_eq!(buffer.lines().nth(2).unwrap(), b"xthird");
+        }
+
+        // After the last line
+        view.move_cursor_to_line(10);
+        view.insert_char('y');
+
+        {
+            let mut buffer = view.buffer.lock().unwrap();
+            assert_eq!(buffer.lines().nth(2).unwrap(), b"xthirdy");
+        }
+    }
+
+    /// View of `testcase`, without typing it
+    fn setup_text_view(testcase: &'static str) -> View {
+        let theme_name = String::from("base16-eighties.dark");
+        let theme_set = Rc::new(ThemeSet::load_defaults());
+        let buffer = Arc::new(Mutex::new(Buffer::new()));
+        buffer.lock().unwrap().replace_ranges(&[(0, 0, testcase.bytes().collect())]);
+        View::new(buffer, theme_set, theme_name, 50, 50)
+    }
+
+    fn text(view: &View) -> String {
+        String::from_utf8(view.buffer.lock().unwrap().text()).unwrap()
+    }
+
+    fn next_word() -> TextObject {
+        TextObject {
+            kind: Kind::Word(Anchor::Start),
+            offset: Offset::Forward(1, Mark::Cursor(0)),
+        }
+    }
+
+    #[test]
+    fn test_delete_and_paste_lines() {
+        let mut view = setup_text_view("first\nsecond\nthird\n");
+        view.apply_lines(Operator::Delete, 2);
+        assert_eq!(text(&view), "third\n");
+
+        view.paste(Anchor::After);
+        assert_eq!(text(&view), "third\nfirst\nsecond\n");
+
+        // The paste is undone at once
+        view.undo();
+        assert_eq!(text(&view), "third\n");
+    }
+
+    #[test]
+    fn test_delete_with_counts() {
+        // `3dd`
+        let mut view = setup_text_view("one\ntwo\nthree\nfour\n");
+        view.apply_lines(Operator::Delete, 3);
+        assert_eq!(text(&view), "four\n");
+        assert_eq!(view.register, (b"one\ntwo\nthree\n".to_vec(), true));
+
+        // `2dw`
+        let mut view = setup_text_view("one two three");
+        view.apply(Operator::Delete, TextObject {
+            kind: Kind::Word(Anchor::Start),
+            offset: Offset::Forward(2, Mark::Cursor(0)),
+        });
+        assert_eq!(text(&view), "three");
+        assert_eq!(view.register, (b"one two ".to_vec(), false));
+    }
+
+    #[test]
+    fn test_change_and_yank_word() {
+        let mut view = setup_text_view("one two three");
+        view.apply(Operator::Change, next_word());
+        assert_eq!(text(&view), " two three");
+        assert_eq!(view.register, (b"one".to_vec(), false));
+
+        let mut view = setup_text_view("one two three");
+        view.apply(Operator::Yank, next_word());
+        assert_eq!(text(&view), "one two three");
+
+        view.paste(Anchor::Before);
+        assert_eq!(text(&view), "one one two three");
+    }
+
+    #[test]
+    fn test_open_line() {
+        let mut view = setup_text_view("first\nsecond");
+        view.open_line(Anchor::After);
+        view.insert_char('x');
+        assert_eq!(text(&view), "first\nx\nsecond");
+
+        view.open_line(Anchor::Before);
+        assert_eq!(text(&view), "first\n\nx\nsecond");
+    }
+
+    #[test]
+    fn test_visual_selection() {
+        let mut view = setup_text_view("first\nsecond\nthird\n");
+        view.start_selection(true);
+        view.move_cursor_to_line(2);
+        view.apply_selection(Operator::Delete);
+        assert_eq!(text(&view), "third\n");
+        assert!(view.selection.is_none());
+
+        // The char under the cursor is selected
+        let mut view = setup_text_view("first\nsecond\n");
+        view.start_selection(false);
+        view.buffer.lock().unwrap().set_mark(Mark::Cursor(0), 2);
+        view.apply_selection(Operator::Delete);
+        assert_eq!(text(&view), "st\nsecond\n");
+    }
+
+    /// Text after the substitution `command` at `cursor`, with the `answers`
+    /// to the confirmations
+    fn substitute(testcase: &'static str, cursor: usize, command: &str, answers: &str) -> String {
+        let mut view = setup_text_view(testcase);
+        view.set_cursor(cursor);
+        view.substitute(parse_substitute(command).unwrap());
+        for answer in answers.chars() {
+            view.answer_replace(Some(answer.to_string()));
+        }
+        text(&view)
+    }
+
+    #[test]
+    fn test_substitute() {
+        let testcase = "a a\na a\na a\n";
+
+        // Line of the cursor
+        assert_eq!(substitute(testcase, 5, "s/a/b/", "a"), "a a\nb a\na a\n");
+        assert_eq!(substitute(testcase, 5, "s/a/b/g", "a"), "a a\nb b\na a\n");
+        assert_eq!(substitute(testcase, 5, "s/a/b/g", "ny"), "a a\na b\na a\n");
+
+        // Whole buffer
+        assert_eq!(substitute(testcase, 5, "%s/a/b/", "a"), "b a\nb a\nb a\n");
+        assert_eq!(substitute(testcase, 5, "%s/a/b/", "yny"), "b a\na a\nb a\n");
+        assert_eq!(substitute(testcase, 5, "%s/a/b/g", "a"), "b b\nb b\nb b\n");
+
+        assert_eq!(substitute("a/b a\n", 0, "s/a\\/b/c/", "y"), "c a\n");
+    }
+
+    #[test]
+    fn test_layout() {
+        assert_eq!(layout(None, 400, 240), vec![(0, 0, 99, 30)]);
+        assert_eq!(layout(Some(Split::Horizontal), 400, 240),
+                   vec![(0, 0, 99, 15), (0, 105, 99, 15)]);
+        assert_eq!(layout(Some(Split::Vertical), 400, 240),
+                   vec![(0, 0, 49, 30), (200, 0, 49, 30)]);
+
+        // The second view takes the odd row or pixels
+        assert_eq!(layout(Some(Split::Horizontal), 400, 247),
+                   vec![(0, 0, 99, 15), (0, 105, 99, 16)]);
+        assert_eq!(layout(Some(Split::Vertical), 409, 240),
+                   vec![(0, 0, 50, 30), (204, 0, 50, 30)]);
+    }
+
+    #[test]
+    fn test_split_focus() {
+        let mut view = setup_text_view("first\nsecond\n");
+        let mut other = view.split(1);
+        assert!(!other.focused);
+
+        // The commands of the focused view move the marks 0
+        view.move_cursor_to_line(2);
+        view.insert_char('x');
+        other.refresh_marks();
+        assert_eq!(other.cursor_idx(), 0);
+
+        other.take_focus(&mut view);
+        assert!(other.focused && !view.focused);
+        assert_eq!(other.cursor, Mark::Cursor(0));
+        assert_eq!(view.cursor, Mark::Cursor(1));
+        assert_eq!(view.cursor_idx(), 7);
+        other.insert_char('y');
+        assert_eq!(text(&other), "yfirst\nxsecond\n");
+    }
 }
@@ -0,0 +1,170 @@
+use std::cmp::max;
+use std::sync::{Arc, Mutex};
+
+use config::Players;
+use gfx;
+use plugins::error::ScriptError;
+
+/// Choices of the error screen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorAction {
+    OpenEditor,
+    Restart,
+    CopyToLog,
+}
+
+const ACTIONS: [(ErrorAction, &'static str); 3] = [(ErrorAction::OpenEditor, "Open in editor"),
+                                                   (ErrorAction::Restart, "Restart cart"),
+                                                   (ErrorAction::CopyToLog, "Copy to log")];
+
+/// Screen displayed instead of the cartridge when one of its scripts failed
+pub struct ErrorScreen {
+    idx: usize,
+    copied: bool,
+}
+
+impl ErrorScreen {
+    pub fn new() -> ErrorScreen {
+        ErrorScreen {
+            idx: 0,
+            copied: false,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.idx = 0;
+        self.copied = false;
+    }
+
+    /// The selected action, if any
+    pub fn update(&mut self, players: Arc<Mutex<Players>>) -> Option<ErrorAction> {
+        let players = &mut players.lock().unwrap();
+
+        if players.btnp(0, 4) {
+            let action = ACTIONS[self.idx].0;
+            if action == ErrorAction::CopyToLog {
+                self.copied = true;
+            }
+            return Some(action);
+        }
+
+        if players.btnp(0, 2) && self.idx > 0 {
+            self.idx -= 1;
+        }
+
+        if players.btnp(0, 3) && self.idx < ACTIONS.len() - 1 {
+            self.idx += 1;
+        }
+
+        None
+    }
+
+    /// Drawn with the pico-8 font, the font of the cartridge is restored after
+    pub fn draw(&mut self, error: &ScriptError, screen: &mut gfx::Screen) {
+        let width = screen.width as i32;
+        let height = screen.height as i32;
+        // 4 pixels by character with the pico-8 font
+        let columns = max((width - 8) / 4, 1) as usize;
+
+        let font = screen.font;
+        screen.font("pico-8");
+        screen.cls(-1);
+        screen.rectfill(0, 0, width, 10, 8);
+        screen.print("SCRIPT ERROR".to_string(), 4, 2, 7);
+
+        // Keep the room of the actions
+        let bottom = height - 8 * (ACTIONS.len() as i32 + 2);
+        let mut y = 16;
+
+        if let (&Some(ref file), Some(line)) = (&error.file, error.line) {
+            screen.print(format!("{} line {}", file, line), 4, y, 10);
+            y += 10;
+        }
+
+        for (line, color) in wrap(error, columns) {
+            if y + 8 > bottom {
+                break;
+            }
+            screen.print(line, 4, y, color);
+            y += 8;
+        }
+
+        for (pos, &(_, label)) in ACTIONS.iter().enumerate() {
+            let y = bottom + 4 + (pos as i32) * 8;
+            if pos == self.idx {
+                screen.print(">".to_string(), 4, y, 7);
+            }
+            screen.print(label.to_string(), 12, y, 7);
+        }
+
+        if self.copied {
+            screen.print("Copied to the log".to_string(), width / 2, bottom + 4 + 16, 11);
+        }
+
+        screen.font = font;
+    }
+}
+
+/// Lines of the message and of the traceback cut at `columns` characters, with
+/// their color
+fn wrap(error: &ScriptError, columns: usize) -> Vec<(String, i32)> {
+    let mut lines = Vec::new();
+
+    let message = error.message.lines().map(|line| (line, 7));
+    let traceback = error.traceback.iter().map(|line| (line.as_str(), 6));
+    for (line, color) in message.chain(traceback) {
+        let chars: Vec<char> = line.chars().collect();
+        for chunk in chars.chunks(columns) {
+            lines.push((chunk.iter().collect::<String>(), color));
+        }
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wrap_the_lines() {
+        let mut error = ScriptError::new("xxxxxxxxxx\nabc");
+        error.traceback = vec!["in _draw".to_string()];
+
+        assert_eq!(wrap(&error, 4),
+                   vec![("xxxx".to_string(), 7),
+                        ("xxxx".to_string(), 7),
+                        ("xx".to_string(), 7),
+                        ("abc".to_string(), 7),
+                        ("in _".to_string(), 6),
+                        ("draw".to_string(), 6)]);
+    }
+
+    #[test]
+    fn draw_above_the_actions() {
+        let mut screen = gfx::Screen::new(400, 240);
+        screen.init();
+        screen.font("bbc");
+
+        let message: Vec<String> = (0..100).map(|idx| format!("line {}", idx)).collect();
+        let mut error = ScriptError::new(&message.join("\n"));
+        error.file = Some("main.lua".to_string());
+        error.line = Some(3);
+
+        ErrorScreen::new().draw(&error, &mut screen);
+        assert_eq!(screen.get_font(), "bbc");
+
+        // Title bar
+        assert_eq!(screen.pget(0, 0), 8);
+
+        // The message stops before the actions
+        let bottom = 240 - 8 * (ACTIONS.len() as u32 + 2);
+        for y in bottom - 2..bottom + 4 {
+            assert!((0..400).all(|x| screen.pget(x, y) == 0));
+        }
+        assert!((16..bottom).any(|y| (0..400).any(|x| screen.pget(x, y) == 7)));
+
+        // The selected action
+        assert!((bottom + 4..bottom + 12).any(|y| (4..8).any(|x| screen.pget(x, y) == 7)));
+    }
+}
@@ -7,6 +7,13 @@ pub struct Info {
     pub seconds: f64,
     pub milliseconds: i64,
     pub elapsed_time: f64,
+    /// Measures of the previous frame, read by `stat`
+    pub fps: f64,
+    pub update_time: f64,
+    pub draw_time: f64,
+    pub memory: f64,
+    /// Measure of the memory called by `stat`, instead of `memory`
+    pub sample_memory: Option<fn() -> f64>,
 }
 
 
@@ -18,6 +25,11 @@ impl Info {
             seconds: 0.0,
             milliseconds: 0,
             elapsed_time: 0.0,
+            fps: 0.0,
+            update_time: 0.0,
+            draw_time: 0.0,
+            memory: 0.0,
+            sample_memory: None,
         }
     }
 
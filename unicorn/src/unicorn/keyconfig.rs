@@ -0,0 +1,151 @@
+use std::sync::{Arc, Mutex};
+
+use config::{Players, MAX_PLAYERS};
+use config::bindings::user_config_path;
+use config::keys::PX8_KEYS;
+use gfx;
+
+/// Number of frames to ignore the input after a capture, so the captured
+/// button doesn't trigger the menu
+const DEBOUNCE_FRAMES: u32 = 10;
+
+/// Key configuration screen, reachable from the pause menu
+pub struct KeyConfig {
+    player: u8,
+    idx: usize,
+    capture: bool,
+    debounce: u32,
+}
+
+impl KeyConfig {
+    pub fn new() -> KeyConfig {
+        KeyConfig {
+            player: 0,
+            idx: 0,
+            capture: false,
+            debounce: 0,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.player = 0;
+        self.idx = 0;
+        self.capture = false;
+        self.debounce = DEBOUNCE_FRAMES;
+    }
+
+    fn items_len(&self) -> usize {
+        // All the buttons + "Reset" + "Back"
+        PX8_KEYS.len() + 2
+    }
+
+    /// Returns false when the configuration screen is closed
+    pub fn update(&mut self, players: Arc<Mutex<Players>>) -> bool {
+        let players = &mut players.lock().unwrap();
+
+        if self.debounce > 0 {
+            self.debounce -= 1;
+            players.take_last_input();
+            return true;
+        }
+
+        if self.capture {
+            if let Some(input) = players.take_last_input() {
+                let key = PX8_KEYS[self.idx];
+
+                info!("[Unicorn][KeyConfig] Player {:?} {:?} -> {:?}",
+                      self.player,
+                      key,
+                      input);
+
+                players.bindings.set(self.player, key, input);
+
+                self.capture = false;
+                self.debounce = DEBOUNCE_FRAMES;
+            }
+            return true;
+        }
+
+        if players.btnp(0, 2) && self.idx > 0 {
+            self.idx -= 1;
+        }
+
+        if players.btnp(0, 3) && self.idx < self.items_len() - 1 {
+            self.idx += 1;
+        }
+
+        if players.btnp(0, 0) && self.player > 0 {
+            self.player -= 1;
+        }
+
+        if players.btnp(0, 1) && self.player < MAX_PLAYERS - 1 {
+            self.player += 1;
+        }
+
+        if players.btnp(0, 4) {
+            if self.idx < PX8_KEYS.len() {
+                players.take_last_input();
+                self.capture = true;
+            } else if self.idx == PX8_KEYS.len() {
+                info!("[Unicorn][KeyConfig] Reset the bindings");
+                players.bindings.reset();
+            } else {
+                players.save_bindings(&user_config_path());
+                return false;
+            }
+        } else if players.btnp(0, 5) {
+            players.save_bindings(&user_config_path());
+            return false;
+        }
+
+        true
+    }
+
+    pub fn draw(&mut self, players: Arc<Mutex<Players>>, screen: &mut gfx::Screen) {
+        let players = players.lock().unwrap();
+
+        screen.cls(-1);
+
+        screen.print(format!("KEY CONFIG  < PLAYER {:?} >", self.player + 1),
+                     10,
+                     4,
+                     7);
+        screen.print("LEFT/RIGHT: PLAYER  A: BIND  B: SAVE AND BACK".to_string(),
+                     10,
+                     14,
+                     6);
+
+        let offset_y = 28;
+
+        for (pos, key) in PX8_KEYS.iter().enumerate() {
+            let y = offset_y + pos as i32 * 10;
+            let color = if pos == self.idx { 7 } else { 6 };
+
+            screen.print(format!("{:?}", key), 20, y, color);
+
+            if self.capture && pos == self.idx {
+                screen.print("PRESS A KEY, BUTTON OR AXIS".to_string(), 90, y, 8);
+            } else {
+                let inputs: Vec<String> = players
+                    .bindings
+                    .inputs(self.player, *key)
+                    .iter()
+                    .map(|input| input.name())
+                    .collect();
+                screen.print(inputs.join(" "), 90, y, color);
+            }
+        }
+
+        let y = offset_y + PX8_KEYS.len() as i32 * 10;
+        screen.print("RESET".to_string(),
+                     20,
+                     y,
+                     if self.idx == PX8_KEYS.len() { 7 } else { 6 });
+        screen.print("BACK".to_string(),
+                     20,
+                     y + 10,
+                     if self.idx == PX8_KEYS.len() + 1 { 7 } else { 6 });
+
+        screen.print(">".to_string(), 12, offset_y + self.idx as i32 * 10, 3);
+    }
+}
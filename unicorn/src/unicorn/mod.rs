@@ -1,19 +1,27 @@
+pub mod autotile;
 pub mod editor;
+pub mod error_screen;
 pub mod info;
+pub mod keyconfig;
 pub mod cartdata;
 pub mod emscripten;
 pub mod noise;
 pub mod math;
 pub mod packet;
+pub mod repl;
+pub mod sheet;
+pub mod tiled;
 pub mod wfc;
 
 use std::fs;
+use std::io;
 use std::collections::HashMap;
 use std::io::Cursor;
 use std::sync::{Arc, Mutex};
 use time;
 use std::fmt;
 use std::cmp::max;
+use std::mem;
 use rand;
 use rand::Rng;
 
@@ -32,9 +40,20 @@ use std::fs::File;
 use plugins::lua_plugin::plugin::LuaPlugin;
 use plugins::python_plugin::plugin::PythonPlugin;
 use plugins::javascript_plugin::plugin::JavascriptPlugin;
+use plugins::wasm_plugin::plugin::WasmPlugin;
+use plugins::dylib_plugin;
+use plugins::dylib_plugin::plugin::DylibPlugin;
+use plugins::api::ApiContext;
+use plugins::error::ScriptError;
+use plugins::sandbox::SandboxPolicy;
 
 use config::Players;
+use config::bindings::user_config_path;
+use config::scancode::Scancode;
+use self::error_screen::{ErrorAction, ErrorScreen};
+use self::keyconfig::KeyConfig;
 use self::noise::Noise;
+use self::repl::{Repl, ReplAction, ReplServer};
 use gfx;
 use cartridge::{Cartridge, CartridgeFormat};
 use sound::sound::{Sound, SoundInternal};
@@ -94,6 +113,25 @@ impl Palette {
         }
     }
 
+    /// Colors that the sprites can use, by index, the missing ones are black
+    pub fn colors(&self) -> Vec<RGB> {
+        let len = self.colors
+            .keys()
+            .filter(|color| **color < 256)
+            .max()
+            .map(|color| *color as usize + 1)
+            .unwrap_or(0);
+
+        let mut colors = vec![RGB::new(0, 0, 0); len];
+        for (color, rgb_value) in &self.colors {
+            if (*color as usize) < len {
+                colors[*color as usize] = rgb_value.clone();
+            }
+        }
+
+        colors
+    }
+
     pub fn get_color(&mut self, color: u32) -> u32 {
         match self.colors.get(&color) {
             Some(rgb_value) => {
@@ -151,6 +189,7 @@ pub enum Code {
     PYTHON = 2,
     RUST = 3,
     JAVASCRIPT = 4,
+    WASM = 5,
 }
 
 
@@ -271,6 +310,10 @@ impl PauseMenu {
         self.selected_idx == self.items.len() as i32 - 2
     }
 
+    pub fn config(&mut self) -> bool {
+        self.selected_idx == 1
+    }
+
     pub fn update(&mut self, players: Arc<Mutex<Players>>) -> bool {
         if players.lock().unwrap().btnp(0, 4) {
             self.selected_idx = self.idx as i32;
@@ -317,10 +360,6 @@ impl PauseMenu {
             }
 
         }
-
-        if self.selected_idx == 1 {
-            screen.cls(-1);
-        }
     }
 }
 
@@ -498,6 +537,10 @@ impl Palettes {
         PALETTE.lock().unwrap().set_colors(colors);
     }
 
+    /// Colors of the current palette, by index
+    pub fn colors(&self) -> Vec<RGB> {
+        PALETTE.lock().unwrap().colors()
+    }
 
     pub fn get_color(&mut self, color: u32) -> u32 {
         PALETTE.lock().unwrap().get_color(color)
@@ -515,6 +558,8 @@ impl Palettes {
 pub struct UnicornConfig {
     pub show_info_overlay: bool,
     pub show_mouse: bool,
+    /// Number of operations kept by the undo history of the editors
+    pub history_depth: usize,
 }
 
 impl UnicornConfig {
@@ -522,6 +567,7 @@ impl UnicornConfig {
         UnicornConfig {
             show_info_overlay: false,
             show_mouse: false,
+            history_depth: editor::history::HISTORY_DEPTH,
         }
     }
 
@@ -532,6 +578,10 @@ impl UnicornConfig {
     pub fn toggle_mouse(&mut self, value: bool) {
         self.show_mouse = value;
     }
+
+    pub fn set_history_depth(&mut self, depth: usize) {
+        self.history_depth = depth;
+    }
 }
 
 pub struct UnicornCartridge {
@@ -544,6 +594,7 @@ pub struct UnicornCartridge {
     pub python_plugin: PythonPlugin,
     pub rust_plugin: Vec<Box<RustPlugin>>,
     pub javascript_plugin: JavascriptPlugin,
+    pub wasm_plugin: WasmPlugin,
     pub music_track: Vec<chiptune::ChiptuneSong>,
     pub sound_tracks: HashMap<String, chiptune::ChiptuneSound>,
     pub sound_tracks_name: Vec<String>,
@@ -570,6 +621,7 @@ impl UnicornCartridge {
             lua_plugin: LuaPlugin::new(),
             python_plugin: PythonPlugin::new(),
             javascript_plugin: JavascriptPlugin::new(),
+            wasm_plugin: WasmPlugin::new(),
             rust_plugin: Vec::new(),
             music_track: Vec::new(),
             sound_tracks: HashMap::new(),
@@ -588,6 +640,7 @@ impl UnicornCartridge {
             python_plugin: PythonPlugin::new(),
             rust_plugin: Vec::new(),
             javascript_plugin: JavascriptPlugin::new(),
+            wasm_plugin: WasmPlugin::new(),
             music_track: Vec::new(),
             sound_tracks: HashMap::new(),
             sound_tracks_name: Vec::new(),
@@ -599,18 +652,113 @@ impl UnicornCartridge {
             "lua" => Code::LUA,
             "python" => Code::PYTHON,
             "javascript" => Code::JAVASCRIPT,
+            "wasm" => Code::WASM,
+            "rust" => Code::RUST,
             _ => Code::UNKNOWN,
         }
     }
 
+    /// Language of the code, like "lua"
+    pub fn get_code_name(&mut self) -> String {
+        self.cartridge.code.get_name()
+    }
+
     pub fn get_code(&mut self) -> String {
         self.cartridge.code.get_data().clone()
     }
 
+    /// Evaluate a line of the REPL in the virtual machine of the script
+    pub fn repl(&mut self, code: &str) -> Result<String, ScriptError> {
+        match self.get_code_type() {
+            Code::LUA => self.lua_plugin.repl(code),
+            Code::JAVASCRIPT => self.javascript_plugin.repl(code),
+            Code::PYTHON => self.python_plugin.repl(code),
+            Code::WASM => self.wasm_plugin.repl(code),
+            _ => Err(ScriptError::new("no REPL for this cartridge")),
+        }
+    }
+
+    /// Names of the global variables of the script
+    pub fn globals(&mut self) -> Vec<String> {
+        match self.get_code_type() {
+            Code::LUA => self.lua_plugin.globals(),
+            Code::JAVASCRIPT => self.javascript_plugin.globals(),
+            Code::PYTHON => self.python_plugin.globals(),
+            Code::WASM => self.wasm_plugin.globals(),
+            _ => Vec::new(),
+        }
+    }
+
+    /// Error of the last failed call of the script
+    pub fn take_error(&mut self) -> Option<ScriptError> {
+        match self.get_code_type() {
+            Code::LUA => self.lua_plugin.take_error(),
+            Code::JAVASCRIPT => self.javascript_plugin.take_error(),
+            Code::PYTHON => self.python_plugin.take_error(),
+            Code::WASM => self.wasm_plugin.take_error(),
+            _ => None,
+        }
+    }
+
+    /// Memory used by the virtual machine of the code, for `stat`
+    pub fn memory(&mut self) -> f64 {
+        match self.get_code_type() {
+            Code::LUA => self.lua_plugin.memory(),
+            Code::JAVASCRIPT => self.javascript_plugin.memory(),
+            _ => 0.0,
+        }
+    }
+
+    /// Measure of the memory, for the virtual machines too slow to measure it
+    /// at each frame
+    pub fn sample_memory(&mut self) -> Option<fn() -> f64> {
+        match self.get_code_type() {
+            Code::PYTHON => Some(PythonPlugin::memory),
+            _ => None,
+        }
+    }
+
+    /// Resume the tasks of the scheduler, see `plugins::scheduler`
+    pub fn tick(&mut self) {
+        match self.get_code_type() {
+            Code::LUA => self.lua_plugin.tick(),
+            Code::JAVASCRIPT => self.javascript_plugin.tick(),
+            Code::PYTHON => self.python_plugin.tick(),
+            _ => (),
+        }
+    }
+
     pub fn set_code(&mut self, data: Vec<String>) {
         self.cartridge.code.set_data(data);
     }
 
+    /// Names and sources of the other code files
+    pub fn get_code_files(&mut self) -> Vec<(String, String)> {
+        self.cartridge
+            .code
+            .files
+            .iter()
+            .map(|file| (file.name.clone(), file.get_data()))
+            .collect()
+    }
+
+    /// Code files of the editor, by name
+    pub fn set_code_files(&mut self, files: Vec<(String, Vec<String>)>) {
+        self.cartridge.code.set_files_data(files);
+    }
+
+    /// Give the code files to the plugin, found by `require`/`import`
+    pub fn set_files(&mut self) {
+        let files = self.cartridge.code.get_files();
+
+        match self.get_code_type() {
+            Code::LUA => self.lua_plugin.set_files(files),
+            Code::JAVASCRIPT => self.javascript_plugin.set_files(files),
+            Code::PYTHON => self.python_plugin.set_files(files),
+            _ => (),
+        }
+    }
+
     pub fn get_palettes(&mut self) -> HashMap<u32, RGB> {
         self.cartridge.palette.colors.clone()
     }
@@ -634,6 +782,7 @@ pub struct Unicorn {
     pub interactive: bool,
     pub state: UnicornState,
     pub pause_menu: PauseMenu,
+    pub keyconfig: KeyConfig,
     pub fps: f64,
     pub draw_time: f64,
     pub init_time: f64,
@@ -642,6 +791,14 @@ pub struct Unicorn {
     pub draw_return: bool,
     pub update_return: bool,
     pub mouse_spr: Vec<u8>,
+    /// Sandbox of the scripts, disabled for the trusted cartridges
+    pub sandbox: bool,
+    /// Error of the failed script, displayed instead of the cartridge
+    pub script_error: Option<ScriptError>,
+    pub error_screen: ErrorScreen,
+    /// Console overlay, and its server started by `start_repl_server`
+    pub repl: Repl,
+    pub repl_server: Option<ReplServer>,
     pub version: u32,
     pub major_version: u32,
     pub minor_version: u32,
@@ -653,7 +810,7 @@ impl Unicorn {
 
         let sound_internal = Arc::new(Mutex::new(SoundInternal::new()));
         let csend = sound_internal.lock().unwrap().csend.clone();
-        let screen = Arc::new(Mutex::new(gfx::Screen::new(400, 240)));
+        let screen = Arc::new(Mutex::new(gfx::Screen::new(gfx::SCREEN_WIDTH, gfx::SCREEN_HEIGHT)));
 
         Unicorn {
             screen: screen.clone(),
@@ -672,6 +829,7 @@ impl Unicorn {
             interactive: false,
             state: UnicornState::RUN,
             pause_menu: PauseMenu::new(),
+            keyconfig: KeyConfig::new(),
             menu: Menu::new(),
             fps: 0.0,
             draw_time: 0.0,
@@ -681,6 +839,11 @@ impl Unicorn {
             draw_return: true,
             update_return: true,
             mouse_spr: Unicorn::mouse_sprite(),
+            sandbox: true,
+            script_error: None,
+            error_screen: ErrorScreen::new(),
+            repl: Repl::new(),
+            repl_server: None,
             version: VERSION,
             major_version: MAJOR_VERSION,
             minor_version: MINOR_VERSION,
@@ -692,6 +855,7 @@ impl Unicorn {
 
         self.sound_internal.lock().unwrap().init();
         self.palettes.lock().unwrap().init();
+        self.players.lock().unwrap().load_bindings(&user_config_path());
 
         self.reset();
     }
@@ -721,6 +885,7 @@ impl Unicorn {
         info!("[Unicorn] Reset");
 
         self.configuration.lock().unwrap().toggle_mouse(false);
+        self.players.lock().unwrap().virtual_pad.reset();
 
         self.palettes.lock().unwrap().reset();
         self.palettes.lock().unwrap().switch_to_palette("pico-8");
@@ -735,6 +900,66 @@ impl Unicorn {
         self.interactive = true;
     }
 
+    pub fn toggle_repl(&mut self) {
+        self.repl.toggle();
+    }
+
+    /// Evaluate a line in the current cartridge
+    pub fn eval(&mut self, code: &str) -> Result<String, ScriptError> {
+        info!("[Unicorn][Repl] Eval {:?}", code);
+
+        match self.cartridges.get_mut(self.current_cartridge) {
+            Some(cartridge) => cartridge.repl(code),
+            None => Err(ScriptError::new("no cartridge")),
+        }
+    }
+
+    /// Text typed in the console
+    pub fn repl_text(&mut self, text: &str) {
+        self.repl.input_text(text);
+    }
+
+    /// Keys of the console, instead of the players when it is visible
+    pub fn repl_key(&mut self, scancode: Scancode) {
+        match self.repl.key(scancode) {
+            Some(ReplAction::Eval(code)) => {
+                let result = self.eval(&code);
+                self.repl.print_result(&result);
+            }
+            Some(ReplAction::Complete) => {
+                let names = match self.cartridges.get_mut(self.current_cartridge) {
+                    Some(cartridge) => cartridge.globals(),
+                    None => Vec::new(),
+                };
+                self.repl.complete(&names);
+            }
+            Some(ReplAction::Close) => self.repl.visible = false,
+            None => (),
+        }
+    }
+
+    /// Accept the clients of the console on a local address
+    /// The server only listens on the other addresses than the loopback without the sandbox
+    pub fn start_repl_server(&mut self, address: &str) -> io::Result<()> {
+        let remote = !self.sandbox;
+        self.repl_server = Some(try!(ReplServer::bind(address, remote)));
+        Ok(())
+    }
+
+    fn update_repl_server(&mut self) {
+        let lines = match self.repl_server {
+            Some(ref mut server) => server.poll(),
+            None => return,
+        };
+
+        for (client, code) in lines {
+            let result = self.eval(&code);
+            if let Some(ref mut server) = self.repl_server {
+                server.reply(client, &result);
+            }
+        }
+    }
+
     pub fn debug_draw(&mut self) {
         let show_info_overlay = self.configuration.lock().unwrap().show_info_overlay;
         if show_info_overlay {
@@ -771,8 +996,17 @@ impl Unicorn {
     }
 
     pub fn update(&mut self) -> bool {
+        self.update_repl_server();
+
         match self.state {
             UnicornState::PAUSE => {
+                if self.pause_menu.config() {
+                    if !self.keyconfig.update(self.players.clone()) {
+                        self.pause_menu.reset();
+                    }
+                    return true;
+                }
+
                 if self.pause_menu.stop() {
                     self.state = UnicornState::RUN;
                 }
@@ -782,14 +1016,23 @@ impl Unicorn {
                     self.sound_internal.lock().unwrap().stop();
                 }
 
-                return self.pause_menu.update(self.players.clone());
+                let return_value = self.pause_menu.update(self.players.clone());
+                if self.pause_menu.config() {
+                    self.keyconfig.reset();
+                }
+
+                return return_value;
             }
             UnicornState::RUN => {
                 if self.is_end() {
                     return false;
                 }
 
-                self.update_time = self.call_update() * 1000.0;
+                if self.script_error.is_none() {
+                    self.update_time = self.call_update() * 1000.0;
+                } else {
+                    self.update_error_screen();
+                }
             }
             UnicornState::INTERACTIVE => {
                 let return_value = self.menu.update(&mut self.cartridges, self.players.clone());
@@ -803,7 +1046,7 @@ impl Unicorn {
             }
             UnicornState::EDITOR => {
                 let cartridge = self.cartridges.get_mut(self.current_cartridge).unwrap();
-                return self.editor.update(cartridge, &mut self.screen.lock().unwrap(), self.players.clone(), self.sound_internal.clone(), self.sound.clone());
+                return self.editor.update(cartridge, &mut self.screen.lock().unwrap(), self.players.clone(), self.palettes.clone(), self.sound_internal.clone(), self.sound.clone());
             }
         }
         true
@@ -812,10 +1055,18 @@ impl Unicorn {
     pub fn draw(&mut self) {
         match self.state {
             UnicornState::PAUSE => {
-                self.pause_menu.draw(&mut self.screen.lock().unwrap());
+                if self.pause_menu.config() {
+                    self.keyconfig.draw(self.players.clone(), &mut self.screen.lock().unwrap());
+                } else {
+                    self.pause_menu.draw(&mut self.screen.lock().unwrap());
+                }
             }
             UnicornState::RUN => {
-                self.draw_time = self.call_draw() * 1000.0;
+                if self.script_error.is_none() {
+                    self.draw_time = self.call_draw() * 1000.0;
+                } else if let Some(ref error) = self.script_error {
+                    self.error_screen.draw(error, &mut self.screen.lock().unwrap());
+                }
             }
             UnicornState::INTERACTIVE => {
                 self.menu.draw(&mut self.cartridges, &mut self.screen.lock().unwrap());
@@ -827,6 +1078,10 @@ impl Unicorn {
             }
         }
 
+        if self.state == UnicornState::RUN {
+            self.draw_virtual_pad();
+        }
+
         if self.state != UnicornState::PAUSE && self.configuration.lock().unwrap().show_mouse {
             let mouse_x = self.players.lock().unwrap().mouse_coordinate(0);
             let mouse_y = self.players.lock().unwrap().mouse_coordinate(1);
@@ -844,6 +1099,10 @@ impl Unicorn {
             }
         }
 
+        if self.repl.visible {
+            self.repl.draw(&mut self.screen.lock().unwrap());
+        }
+
         if self.is_recording() {
             self.record();
         }
@@ -851,6 +1110,33 @@ impl Unicorn {
         self.debug_draw();
     }
 
+    /// Draw the virtual gamepad over the frame, without the camera and the clipping
+    pub fn draw_virtual_pad(&mut self) {
+        let players = self.players.lock().unwrap();
+        if !players.virtual_pad.visible() {
+            return;
+        }
+
+        let screen = &mut self.screen.lock().unwrap();
+
+        for button in players.virtual_pad.buttons.iter() {
+            let r = button.radius;
+            for y in -r..r + 1 {
+                for x in -r..r + 1 {
+                    let d = x * x + y * y;
+                    if d > r * r {
+                        continue;
+                    }
+
+                    // Outline when released, filled when pressed
+                    if button.pressed || d >= (r - 1) * (r - 1) {
+                        screen.putpixel_direct(button.x + x, button.y + y, 6);
+                    }
+                }
+            }
+        }
+    }
+
     pub fn is_end(&self) -> bool {
         !self.update_return
     }
@@ -985,9 +1271,14 @@ impl Unicorn {
             return;
         }
 
+        // The code and the code files of the tabs
+        let idx = self.current_cartridge;
+        self.cartridges[idx].set_code(self.editor.get_code());
+        self.cartridges[idx].set_code_files(self.editor.get_files());
+
         let screen = &self.screen.lock().unwrap();
 
-        let cartridge = &mut self.cartridges[self.current_cartridge].cartridge;
+        let cartridge = &mut self.cartridges[idx].cartridge;
 
         let output_filename = &cartridge.filename.clone();
         info!("[Unicorn][SAVE] Saving the current cartridge in {:?}",
@@ -997,6 +1288,8 @@ impl Unicorn {
         cartridge.gfx.set_sprites(screen.sprites.clone());
         info!("[Unicorn][SAVE] Set the new map");
         cartridge.map.set_map(screen.map.clone());
+        info!("[Unicorn][SAVE] Set the new autotile rules");
+        cartridge.autotile.set_rules(screen.autotiles.clone());
         info!("[Unicorn][SAVE] Set the new flags");
         cartridge.gff.set_flags(screen.sprites.clone());
 
@@ -1073,6 +1366,7 @@ impl Unicorn {
         info!("[Unicorn] Register new cartridge");
 
         let mut unicorn_cartridge = UnicornCartridge::empty("RustPlugin".to_string(), "RustPlugin".to_string());
+        unicorn_cartridge.cartridge.code.code_type = "rust".to_string();
         unicorn_cartridge.rust_plugin.push(Box::new(callback));
         self.add_cartridge(unicorn_cartridge);
     }
@@ -1097,6 +1391,11 @@ impl Unicorn {
             .unwrap()
             .set_map(cartridge.cartridge.map.map.clone());
 
+        self.screen
+            .lock()
+            .unwrap()
+            .set_autotiles(cartridge.cartridge.autotile.rules.clone());
+
         self.palettes.lock().unwrap().set_colors(cartridge.cartridge.palette.colors.clone());
     }
 
@@ -1107,56 +1406,103 @@ impl Unicorn {
         info!("[Unicorn] Loading cartridge {:?}", cartridge);
 
         let data = cartridge.get_code();
+        cartridge.set_files();
 
         let mut ret: bool = false;
 
+        self.script_error = None;
+
+        // The scripts can only access the directory of the cartridge
+        let root = match Path::new(&cartridge.full_filename).parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent.to_path_buf(),
+            _ => Path::new(".").to_path_buf(),
+        };
+        let sandbox = if self.sandbox {
+            SandboxPolicy::new(&root)
+        } else {
+            SandboxPolicy::unrestricted(&root)
+        };
+
+        let context = ApiContext::new(self.palettes.clone(),
+                                      self.players.clone(),
+                                      self.info.clone(),
+                                      self.screen.clone(),
+                                      self.sound.clone(),
+                                      self.noise.clone(),
+                                      self.configuration.clone());
+
         match cartridge.get_code_type() {
             Code::LUA => {
                 info!("[Unicorn] Loading LUA Plugin");
 
-                cartridge
-                    .lua_plugin
-                    .load(self.players.clone(),
-                          self.info.clone(),
-                          self.screen.clone(),
-                          self.noise.clone(),
-                          self.sound.clone());
+                cartridge.lua_plugin.load(context, sandbox);
 
                 ret = cartridge.lua_plugin.load_code(data.clone());
             }
             Code::JAVASCRIPT => {
                 info!("[Unicorn] Loading JAVASCRIPT Plugin");
 
-                cartridge
-                    .javascript_plugin
-                    .load(self.players.clone(),
-                          self.info.clone(),
-                          self.screen.clone(),
-                          self.noise.clone(),
-                          self.sound.clone());
+                cartridge.javascript_plugin.load(context, sandbox);
 
                 ret = cartridge.javascript_plugin.load_code(data.clone());
             }
             Code::PYTHON => {
                 info!("[Unicorn] Loading PYTHON Plugin");
 
-                cartridge
-                    .python_plugin
-                    .load(self.palettes.clone(),
-                          self.players.clone(),
-                          self.info.clone(),
-                          self.screen.clone(),
-                          self.sound.clone(),
-                          self.noise.clone(),
-                          self.configuration.clone());
+                cartridge.python_plugin.load(context, sandbox);
 
                 ret = cartridge.python_plugin.load_code(data.clone());
             }
+            Code::WASM => {
+                info!("[Unicorn] Loading WASM Plugin");
+
+                cartridge.wasm_plugin.load(context, sandbox);
+
+                ret = cartridge.wasm_plugin.load_code(data.clone());
+            }
+            Code::RUST => {
+                info!("[Unicorn] Loading RUST Plugin");
+
+                let library = data.trim();
+                if library.is_empty() {
+                    // Registered with Unicorn::register
+                    ret = !cartridge.rust_plugin.is_empty();
+                } else if self.sandbox {
+                    // A native library can't be sandboxed
+                    error!("[Unicorn] The rust plugin {:?} is only loaded with --unsafe",
+                           library);
+                } else {
+                    // Even unsafe, the library is a file of the cartridge
+                    match dylib_plugin::library_path(&root, library) {
+                        Some(path) => {
+                            match DylibPlugin::load(&path) {
+                                Ok(plugin) => {
+                                    cartridge.rust_plugin.clear();
+                                    cartridge.rust_plugin.push(Box::new(plugin));
+                                    ret = true;
+                                }
+                                Err(e) => {
+                                    error!("[Unicorn] Impossible to load the rust plugin {}", e)
+                                }
+                            }
+                        }
+                        None => {
+                            error!("[Unicorn] The rust plugin {:?} is outside of {:?}",
+                                   library,
+                                   root)
+                        }
+                    }
+                }
+            }
             _ => (),
         }
 
         info!("[Unicorn] LOAD CARTRIDGE {:?}", ret);
 
+        if !ret {
+            self.script_error = cartridge.take_error();
+        }
+
         if ret {
             self.editing = editor;
 
@@ -1166,7 +1512,9 @@ impl Unicorn {
                           self.palettes.clone(),
                           &mut self.screen.lock().unwrap(),
                           cartridge.cartridge.filename.clone(),
-                          data.clone());
+                          data.clone(),
+                          cartridge.get_code_files(),
+                          cartridge.get_code_name());
                 self.state = UnicornState::EDITOR;
             }
         }
@@ -1211,17 +1559,48 @@ impl Unicorn {
                 self.state = UnicornState::RUN;
             }
 
+            unicorn_cartridge.loaded = true;
             self.add_cartridge(unicorn_cartridge);
             self._setup_screen();
 
-            unicorn_cartridge.loaded = true;
-
             self.init();
+        } else if !editor && self.script_error.is_some() {
+            // Show the error of the code, and allow to fix it in the editor
+            self.state = UnicornState::RUN;
+            self.error_screen.reset();
+
+            self.add_cartridge(unicorn_cartridge);
+            self._setup_screen();
         }
 
         ret
     }
 
+    /// Reload the scripts of the current cartridge and run it again
+    pub fn restart(&mut self) {
+        info!("[Unicorn] Restart cartridge");
+
+        let idx = self.current_cartridge;
+        let mut cartridge = mem::replace(&mut self.cartridges[idx],
+                                         UnicornCartridge::empty("".to_string(), "".to_string()));
+
+        // Forget the state of the previous run
+        cartridge.lua_plugin = LuaPlugin::new();
+        cartridge.python_plugin = PythonPlugin::new();
+        cartridge.javascript_plugin = JavascriptPlugin::new();
+        cartridge.wasm_plugin = WasmPlugin::new();
+
+        let ret = self._load_cartridge(&mut cartridge, false);
+        self.cartridges[idx] = cartridge;
+        self.error_screen.reset();
+
+        if ret {
+            self.state = UnicornState::RUN;
+            self._setup_screen();
+            self.init();
+        }
+    }
+
     pub fn add_cartridge(&mut self, mut new_cartridge: UnicornCartridge) {
         info!("[Unicorn] ADD cartridge {:?}", new_cartridge.filename);
 
@@ -1273,15 +1652,23 @@ impl Unicorn {
         ret
     }
 
-    pub fn switch_code(&mut self) {
+    /// Switch between the editor and the cartridge, the code with a syntax error
+    /// is not run unless `force`. False if the editor is kept
+    pub fn switch_code(&mut self, force: bool) -> bool {
         info!("[Unicorn] Switch code");
 
         let idx = self.current_cartridge;
 
         if self.editing {
+            if !force && !self.editor.check_syntax() {
+                warn!("[Unicorn] Syntax error, the editor is kept");
+                return false;
+            }
+
             info!("[Unicorn] Switch editor to run");
 
             self.cartridges[idx].set_code(self.editor.get_code());
+            self.cartridges[idx].set_code_files(self.editor.get_files());
 
             // Reload the code for the Unicorn format
            /* match self.cartridges[idx].cartridge.format {
@@ -1298,6 +1685,7 @@ impl Unicorn {
 
             let data = self.cartridges[idx].get_code();
             let code_type = self.cartridges[idx].get_code_type();
+            self.cartridges[idx].set_files();
 
             match code_type {
                 Code::LUA => {
@@ -1309,12 +1697,19 @@ impl Unicorn {
                 Code::PYTHON => {
                     self.cartridges[idx].python_plugin.load_code(data);
                 }
+                Code::WASM => {
+                    self.cartridges[idx].wasm_plugin.load_code(data);
+                }
                 _ => (),
             }
 
             self.editing = false;
             self.state = UnicornState::RUN;
+            self.script_error = None;
             self.reset();
+
+            // Syntax error of the new code
+            self.check_script_error();
         } else {
             info!("[Unicorn] Switch run to editor");
             info!("[Unicorn] Back to {:?}/{:?}", self.current_cartridge, self.cartridges.len());
@@ -1325,17 +1720,66 @@ impl Unicorn {
                 self.load_cartridge(filename.as_str(), full_filename.as_str(), false);
             }
             let code = self.cartridges[self.current_cartridge].get_code();
+            let files = self.cartridges[self.current_cartridge].get_code_files();
+            let language = self.cartridges[self.current_cartridge].get_code_name();
 
             self.editor
                 .init(self.configuration.clone(),
                       self.palettes.clone(),
                       &mut self.screen.lock().unwrap(),
                       filename,
-                      code);
+                      code,
+                      files,
+                      language);
             self.editing = true;
             self.state = UnicornState::EDITOR;
             self.sound_internal.lock().unwrap().stop();
         }
+
+        true
+    }
+
+    /// Stop the cartridge when one of its scripts failed
+    fn check_script_error(&mut self) {
+        let error = match self.cartridges.get_mut(self.current_cartridge) {
+            Some(cartridge) => cartridge.take_error(),
+            None => None,
+        };
+
+        if let Some(error) = error {
+            error!("[Unicorn] Script error {}", error.report());
+
+            // The cartridge is stopped by the error screen, not by the failed call
+            self.update_return = true;
+            self.draw_return = true;
+            self.error_screen.reset();
+            self.script_error = Some(error);
+        }
+    }
+
+    fn update_error_screen(&mut self) {
+        match self.error_screen.update(self.players.clone()) {
+            Some(ErrorAction::OpenEditor) => {
+                let location = match self.script_error {
+                    Some(ScriptError { file: Some(ref file), line: Some(line), .. }) => {
+                        Some((file.clone(), line))
+                    }
+                    _ => None,
+                };
+
+                self.switch_code(false);
+                if let Some((file, line)) = location {
+                    self.editor.goto_line(&file, line);
+                }
+            }
+            Some(ErrorAction::Restart) => self.restart(),
+            Some(ErrorAction::CopyToLog) => {
+                if let Some(ref error) = self.script_error {
+                    error!("[Unicorn] Script error\n{}", error.report());
+                }
+            }
+            None => (),
+        }
     }
 
     pub fn call_init(&mut self) -> f64 {
@@ -1349,6 +1793,7 @@ impl Unicorn {
             Code::LUA => self.cartridges[self.current_cartridge].lua_plugin.init(),
             Code::JAVASCRIPT => self.cartridges[self.current_cartridge].javascript_plugin.init(),
             Code::PYTHON => self.cartridges[self.current_cartridge].python_plugin.init(),
+            Code::WASM => self.cartridges[self.current_cartridge].wasm_plugin.init(),
             Code::RUST => {
                 self.draw_return = true;
                 for callback in &mut self.cartridges[self.current_cartridge].rust_plugin {
@@ -1358,6 +1803,8 @@ impl Unicorn {
             _ => error!("[Unicorn] Impossible to match a plugin"),
         }
 
+        self.check_script_error();
+
         let diff_time = time::now() - current_time;
         let nanoseconds = (diff_time.num_nanoseconds().unwrap() as f64) -
                           (diff_time.num_seconds() * 1000000000) as f64;
@@ -1379,6 +1826,9 @@ impl Unicorn {
             Code::PYTHON => {
                 self.draw_return = self.cartridges[self.current_cartridge].python_plugin.draw()
             }
+            Code::WASM => {
+                self.draw_return = self.cartridges[self.current_cartridge].wasm_plugin.draw()
+            }
             Code::RUST => {
                 self.draw_return = true;
 
@@ -1390,6 +1840,8 @@ impl Unicorn {
             _ => (),
         }
 
+        self.check_script_error();
+
         let diff_time = time::now() - current_time;
         let nanoseconds = (diff_time.num_nanoseconds().unwrap() as f64) -
                           (diff_time.num_seconds() * 1000000000) as f64;
@@ -1398,31 +1850,61 @@ impl Unicorn {
         diff_time.num_seconds() as f64 + nanoseconds / 1000000000.0
     }
 
+    /// Measures of the previous frame, read by `stat`
+    fn update_stats(&mut self) {
+        let cartridge = &mut self.cartridges[self.current_cartridge];
+        let sample_memory = cartridge.sample_memory();
+        let memory = match sample_memory {
+            Some(_) => 0.0,
+            None => cartridge.memory(),
+        };
+
+        let mut info = self.info.lock().unwrap();
+        info.fps = self.fps;
+        info.update_time = self.update_time;
+        info.draw_time = self.draw_time;
+        info.memory = memory;
+        info.sample_memory = sample_memory;
+    }
+
     pub fn call_update(&mut self) -> f64 {
+        self.update_stats();
+
         let current_time = time::now();
 
-        match self.current_code_type {
-            Code::LUA => {
-                self.update_return = self.cartridges[self.current_cartridge].lua_plugin.update()
-            }
-            Code::JAVASCRIPT => {
-                self.update_return = self.cartridges[self.current_cartridge].javascript_plugin.update()
-            }
-            Code::PYTHON => {
-                self.update_return = self.cartridges[self.current_cartridge]
-                    .python_plugin
-                    .update()
-            }
-            Code::RUST => {
-                self.update_return = true;
+        // The tasks run before `_update`, which is skipped when one of them failed
+        self.cartridges[self.current_cartridge].tick();
+        self.check_script_error();
 
-                for callback in &mut self.cartridges[self.current_cartridge].rust_plugin {
-                    callback.update(&mut self.players.lock().unwrap());
+        if self.script_error.is_none() {
+            match self.current_code_type {
+                Code::LUA => {
+                    self.update_return = self.cartridges[self.current_cartridge].lua_plugin.update()
                 }
+                Code::JAVASCRIPT => {
+                    self.update_return = self.cartridges[self.current_cartridge].javascript_plugin.update()
+                }
+                Code::PYTHON => {
+                    self.update_return = self.cartridges[self.current_cartridge]
+                        .python_plugin
+                        .update()
+                }
+                Code::WASM => {
+                    self.update_return = self.cartridges[self.current_cartridge].wasm_plugin.update()
+                }
+                Code::RUST => {
+                    self.update_return = true;
+
+                    for callback in &mut self.cartridges[self.current_cartridge].rust_plugin {
+                        callback.update(&mut self.players.lock().unwrap());
+                    }
+                }
+                _ => (),
             }
-            _ => (),
         }
 
+        self.check_script_error();
+
         let diff_time = time::now() - current_time;
         let nanoseconds = (diff_time.num_nanoseconds().unwrap() as f64) -
                           (diff_time.num_seconds() * 1000000000) as f64;
@@ -221,6 +221,7 @@ create_packets!(
         field note: u16 =,
         field panning: i32 =,
         field rate: i32 =,
+        field priority: i32 =,
     }
     packet ChiptuneLoadSFX {
         field filename: String =,
@@ -232,9 +233,26 @@ create_packets!(
         field pause: bool =,
         field resume: bool =,
     }
-    packet ChiptuneVolume {
+    packet ChiptuneBusVolume {
+        field bus: i32 =,
         field volume: i32 =,
     }
+    packet ChiptuneChannelVolume {
+        field chan: i32 =,
+        field volume: i32 =,
+    }
+    packet ChiptuneChannelPanning {
+        field chan: i32 =,
+        field panning: i32 =,
+    }
+    packet ChiptuneChannelReserve {
+        field chan: i32 =,
+        field reserved: bool =,
+    }
+    packet ChiptuneDucking {
+        field volume: i32 =,
+        field priority: i32 =,
+    }
 );
 
 
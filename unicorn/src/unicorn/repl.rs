@@ -0,0 +1,426 @@
+//! Console evaluating the code in the virtual machine of the running cartridge.
+//!
+//! The console is a drop-down overlay, toggled by the frontend, and a TCP server
+//! on a local address: each line received is evaluated, and the result is sent
+//! back, followed by a `> ` prompt. The other addresses are only allowed
+//! without the sandbox, the server has no authentication. The requests of the
+//! web browsers (`GET `, `POST `...) and the lines longer than `MAX_LINE` close
+//! the connection.
+
+use std::io;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+use config::scancode::Scancode;
+use config::textinput::TextInput;
+use gfx;
+use plugins::error::ScriptError;
+
+/// Address of the server, local only
+pub const DEFAULT_ADDRESS: &'static str = "127.0.0.1:7777";
+
+/// Size of a line received by the server, in bytes
+const MAX_LINE: usize = 64 * 1024;
+
+/// First words of the HTTP requests, sent to the server by the web pages
+const HTTP_METHODS: [&'static str; 7] = ["GET ", "POST ", "PUT ", "HEAD ", "DELETE ",
+                                          "OPTIONS ", "PATCH "];
+
+const MAX_HISTORY: usize = 100;
+const MAX_OUTPUT: usize = 100;
+const PROMPT: &'static str = "> ";
+
+/// Requests of the console to the cartridge
+#[derive(Clone, Debug, PartialEq)]
+pub enum ReplAction {
+    Eval(String),
+    Complete,
+    Close,
+}
+
+/// Identifier before `cursor`, the word to complete
+fn completion_word(text: &str, cursor: usize) -> String {
+    let chars: Vec<char> = text.chars().take(cursor).collect();
+    let start = chars.iter()
+        .rposition(|c| !(c.is_alphanumeric() || *c == '_'))
+        .map_or(0, |idx| idx + 1);
+    chars[start..].iter().cloned().collect()
+}
+
+/// Longest prefix of all the names
+fn common_prefix(names: &[String]) -> String {
+    let mut prefix: Vec<char> = match names.first() {
+        Some(name) => name.chars().collect(),
+        None => return "".to_string(),
+    };
+
+    for name in names.iter().skip(1) {
+        let len = prefix.iter()
+            .zip(name.chars())
+            .take_while(|&(a, b)| *a == b)
+            .count();
+        prefix.truncate(len);
+    }
+
+    prefix.into_iter().collect()
+}
+
+pub struct Repl {
+    pub visible: bool,
+    input: TextInput,
+    history: Vec<String>,
+    /// Position in the history, equal to its length for a new line
+    history_idx: usize,
+    /// Lines and their colors
+    output: Vec<(String, i32)>,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        let mut input = TextInput::new();
+        input.start("", 0);
+
+        Repl {
+            visible: false,
+            input: input,
+            history: Vec::new(),
+            history_idx: 0,
+            output: Vec::new(),
+        }
+    }
+
+    pub fn toggle(&mut self) {
+        self.visible = !self.visible;
+    }
+
+    pub fn input_text(&mut self, text: &str) {
+        self.input.insert(text);
+    }
+
+    pub fn key(&mut self, scancode: Scancode) -> Option<ReplAction> {
+        match scancode {
+            Scancode::Return | Scancode::KpEnter => {
+                let line = self.input.get();
+                self.input.start("", 0);
+                self.print(&format!("{}{}", PROMPT, line), 7);
+
+                if line.trim().is_empty() {
+                    return None;
+                }
+
+                if self.history.last() != Some(&line) {
+                    self.history.push(line.clone());
+                    if self.history.len() > MAX_HISTORY {
+                        self.history.remove(0);
+                    }
+                }
+                self.history_idx = self.history.len();
+
+                Some(ReplAction::Eval(line))
+            }
+            Scancode::Tab => Some(ReplAction::Complete),
+            Scancode::Escape => Some(ReplAction::Close),
+            Scancode::Up => {
+                if self.history_idx > 0 {
+                    self.history_idx -= 1;
+                    self.input.start(&self.history[self.history_idx], 0);
+                }
+                None
+            }
+            Scancode::Down => {
+                if self.history_idx < self.history.len() {
+                    self.history_idx += 1;
+                }
+                let line = self.history.get(self.history_idx).cloned().unwrap_or("".to_string());
+                self.input.start(&line, 0);
+                None
+            }
+            _ => {
+                self.input.key(scancode);
+                None
+            }
+        }
+    }
+
+    pub fn print(&mut self, text: &str, color: i32) {
+        for line in text.lines() {
+            self.output.push((line.to_string(), color));
+        }
+
+        let len = self.output.len();
+        if len > MAX_OUTPUT {
+            self.output.drain(..len - MAX_OUTPUT);
+        }
+    }
+
+    pub fn print_result(&mut self, result: &Result<String, ScriptError>) {
+        match *result {
+            Ok(ref value) => self.print(value, 6),
+            Err(ref error) => self.print(&error.report(), 8),
+        }
+    }
+
+    /// Complete the word before the cursor with the matching names
+    pub fn complete(&mut self, names: &[String]) {
+        let word = completion_word(&self.input.get(), self.input.cursor());
+
+        let mut matches: Vec<String> = names.iter()
+            .filter(|name| name.starts_with(&word))
+            .cloned()
+            .collect();
+        matches.sort();
+        matches.dedup();
+
+        let prefix = common_prefix(&matches);
+        if prefix.len() > word.len() {
+            self.input.insert(&prefix[word.len()..]);
+        } else if matches.len() > 1 {
+            self.print(&matches.join(" "), 6);
+        }
+    }
+
+    pub fn draw(&mut self, screen: &mut gfx::Screen) {
+        let width = screen.width as i32;
+        let height = (screen.height / 2) as i32;
+
+        let font = screen.get_font();
+        screen.font("pico-8");
+
+        for y in 0..height {
+            for x in 0..width {
+                screen.putpixel_direct(x, y, 0);
+            }
+        }
+        for x in 0..width {
+            screen.putpixel_direct(x, height, 6);
+        }
+
+        // The input line at the bottom, the last lines of the output above
+        let lines = ((height - 12) / 8) as usize;
+        let start = self.output.len().saturating_sub(lines);
+        for (i, &(ref line, color)) in self.output[start..].iter().enumerate() {
+            screen.force_print(line.clone(), 2, 2 + (i as i32) * 8, color);
+        }
+
+        let y = height - 9;
+        let text = format!("{}{}", PROMPT, self.input.get());
+        screen.force_print(text, 2, y, 7);
+
+        // 4 pixels by character with the pico-8 font
+        let cursor_x = 2 + ((PROMPT.len() + self.input.cursor()) as i32) * 4;
+        for x in cursor_x..cursor_x + 3 {
+            screen.putpixel_direct(x, y + 6, 8);
+        }
+
+        screen.font(&font);
+    }
+}
+
+struct ReplClient {
+    stream: TcpStream,
+    buffer: Vec<u8>,
+}
+
+/// Server of the console, polled by the console at each frame
+pub struct ReplServer {
+    listener: TcpListener,
+    clients: Vec<ReplClient>,
+}
+
+impl ReplServer {
+    /// Bind the server on `address`, a loopback address unless `remote` is allowed
+    pub fn bind(address: &str, remote: bool) -> io::Result<ReplServer> {
+        let addresses: Vec<_> = try!(address.to_socket_addrs()).collect();
+        if !remote && addresses.iter().any(|address| !address.ip().is_loopback()) {
+            return Err(io::Error::new(io::ErrorKind::PermissionDenied,
+                                      format!("{} is not a local address, it needs --unsafe",
+                                              address)));
+        }
+
+        let listener = try!(TcpListener::bind(address));
+        try!(listener.set_nonblocking(true));
+
+        info!("[Unicorn][Repl] Listening on {:?}", address);
+        if addresses.iter().any(|address| !address.ip().is_loopback()) {
+            warn!("[Unicorn][Repl] {} is reachable from the network without authentication, \
+                   anyone connecting to it runs code in the cartridge",
+                  address);
+        }
+
+        Ok(ReplServer {
+               listener: listener,
+               clients: Vec::new(),
+           })
+    }
+
+    /// The lines received since the last poll, with the index of their client
+    pub fn poll(&mut self) -> Vec<(usize, String)> {
+        while let Ok((mut stream, address)) = self.listener.accept() {
+            info!("[Unicorn][Repl] Connection from {:?}", address);
+
+            if stream.set_nonblocking(true).is_ok() {
+                let _ = stream.write_all(PROMPT.as_bytes());
+                self.clients.push(ReplClient {
+                                      stream: stream,
+                                      buffer: Vec::new(),
+                                  });
+            }
+        }
+
+        let mut lines = Vec::new();
+        let mut closed = Vec::new();
+
+        for (idx, client) in self.clients.iter_mut().enumerate() {
+            let mut close = false;
+
+            let mut data = [0; 1024];
+            loop {
+                match client.stream.read(&mut data) {
+                    Ok(0) => {
+                        close = true;
+                        break;
+                    }
+                    Ok(len) => client.buffer.extend_from_slice(&data[..len]),
+                    Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(_) => {
+                        close = true;
+                        break;
+                    }
+                }
+            }
+
+            while let Some(end) = client.buffer.iter().position(|c| *c == b'\n') {
+                let line: Vec<u8> = client.buffer.drain(..end + 1).collect();
+                let line = String::from_utf8_lossy(&line).trim_right().to_string();
+
+                if HTTP_METHODS.iter().any(|method| line.starts_with(method)) {
+                    warn!("[Unicorn][Repl] HTTP request ignored");
+                    close = true;
+                    break;
+                }
+                lines.push((idx, line));
+            }
+
+            // Without a new line, the buffer would grow forever
+            if client.buffer.len() > MAX_LINE {
+                warn!("[Unicorn][Repl] Line longer than {} bytes", MAX_LINE);
+                close = true;
+            }
+
+            if close {
+                closed.push(idx);
+            }
+        }
+
+        // Forget the closed clients and their lines
+        for idx in closed.into_iter().rev() {
+            info!("[Unicorn][Repl] Connection closed");
+            self.clients.remove(idx);
+            lines = lines.into_iter()
+                .filter(|&(client, _)| client != idx)
+                .map(|(client, line)| if client > idx { (client - 1, line) } else { (client, line) })
+                .collect();
+        }
+
+        lines
+    }
+
+    pub fn reply(&mut self, client: usize, result: &Result<String, ScriptError>) {
+        let text = match *result {
+            Ok(ref value) => value.clone(),
+            Err(ref error) => format!("error: {}", error.report()),
+        };
+
+        if let Some(client) = self.clients.get_mut(client) {
+            let mut reply = text;
+            if !reply.is_empty() {
+                reply.push('\n');
+            }
+            reply.push_str(PROMPT);
+
+            let _ = client.stream.write_all(reply.as_bytes());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn complete_word() {
+        assert_eq!(completion_word("print(pl", 8), "pl");
+        assert_eq!(completion_word("x = math.fl", 11), "fl");
+        assert_eq!(completion_word("player_x + 1", 6), "player");
+        assert_eq!(completion_word("", 0), "");
+
+        let names = vec!["player_x".to_string(), "player_y".to_string()];
+        assert_eq!(common_prefix(&names), "player_");
+        assert_eq!(common_prefix(&[]), "");
+    }
+
+    #[test]
+    fn bind_local_only() {
+        assert!(ReplServer::bind("127.0.0.1:0", false).is_ok());
+        assert!(ReplServer::bind("localhost:0", false).is_ok());
+
+        let error = ReplServer::bind("0.0.0.0:0", false).err().unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        assert!(ReplServer::bind("0.0.0.0:0", true).is_ok());
+    }
+
+    /// Poll the server until `done`, the lines received in the meantime
+    fn poll_until<F>(server: &mut ReplServer, done: F) -> Vec<(usize, String)>
+        where F: Fn(&ReplServer, &[(usize, String)]) -> bool
+    {
+        let mut lines = Vec::new();
+        for _ in 0..200 {
+            lines.extend(server.poll());
+            if done(server, &lines) {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        lines
+    }
+
+    fn connect(server: &mut ReplServer) -> TcpStream {
+        let client = TcpStream::connect(server.listener.local_addr().unwrap()).unwrap();
+        poll_until(server, |server, _| !server.clients.is_empty());
+        assert_eq!(server.clients.len(), 1);
+        client
+    }
+
+    #[test]
+    fn receive_lines() {
+        let mut server = ReplServer::bind("127.0.0.1:0", false).unwrap();
+        let mut client = connect(&mut server);
+
+        client.write_all(b"x = 1\nprint(x)\r\n").unwrap();
+        assert_eq!(poll_until(&mut server, |_, lines| lines.len() >= 2),
+                   vec![(0, "x = 1".to_string()), (0, "print(x)".to_string())]);
+    }
+
+    #[test]
+    fn close_http_requests() {
+        let mut server = ReplServer::bind("127.0.0.1:0", false).unwrap();
+        let mut client = connect(&mut server);
+
+        client.write_all(b"POST / HTTP/1.1\r\nHost: localhost\r\n\r\nos.exit()\n").unwrap();
+        assert!(poll_until(&mut server, |server, _| server.clients.is_empty()).is_empty());
+        assert!(server.clients.is_empty());
+    }
+
+    #[test]
+    fn close_long_lines() {
+        let mut server = ReplServer::bind("127.0.0.1:0", false).unwrap();
+        let mut client = connect(&mut server);
+
+        let _ = client.write_all(&vec![b'x'; MAX_LINE + 1]);
+        assert!(poll_until(&mut server, |server, _| server.clients.is_empty()).is_empty());
+        assert!(server.clients.is_empty());
+    }
+}
@@ -0,0 +1,196 @@
+use std::cmp;
+use std::fs::File;
+use std::path::Path;
+
+use image;
+
+use gfx::Sprite;
+use unicorn::RGB;
+
+/// Sprites by line of the sprite sheet
+pub const SPRITES_PER_LINE: usize = 50;
+
+/// Color of the palette closest to the RGB value
+pub fn nearest_color(colors: &[RGB], r: u8, g: u8, b: u8) -> u8 {
+    let distance = |color: &RGB| {
+        let dr = color.r as i32 - r as i32;
+        let dg = color.g as i32 - g as i32;
+        let db = color.b as i32 - b as i32;
+        dr * dr + dg * dg + db * db
+    };
+
+    colors.iter()
+        .take(256)
+        .enumerate()
+        .min_by_key(|&(_, color)| distance(color))
+        .map(|(idx, _)| idx as u8)
+        .unwrap_or(0)
+}
+
+/// Size in pixels of the sprite sheet
+pub fn sheet_size(sprites: &[Sprite]) -> (u32, u32) {
+    let lines = (sprites.len() + SPRITES_PER_LINE - 1) / SPRITES_PER_LINE;
+    ((SPRITES_PER_LINE * 8) as u32, (lines * 8) as u32)
+}
+
+/// Colors of the pixels of the sprite sheet, by lines
+pub fn sheet_pixels(sprites: &[Sprite]) -> Vec<u8> {
+    let (width, height) = sheet_size(sprites);
+    let mut pixels = vec![0; (width * height) as usize];
+
+    for (idx, sprite) in sprites.iter().enumerate() {
+        let x = (idx % SPRITES_PER_LINE) * 8;
+        let y = (idx / SPRITES_PER_LINE) * 8;
+        for (pixel, color) in sprite.data.iter().enumerate() {
+            pixels[x + pixel % 8 + (y + pixel / 8) * width as usize] = *color;
+        }
+    }
+
+    pixels
+}
+
+/// Colors of the pixels of the map drawn with the sprites, by lines. The
+/// sprite 0 is not drawn, like with `map()`.
+pub fn map_pixels(sprites: &[Sprite], map: &[u32], width: usize, height: usize) -> Vec<u8> {
+    let mut pixels = vec![0; width * 8 * height * 8];
+
+    for y in 0..height {
+        for x in 0..width {
+            let sprite = *map.get(x + y * width).unwrap_or(&0) as usize;
+            if sprite == 0 || sprite >= sprites.len() {
+                continue;
+            }
+
+            for (pixel, color) in sprites[sprite].data.iter().enumerate() {
+                pixels[x * 8 + pixel % 8 + (y * 8 + pixel / 8) * width * 8] = *color;
+            }
+        }
+    }
+
+    pixels
+}
+
+/// Set the sprites with the RGBA pixels of an image, quantized to the
+/// colors. The transparent pixels are the color 0, the pixels out of the
+/// sprite sheet are ignored.
+pub fn quantize(sprites: &mut [Sprite], rgba: &[u8], width: u32, height: u32, colors: &[RGB]) {
+    let (sheet_width, sheet_height) = sheet_size(sprites);
+
+    for y in 0..cmp::min(height, sheet_height) {
+        for x in 0..cmp::min(width, sheet_width) {
+            let sprite = (x / 8) as usize + (y / 8) as usize * SPRITES_PER_LINE;
+            if sprite >= sprites.len() {
+                continue;
+            }
+
+            let offset = ((x + y * width) * 4) as usize;
+            let pixel = &rgba[offset..offset + 4];
+            let color = if pixel[3] < 128 {
+                0
+            } else {
+                nearest_color(colors, pixel[0], pixel[1], pixel[2])
+            };
+
+            sprites[sprite].data[((x % 8) + (y % 8) * 8) as usize] = color;
+        }
+    }
+}
+
+fn save_png(filename: &str, pixels: &[u8], width: u32, height: u32, colors: &[RGB]) -> Result<(), String> {
+    let mut buffer = Vec::with_capacity(pixels.len() * 3);
+    for color in pixels {
+        match colors.get(*color as usize) {
+            Some(rgb) => buffer.extend_from_slice(&[rgb.r, rgb.g, rgb.b]),
+            None => buffer.extend_from_slice(&[0, 0, 0]),
+        }
+    }
+
+    let image = match image::ImageBuffer::from_raw(width, height, buffer) {
+        Some(image) => image::DynamicImage::ImageRgb8(image),
+        None => return Err(format!("Invalid image {}x{}", width, height)),
+    };
+
+    let mut output = try!(File::create(&Path::new(filename)).map_err(|e| e.to_string()));
+    image.save(&mut output, image::ImageFormat::PNG).map_err(|e| e.to_string())
+}
+
+/// Save the sprite sheet in a PNG file
+pub fn export_sheet_png(filename: &str, sprites: &[Sprite], colors: &[RGB]) -> Result<(), String> {
+    info!("[Unicorn][SHEET] Export the sprite sheet in {:?}", filename);
+
+    let (width, height) = sheet_size(sprites);
+    save_png(filename, &sheet_pixels(sprites), width, height, colors)
+}
+
+/// Save the map drawn with the sprites in a PNG file
+pub fn export_map_png(filename: &str,
+                      sprites: &[Sprite],
+                      map: &[u32],
+                      width: usize,
+                      height: usize,
+                      colors: &[RGB])
+                      -> Result<(), String> {
+    info!("[Unicorn][SHEET] Export the map {:?}x{:?} in {:?}", width, height, filename);
+
+    let pixels = map_pixels(sprites, map, width, height);
+    save_png(filename, &pixels, (width * 8) as u32, (height * 8) as u32, colors)
+}
+
+/// Load a PNG file in the sprite sheet, quantized to the colors
+pub fn import_sheet_png(filename: &str, sprites: &mut [Sprite], colors: &[RGB]) -> Result<(), String> {
+    info!("[Unicorn][SHEET] Import the sprite sheet from {:?}", filename);
+
+    let image = try!(image::open(&Path::new(filename)).map_err(|e| e.to_string())).to_rgba();
+    let (width, height) = image.dimensions();
+
+    quantize(sprites, &image.into_raw(), width, height, colors);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn colors() -> Vec<RGB> {
+        vec![RGB::new(0, 0, 0), RGB::new(255, 0, 0), RGB::new(0, 0, 255), RGB::new(255, 255, 255)]
+    }
+
+    #[test]
+    fn quantize_sheet() {
+        assert_eq!(nearest_color(&colors(), 200, 30, 20), 1);
+        assert_eq!(nearest_color(&colors(), 20, 40, 180), 2);
+        assert_eq!(nearest_color(&colors(), 180, 190, 200), 3);
+
+        // Pixels of the first two lines of sprites, one is transparent
+        let mut sprites = vec![Sprite::new([3; 64]); 60];
+        let mut rgba = vec![0; 16 * 9 * 4];
+        for &(x, y, pixel) in &[(8, 1, [250, 10, 10, 255]), (9, 1, [250, 250, 250, 0]), (0, 8, [10, 10, 250, 255])] {
+            let offset = (x + y * 16) * 4;
+            rgba[offset..offset + 4].copy_from_slice(&pixel);
+        }
+        quantize(&mut sprites, &rgba, 16, 9, &colors());
+
+        assert_eq!(sprites[1].data[8], 1);
+        assert_eq!(sprites[1].data[9], 0);
+        assert_eq!(sprites[50].data[0], 2);
+        assert_eq!(sprites[2].data[0], 3);
+
+        let pixels = sheet_pixels(&sprites);
+        assert_eq!(sheet_size(&sprites), (400, 16));
+        assert_eq!(pixels[8 + 400], 1);
+        assert_eq!(pixels[8 * 400], 2);
+    }
+
+    #[test]
+    fn render_map() {
+        let mut sprites = vec![Sprite::new([3; 64]); 2];
+        sprites[1].data[0] = 1;
+
+        let pixels = map_pixels(&sprites, &[1, 0, 0, 1], 2, 2);
+        assert_eq!(pixels.len(), 16 * 16);
+        assert_eq!(pixels[0], 1);
+        assert_eq!(pixels[1], 3);
+        assert_eq!(pixels[8], 0);
+        assert_eq!(pixels[8 + 8 * 16], 1);
+    }
+}
@@ -0,0 +1,617 @@
+use std::cmp;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use serde_json;
+use xml::reader::{EventReader, XmlEvent};
+
+use gfx::Sprite;
+use unicorn::sheet::{sheet_size, SPRITES_PER_LINE};
+
+/// Global tile id of the first sprite, the id 0 is an empty cell
+const FIRST_GID: u32 = 1;
+
+/// Bits of the flipped tiles in the global tile ids
+const FLIPPED_MASK: u32 = 0x1fffffff;
+
+/// Map in the format of the Tiled editor, with one layer of tiles and the
+/// sprite sheet as tileset. The flags of the sprites are the `flag0` to
+/// `flag7` boolean properties of the tiles.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TiledMap {
+    pub width: usize,
+    pub height: usize,
+    /// Sprite of each cell, by lines
+    pub cells: Vec<u32>,
+    /// Flags of each sprite of the tileset
+    pub flags: Vec<u8>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonMap {
+    width: usize,
+    height: usize,
+    tilewidth: u32,
+    tileheight: u32,
+    #[serde(default)]
+    orientation: String,
+    #[serde(default)]
+    renderorder: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    version: serde_json::Value,
+    layers: Vec<JsonLayer>,
+    #[serde(default)]
+    tilesets: Vec<JsonTileset>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonLayer {
+    #[serde(default)]
+    name: String,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    width: usize,
+    #[serde(default)]
+    height: usize,
+    #[serde(default)]
+    x: i32,
+    #[serde(default)]
+    y: i32,
+    #[serde(default)]
+    opacity: f32,
+    #[serde(default)]
+    visible: bool,
+    #[serde(default)]
+    data: Vec<u32>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonTileset {
+    firstgid: u32,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    image: String,
+    #[serde(default)]
+    imagewidth: u32,
+    #[serde(default)]
+    imageheight: u32,
+    #[serde(default)]
+    tilewidth: u32,
+    #[serde(default)]
+    tileheight: u32,
+    #[serde(default)]
+    columns: u32,
+    #[serde(default)]
+    tilecount: u32,
+    #[serde(default)]
+    margin: u32,
+    #[serde(default)]
+    spacing: u32,
+    #[serde(default)]
+    tiles: Vec<JsonTile>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonTile {
+    id: u32,
+    #[serde(default)]
+    properties: Vec<JsonProperty>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct JsonProperty {
+    name: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+    value: serde_json::Value,
+}
+
+/// Number of the flag of a tile property, `flag0` to `flag7`
+fn property_flag(name: &str) -> Option<u8> {
+    if !name.starts_with("flag") {
+        return None;
+    }
+
+    match name[4..].parse::<u8>() {
+        Ok(flag) if flag < 8 => Some(flag),
+        _ => None,
+    }
+}
+
+/// Tiles of a tileset, the tiles of the tilesets follow each other in the
+/// sprite sheet
+struct Tileset {
+    firstgid: u32,
+    tilecount: Option<u32>,
+    /// Flags of the tiles, by id
+    flags: Vec<u8>,
+}
+
+impl Tileset {
+    fn new(firstgid: u32, tilecount: Option<u32>) -> Tileset {
+        Tileset {
+            firstgid: firstgid,
+            tilecount: tilecount,
+            flags: Vec::new(),
+        }
+    }
+
+    /// Set the flag of the property `name` of the tile `id`, when `value` is true
+    fn set_property(&mut self, id: usize, name: &str, value: bool) {
+        if let Some(flag) = property_flag(name) {
+            if id >= self.flags.len() {
+                self.flags.resize(id + 1, 0);
+            }
+            if value {
+                self.flags[id] |= 1 << flag;
+            }
+        }
+    }
+}
+
+/// Sprites of the global tile ids, and the flags of the sprites
+fn tilesets_sprites(mut tilesets: Vec<Tileset>, gids: &[u32]) -> (Vec<u32>, Vec<u8>) {
+    if tilesets.is_empty() {
+        tilesets.push(Tileset::new(FIRST_GID, None));
+    }
+    tilesets.sort_by_key(|tileset| tileset.firstgid);
+
+    // First sprite of each tileset, the number of tiles of the tilesets
+    // without tilecount is given by the next one
+    let mut bases = Vec::new();
+    let mut flags = Vec::new();
+    for (idx, tileset) in tilesets.iter().enumerate() {
+        let base = flags.len();
+        let count = match (tileset.tilecount, tilesets.get(idx + 1)) {
+            (Some(count), _) => count as usize,
+            (None, Some(next)) => (next.firstgid - tileset.firstgid) as usize,
+            (None, None) => tileset.flags.len(),
+        };
+
+        bases.push(base as u32);
+        flags.extend(tileset.flags.iter().take(count));
+        flags.resize(base + count, 0);
+    }
+
+    let sprites = gids.iter()
+        .map(|gid| {
+            let gid = gid & FLIPPED_MASK;
+            match tilesets.iter().rposition(|tileset| tileset.firstgid <= gid) {
+                Some(idx) => bases[idx] + gid - tilesets[idx].firstgid,
+                None => 0,
+            }
+        })
+        .collect();
+
+    (sprites, flags)
+}
+
+/// Global tile ids of the data of a layer, in CSV or in the `<tile>` elements
+/// without encoding
+fn layer_data(encoding: Option<String>, text: &str, tiles: &[u32]) -> Result<Vec<u32>, String> {
+    match encoding.as_ref().map(|encoding| encoding.as_str()) {
+        Some("csv") => {
+            let mut gids = Vec::new();
+            for value in text.split(',') {
+                let value = value.trim();
+                if value.is_empty() {
+                    continue;
+                }
+
+                gids.push(try!(value.parse::<u32>().map_err(|e| e.to_string())));
+            }
+            Ok(gids)
+        }
+        None => Ok(tiles.to_vec()),
+        Some(_) => Err("Only the CSV encoding of the layers is supported".to_string()),
+    }
+}
+
+impl TiledMap {
+    pub fn new(map: &[u32], width: usize, height: usize, sprites: &[Sprite]) -> TiledMap {
+        let mut cells = map.to_vec();
+        cells.resize(width * height, 0);
+
+        TiledMap {
+            width: width,
+            height: height,
+            cells: cells,
+            flags: sprites.iter().map(|sprite| sprite.flags).collect(),
+        }
+    }
+
+    /// Global tile ids of the cells, the sprite 0 is an empty cell
+    fn gids(&self) -> Vec<u32> {
+        self.cells
+            .iter()
+            .map(|sprite| if *sprite == 0 { 0 } else { sprite + FIRST_GID })
+            .collect()
+    }
+
+    /// Size in pixels of the image of the tileset
+    fn image_size(&self) -> (u32, u32) {
+        sheet_size(&vec![Sprite::new([0; 64]); self.flags.len()])
+    }
+
+    /// Tiled JSON map, the tileset uses the sprite sheet saved in `image`
+    pub fn to_json(&self, image: &str) -> Result<String, String> {
+        let (image_width, image_height) = self.image_size();
+
+        let tiles = self.flags
+            .iter()
+            .enumerate()
+            .filter(|&(_, flags)| *flags != 0)
+            .map(|(id, flags)| {
+                JsonTile {
+                    id: id as u32,
+                    properties: (0..8)
+                        .filter(|flag| flags & (1 << flag) != 0)
+                        .map(|flag| {
+                            JsonProperty {
+                                name: format!("flag{}", flag),
+                                kind: "bool".to_string(),
+                                value: serde_json::Value::Bool(true),
+                            }
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+
+        let map = JsonMap {
+            width: self.width,
+            height: self.height,
+            tilewidth: 8,
+            tileheight: 8,
+            orientation: "orthogonal".to_string(),
+            renderorder: "right-down".to_string(),
+            kind: "map".to_string(),
+            version: serde_json::Value::String("1.0".to_string()),
+            layers: vec![JsonLayer {
+                             name: "map".to_string(),
+                             kind: "tilelayer".to_string(),
+                             width: self.width,
+                             height: self.height,
+                             x: 0,
+                             y: 0,
+                             opacity: 1.0,
+                             visible: true,
+                             data: self.gids(),
+                         }],
+            tilesets: vec![JsonTileset {
+                               firstgid: FIRST_GID,
+                               name: "sprites".to_string(),
+                               image: image.to_string(),
+                               imagewidth: image_width,
+                               imageheight: image_height,
+                               tilewidth: 8,
+                               tileheight: 8,
+                               columns: SPRITES_PER_LINE as u32,
+                               tilecount: self.flags.len() as u32,
+                               margin: 0,
+                               spacing: 0,
+                               tiles: tiles,
+                           }],
+        };
+
+        serde_json::to_string_pretty(&map).map_err(|e| e.to_string())
+    }
+
+    /// Read the first layer of tiles of a Tiled JSON map
+    pub fn from_json(data: &str) -> Result<TiledMap, String> {
+        let map: JsonMap = try!(serde_json::from_str(data).map_err(|e| e.to_string()));
+
+        let layer = match map.layers.iter().find(|layer| layer.kind == "tilelayer") {
+            Some(layer) => layer,
+            None => return Err("No layer of tiles".to_string()),
+        };
+
+        let mut tilesets = Vec::new();
+        for json_tileset in &map.tilesets {
+            let tilecount = if json_tileset.tilecount > 0 { Some(json_tileset.tilecount) } else { None };
+            let mut tileset = Tileset::new(json_tileset.firstgid, tilecount);
+            for tile in &json_tileset.tiles {
+                for property in &tile.properties {
+                    let set = property.value == serde_json::Value::Bool(true) ||
+                              property.value == serde_json::Value::String("true".to_string());
+                    tileset.set_property(tile.id as usize, &property.name, set);
+                }
+            }
+            tilesets.push(tileset);
+        }
+
+        let (mut cells, flags) = tilesets_sprites(tilesets, &layer.data);
+        cells.resize(map.width * map.height, 0);
+
+        Ok(TiledMap {
+               width: map.width,
+               height: map.height,
+               cells: cells,
+               flags: flags,
+           })
+    }
+
+    /// Tiled TMX map with the cells in CSV, the tileset uses the sprite sheet
+    /// saved in `image`
+    pub fn to_tmx(&self, image: &str) -> String {
+        let (image_width, image_height) = self.image_size();
+
+        let mut data = String::new();
+        data.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        data.push_str(&format!("<map version=\"1.0\" orientation=\"orthogonal\" renderorder=\"right-down\" \
+                                width=\"{}\" height=\"{}\" tilewidth=\"8\" tileheight=\"8\">\n",
+                               self.width,
+                               self.height));
+        data.push_str(&format!(" <tileset firstgid=\"{}\" name=\"sprites\" tilewidth=\"8\" tileheight=\"8\" \
+                                tilecount=\"{}\" columns=\"{}\">\n",
+                               FIRST_GID,
+                               self.flags.len(),
+                               SPRITES_PER_LINE));
+        data.push_str(&format!("  <image source=\"{}\" width=\"{}\" height=\"{}\"/>\n",
+                               image.replace('&', "&amp;").replace('"', "&quot;"),
+                               image_width,
+                               image_height));
+
+        for (id, flags) in self.flags.iter().enumerate() {
+            if *flags == 0 {
+                continue;
+            }
+
+            data.push_str(&format!("  <tile id=\"{}\">\n   <properties>\n", id));
+            for flag in (0..8).filter(|flag| flags & (1 << flag) != 0) {
+                data.push_str(&format!("    <property name=\"flag{}\" type=\"bool\" value=\"true\"/>\n",
+                                       flag));
+            }
+            data.push_str("   </properties>\n  </tile>\n");
+        }
+        data.push_str(" </tileset>\n");
+
+        data.push_str(&format!(" <layer name=\"map\" width=\"{}\" height=\"{}\">\n  <data encoding=\"csv\">\n",
+                               self.width,
+                               self.height));
+        let gids = self.gids();
+        for (y, line) in gids.chunks(cmp::max(self.width, 1)).enumerate() {
+            let line: Vec<String> = line.iter().map(|gid| gid.to_string()).collect();
+            data.push_str(&line.join(","));
+            if y + 1 < self.height {
+                data.push(',');
+            }
+            data.push('\n');
+        }
+        data.push_str("  </data>\n </layer>\n</map>\n");
+
+        data
+    }
+
+    /// Read the first layer of tiles of a Tiled TMX map, with the cells in CSV
+    /// or in `<tile>` elements
+    pub fn from_tmx(data: &str) -> Result<TiledMap, String> {
+        let mut size = None;
+        let mut tilesets: Vec<Tileset> = Vec::new();
+        // Id of the tile of the tileset read
+        let mut tile = None;
+        // Encoding and content of the data of the first layer, while it is read
+        let mut layer: Option<(Option<String>, String)> = None;
+        let mut gids: Option<Vec<u32>> = None;
+        let mut layer_gids = Vec::new();
+
+        for event in EventReader::from_str(data) {
+            match try!(event.map_err(|e| e.to_string())) {
+                XmlEvent::StartElement { name, attributes, .. } => {
+                    let attribute = |key: &str| {
+                        attributes.iter()
+                            .find(|attribute| attribute.name.local_name == key)
+                            .map(|attribute| attribute.value.trim().to_string())
+                    };
+                    let number = |key: &str| attribute(key).and_then(|value| value.parse::<u32>().ok());
+
+                    match name.local_name.as_str() {
+                        "map" => {
+                            size = match (number("width"), number("height")) {
+                                (Some(width), Some(height)) => Some((width as usize, height as usize)),
+                                _ => return Err("No size of the map".to_string()),
+                            };
+                        }
+                        "tileset" => {
+                            tilesets.push(Tileset::new(number("firstgid").unwrap_or(FIRST_GID),
+                                                       number("tilecount")));
+                        }
+                        "tile" if layer.is_some() => layer_gids.push(number("gid").unwrap_or(0)),
+                        "tile" => tile = number("id"),
+                        "property" => {
+                            if let (Some(id), Some(tileset)) = (tile, tilesets.last_mut()) {
+                                let name = attribute("name").unwrap_or_default();
+                                let value = attribute("value").map_or(false, |value| value == "true");
+                                tileset.set_property(id as usize, &name, value);
+                            }
+                        }
+                        "data" if gids.is_none() => layer = Some((attribute("encoding"), String::new())),
+                        _ => (),
+                    }
+                }
+                XmlEvent::EndElement { name } => {
+                    match name.local_name.as_str() {
+                        "tile" if layer.is_none() => tile = None,
+                        "data" => {
+                            if let Some((encoding, text)) = layer.take() {
+                                gids = Some(try!(layer_data(encoding, &text, &layer_gids)));
+                            }
+                        }
+                        _ => (),
+                    }
+                }
+                XmlEvent::Characters(text) |
+                XmlEvent::Whitespace(text) |
+                XmlEvent::CData(text) => {
+                    if let Some((_, ref mut content)) = layer {
+                        content.push_str(&text);
+                    }
+                }
+                _ => (),
+            }
+        }
+
+        let (width, height) = match size {
+            Some(size) => size,
+            None => return Err("No map".to_string()),
+        };
+
+        let gids = match gids {
+            Some(gids) => gids,
+            None => return Err("No layer of tiles".to_string()),
+        };
+
+        let (mut cells, flags) = tilesets_sprites(tilesets, &gids);
+        cells.resize(width * height, 0);
+
+        Ok(TiledMap {
+               width: width,
+               height: height,
+               cells: cells,
+               flags: flags,
+           })
+    }
+
+    /// Copy the cells in the map, without the ones out of it, and the flags
+    /// in the sprites
+    pub fn apply(&self, map: &mut Vec<u32>, width: usize, height: usize, sprites: &mut [Sprite]) {
+        map.resize(width * height, 0);
+
+        for y in 0..cmp::min(self.height, height) {
+            for x in 0..cmp::min(self.width, width) {
+                let sprite = self.cells[x + y * self.width];
+                map[x + y * width] = if (sprite as usize) < sprites.len() { sprite } else { 0 };
+            }
+        }
+
+        for (sprite, flags) in sprites.iter_mut().zip(self.flags.iter()) {
+            sprite.flags = *flags;
+        }
+    }
+
+    /// Save in TMX, or in JSON with the `.json` extension
+    pub fn save(&self, filename: &str, image: &str) -> Result<(), String> {
+        info!("[Unicorn][TILED] Export the map {:?}x{:?} in {:?}", self.width, self.height, filename);
+
+        let data = if filename.ends_with(".json") {
+            try!(self.to_json(image))
+        } else {
+            self.to_tmx(image)
+        };
+
+        let mut f = try!(File::create(&Path::new(filename)).map_err(|e| e.to_string()));
+        f.write_all(data.as_bytes()).map_err(|e| e.to_string())
+    }
+
+    /// Load a TMX map, or a JSON map with the `.json` extension
+    pub fn load(filename: &str) -> Result<TiledMap, String> {
+        info!("[Unicorn][TILED] Import the map from {:?}", filename);
+
+        let mut f = try!(File::open(&Path::new(filename)).map_err(|e| e.to_string()));
+        let mut data = String::new();
+        try!(f.read_to_string(&mut data).map_err(|e| e.to_string()));
+
+        if filename.ends_with(".json") {
+            TiledMap::from_json(&data)
+        } else {
+            TiledMap::from_tmx(&data)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiled_map() -> TiledMap {
+        let mut sprites = vec![Sprite::new([0; 64]); 60];
+        sprites[3].flags = 0b101;
+        sprites[55].flags = 0x80;
+
+        TiledMap::new(&[0, 3, 55, 1, 0, 2], 3, 2, &sprites)
+    }
+
+    #[test]
+    fn tiled_formats() {
+        let map = tiled_map();
+        assert_eq!(map.gids(), vec![0, 4, 56, 2, 0, 3]);
+
+        assert_eq!(TiledMap::from_json(&map.to_json("sheet.png").unwrap()), Ok(map.clone()));
+        assert_eq!(TiledMap::from_tmx(&map.to_tmx("sheet.png")), Ok(map.clone()));
+
+        // The flipped tiles are the same sprites
+        let tmx = map.to_tmx("sheet.png").replace(",56,", &format!(",{},", 56 | 0x80000000u32));
+        assert_eq!(TiledMap::from_tmx(&tmx), Ok(map));
+
+        assert!(TiledMap::from_tmx("<map width=\"2\" height=\"1\"><layer><data encoding=\"base64\">AAAA</data></layer></map>")
+                    .is_err());
+    }
+
+    #[test]
+    fn tmx_syntax() {
+        // Single quotes, entities, CRLF and tabs in the data, and a comment
+        let tmx = "<?xml version='1.0'?>\r\n<map width='3' height = '2' tilewidth='8'>\r\n\
+                   <!-- <map width='9' height='9'> -->\r\n\
+                   <tileset firstgid='1' name='a &amp; b' tilecount='60'>\r\n\
+                   <image source='sheet &quot;1&quot;.png'/>\r\n\
+                   <tile id='3'><properties>\
+                   <property name='flag&#48;' type='bool' value='true'/>\
+                   <property name=\"flag2\" value=\"true\"/>\
+                   </properties></tile>\r\n\
+                   <tile id='55'><properties><property name='flag7' value='true'/></properties></tile>\
+                   </tileset>\r\n\
+                   <layer name='map'><data encoding='csv'>\r\n\t0,4,56,\r\n\t2 , 0,3\r\n</data></layer>\r\n\
+                   <layer name='other'><data encoding='csv'>1,1,1,1,1,1</data></layer>\
+                   </map>";
+        assert_eq!(TiledMap::from_tmx(tmx), Ok(tiled_map()));
+
+        // The cells in XML elements
+        let tmx = "<map width=\"3\" height=\"2\"><tileset firstgid=\"1\" tilecount=\"60\"/>\
+                   <layer><data>\n <tile gid=\"0\"/><tile gid=\"4\"/><tile gid=\"56\"/>\
+                   <tile gid=\"2\"/><tile/><tile gid=\"3\"/>\n</data></layer></map>";
+        assert_eq!(TiledMap::from_tmx(tmx).unwrap().cells, tiled_map().cells);
+
+        assert!(TiledMap::from_tmx("<map width=\"2\"><layer><data encoding=\"csv\">1,1</data></layer></map>")
+                    .is_err());
+        assert!(TiledMap::from_tmx("<map width=\"2\" height=\"1\"><layer></layer></map>").is_err());
+        assert!(TiledMap::from_tmx("<map width=\"2\" height=\"1\">").is_err());
+    }
+
+    #[test]
+    fn tmx_tilesets() {
+        // The sprites of the second tileset follow the 4 tiles of the first one
+        let tmx = "<map width=\"4\" height=\"1\">\
+                   <tileset firstgid=\"11\" tilecount=\"3\">\
+                   <tile id=\"1\"><properties><property name=\"flag1\" value=\"true\"/></properties></tile>\
+                   </tileset>\
+                   <tileset firstgid=\"5\" tilecount=\"4\"/>\
+                   <layer><data encoding=\"csv\">0,6,12,2</data></layer></map>";
+        let map = TiledMap::from_tmx(tmx).unwrap();
+        assert_eq!(map.cells, vec![0, 1, 5, 0]);
+        assert_eq!(map.flags, vec![0, 0, 0, 0, 0, 0b10, 0]);
+
+        // Without tilecount, the tiles go to the next tileset
+        let tmx = "<map width=\"2\" height=\"1\">\
+                   <tileset firstgid=\"1\" source=\"a.tsx\"/><tileset firstgid=\"9\" source=\"b.tsx\"/>\
+                   <layer><data encoding=\"csv\">8,10</data></layer></map>";
+        let map = TiledMap::from_tmx(tmx).unwrap();
+        assert_eq!(map.cells, vec![7, 9]);
+    }
+
+    #[test]
+    fn apply_tiled_map() {
+        let mut sprites = vec![Sprite::new([0; 64]); 50];
+        let mut map = vec![9; 4 * 2];
+        tiled_map().apply(&mut map, 4, 2, &mut sprites);
+
+        // The sprite 55 is not in the sheet and the last column is kept
+        assert_eq!(map, vec![0, 3, 0, 9, 1, 0, 2, 9]);
+        assert_eq!(sprites[3].flags, 0b101);
+        assert_eq!(sprites[4].flags, 0);
+    }
+}
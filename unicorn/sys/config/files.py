@@ -0,0 +1,48 @@
+# Code files of the cartridge, imported before the modules of the directory
+
+import importlib.util
+import sys
+
+# Sources of the code files, by filename: "enemies.py", "world/map.py"
+UNICORN_FILES = {}
+
+class UnicornFileFinder:
+    @classmethod
+    def find_spec(cls, name, path=None, target=None):
+        base = name.replace(".", "/")
+        if base + ".py" in UNICORN_FILES:
+            return importlib.util.spec_from_loader(name, cls)
+        if any(filename.startswith(base + "/") for filename in UNICORN_FILES):
+            # A directory of code files
+            return importlib.util.spec_from_loader(name, cls, is_package=True)
+        return None
+
+    @classmethod
+    def create_module(cls, spec):
+        return None
+
+    @classmethod
+    def exec_module(cls, module):
+        filename = module.__name__.replace(".", "/") + ".py"
+        source = UNICORN_FILES.get(filename)
+        if source is not None:
+            exec(compile(source, filename, "exec"), module.__dict__)
+
+def unicorn_file_exists(module):
+    return module + ".py" in UNICORN_FILES or \
+        any(filename.startswith(module + "/") for filename in UNICORN_FILES)
+
+def unicorn_files(files):
+    # The modules are imported again with the new code
+    for name, module in list(sys.modules.items()):
+        spec = getattr(module, "__spec__", None)
+        if getattr(spec, "loader", None) is UnicornFileFinder:
+            del sys.modules[name]
+
+    UNICORN_FILES.clear()
+    UNICORN_FILES.update(files)
+
+# Replace the finder of the previous cartridge
+sys.meta_path[:] = [finder for finder in sys.meta_path
+                    if getattr(finder, "__name__", None) != "UnicornFileFinder"]
+sys.meta_path.insert(0, UnicornFileFinder)
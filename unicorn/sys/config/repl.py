@@ -0,0 +1,21 @@
+# Evaluation of the REPL of the console, in the globals of the cartridge
+
+def unicorn_repl(code):
+    try:
+        compiled = compile(code, "repl", "eval")
+    except SyntaxError:
+        # A statement
+        exec(compile(code, "repl", "exec"), globals())
+        return ""
+
+    value = eval(compiled, globals())
+    if value is None:
+        return ""
+    return repr(value)
+
+def unicorn_globals():
+    # Without an import, blocked by the sandbox
+    builtins = __builtins__
+    if not isinstance(builtins, dict):
+        builtins = vars(builtins)
+    return sorted(set(globals()) | set(builtins))
@@ -0,0 +1,168 @@
+# Sandbox of the python cartridges, see plugins::sandbox
+#
+# This is only a guard against the mistakes, Python can't be confined from the
+# inside: the wrappers of the modules only leave out their private names.
+#
+# Run in its own globals: the cartridges only see the hooks of builtins, and
+# the host keeps the object returned by unicorn_sandbox().
+
+UNICORN_SANDBOX_MODULES = frozenset([
+    "bisect", "collections", "copy", "functools", "heapq", "itertools",
+    "json", "math", "operator", "random", "re", "string", "time",
+])
+
+class UnicornBudgetExceeded(Exception):
+    pass
+
+def unicorn_sandbox():
+    import builtins
+    import ctypes
+    import os
+    import sys
+    import sysconfig
+    import threading
+    import time
+    import types
+
+    # The real functions, only kept by the closures
+    real_import = builtins.__import__
+    real_open = builtins.open
+
+    stdlib = [os.path.realpath(sysconfig.get_path(name)) for name in ("stdlib", "platstdlib")]
+    site = [os.path.realpath(sysconfig.get_path(name)) for name in ("purelib", "platlib")]
+
+    policy = {"root": None, "file_exists": None}
+    # Wrappers of the whitelisted modules, by name: (module, wrapper)
+    proxies = {}
+    # Deadline of the current call, checked by the watchdog
+    budget = {"deadline": None, "thread": None}
+
+    def inside(path, directories):
+        return any(directory is not None and
+                   os.path.commonpath([directory, path]) == directory
+                   for directory in directories)
+
+    def origin(module):
+        spec = getattr(module, "__spec__", None)
+        path = getattr(spec, "origin", None)
+        if path in ("built-in", "frozen"):
+            return path
+        if isinstance(path, str) and os.path.isabs(path):
+            return os.path.realpath(path)
+        return None
+
+    def is_stdlib(module):
+        path = origin(module)
+        if path in ("built-in", "frozen"):
+            return True
+        return path is not None and inside(path, stdlib) and \
+            not inside(path, site) and not inside(path, [policy["root"]])
+
+    def importer(globals):
+        # The module running the import, found by its identity: the name in
+        # the globals can be changed by the cartridge, not sys.modules
+        name = (globals or {}).get("__name__")
+        module = sys.modules.get(name) if isinstance(name, str) else None
+        if module is not None and getattr(module, "__dict__", None) is globals:
+            return module
+        return None
+
+    def proxy(module):
+        cached = proxies.get(module.__name__)
+        if cached is not None and cached[0] is module:
+            wrapper = cached[1]
+        else:
+            # Only the public names, without the modules used by the implementation
+            wrapper = types.ModuleType(module.__name__, module.__doc__)
+            proxies[module.__name__] = (module, wrapper)
+
+        # Completed with the submodules imported since the last time
+        for key, value in list(vars(module).items()):
+            if key.startswith("_") or key in vars(wrapper):
+                continue
+            if isinstance(value, types.ModuleType):
+                if not value.__name__.startswith(module.__name__ + "."):
+                    continue
+                value = proxy(value)
+            setattr(wrapper, key, value)
+        return wrapper
+
+    def confined(path):
+        root = policy["root"]
+        path = os.path.realpath(os.path.join(root, path))
+        if os.path.commonpath([root, path]) != root:
+            raise PermissionError("access denied: %s" % path)
+        return path
+
+    def cartridge_module(package):
+        root = policy["root"]
+        if not policy["file_exists"](package) and \
+           not os.path.exists(os.path.join(root, package + ".py")) and \
+           not os.path.isdir(os.path.join(root, package)):
+            return False
+        # A file of the cartridge doesn't replace a module already imported
+        module = sys.modules.get(package)
+        return module is None or not is_stdlib(module)
+
+    def sandbox_import(name, globals=None, locals=None, fromlist=(), level=0):
+        module = importer(globals)
+
+        # The modules of the standard library import what they want
+        if module is not None and is_stdlib(module):
+            return real_import(name, globals, locals, fromlist, level)
+
+        if level == 0:
+            package = name.partition(".")[0]
+            if package in UNICORN_SANDBOX_MODULES:
+                return proxy(real_import(name, globals, locals, fromlist, level))
+            if not cartridge_module(package):
+                raise ImportError("module %s is not available" % name)
+        return real_import(name, globals, locals, fromlist, level)
+
+    def sandbox_open(file, mode="r", *args, **kwargs):
+        if not isinstance(file, str):
+            raise PermissionError("access denied: %s" % file)
+        return real_open(confined(file), mode, *args, **kwargs)
+
+    # The watchdog raises UnicornBudgetExceeded in the calls running after the deadline
+    def watchdog():
+        while True:
+            time.sleep(0.01)
+            deadline = budget["deadline"]
+            if deadline is not None and time.monotonic() > deadline:
+                budget["deadline"] = None
+                ctypes.pythonapi.PyThreadState_SetAsyncExc(
+                    ctypes.c_ulong(budget["thread"]),
+                    ctypes.py_object(UnicornBudgetExceeded))
+
+    threading.Thread(target=watchdog, daemon=True).start()
+
+    class UnicornSandbox:
+        def install(self, root, file_exists):
+            self.uninstall()
+            policy["root"] = os.path.realpath(root)
+            policy["file_exists"] = file_exists
+            sys.path.insert(0, policy["root"])
+            builtins.__import__ = sandbox_import
+            builtins.open = sandbox_open
+
+        def uninstall(self):
+            builtins.__import__ = real_import
+            builtins.open = real_open
+            if policy["root"] in sys.path:
+                sys.path.remove(policy["root"])
+            policy["root"] = None
+
+        def start(self, seconds):
+            budget["thread"] = threading.get_ident()
+            budget["deadline"] = time.monotonic() + seconds
+
+        def stop(self):
+            budget["deadline"] = None
+
+        def import_module(self, name):
+            # The imports of the host, rejected by the hook
+            real_import(name)
+            return sys.modules[name]
+
+    return UnicornSandbox()
@@ -0,0 +1,92 @@
+# Scheduler of the python cartridges, see plugins::scheduler
+#
+# The tasks are generators: "yield wait(frames)" suspends the task.
+
+import math
+
+# Generator and frames to wait of the tasks, by id
+UNICORN_TASKS = {}
+UNICORN_SCHEDULER = {"fps": 60, "last_id": 0}
+
+def wait(frames=1):
+    return frames
+
+def spawn(fn, *args):
+    def task():
+        result = fn(*args)
+        if hasattr(result, "send"):
+            yield from result
+
+    UNICORN_SCHEDULER["last_id"] += 1
+    UNICORN_TASKS[UNICORN_SCHEDULER["last_id"]] = [task(), 0]
+    return UNICORN_SCHEDULER["last_id"]
+
+def after(seconds, fn):
+    def task():
+        yield wait(seconds * UNICORN_SCHEDULER["fps"])
+        fn()
+    return spawn(task)
+
+def every(seconds, fn):
+    def task():
+        while True:
+            yield wait(seconds * UNICORN_SCHEDULER["fps"])
+            if fn() is False:
+                return
+    return spawn(task)
+
+def tween(obj, field, to, duration, easing="linear"):
+    if not callable(easing):
+        name = easing
+        if ease(name, 0) is None:
+            raise ValueError("unknown easing %s" % name)
+        easing = lambda t: ease(name, t)
+
+    if isinstance(obj, dict):
+        get = lambda: obj[field]
+        def set(value):
+            obj[field] = value
+    else:
+        get = lambda: getattr(obj, field)
+        set = lambda value: setattr(obj, field, value)
+
+    def task():
+        start = get()
+        frames = max(1, int(math.floor(duration * UNICORN_SCHEDULER["fps"] + 0.5)))
+        for frame in range(1, frames + 1):
+            set(start + (to - start) * easing(frame / frames))
+            if frame < frames:
+                yield wait()
+    return spawn(task)
+
+def cancel(id):
+    UNICORN_TASKS.pop(id, None)
+
+def unicorn_scheduler(fps):
+    UNICORN_SCHEDULER["fps"] = fps
+    UNICORN_TASKS.clear()
+
+def unicorn_scheduler_tick():
+    for id in sorted(UNICORN_TASKS):
+        task = UNICORN_TASKS.get(id)
+        # Cancelled by another task
+        if task is None:
+            continue
+
+        task[1] -= 1
+        if task[1] > 0:
+            continue
+
+        try:
+            frames = next(task[0])
+        except StopIteration:
+            del UNICORN_TASKS[id]
+            continue
+        except Exception:
+            UNICORN_TASKS.pop(id, None)
+            raise
+
+        try:
+            task[1] = max(1, int(math.ceil(frames)))
+        except TypeError:
+            task[1] = 1